@@ -0,0 +1,139 @@
+//! Задержка построения отчёта для дома из 1000 устройств (20 комнат по 50 устройств).
+//!
+//! `cargo bench -p smart_home_web --bench house_report`
+//!
+//! Сценарии: параметры из хранилища в памяти; живые устройства с задержкой ответа
+//! `DEVICE_LATENCY` (последовательный опрос занял бы `DEVICES * DEVICE_LATENCY`);
+//! MongoDB, если задана переменная `MONGO_DB_URI`.
+
+use async_trait::async_trait;
+use smart_home_web::prelude::*;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+const HOUSE_NAME: &str = "Большой дом";
+const HOUSE_ADDRESS: &str = "ул. Умных домов, д.1000";
+const ROOMS: usize = 20;
+const DEVICES_PER_ROOM: usize = 50;
+const DEVICES: usize = ROOMS * DEVICES_PER_ROOM;
+const DEVICE_LATENCY: Duration = Duration::from_millis(5);
+const ITERATIONS: usize = 20;
+
+/// Источник информации, отвечающий за каждое устройство с задержкой сети
+struct SlowDeviceInfoProvider;
+
+#[async_trait]
+impl SmartDeviceInfoProvider for SlowDeviceInfoProvider {
+    fn contains(&self, _house: &str, _room: &str, _device: &str) -> bool {
+        true
+    }
+
+    async fn device_info(
+        &self,
+        _house: &str,
+        _room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceInfo, SmartHouseError> {
+        tokio::time::sleep(DEVICE_LATENCY).await;
+
+        Ok(SmartDeviceInfo::new(
+            device.to_string(),
+            DeviceStatus::On.to_string(),
+            100.0,
+            20.0,
+        ))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), SmartHouseError> {
+    let app_data = new_house(AppData::new(
+        HOUSE_NAME.to_string(),
+        HOUSE_ADDRESS.to_string(),
+        Box::new(SmartHouseStorageMemory::new()),
+    ))
+    .await?;
+    bench("memory", &app_data).await?;
+
+    let app_data = new_house(AppData::new(
+        HOUSE_NAME.to_string(),
+        HOUSE_ADDRESS.to_string(),
+        Box::new(SmartHouseStorageMemory::new()),
+    ))
+    .await?
+    .with_device_info_provider(Box::new(SlowDeviceInfoProvider));
+    bench(
+        &format!("memory + live devices ({DEVICE_LATENCY:?} each)"),
+        &app_data,
+    )
+    .await?;
+    println!(
+        "  sequential polling would take at least {:?}",
+        DEVICE_LATENCY * DEVICES as u32
+    );
+
+    if let Ok(uri) = env::var("MONGO_DB_URI") {
+        let storage = SmartHouseStorageMongoDB::new(&uri).await?;
+        let app_data = new_house(AppData::new(
+            HOUSE_NAME.to_string(),
+            HOUSE_ADDRESS.to_string(),
+            Box::new(storage),
+        ))
+        .await?;
+        bench("mongodb", &app_data).await?;
+        app_data.storage.remove_house(HOUSE_NAME, None).await?;
+    }
+
+    Ok(())
+}
+
+/// Заполняет дом комнатами и устройствами
+async fn new_house(mut app_data: AppData) -> Result<AppData, SmartHouseError> {
+    app_data.init(HashMap::new()).await?;
+
+    for room in 0..ROOMS {
+        let room = format!("Комната-{room:02}");
+        app_data.add_room(HOUSE_NAME, &room, None).await?;
+        for device in 0..DEVICES_PER_ROOM {
+            let device = format!("Розетка-{device:02}");
+            let meta = SmartDeviceMeta::new(DeviceKind::Socket);
+            app_data
+                .add_device_with_meta(HOUSE_NAME, &room, &device, &meta, None)
+                .await?;
+        }
+    }
+
+    Ok(app_data)
+}
+
+async fn bench(name: &str, app_data: &AppData) -> Result<(), SmartHouseError> {
+    let mut timings = Vec::with_capacity(ITERATIONS);
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        let report = app_data.house_report(HOUSE_NAME).await?;
+        timings.push(start.elapsed());
+        let report = serde_json::to_value(report).expect("отчёт сериализуется в JSON");
+        let devices = report["devices"]
+            .as_object()
+            .expect("устройства по комнатам");
+        assert_eq!(
+            devices
+                .values()
+                .filter_map(|d| d.as_array())
+                .map(Vec::len)
+                .sum::<usize>(),
+            DEVICES
+        );
+    }
+    timings.sort();
+
+    println!(
+        "{name}: {DEVICES} devices, median {:?}, p95 {:?}, min {:?}",
+        timings[ITERATIONS / 2],
+        timings[ITERATIONS * 95 / 100],
+        timings[0]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,225 @@
+"use strict";
+
+// Панель управления основным домом через REST API сервера: структура дома
+// из /house/export, актуальные показания из /house/report, события из /events.
+
+const REPORT_INTERVAL_MS = 5000;
+const EXPORT_INTERVAL_MS = 60000;
+const API_KEY_STORAGE = "smart_home_api_key";
+const CONTROLLABLE_KINDS = ["socket", "switch"];
+const KIND_NAMES = {
+    socket: "розетка",
+    switch: "выключатель",
+    thermometer: "термометр",
+    unknown: "",
+};
+const STATUS_ON = ["on", "включено"];
+const STATUS_OFF = ["off", "выключено"];
+
+const state = {
+    house: null,
+    events: null,
+};
+
+function apiKey() {
+    return localStorage.getItem(API_KEY_STORAGE) || "";
+}
+
+async function api(method, path, body) {
+    const headers = {};
+    if (apiKey()) {
+        headers["X-API-Key"] = apiKey();
+    }
+    if (body !== undefined) {
+        headers["Content-Type"] = "application/json";
+    }
+
+    const response = await fetch(path, {
+        method,
+        headers,
+        body: body === undefined ? undefined : JSON.stringify(body),
+    });
+    const data = await response.json().catch(() => null);
+    if (!response.ok) {
+        const error = new Error(data?.message || `${response.status} ${response.statusText}`);
+        error.code = data?.code;
+        error.status = response.status;
+        throw error;
+    }
+
+    return data;
+}
+
+function showMessage(text) {
+    const message = document.getElementById("message");
+    message.textContent = text || "";
+    message.hidden = !text;
+}
+
+function showError(err) {
+    if (err.status === 401) {
+        showMessage("Требуется ключ API: " + err.message);
+    } else {
+        showMessage(err.message);
+    }
+}
+
+function devicePath(room, device) {
+    return `/rooms/${encodeURIComponent(room)}/devices/${encodeURIComponent(device)}`;
+}
+
+function statusOf(text) {
+    const status = (text || "").toLowerCase();
+    if (STATUS_ON.includes(status)) {
+        return "on";
+    }
+    if (STATUS_OFF.includes(status)) {
+        return "off";
+    }
+    return "unknown";
+}
+
+function render(house) {
+    document.getElementById("house-name").textContent = house.name;
+    document.getElementById("house-address").textContent = house.address;
+    document.title = `Умный дом: ${house.name}`;
+
+    const rooms = document.getElementById("rooms");
+    rooms.replaceChildren();
+    for (const room of house.rooms) {
+        const section = document.getElementById("room-template").content.cloneNode(true);
+        section.querySelector("h2").textContent = room.name;
+        section.querySelector(".empty").hidden = room.devices.length > 0;
+
+        const devices = section.querySelector(".devices");
+        for (const device of room.devices) {
+            devices.append(renderDevice(room.name, device));
+        }
+        rooms.append(section);
+    }
+}
+
+function renderDevice(room, device) {
+    const item = document.getElementById("device-template").content.firstElementChild.cloneNode(true);
+    item.dataset.room = room;
+    item.dataset.device = device.name;
+    item.querySelector(".device-name").textContent = device.name;
+    item.querySelector(".device-kind").textContent = KIND_NAMES[device.meta.kind] ?? device.meta.kind;
+
+    const controls = item.querySelector(".controls");
+    controls.hidden = !CONTROLLABLE_KINDS.includes(device.meta.kind);
+    for (const button of controls.querySelectorAll("button")) {
+        button.addEventListener("click", () => sendCommand(room, device, button.dataset.command, controls));
+    }
+
+    updateDevice(item, device.info || {}, false);
+    return item;
+}
+
+function updateDevice(item, info, unavailable) {
+    const status = item.querySelector(".status");
+    const value = statusOf(info.status);
+    status.className = `status ${value}` + (unavailable ? " unavailable" : "");
+    status.textContent = unavailable ? "недоступно" : (info.status ?? "неизвестно");
+
+    const readings = [];
+    if (info.power) {
+        readings.push(`${Number(info.power).toFixed(1)} Вт`);
+    }
+    if (info.temp) {
+        readings.push(`${Number(info.temp).toFixed(1)} °C`);
+    }
+    item.querySelector(".readings").textContent = readings.join(", ");
+}
+
+// устройство с адресом управляется командой, остальным меняется сохранённый статус
+async function sendCommand(room, device, command, controls) {
+    for (const button of controls.querySelectorAll("button")) {
+        button.disabled = true;
+    }
+    try {
+        if (device.meta.address) {
+            await api("POST", `${devicePath(room, device.name)}/${command}`);
+        } else {
+            await api("PATCH", devicePath(room, device.name), { status: command });
+        }
+        showMessage("");
+        await loadReport();
+    } catch (err) {
+        showError(err);
+    } finally {
+        for (const button of controls.querySelectorAll("button")) {
+            button.disabled = false;
+        }
+    }
+}
+
+async function loadHouse() {
+    try {
+        state.house = await api("GET", "/house/export?format=json");
+        render(state.house);
+        showMessage("");
+        subscribe();
+        await loadReport();
+    } catch (err) {
+        showError(err);
+    }
+}
+
+async function loadReport() {
+    if (!state.house) {
+        return;
+    }
+
+    const report = await api("GET", "/house/report");
+    for (const [room, devices] of Object.entries(report.devices)) {
+        for (const info of devices) {
+            for (const item of document.querySelectorAll(".device")) {
+                // к имени недоступного устройства отчёт добавляет пояснение в скобках
+                const name = item.dataset.device;
+                if (item.dataset.room === room && info.name.startsWith(name)) {
+                    updateDevice(item, info, info.name !== name);
+                }
+            }
+        }
+    }
+    document.getElementById("updated").textContent =
+        "обновлено в " + new Date().toLocaleTimeString();
+}
+
+// EventSource не передаёт заголовки, поэтому при включённой аутентификации
+// остаётся только периодический опрос
+function subscribe() {
+    if (state.events || apiKey()) {
+        return;
+    }
+
+    const events = new EventSource(`/events?house=${encodeURIComponent(state.house.name)}`);
+    events.onopen = () => {
+        document.getElementById("live").textContent = "обновление по событиям";
+    };
+    events.onerror = () => {
+        events.close();
+        state.events = null;
+        document.getElementById("live").textContent = "обновление каждые 5 с";
+    };
+    for (const name of ["room_added", "room_removed", "device_added", "device_removed"]) {
+        events.addEventListener(name, () => loadHouse());
+    }
+    events.addEventListener("device_updated", () => loadReport().catch(showError));
+    state.events = events;
+}
+
+document.getElementById("api-key").value = apiKey();
+document.getElementById("auth-form").addEventListener("submit", (event) => {
+    event.preventDefault();
+    localStorage.setItem(API_KEY_STORAGE, document.getElementById("api-key").value.trim());
+    state.events?.close();
+    state.events = null;
+    loadHouse();
+});
+document.getElementById("refresh").addEventListener("click", () => loadHouse());
+
+setInterval(() => loadReport().catch(showError), REPORT_INTERVAL_MS);
+setInterval(() => loadHouse(), EXPORT_INTERVAL_MS);
+loadHouse();
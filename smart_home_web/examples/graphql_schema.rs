@@ -0,0 +1,7 @@
+use smart_home_web::prelude::graphql_schema_sdl;
+
+/// Схема GraphQL для генерации клиентов:
+/// `cargo run --example graphql_schema > schema.graphql`
+fn main() {
+    print!("{}", graphql_schema_sdl());
+}
@@ -3,6 +3,8 @@ use smart_home_web::prelude::*;
 use std::collections::HashMap;
 use std::sync::atomic::Ordering::SeqCst;
 
+const HOUSE_NAME: &str = "Мой умный дом";
+const HOUSE_ADDRESS: &str = "ул. Умных домов, д.1, кв.2";
 const KITCHEN: &str = "Кухня";
 const LIVING_ROOM: &str = "Гостинная";
 const BEDROOM: &str = "Спальня";
@@ -15,11 +17,25 @@ const SOCKET_3: &str = "Розетка-3";
 const SWITCH_1: &str = "Выключатель-1";
 const SWITCH_2: &str = "Выключатель-2";
 
-fn main() -> Result<(), SmartHouseError> {
+const DEVICES: [(&str, &str, DeviceKind); 10] = [
+    (KITCHEN, SOCKET_1, DeviceKind::Socket),
+    (KITCHEN, SOCKET_2, DeviceKind::Socket),
+    (KITCHEN, SWITCH_1, DeviceKind::Switch),
+    (LIVING_ROOM, THERMOMETER_1, DeviceKind::Thermometer),
+    (LIVING_ROOM, SOCKET_1, DeviceKind::Socket),
+    (LIVING_ROOM, SWITCH_2, DeviceKind::Switch),
+    (BEDROOM, THERMOMETER_2, DeviceKind::Thermometer),
+    (BEDROOM, SWITCH_1, DeviceKind::Switch),
+    (BEDROOM, SWITCH_2, DeviceKind::Switch),
+    (HALLWAY, SOCKET_3, DeviceKind::Socket),
+];
+
+#[tokio::main]
+async fn main() -> Result<(), SmartHouseError> {
     // Инициализация дома
     let mut house = SmartHouse::new(
-        "Мой умный дом".to_string(),
-        "ул. Умных домов, д.1, кв.2".to_string(),
+        HOUSE_NAME.to_string(),
+        HOUSE_ADDRESS.to_string(),
         HashMap::from([
             (KITCHEN, &[SOCKET_1, SOCKET_2, SWITCH_1][..]),
             (LIVING_ROOM, &[THERMOMETER_1, SOCKET_1, SWITCH_2]),
@@ -31,6 +47,25 @@ fn main() -> Result<(), SmartHouseError> {
     house.add_room(HALLWAY)?;
     house.add_device(HALLWAY, SOCKET_3)?;
 
+    // Реестр устройств с их типами
+    let registry = SmartHouseStorageMemory::new();
+    registry.add_house(HOUSE_NAME, HOUSE_ADDRESS).await?;
+    for (room, device, kind) in DEVICES {
+        match registry.add_room(house.name(), room, None).await {
+            Ok(()) | Err(SmartHouseError::RoomAlreadyExistsError(_)) => (),
+            Err(err) => return Err(err),
+        }
+        registry
+            .add_device(
+                house.name(),
+                room,
+                device,
+                &SmartDeviceMeta::new(kind),
+                None,
+            )
+            .await?;
+    }
+
     // Инициализация устройств в доме со случайными показателями
     let mut sockets = vec![];
     let mut thermometers = vec![];
@@ -47,8 +82,8 @@ fn main() -> Result<(), SmartHouseError> {
             None => return Err(SmartHouseError::DevicesNotFoundError),
         };
         for device in devices {
-            match device {
-                SOCKET_1 | SOCKET_2 | SOCKET_3 => {
+            match registry.device(house.name(), room, device).await?.kind {
+                DeviceKind::Socket => {
                     let socket = SmartSocket::new(
                         device.to_string(),
                         room.to_string(),
@@ -70,7 +105,7 @@ fn main() -> Result<(), SmartHouseError> {
                     }
                     sockets.push(socket);
                 }
-                THERMOMETER_1 | THERMOMETER_2 => {
+                DeviceKind::Thermometer => {
                     let thermometer =
                         SmartThermometer::new(device.to_string(), room.to_string(), 0.0);
                     thermometer
@@ -78,7 +113,7 @@ fn main() -> Result<(), SmartHouseError> {
                         .store(rand::thread_rng().gen_range(20.0..30.0), SeqCst);
                     thermometers.push(thermometer);
                 }
-                SWITCH_1 | SWITCH_2 => {
+                DeviceKind::Switch => {
                     let switch = SmartSwitch::new(
                         device.to_string(),
                         room.to_string(),
@@ -91,7 +126,7 @@ fn main() -> Result<(), SmartHouseError> {
                     }
                     switches.push(switch);
                 }
-                _ => {}
+                DeviceKind::Unknown => {}
             }
         }
     }
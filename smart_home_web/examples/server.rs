@@ -1,49 +1,72 @@
+use futures::future::{select_all, BoxFuture};
 use rand::Rng;
 use smart_home_web::prelude::*;
-use tokio::{select, time};
+use tokio::time;
 
-const SOCKET_ADDR: &str = "127.0.0.1:54321";
-const THERMOMETER_ADDR: &str = "127.0.0.1:12345";
-const SWITCH_ADDR: &str = "127.0.0.1:31254";
+// устройства с адресами из той же конфигурации, что и у examples/http_server.rs
+const DEFAULT_CONFIG: &str = include_str!("config.toml");
 
 #[tokio::main]
 async fn main() -> Result<(), SmartHouseError> {
-    tokio::spawn(async move {
-        for _ in 0..100 {
-            time::sleep(time::Duration::from_secs_f32(1.5)).await;
-            let temp = rand::thread_rng().gen_range(20.0..25.0);
-            match SmartThermometer::send_command(THERMOMETER_ADDR, temp.to_string().as_str()).await
-            {
-                Ok(result) => {
-                    println!("SERVER: SmartThermometer sensor sent datagram -> '{result}'")
+    let config = AppConfig::load(AppConfig::from_toml(DEFAULT_CONFIG)?)?;
+
+    let mut devices: Vec<BoxFuture<Result<(), SmartHouseError>>> = Vec::new();
+    for house in &config.houses {
+        for room in &house.rooms {
+            for device in &room.devices {
+                let Some(addr) = device.meta.address.clone() else {
+                    continue;
+                };
+                let info = device.info.clone().unwrap_or_default();
+                let status = match info.status.as_deref() {
+                    Some(status) => status.parse()?,
+                    None => DeviceStatus::Unknown,
+                };
+                let (name, room) = (device.name.clone(), room.name.clone());
+
+                match device.meta.kind {
+                    DeviceKind::Socket => {
+                        let power = info.power.unwrap_or_default();
+                        let socket = SmartSocket::new(name, room, status, power);
+                        devices.push(Box::pin(async move { socket.listen(&addr).await }));
+                    }
+                    DeviceKind::Switch => {
+                        let switch = SmartSwitch::new(name, room, status);
+                        devices.push(Box::pin(async move { switch.listen(&addr).await }));
+                    }
+                    DeviceKind::Thermometer => {
+                        let temp = info.temp.unwrap_or_default();
+                        let thermometer = SmartThermometer::new(name, room, temp);
+                        tokio::spawn(feed_thermometer(addr.clone()));
+                        devices.push(Box::pin(async move { thermometer.listen(&addr).await }));
+                    }
+                    DeviceKind::Unknown => {
+                        eprintln!("SERVER: пропущено устройство '{name}' неизвестного типа")
+                    }
                 }
-                Err(err) => eprintln!("SERVER: ThermSensor error -> {}", err),
             }
         }
-    });
-
-    let smart_thermometer =
-        SmartThermometer::new("Термометрик".to_string(), "Комнатка-2".to_string(), 22.33)
-            .listen(THERMOMETER_ADDR);
+    }
 
-    let smart_socket = SmartSocket::new(
-        "Розеточка".to_string(),
-        "Комнатка-1".to_string(),
-        DeviceStatus::Off,
-        0.0,
-    )
-    .listen(SOCKET_ADDR);
+    if devices.is_empty() {
+        return Err(SmartHouseError::ValidationError(
+            "в конфигурации нет устройств с адресом".to_string(),
+        ));
+    }
 
-    let smart_switch = SmartSwitch::new(
-        "Выключателик".to_string(),
-        "Комнатка-3".to_string(),
-        DeviceStatus::Off,
-    )
-    .listen(SWITCH_ADDR);
+    select_all(devices).await.0
+}
 
-    select! {
-        st_result = smart_thermometer => st_result,
-        ss_result = smart_socket => ss_result,
-        sw_result = smart_switch => sw_result
+/// Имитирует датчик, присылающий термометру случайную температуру
+async fn feed_thermometer(addr: String) {
+    for _ in 0..100 {
+        time::sleep(time::Duration::from_secs_f32(1.5)).await;
+        let temp = rand::thread_rng().gen_range(20.0..25.0);
+        match SmartThermometer::send_command(&addr, temp.to_string().as_str()).await {
+            Ok(result) => {
+                println!("SERVER: SmartThermometer sensor sent datagram -> '{result}'")
+            }
+            Err(err) => eprintln!("SERVER: ThermSensor error -> {}", err),
+        }
     }
 }
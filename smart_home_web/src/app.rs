@@ -1,12 +1,51 @@
-use crate::prelude::{DeviceStatus, SmartDeviceInfo, SmartHouseError, SmartHouseReport};
-use crate::smart_house_storage::SmartHouseDeviceStorage;
-use std::collections::BTreeMap;
+use crate::audit::{audit_target, current_actor};
+use crate::auth::{generate_key, hash_key};
+use crate::idempotency::DEFAULT_IDEMPOTENCY_TTL;
+use crate::limits::{Limits, NameKind, RateLimiter};
+use crate::metrics::Metrics;
+use crate::prelude::{
+    ApiUser, AppConfig, AuditQuery, AuditRecord, DeviceCommand, DeviceCommandResult,
+    DeviceController, DeviceHistoryPoint, DeviceInfoProviderKind, DeviceQuery, DeviceReading,
+    DeviceStatus, HistoryQuery, HouseDevices, HouseSnapshot, IdempotencyRecord, IdempotentResponse,
+    ImportMode, ImportReport, NetworkDeviceInfoProvider, Page, ReadingAggregate, Role, RoomDevice,
+    RoomQuery, SmartDeviceInfo, SmartDeviceInfoProvider, SmartDeviceInfoUpdate, SmartDeviceMeta,
+    SmartDeviceRecord, SmartHouseError, SmartHouseEvent, SmartHouseRecord, SmartHouseReport,
+    SmartHouseStorageMemory, SmartHouseStorageMongoDB, StorageBackend,
+};
+use crate::smart_house_snapshot::{ImportAction, ImportFailure};
+use crate::smart_house_storage::{check_version, SmartHouseDeviceStorage};
+use chrono::{Duration, Utc};
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::time::Instant;
+use tokio::sync::{broadcast, watch};
 
 const DEVICE_NOT_FOUND_IN_PROVIDER: &str = " (устройство не найдено в источнике информации)";
+const DEVICE_UNAVAILABLE: &str = " (устройство недоступно)";
+const DEFAULT_HISTORY_PERIOD_SECS: i64 = 3600;
+const DEFAULT_HISTORY_STEP_SECS: u64 = 60;
+const MAX_HISTORY_POINTS: i64 = 10000;
+const EVENTS_CAPACITY: usize = 256;
+const DEFAULT_EVENTS_HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(15);
+const REPORT_CONCURRENCY: usize = 64;
+const STORAGE_PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
 pub struct AppData {
     pub name: String,
     address: String,
     pub storage: Box<dyn SmartHouseDeviceStorage + Send + Sync>,
+    provider: Option<Box<dyn SmartDeviceInfoProvider + Send + Sync>>,
+    controller: DeviceController,
+    events: broadcast::Sender<SmartHouseEvent>,
+    events_heartbeat: std::time::Duration,
+    /// `true` после начала остановки сервера, длительные ответы завершаются
+    shutdown: watch::Sender<bool>,
+    metrics: Metrics,
+    limits: Limits,
+    rate_limiter: RateLimiter,
+    idempotency_ttl: Option<std::time::Duration>,
 }
 
 impl AppData {
@@ -19,76 +58,1030 @@ impl AppData {
             name,
             address,
             storage,
+            provider: None,
+            controller: DeviceController::default(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            events_heartbeat: DEFAULT_EVENTS_HEARTBEAT,
+            shutdown: watch::channel(false).0,
+            metrics: Metrics::new(),
+            rate_limiter: RateLimiter::new(&Limits::default()),
+            limits: Limits::default(),
+            idempotency_ttl: Some(DEFAULT_IDEMPOTENCY_TTL),
+        }
+    }
+
+    /// Хранилище, дома и источник информации об устройствах из конфигурации.
+    /// Дома импортируются без замены уже существующих комнат и устройств.
+    pub async fn from_config(config: &AppConfig) -> Result<Self, SmartHouseError> {
+        let storage: Box<dyn SmartHouseDeviceStorage + Send + Sync> = match config.storage.backend {
+            StorageBackend::Memory => Box::new(SmartHouseStorageMemory::new()),
+            StorageBackend::MongoDB => {
+                let uri = config.storage.mongo_db_uri.as_deref().unwrap_or_default();
+                Box::new(
+                    SmartHouseStorageMongoDB::connect(
+                        uri,
+                        config.storage.connect_attempts,
+                        config.storage.connect_delay(),
+                    )
+                    .await?,
+                )
+            }
+        };
+
+        let house = config.default_house();
+        let app_data = Self::new(house.name.clone(), house.address.clone(), storage)
+            .with_limits(config.limits.clone())
+            .with_idempotency_ttl(config.server.idempotency_ttl());
+        for house in &config.houses {
+            let report = app_data
+                .import_house(house, ImportMode::Merge, false)
+                .await?;
+            info!(
+                "House '{}' loaded from config: {} created, {} conflicts",
+                report.house,
+                report.created.len(),
+                report.conflicts.len()
+            );
+        }
+
+        match config.device_info_provider {
+            DeviceInfoProviderKind::Storage => Ok(app_data),
+            DeviceInfoProviderKind::Network => {
+                let provider = app_data.network_devices().await?;
+                Ok(app_data.with_device_info_provider(Box::new(provider)))
+            }
+        }
+    }
+
+    /// Устройства всех сохранённых домов с известным типом и адресом в реестре,
+    /// устройства, добавленные позже, регистрируются в `add_device_with_meta`
+    async fn network_devices(&self) -> Result<NetworkDeviceInfoProvider, SmartHouseError> {
+        let provider = NetworkDeviceInfoProvider::default();
+        for house in self.houses().await? {
+            for room in self.rooms(&house.name).await? {
+                for record in self.device_records(&house.name, &room).await? {
+                    if let (false, Some(address)) = (record.kind.is_unknown(), &record.address) {
+                        provider.add_device(&house.name, &room, &record.name, record.kind, address);
+                    }
+                }
+            }
+        }
+
+        Ok(provider)
+    }
+
+    pub fn with_device_info_provider(
+        mut self,
+        provider: Box<dyn SmartDeviceInfoProvider + Send + Sync>,
+    ) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    pub fn with_device_controller(mut self, controller: DeviceController) -> Self {
+        self.controller = controller;
+        self
+    }
+
+    /// Ограничения на названия и запросы, по умолчанию `Limits::default()`
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.rate_limiter = RateLimiter::new(&limits);
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub(crate) fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Сколько хранить ответы на запросы с ключом идемпотентности, по умолчанию сутки.
+    /// `None` - заголовок `Idempotency-Key` не учитывается.
+    pub fn with_idempotency_ttl(mut self, ttl: Option<std::time::Duration>) -> Self {
+        self.idempotency_ttl = ttl;
+        self
+    }
+
+    pub fn idempotency_ttl(&self) -> Option<std::time::Duration> {
+        self.idempotency_ttl
+    }
+
+    /// Подписка на изменения домов, комнат и устройств
+    pub fn subscribe(&self) -> broadcast::Receiver<SmartHouseEvent> {
+        self.events.subscribe()
+    }
+
+    /// Как часто поток событий без изменений отправляет комментарий, чтобы прокси
+    /// не закрыли соединение, по умолчанию раз в 15 секунд
+    pub fn with_events_heartbeat(mut self, heartbeat: std::time::Duration) -> Self {
+        self.events_heartbeat = heartbeat;
+        self
+    }
+
+    pub fn events_heartbeat(&self) -> std::time::Duration {
+        self.events_heartbeat
+    }
+
+    /// Сообщает о начале остановки сервера: потоки событий завершаются,
+    /// не дожидаясь `shutdown_timeout`
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Выполняет операцию хранилища, записывая её длительность в метрики
+    pub(crate) async fn metered<T>(
+        &self,
+        operation: &str,
+        future: impl Future<Output = Result<T, SmartHouseError>>,
+    ) -> Result<T, SmartHouseError> {
+        let start = Instant::now();
+        let result = future.await;
+        self.metrics.observe_storage(operation, start.elapsed());
+
+        result
+    }
+
+    /// Выполняет изменение и записывает в журнал аудита автора, операцию, цель и результат.
+    /// Ошибка записи в журнал не отменяет уже выполненное изменение.
+    async fn audited<T>(
+        &self,
+        operation: &str,
+        target: String,
+        future: impl Future<Output = Result<T, SmartHouseError>>,
+    ) -> Result<T, SmartHouseError> {
+        let result = future.await;
+
+        let record = AuditRecord::new(current_actor(), operation, target, result.as_ref().err());
+        if let Err(err) = self
+            .metered("add_audit_record", self.storage.add_audit_record(&record))
+            .await
+        {
+            warn!("audit record of '{operation}' is not saved: {err}");
+        }
+
+        result
+    }
+
+    /// Сохраняет запрос с ключом идемпотентности как выполняющийся или возвращает
+    /// уже сохранённый запрос с тем же ключом
+    pub(crate) async fn start_idempotent_request(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, SmartHouseError> {
+        self.metered(
+            "start_idempotent_request",
+            self.storage.start_idempotent_request(record),
+        )
+        .await
+    }
+
+    /// Сохраняет ответ для повторных запросов на `ttl`. Без сохранённого ответа повторный
+    /// запрос получит 409 до конца блокировки ключа, поэтому ошибка только записывается в журнал.
+    pub(crate) async fn complete_idempotent_request(
+        &self,
+        key: &str,
+        response: IdempotentResponse,
+        ttl: std::time::Duration,
+    ) {
+        if let Err(err) = self
+            .metered(
+                "complete_idempotent_request",
+                self.storage
+                    .complete_idempotent_request(key, &response, Utc::now() + ttl),
+            )
+            .await
+        {
+            warn!("response for idempotency key '{key}' is not saved: {err}");
+        }
+    }
+
+    /// Забывает ключ идемпотентности, чтобы запрос можно было повторить
+    pub(crate) async fn remove_idempotent_request(&self, key: &str) {
+        if let Err(err) = self
+            .metered(
+                "remove_idempotent_request",
+                self.storage.remove_idempotent_request(key),
+            )
+            .await
+        {
+            warn!("idempotency key '{key}' is not removed: {err}");
+        }
+    }
+
+    /// Журнал аудита изменений, от новых записей к старым
+    pub async fn audit_log(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>, SmartHouseError> {
+        let query = query.validate()?;
+
+        self.metered("audit_records", self.storage.audit_records(&query))
+            .await
+    }
+
+    /// Проверяет доступность хранилища, ожидая ответ не дольше `STORAGE_PING_TIMEOUT`
+    pub async fn ping_storage(&self) -> Result<(), SmartHouseError> {
+        match tokio::time::timeout(
+            STORAGE_PING_TIMEOUT,
+            self.metered("ping", self.storage.ping()),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => Err(SmartHouseError::OtherError(format!(
+                "хранилище не ответило за {STORAGE_PING_TIMEOUT:?}"
+            ))),
         }
     }
 
-    pub async fn rooms(&self) -> Result<Vec<String>, SmartHouseError> {
-        let mut rooms = self.storage.rooms().await?;
+    fn emit(&self, event: SmartHouseEvent) {
+        // ошибка означает лишь отсутствие подписчиков
+        let _ = self.events.send(event);
+    }
+
+    /// Создаёт дом по умолчанию и заполняет его тестовыми устройствами
+    pub async fn init(
+        &mut self,
+        devices_info: HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>>,
+    ) -> Result<(), SmartHouseError> {
+        self.storage
+            .init(&self.name, &self.address, devices_info)
+            .await
+    }
+
+    pub async fn houses(&self) -> Result<Vec<SmartHouseRecord>, SmartHouseError> {
+        let mut houses = self.metered("houses", self.storage.houses()).await?;
+        houses.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(houses)
+    }
+
+    pub async fn house(&self, house: &str) -> Result<SmartHouseRecord, SmartHouseError> {
+        self.metered("house", self.storage.house(house)).await
+    }
+
+    pub async fn add_house(&self, house: &str, address: &str) -> Result<(), SmartHouseError> {
+        self.audited("add_house", audit_target(&[house]), async {
+            self.limits.validate_name(NameKind::House, house)?;
+
+            self.metered("add_house", self.storage.add_house(house, address))
+                .await?;
+            self.emit(SmartHouseEvent::HouseAdded {
+                house: house.to_string(),
+            });
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Удаляет дом, при заданной `version` - только если версия дома не изменилась
+    pub async fn remove_house(
+        &self,
+        house: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.audited("remove_house", audit_target(&[house]), async {
+            if house == self.name {
+                return Err(SmartHouseError::ValidationError(format!(
+                    "дом по умолчанию '{house}' нельзя удалить"
+                )));
+            }
+
+            self.metered("remove_house", self.storage.remove_house(house, version))
+                .await?;
+            self.emit(SmartHouseEvent::HouseRemoved {
+                house: house.to_string(),
+            });
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn rooms(&self, house: &str) -> Result<Vec<String>, SmartHouseError> {
+        let mut rooms = self.metered("rooms", self.storage.rooms(house)).await?;
         rooms.sort();
 
         Ok(rooms)
     }
 
-    pub async fn add_room(&self, room: &str) -> Result<(), SmartHouseError> {
-        self.storage.add_room(room).await
+    /// Страница комнат дома, отфильтрованных и отсортированных по запросу
+    pub async fn find_rooms(
+        &self,
+        house: &str,
+        query: &RoomQuery,
+    ) -> Result<Page<String>, SmartHouseError> {
+        let query = query.validate()?;
+        let rooms = self
+            .metered(
+                "find_rooms",
+                self.storage.find_rooms(
+                    house,
+                    &RoomQuery {
+                        limit: query.limit.map(|limit| limit + 1),
+                        ..query.clone()
+                    },
+                ),
+            )
+            .await?;
+
+        Ok(Page::new(rooms, query.limit, String::as_str))
+    }
+
+    /// Добавляет комнату, при заданной `version` - только если версия дома не изменилась
+    pub async fn add_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.audited("add_room", audit_target(&[house, room]), async {
+            self.limits.validate_name(NameKind::Room, room)?;
+
+            self.metered("add_room", self.storage.add_room(house, room, version))
+                .await?;
+            self.emit(SmartHouseEvent::RoomAdded {
+                house: house.to_string(),
+                room: room.to_string(),
+            });
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Удаляет комнату, при заданной `version` - только если версия комнаты не изменилась
+    pub async fn remove_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.audited("remove_room", audit_target(&[house, room]), async {
+            self.metered(
+                "remove_room",
+                self.storage.remove_room(house, room, version),
+            )
+            .await?;
+            self.emit(SmartHouseEvent::RoomRemoved {
+                house: house.to_string(),
+                room: room.to_string(),
+            });
+
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn remove_room(&self, room: &str) -> Result<(), SmartHouseError> {
-        self.storage.remove_room(room).await
+    /// Версия комнаты для `ETag` списка её устройств
+    pub async fn room_version(&self, house: &str, room: &str) -> Result<u64, SmartHouseError> {
+        self.metered("room_version", self.storage.room_version(house, room))
+            .await
     }
 
-    pub async fn devices(&self, room: &str) -> Result<Vec<String>, SmartHouseError> {
-        let mut devices = self.storage.devices(room).await?;
+    pub async fn devices(&self, house: &str, room: &str) -> Result<Vec<String>, SmartHouseError> {
+        let mut devices = self
+            .metered("devices", self.storage.devices(house, room))
+            .await?;
         devices.sort();
 
         Ok(devices)
     }
 
-    pub async fn add_device(&self, room: &str, device: &str) -> Result<(), SmartHouseError> {
-        self.storage.add_device(room, device).await
+    pub async fn device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceRecord, SmartHouseError> {
+        self.metered("device", self.storage.device(house, room, device))
+            .await
     }
 
-    pub async fn remove_device(&self, room: &str, device: &str) -> Result<(), SmartHouseError> {
-        self.storage.remove_device(room, device).await
+    pub async fn device_records(
+        &self,
+        house: &str,
+        room: &str,
+    ) -> Result<Vec<SmartDeviceRecord>, SmartHouseError> {
+        let mut records = self
+            .metered("device_records", self.storage.device_records(house, room))
+            .await?;
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(records)
+    }
+
+    /// Страница устройств комнаты, отфильтрованных и отсортированных по запросу
+    pub async fn find_devices(
+        &self,
+        house: &str,
+        room: &str,
+        query: &DeviceQuery,
+    ) -> Result<Page<SmartDeviceRecord>, SmartHouseError> {
+        let query = query.validate()?;
+        let records = self
+            .metered(
+                "find_devices",
+                self.storage.find_devices(
+                    house,
+                    room,
+                    &DeviceQuery {
+                        limit: query.limit.map(|limit| limit + 1),
+                        ..query.clone()
+                    },
+                ),
+            )
+            .await?;
+
+        Ok(Page::new(records, query.limit, |record| {
+            record.name.as_str()
+        }))
+    }
+
+    pub async fn add_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Result<(), SmartHouseError> {
+        self.add_device_with_meta(house, room, device, &SmartDeviceMeta::default(), None)
+            .await
+    }
+
+    /// Добавляет устройство, при заданной `version` - только если версия комнаты не изменилась
+    pub async fn add_device_with_meta(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        meta: &SmartDeviceMeta,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.audited("add_device", audit_target(&[house, room, device]), async {
+            self.limits.validate_name(NameKind::Device, device)?;
+            meta.validate()?;
+
+            self.metered(
+                "add_device",
+                self.storage.add_device(house, room, device, meta, version),
+            )
+            .await?;
+            if let Some(provider) = &self.provider {
+                match (meta.kind.is_unknown(), &meta.address) {
+                    (false, Some(address)) => {
+                        provider.register_device(house, room, device, meta.kind, address)
+                    }
+                    _ => provider.unregister_device(house, room, device),
+                }
+            }
+            self.emit(SmartHouseEvent::DeviceAdded {
+                house: house.to_string(),
+                room: room.to_string(),
+                device: device.to_string(),
+            });
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Удаляет устройство, при заданной `version` - только если версия устройства не изменилась
+    pub async fn remove_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.audited(
+            "remove_device",
+            audit_target(&[house, room, device]),
+            async {
+                self.metered(
+                    "remove_device",
+                    self.storage.remove_device(house, room, device, version),
+                )
+                .await?;
+                if let Some(provider) = &self.provider {
+                    provider.unregister_device(house, room, device);
+                }
+                self.emit(SmartHouseEvent::DeviceRemoved {
+                    house: house.to_string(),
+                    room: room.to_string(),
+                    device: device.to_string(),
+                });
+
+                Ok(())
+            },
+        )
+        .await
     }
 
     pub async fn device_info(
         &self,
+        house: &str,
         room: &str,
         device: &str,
     ) -> Result<SmartDeviceInfo, SmartHouseError> {
-        let info = self
-            .storage
-            .device_info(room, device)
+        let mut info = self.fetch_device_info(house, room, device).await;
+        if info.kind.is_unknown() {
+            if let Ok(record) = self
+                .metered("device", self.storage.device(house, room, device))
+                .await
+            {
+                info.kind = record.kind;
+            }
+        }
+
+        Ok(info)
+    }
+
+    async fn fetch_device_info(&self, house: &str, room: &str, device: &str) -> SmartDeviceInfo {
+        if let Some(info) = self.live_device_info(house, room, device).await {
+            return info;
+        }
+
+        self.metered("device_info", self.storage.device_info(house, room, device))
+            .await
+            .unwrap_or_else(|_| missing_device_info(device))
+    }
+
+    /// Параметры устройства от источника информации, `None`, если источник его не знает
+    async fn live_device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Option<SmartDeviceInfo> {
+        let provider = self.provider.as_ref()?;
+        if !provider.contains(house, room, device) {
+            return None;
+        }
+
+        let info = provider
+            .device_info(house, room, device)
             .await
-            .unwrap_or_else(|_| SmartDeviceInfo {
-                name: device.to_string() + DEVICE_NOT_FOUND_IN_PROVIDER,
-                status: DeviceStatus::Unknown.to_string(),
-                power: 0.0,
-                temp: 0.0,
+            .unwrap_or_else(|err| {
+                warn!("device '{device}' in room '{room}' is unavailable: {err}");
+                SmartDeviceInfo::new(
+                    device.to_string() + DEVICE_UNAVAILABLE,
+                    DeviceStatus::Unknown.to_string(),
+                    0.0,
+                    0.0,
+                )
             });
 
-        Ok(info)
+        Some(info)
     }
 
-    pub async fn house_report(&self) -> Result<SmartHouseReport, SmartHouseError> {
-        let rooms = self.rooms().await?;
-        let mut devices_info: BTreeMap<String, Vec<SmartDeviceInfo>> = BTreeMap::new();
+    pub async fn update_device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        update: &SmartDeviceInfoUpdate,
+        version: Option<u64>,
+    ) -> Result<(SmartDeviceInfo, u64), SmartHouseError> {
+        self.audited(
+            "update_device_info",
+            audit_target(&[house, room, device]),
+            async {
+                let update = update.validate()?;
+
+                self.store_device_info(house, room, device, &update, version)
+                    .await
+            },
+        )
+        .await
+    }
+
+    /// Сохраняет параметры устройства и показание в историю без проверки и аудита:
+    /// вызывающий уже проверил данные или получил их от самого устройства
+    async fn store_device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        update: &SmartDeviceInfoUpdate,
+        version: Option<u64>,
+    ) -> Result<(SmartDeviceInfo, u64), SmartHouseError> {
+        let (info, version) = self
+            .metered(
+                "update_device_info",
+                self.storage
+                    .update_device_info(house, room, device, update, version),
+            )
+            .await?;
+        // изменение уже сохранено, ошибка истории не должна превращать его в неудачу
+        if let Err(err) = self
+            .metered(
+                "add_reading",
+                self.storage.add_reading(
+                    house,
+                    room,
+                    device,
+                    &DeviceReading::new(Utc::now(), &info),
+                ),
+            )
+            .await
+        {
+            warn!("reading of device '{device}' in room '{room}' is not saved: {err}");
+        }
+        self.emit(SmartHouseEvent::DeviceUpdated {
+            house: house.to_string(),
+            room: room.to_string(),
+            device: device.to_string(),
+            info: info.clone(),
+        });
+
+        Ok((info, version))
+    }
 
-        for room in rooms {
-            let devices = self.devices(&room).await?;
-            for device in devices {
-                let info = self.device_info(&room, &device).await?;
-                devices_info.entry(room.clone()).or_default().push(info);
+    pub async fn api_users(&self) -> Result<Vec<ApiUser>, SmartHouseError> {
+        let mut users = self.metered("api_users", self.storage.api_users()).await?;
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(users)
+    }
+
+    /// Создаёт пользователя со случайным ключом API, ключ возвращается только здесь
+    pub async fn add_api_user(
+        &self,
+        name: &str,
+        role: Role,
+    ) -> Result<(ApiUser, String), SmartHouseError> {
+        let key = generate_key();
+        let user = self.add_api_user_with_key(name, role, &key).await?;
+
+        Ok((user, key))
+    }
+
+    /// Создаёт пользователя с заданным ключом API, например администратора из конфигурации
+    pub async fn add_api_user_with_key(
+        &self,
+        name: &str,
+        role: Role,
+        key: &str,
+    ) -> Result<ApiUser, SmartHouseError> {
+        self.audited("add_api_user", name.to_string(), async {
+            self.limits.validate_name(NameKind::User, name)?;
+            if key.is_empty() {
+                return Err(SmartHouseError::ValidationError(
+                    "ключ API не может быть пустым".to_string(),
+                ));
+            }
+
+            let user = ApiUser::new(name, role);
+            self.metered(
+                "add_api_user",
+                self.storage.add_api_user(&user, &hash_key(key)),
+            )
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    /// Заменяет ключ API пользователя
+    pub async fn set_api_user_key(&self, name: &str, key: &str) -> Result<(), SmartHouseError> {
+        self.audited("set_api_user_key", name.to_string(), async {
+            if key.is_empty() {
+                return Err(SmartHouseError::ValidationError(
+                    "ключ API не может быть пустым".to_string(),
+                ));
+            }
+
+            self.metered(
+                "set_api_user_key",
+                self.storage.set_api_user_key(name, &hash_key(key)),
+            )
+            .await
+        })
+        .await
+    }
+
+    pub async fn remove_api_user(&self, name: &str) -> Result<(), SmartHouseError> {
+        self.audited("remove_api_user", name.to_string(), async {
+            self.metered("remove_api_user", self.storage.remove_api_user(name))
+                .await
+        })
+        .await
+    }
+
+    /// Отправляет команду устройству по адресу из реестра и сохраняет полученные показания,
+    /// при заданной `version` - только если версия устройства не изменилась
+    pub async fn send_device_command(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        command: &DeviceCommand,
+        version: Option<u64>,
+    ) -> Result<DeviceCommandResult, SmartHouseError> {
+        self.audited(
+            "send_device_command",
+            audit_target(&[house, room, device]),
+            async {
+                let record = self
+                    .metered("device", self.storage.device(house, room, device))
+                    .await?;
+                check_version(version, record.version, || {
+                    format!("устройство '{device}' в комнате '{room}'")
+                })?;
+                let address = match &record.address {
+                    Some(address) => address,
+                    None => {
+                        return Err(SmartHouseError::ValidationError(format!(
+                            "у устройства '{device}' не задан адрес, управление невозможно"
+                        )))
+                    }
+                };
+
+                let result = self
+                    .controller
+                    .execute(device, record.kind, address, command)
+                    .await?;
+
+                if let Some(provider) = &self.provider {
+                    provider.invalidate(house, room, device);
+                }
+
+                let update = result.update();
+                if !update.is_empty() {
+                    self.store_device_info(house, room, device, &update, None)
+                        .await?;
+                }
+
+                Ok(result)
+            },
+        )
+        .await
+    }
+
+    /// Сохраняет в историю текущие показания устройств всех домов, возвращает количество показаний
+    pub async fn record_history(&self) -> Result<usize, SmartHouseError> {
+        let mut count = 0;
+        let timestamp = Utc::now();
+
+        for house in self.houses().await? {
+            let house = house.name;
+            for room in self.rooms(&house).await? {
+                for device in self.devices(&house, &room).await? {
+                    let info = match &self.provider {
+                        Some(provider) if provider.contains(&house, &room, &device) => {
+                            provider.device_info(&house, &room, &device).await
+                        }
+                        _ => {
+                            self.metered(
+                                "device_info",
+                                self.storage.device_info(&house, &room, &device),
+                            )
+                            .await
+                        }
+                    };
+
+                    if let Ok(info) = info {
+                        let reading = DeviceReading::new(timestamp, &info);
+                        self.metered(
+                            "add_reading",
+                            self.storage.add_reading(&house, &room, &device, &reading),
+                        )
+                        .await?;
+                        count += 1;
+                    }
+                }
             }
         }
 
+        Ok(count)
+    }
+
+    pub async fn device_history(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        query: &HistoryQuery,
+    ) -> Result<Vec<DeviceHistoryPoint>, SmartHouseError> {
+        let to = query.to.unwrap_or_else(Utc::now);
+        let from = match query.from {
+            Some(from) => Some(from),
+            None => to.checked_sub_signed(Duration::seconds(DEFAULT_HISTORY_PERIOD_SECS)),
+        };
+        let step = query.step.unwrap_or(DEFAULT_HISTORY_STEP_SECS);
+
+        let from = match from {
+            Some(from) if from < to => from,
+            _ => {
+                return Err(SmartHouseError::ValidationError(
+                    "начало периода должно быть раньше его конца".to_string(),
+                ))
+            }
+        };
+        // шаг, не представимый как `Duration`, - ошибка проверки, а не паника
+        let step = match i64::try_from(step).ok().and_then(Duration::try_seconds) {
+            Some(step) if step > Duration::zero() => step,
+            _ => {
+                return Err(SmartHouseError::ValidationError(format!(
+                    "некорректный шаг агрегации {step}"
+                )))
+            }
+        };
+        if (to - from).num_seconds() / step.num_seconds() > MAX_HISTORY_POINTS {
+            return Err(SmartHouseError::ValidationError(format!(
+                "период содержит более {MAX_HISTORY_POINTS} интервалов"
+            )));
+        }
+
+        self.metered("device", self.storage.device(house, room, device))
+            .await?;
+        let readings = self
+            .metered(
+                "readings",
+                self.storage.readings(house, room, device, from, to),
+            )
+            .await?;
+
+        let mut buckets: BTreeMap<i64, Vec<DeviceReading>> = BTreeMap::new();
+        for reading in readings {
+            let index = (reading.timestamp - from).num_seconds() / step.num_seconds();
+            buckets.entry(index).or_default().push(reading);
+        }
+
+        let history = buckets
+            .into_iter()
+            .map(|(index, readings)| {
+                let bucket_from = from + step * index as i32;
+                DeviceHistoryPoint {
+                    from: bucket_from,
+                    to: bucket_from
+                        .checked_add_signed(step)
+                        .map_or(to, |bucket_to| bucket_to.min(to)),
+                    count: readings.len(),
+                    power: ReadingAggregate::new(readings.iter().map(|r| r.power)),
+                    temp: ReadingAggregate::new(readings.iter().map(|r| r.temp)),
+                }
+            })
+            .collect();
+
+        Ok(history)
+    }
+
+    /// Отчёт строится по одному запросу к хранилищу, устройства из источника информации
+    /// опрашиваются параллельно, не более `REPORT_CONCURRENCY` одновременно
+    pub async fn house_report(&self, house: &str) -> Result<SmartHouseReport, SmartHouseError> {
+        let HouseDevices {
+            house: record,
+            devices,
+        } = self
+            .metered("house_devices", self.storage.house_devices(house))
+            .await?;
+
+        let infos: Vec<(String, SmartDeviceInfo)> = stream::iter(devices)
+            .map(|device| self.report_device_info(house, device))
+            .buffered(REPORT_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut devices_info: BTreeMap<String, Vec<SmartDeviceInfo>> = BTreeMap::new();
+        for (room, info) in infos {
+            devices_info.entry(room).or_default().push(info);
+        }
+
         let report = SmartHouseReport {
-            name: self.name.clone(),
-            address: self.address.clone(),
+            name: record.name,
+            address: record.address,
             devices: devices_info,
         };
 
         Ok(report)
     }
+
+    /// Обновляет показания устройств основного дома в метриках по сохранённым
+    /// параметрам, без опроса самих устройств
+    pub async fn update_device_metrics(&self) -> Result<(), SmartHouseError> {
+        let house_devices = self
+            .metered("house_devices", self.storage.house_devices(&self.name))
+            .await?;
+        self.metrics.set_devices(&house_devices);
+
+        Ok(())
+    }
+
+    /// Конфигурация дома: все комнаты (в том числе пустые), устройства и их параметры
+    pub async fn export_house(&self, house: &str) -> Result<HouseSnapshot, SmartHouseError> {
+        let house_devices = self
+            .metered("house_devices", self.storage.house_devices(house))
+            .await?;
+        let rooms = self.metered("rooms", self.storage.rooms(house)).await?;
+
+        Ok(HouseSnapshot::new(rooms, house_devices))
+    }
+
+    /// Импортирует конфигурацию в дом с её именем, создавая его при необходимости.
+    /// При `dry_run` только проверяет конфигурацию и возвращает план изменений.
+    /// Изменения применяются по одному, лишнее удаляется после добавлений: при ошибке
+    /// хранилища уже применённые изменения сохраняются и возвращаются в `ImportError`.
+    pub async fn import_house(
+        &self,
+        snapshot: &HouseSnapshot,
+        mode: ImportMode,
+        dry_run: bool,
+    ) -> Result<ImportReport, SmartHouseError> {
+        self.limits.validate_name(NameKind::House, &snapshot.name)?;
+        for room in &snapshot.rooms {
+            self.limits.validate_name(NameKind::Room, &room.name)?;
+            for device in &room.devices {
+                self.limits.validate_name(NameKind::Device, &device.name)?;
+            }
+        }
+        let snapshot = snapshot.validate()?;
+
+        let current = match self.export_house(&snapshot.name).await {
+            Ok(current) => Some(current),
+            Err(SmartHouseError::HouseNotFoundError(_)) => None,
+            Err(err) => return Err(err),
+        };
+        let plan = snapshot.plan(current.as_ref(), mode, dry_run);
+        if dry_run {
+            return Ok(plan.report);
+        }
+
+        for (index, action) in plan.actions.iter().enumerate() {
+            if let Err(error) = self.apply_import_action(&snapshot.name, action).await {
+                return Err(SmartHouseError::ImportError(Box::new(ImportFailure {
+                    applied: plan.report.applied(&plan.actions[index..]),
+                    failed: action.change(),
+                    error,
+                })));
+            }
+        }
+
+        Ok(plan.report)
+    }
+
+    async fn apply_import_action(
+        &self,
+        house: &str,
+        action: &ImportAction,
+    ) -> Result<(), SmartHouseError> {
+        match action {
+            ImportAction::AddHouse { address } => self.add_house(house, address).await?,
+            ImportAction::AddRoom { room } => self.add_room(house, room, None).await?,
+            ImportAction::RemoveRoom { room } => self.remove_room(house, room, None).await?,
+            ImportAction::AddDevice { room, device, meta } => {
+                self.add_device_with_meta(house, room, device, meta, None)
+                    .await?
+            }
+            ImportAction::RemoveDevice { room, device } => {
+                self.remove_device(house, room, device, None).await?
+            }
+            ImportAction::UpdateDeviceInfo {
+                room,
+                device,
+                update,
+            } => {
+                self.update_device_info(house, room, device, update, None)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn report_device_info(
+        &self,
+        house: &str,
+        device: RoomDevice,
+    ) -> (String, SmartDeviceInfo) {
+        let RoomDevice { room, record, info } = device;
+
+        let mut info = match self.live_device_info(house, &room, &record.name).await {
+            Some(info) => info,
+            None => info.unwrap_or_else(|| missing_device_info(&record.name)),
+        };
+        if info.kind.is_unknown() {
+            info.kind = record.kind;
+        }
+
+        (room, info)
+    }
+}
+
+fn missing_device_info(device: &str) -> SmartDeviceInfo {
+    SmartDeviceInfo::new(
+        device.to_string() + DEVICE_NOT_FOUND_IN_PROVIDER,
+        DeviceStatus::Unknown.to_string(),
+        0.0,
+        0.0,
+    )
 }
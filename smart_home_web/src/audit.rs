@@ -0,0 +1,97 @@
+use crate::limits::client_ip;
+use crate::prelude::{ApiUser, SmartHouseError};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use utoipa::ToSchema;
+
+/// Автор изменений, сделанных самим сервером: загрузка конфигурации, фоновые задачи
+const SYSTEM_ACTOR: &str = "system";
+
+tokio::task_local! {
+    /// Автор изменений в текущем запросе, назначается `audit_middleware`
+    static ACTOR: String;
+}
+
+/// Результат изменения в журнале аудита
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditResult {
+    Ok,
+    Error,
+}
+
+/// Запись журнала аудита об изменении
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    /// Пользователь API, IP-адрес клиента без аутентификации, `mqtt` для команд
+    /// из MQTT или `system`
+    pub actor: String,
+    /// Метод `AppData`, например `remove_room`
+    pub operation: String,
+    /// Дом, комната и устройство через `/` или имя пользователя API
+    pub target: String,
+    pub result: AuditResult,
+    /// Текст ошибки, если изменение не выполнено
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(
+        actor: String,
+        operation: &str,
+        target: String,
+        error: Option<&SmartHouseError>,
+    ) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            actor,
+            operation: operation.to_string(),
+            target,
+            result: match error {
+                Some(_) => AuditResult::Error,
+                None => AuditResult::Ok,
+            },
+            error: error.map(|err| err.to_string()),
+        }
+    }
+}
+
+/// Автор изменений в текущем запросе или `system` вне запроса
+pub(crate) fn current_actor() -> String {
+    ACTOR
+        .try_with(|actor| actor.clone())
+        .unwrap_or_else(|_| SYSTEM_ACTOR.to_string())
+}
+
+/// Выполняет `future` от имени автора изменений `actor`
+pub(crate) async fn with_actor<F: Future>(actor: String, future: F) -> F::Output {
+    ACTOR.scope(actor, future).await
+}
+
+/// Цель изменения для журнала аудита: дом, комната и устройство через `/`
+pub(crate) fn audit_target(parts: &[&str]) -> String {
+    parts.join("/")
+}
+
+/// Назначает автора изменений запроса: аутентифицированного пользователя API
+/// или, без аутентификации, IP-адрес клиента.
+/// Должен быть обёрнут `auth_middleware`, чтобы пользователь был уже известен.
+pub async fn audit_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let actor = match req.extensions().get::<ApiUser>() {
+        Some(user) => user.name.clone(),
+        None => client_ip(&req),
+    };
+
+    let response = with_actor(actor, next.call(req)).await?;
+
+    Ok(response.map_into_boxed_body())
+}
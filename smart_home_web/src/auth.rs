@@ -0,0 +1,290 @@
+use crate::graphql::{GRAPHQL_PATH, GRAPHQL_SCHEMA_PATH};
+use crate::prelude::{AppData, SmartHouseError};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, AUTHORIZATION};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use utoipa::ToSchema;
+
+pub(crate) const API_KEY_HEADER: &str = "X-API-Key";
+const BEARER_PREFIX: &str = "Bearer ";
+const API_KEY_BYTES: usize = 32;
+const DEFAULT_TOKEN_TTL_SECS: i64 = 3600;
+const PUBLIC_PATHS: [&str; 5] = [
+    "/swagger-ui",
+    "/api-docs",
+    "/dashboard",
+    "/healthz",
+    "/readyz",
+];
+const ADMIN_PATHS: [&str; 2] = ["/users", "/audit"];
+const OPERATOR_ACTIONS: [&str; 3] = ["/command", "/on", "/off"];
+
+/// Роль пользователя API, каждая следующая роль включает права предыдущей
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Только чтение
+    Viewer,
+    /// Управление устройствами
+    Operator,
+    /// Добавление и удаление домов, комнат, устройств и пользователей
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Operator => write!(f, "operator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Пользователь API
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiUser {
+    pub name: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiUser {
+    pub fn new(name: &str, role: Role) -> Self {
+        Self {
+            name: name.to_string(),
+            role,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    role: Role,
+    iat: i64,
+    exp: i64,
+}
+
+/// Проверяет ключи API (заголовок `X-API-Key`) и JWT (`Authorization: Bearer`).
+/// Без секрета JWT принимаются только ключи API.
+pub struct Authenticator {
+    jwt: Option<(EncodingKey, DecodingKey)>,
+    token_ttl: Duration,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self {
+            jwt: None,
+            token_ttl: Duration::seconds(DEFAULT_TOKEN_TTL_SECS),
+        }
+    }
+
+    pub fn with_jwt_secret(mut self, secret: &str, token_ttl: std::time::Duration) -> Self {
+        self.jwt = Some((
+            EncodingKey::from_secret(secret.as_bytes()),
+            DecodingKey::from_secret(secret.as_bytes()),
+        ));
+        self.token_ttl = Duration::from_std(token_ttl).unwrap_or(self.token_ttl);
+        self
+    }
+
+    /// Выпускает JWT для пользователя, возвращает токен и время его истечения
+    pub fn issue_token(&self, user: &ApiUser) -> Result<(String, DateTime<Utc>), SmartHouseError> {
+        let (encoding_key, _) = match &self.jwt {
+            Some(keys) => keys,
+            None => {
+                return Err(SmartHouseError::ValidationError(
+                    "выпуск JWT не настроен".to_string(),
+                ))
+            }
+        };
+
+        let now = Utc::now();
+        let expires_at = now + self.token_ttl;
+        let claims = Claims {
+            sub: user.name.clone(),
+            role: user.role,
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+        };
+
+        match encode(&Header::default(), &claims, encoding_key) {
+            Ok(token) => Ok((token, expires_at)),
+            Err(err) => Err(SmartHouseError::OtherError(err.to_string())),
+        }
+    }
+
+    pub async fn authenticate(
+        &self,
+        app_data: &AppData,
+        headers: &HeaderMap,
+    ) -> Result<ApiUser, SmartHouseError> {
+        if let Some(key) = headers.get(API_KEY_HEADER) {
+            let key = key.to_str().unwrap_or_default();
+            return app_data
+                .metered(
+                    "api_user_by_key_hash",
+                    app_data.storage.api_user_by_key_hash(&hash_key(key)),
+                )
+                .await;
+        }
+
+        let token = match headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+        {
+            Some(token) => token,
+            None => {
+                return Err(SmartHouseError::UnauthorizedError(format!(
+                    "нужен заголовок {API_KEY_HEADER} или {AUTHORIZATION}: {BEARER_PREFIX}<JWT>"
+                )))
+            }
+        };
+
+        let (_, decoding_key) = match &self.jwt {
+            Some(keys) => keys,
+            None => {
+                return Err(SmartHouseError::UnauthorizedError(
+                    "JWT не принимаются, используйте ключ API".to_string(),
+                ))
+            }
+        };
+
+        let claims = match decode::<Claims>(token, decoding_key, &Validation::new(Algorithm::HS256))
+        {
+            Ok(data) => data.claims,
+            Err(err) => {
+                return Err(SmartHouseError::UnauthorizedError(format!(
+                    "некорректный JWT: {err}"
+                )))
+            }
+        };
+
+        // роль берётся из хранилища: удалённый пользователь теряет доступ сразу
+        match app_data
+            .metered("api_user", app_data.storage.api_user(&claims.sub))
+            .await
+        {
+            Ok(user) => Ok(user),
+            Err(SmartHouseError::UserNotFoundError(name)) => Err(
+                SmartHouseError::UnauthorizedError(format!("пользователь '{name}' удалён")),
+            ),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Default for Authenticator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Минимальная роль для запроса, `None` для общедоступных путей.
+/// `path` - шаблон маршрута, чтобы имена комнат и устройств не влияли на права.
+pub fn required_role(method: &Method, path: &str) -> Option<Role> {
+    if path == "/"
+        || path == GRAPHQL_SCHEMA_PATH
+        || PUBLIC_PATHS.iter().any(|public| path.starts_with(public))
+    {
+        return None;
+    }
+
+    // роли для изменений через GraphQL проверяются в самих изменениях
+    if path == GRAPHQL_PATH {
+        return Some(Role::Viewer);
+    }
+
+    if ADMIN_PATHS.iter().any(|admin| path.starts_with(admin)) {
+        return Some(Role::Admin);
+    }
+
+    if path.starts_with("/auth/") || *method == Method::GET || *method == Method::HEAD {
+        return Some(Role::Viewer);
+    }
+
+    if *method == Method::PUT
+        || *method == Method::PATCH
+        || (*method == Method::POST && OPERATOR_ACTIONS.iter().any(|action| path.ends_with(action)))
+    {
+        return Some(Role::Operator);
+    }
+
+    Some(Role::Admin)
+}
+
+/// Проверяет права на запрос, если в приложении зарегистрирован `Authenticator`.
+/// Аутентифицированный пользователь доступен обработчикам как `web::ReqData<ApiUser>`.
+pub async fn auth_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let authenticator = req.app_data::<web::Data<Authenticator>>().cloned();
+    let app_data = req.app_data::<web::Data<AppData>>().cloned();
+
+    if let (Some(authenticator), Some(app_data)) = (authenticator, app_data) {
+        let path = req
+            .match_pattern()
+            .unwrap_or_else(|| req.path().to_string());
+        if let Some(required) = required_role(req.method(), &path) {
+            match authorize(&authenticator, &app_data, req.headers(), required).await {
+                Ok(user) => {
+                    req.extensions_mut().insert(user);
+                }
+                Err(err) => return Ok(req.error_response(err)),
+            }
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+pub(crate) async fn authorize(
+    authenticator: &Authenticator,
+    app_data: &AppData,
+    headers: &HeaderMap,
+    required: Role,
+) -> Result<ApiUser, SmartHouseError> {
+    let user = authenticator.authenticate(app_data, headers).await?;
+    check_role(&user, required)?;
+
+    Ok(user)
+}
+
+pub(crate) fn check_role(user: &ApiUser, required: Role) -> Result<(), SmartHouseError> {
+    if user.role < required {
+        return Err(SmartHouseError::ForbiddenError(format!(
+            "роль '{}' пользователя '{}', требуется '{required}'",
+            user.role, user.name
+        )));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+pub(crate) fn generate_key() -> String {
+    let mut bytes = [0u8; API_KEY_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,66 @@
+use actix_web::http::header::{self, EntityTag, IfNoneMatch};
+use actix_web::{get, web, HttpResponse};
+use rust_embed::RustEmbed;
+
+const DASHBOARD_PATH: &str = "/dashboard";
+const INDEX: &str = "index.html";
+/// Файлы встроены в сервер и меняются только вместе с ним, но браузер
+/// всё равно проверяет их по `ETag` перед использованием
+const CACHE_CONTROL: &str = "no-cache";
+
+/// Веб-панель управления из каталога `dashboard/`, встраивается при сборке
+#[derive(RustEmbed)]
+#[folder = "dashboard/"]
+struct DashboardAssets;
+
+/// Файлы веб-панели, `/dashboard/` - страница панели
+#[get("/dashboard/{path:.*}")]
+async fn get_dashboard_asset(
+    path: web::Path<String>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+) -> HttpResponse {
+    let path = match path.as_str() {
+        "" => INDEX,
+        path => path,
+    };
+    let Some(asset) = DashboardAssets::get(path) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let etag = EntityTag::new_strong(
+        asset
+            .metadata
+            .sha256_hash()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect(),
+    );
+    let not_modified = match if_none_match {
+        Some(web::Header(IfNoneMatch::Any)) => true,
+        Some(web::Header(IfNoneMatch::Items(tags))) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header((header::CACHE_CONTROL, CACHE_CONTROL));
+    if not_modified {
+        return response.finish();
+    }
+
+    response
+        .content_type(asset.metadata.mimetype())
+        .body(asset.data.into_owned())
+}
+
+/// Веб-панель по адресу `/dashboard/`, также открывается с `/`
+pub fn dashboard_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::redirect("/", "/dashboard/"))
+        .service(web::redirect(DASHBOARD_PATH, "/dashboard/"))
+        .service(get_dashboard_asset);
+}
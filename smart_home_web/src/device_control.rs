@@ -0,0 +1,221 @@
+use crate::prelude::{
+    DeviceKind, SmartDevice, SmartDeviceInfoUpdate, SmartHouseError, SmartSocket, SmartSwitch,
+    SmartThermometer,
+};
+use crate::smart_device::{parse_status, parse_value, DEFAULT_DEVICE_TIMEOUT};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time;
+use utoipa::ToSchema;
+
+const UNKNOWN_COMMAND: &str = "unknown command";
+
+/// Команда управления устройством
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DeviceCommand {
+    /// Включить розетку или выключатель
+    On,
+    /// Выключить розетку или выключатель
+    Off,
+    /// Текущий статус розетки или выключателя
+    Status,
+    /// Текущая мощность розетки
+    Power,
+    /// Текущая температура термометра
+    Temp,
+    /// Установить температуру термометра
+    SetTemp { value: f32 },
+    /// Описание устройства в свободной форме
+    Info,
+}
+
+/// Результат выполнения команды, незаполненные поля команда не возвращает
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeviceCommandResult {
+    pub device: String,
+    pub kind: DeviceKind,
+    pub command: DeviceCommand,
+    pub response: String,
+    pub status: Option<String>,
+    pub power: Option<f32>,
+    pub temp: Option<f32>,
+}
+
+impl DeviceCommandResult {
+    fn new(device: &str, kind: DeviceKind, command: &DeviceCommand, response: String) -> Self {
+        Self {
+            device: device.to_string(),
+            kind,
+            command: command.clone(),
+            response,
+            status: None,
+            power: None,
+            temp: None,
+        }
+    }
+
+    /// Изменения параметров устройства, которые стали известны после команды
+    pub fn update(&self) -> SmartDeviceInfoUpdate {
+        SmartDeviceInfoUpdate {
+            status: self.status.clone(),
+            power: self.power,
+            temp: self.temp,
+        }
+    }
+}
+
+/// Отправляет команды устройствам по сети: розеткам и выключателям по TCP, термометрам по UDP.
+pub struct DeviceController {
+    timeout: Duration,
+}
+
+impl DeviceController {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+
+    pub async fn execute(
+        &self,
+        device: &str,
+        kind: DeviceKind,
+        address: &str,
+        command: &DeviceCommand,
+    ) -> Result<DeviceCommandResult, SmartHouseError> {
+        match (kind, command) {
+            (DeviceKind::Socket, DeviceCommand::On | DeviceCommand::Off) => {
+                let response = self
+                    .send::<SmartSocket>(address, command_name(command))
+                    .await?;
+                let status = self.send::<SmartSocket>(address, "status").await?;
+                let power = self.send::<SmartSocket>(address, "power").await?;
+                let mut result = DeviceCommandResult::new(device, kind, command, response);
+                result.status = Some(parse_status(&status, SmartHouseError::DeviceControlError)?);
+                result.power = Some(parse_value(&power, SmartHouseError::DeviceControlError)?);
+                Ok(result)
+            }
+            (DeviceKind::Socket, DeviceCommand::Status) => {
+                let response = self.send::<SmartSocket>(address, "status").await?;
+                let mut result = DeviceCommandResult::new(device, kind, command, response);
+                result.status = Some(parse_status(
+                    &result.response,
+                    SmartHouseError::DeviceControlError,
+                )?);
+                Ok(result)
+            }
+            (DeviceKind::Socket, DeviceCommand::Info) => {
+                let response = self.send::<SmartSocket>(address, "info").await?;
+                Ok(DeviceCommandResult::new(device, kind, command, response))
+            }
+            (DeviceKind::Socket, DeviceCommand::Power) => {
+                let response = self.send::<SmartSocket>(address, "power").await?;
+                let mut result = DeviceCommandResult::new(device, kind, command, response);
+                result.power = Some(parse_value(
+                    &result.response,
+                    SmartHouseError::DeviceControlError,
+                )?);
+                Ok(result)
+            }
+            (DeviceKind::Switch, DeviceCommand::On | DeviceCommand::Off) => {
+                let response = self
+                    .send::<SmartSwitch>(address, command_name(command))
+                    .await?;
+                let status = self.send::<SmartSwitch>(address, "status").await?;
+                let mut result = DeviceCommandResult::new(device, kind, command, response);
+                result.status = Some(parse_status(&status, SmartHouseError::DeviceControlError)?);
+                Ok(result)
+            }
+            (DeviceKind::Switch, DeviceCommand::Status) => {
+                let response = self.send::<SmartSwitch>(address, "status").await?;
+                let mut result = DeviceCommandResult::new(device, kind, command, response);
+                result.status = Some(parse_status(
+                    &result.response,
+                    SmartHouseError::DeviceControlError,
+                )?);
+                Ok(result)
+            }
+            (DeviceKind::Switch, DeviceCommand::Info) => {
+                let response = self.send::<SmartSwitch>(address, "info").await?;
+                Ok(DeviceCommandResult::new(device, kind, command, response))
+            }
+            (DeviceKind::Thermometer, DeviceCommand::Temp) => {
+                let response = self.send::<SmartThermometer>(address, "temp").await?;
+                let mut result = DeviceCommandResult::new(device, kind, command, response);
+                result.temp = Some(parse_value(
+                    &result.response,
+                    SmartHouseError::DeviceControlError,
+                )?);
+                Ok(result)
+            }
+            (DeviceKind::Thermometer, DeviceCommand::SetTemp { value }) => {
+                SmartDeviceInfoUpdate {
+                    temp: Some(*value),
+                    ..Default::default()
+                }
+                .validate()?;
+                let response = self
+                    .send::<SmartThermometer>(address, &value.to_string())
+                    .await?;
+                let mut result = DeviceCommandResult::new(device, kind, command, response);
+                result.temp = Some(parse_value(
+                    &result.response,
+                    SmartHouseError::DeviceControlError,
+                )?);
+                Ok(result)
+            }
+            (DeviceKind::Thermometer, DeviceCommand::Info) => {
+                let response = self.send::<SmartThermometer>(address, "info").await?;
+                Ok(DeviceCommandResult::new(device, kind, command, response))
+            }
+            (DeviceKind::Unknown, _) => Err(SmartHouseError::ValidationError(format!(
+                "неизвестный тип устройства '{device}', управление невозможно"
+            ))),
+            _ => Err(SmartHouseError::ValidationError(format!(
+                "команда '{}' не поддерживается устройством '{device}'",
+                command_name(command)
+            ))),
+        }
+    }
+
+    async fn send<D: SmartDevice>(
+        &self,
+        addr: &str,
+        command: &str,
+    ) -> Result<String, SmartHouseError> {
+        let response = match time::timeout(self.timeout, D::send_command(addr, command)).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(err)) => return Err(SmartHouseError::DeviceControlError(err.to_string())),
+            Err(_) => {
+                return Err(SmartHouseError::DeviceControlError(format!(
+                    "устройство '{addr}' не ответило за {:?}",
+                    self.timeout
+                )))
+            }
+        };
+
+        match response.as_str() {
+            UNKNOWN_COMMAND => Err(SmartHouseError::DeviceControlError(format!(
+                "устройство '{addr}' не распознало команду '{command}'"
+            ))),
+            _ => Ok(response),
+        }
+    }
+}
+
+impl Default for DeviceController {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEVICE_TIMEOUT)
+    }
+}
+
+fn command_name(command: &DeviceCommand) -> &'static str {
+    match command {
+        DeviceCommand::On => "on",
+        DeviceCommand::Off => "off",
+        DeviceCommand::Status => "status",
+        DeviceCommand::Power => "power",
+        DeviceCommand::Temp => "temp",
+        DeviceCommand::SetTemp { .. } => "set_temp",
+        DeviceCommand::Info => "info",
+    }
+}
@@ -0,0 +1,456 @@
+use crate::auth::check_role;
+use crate::prelude::{
+    ApiUser, AppData, DeviceHistoryPoint, DeviceKind, HistoryQuery, Role, SmartDeviceInfo,
+    SmartDeviceInfoUpdate, SmartDeviceMeta, SmartDeviceRecord, SmartHouseError, SmartHouseRecord,
+};
+use actix_web::{get, post, web, HttpResponse};
+use async_graphql::{
+    Context, EmptySubscription, ErrorExtensions, Object, Request, ResultExt, Schema, Value,
+};
+use chrono::{DateTime, Utc};
+
+pub(crate) const GRAPHQL_PATH: &str = "/graphql";
+pub(crate) const GRAPHQL_SCHEMA_PATH: &str = "/graphql/schema.graphql";
+const MAX_QUERY_DEPTH: usize = 10;
+const MAX_QUERY_COMPLEXITY: usize = 1000;
+
+pub type SmartHomeSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Схема GraphQL поверх `AppData`. `AppData` и, с аутентификацией, `ApiUser`
+/// передаются в данных каждого запроса.
+pub fn graphql_schema() -> SmartHomeSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish()
+}
+
+/// Схема GraphQL в формате SDL для генерации клиентов, та же, что по адресу
+/// `/graphql/schema.graphql`
+pub fn graphql_schema_sdl() -> String {
+    graphql_schema().sdl()
+}
+
+/// Запросы и изменения GraphQL, `POST /graphql`
+#[post("/graphql")]
+async fn post_graphql(
+    schema: web::Data<SmartHomeSchema>,
+    app_data: web::Data<AppData>,
+    user: Option<web::ReqData<ApiUser>>,
+    request: web::Json<Request>,
+) -> HttpResponse {
+    let mut request = request.into_inner().data(app_data);
+    if let Some(user) = user {
+        request = request.data(user.into_inner());
+    }
+
+    HttpResponse::Ok().json(schema.execute(request).await)
+}
+
+/// Схема GraphQL в формате SDL
+#[get("/graphql/schema.graphql")]
+async fn get_graphql_schema(schema: web::Data<SmartHomeSchema>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(schema.sdl())
+}
+
+/// GraphQL по адресу `/graphql`, схема - `/graphql/schema.graphql`
+pub fn graphql_config(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::Data::new(graphql_schema()))
+        .service(post_graphql)
+        .service(get_graphql_schema);
+}
+
+/// Ошибки с машиночитаемым кодом `extensions.code`, как `code` в ответах REST API
+impl ErrorExtensions for SmartHouseError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string()).extend_with(|_, extensions| {
+            if let Ok(code) = Value::from_json(serde_json::json!(self.code())) {
+                extensions.set("code", code);
+            }
+            if let Some(details) = self.details().and_then(|d| Value::from_json(d).ok()) {
+                extensions.set("details", details);
+            }
+        })
+    }
+}
+
+fn app_data<'a>(ctx: &Context<'a>) -> &'a AppData {
+    ctx.data_unchecked::<web::Data<AppData>>()
+}
+
+/// Без названия - основной дом приложения
+fn house_name(ctx: &Context<'_>, house: Option<String>) -> String {
+    house.unwrap_or_else(|| app_data(ctx).name.clone())
+}
+
+/// Проверяет роль пользователя, если включена аутентификация
+fn require(ctx: &Context<'_>, required: Role) -> async_graphql::Result<()> {
+    match ctx.data_opt::<ApiUser>() {
+        Some(user) => check_role(user, required).extend(),
+        None => Ok(()),
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object(name = "Query")]
+impl QueryRoot {
+    /// Все дома
+    async fn houses(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<House>> {
+        let houses = app_data(ctx).houses().await.extend()?;
+
+        Ok(houses.into_iter().map(House).collect())
+    }
+
+    /// Дом по названию, без названия - основной дом
+    async fn house(&self, ctx: &Context<'_>, name: Option<String>) -> async_graphql::Result<House> {
+        let house = house_name(ctx, name);
+
+        Ok(House(app_data(ctx).house(&house).await.extend()?))
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object(name = "Mutation")]
+impl MutationRoot {
+    async fn add_house(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        address: String,
+    ) -> async_graphql::Result<House> {
+        require(ctx, Role::Admin)?;
+        let app_data = app_data(ctx);
+        app_data.add_house(&name, &address).await.extend()?;
+
+        Ok(House(app_data.house(&name).await.extend()?))
+    }
+
+    /// Удаляет дом, при заданной `version` - только если версия дома не изменилась
+    async fn remove_house(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        version: Option<u64>,
+    ) -> async_graphql::Result<bool> {
+        require(ctx, Role::Admin)?;
+        app_data(ctx).remove_house(&name, version).await.extend()?;
+
+        Ok(true)
+    }
+
+    async fn add_room(
+        &self,
+        ctx: &Context<'_>,
+        house: Option<String>,
+        name: String,
+    ) -> async_graphql::Result<Room> {
+        require(ctx, Role::Admin)?;
+        let house = house_name(ctx, house);
+        app_data(ctx).add_room(&house, &name, None).await.extend()?;
+
+        Ok(Room { house, name })
+    }
+
+    /// Удаляет комнату, при заданной `version` - только если версия комнаты не изменилась
+    async fn remove_room(
+        &self,
+        ctx: &Context<'_>,
+        house: Option<String>,
+        name: String,
+        version: Option<u64>,
+    ) -> async_graphql::Result<bool> {
+        require(ctx, Role::Admin)?;
+        let house = house_name(ctx, house);
+        app_data(ctx)
+            .remove_room(&house, &name, version)
+            .await
+            .extend()?;
+
+        Ok(true)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn add_device(
+        &self,
+        ctx: &Context<'_>,
+        house: Option<String>,
+        room: String,
+        name: String,
+        #[graphql(default_with = "DeviceKind::Unknown")] kind: DeviceKind,
+        address: Option<String>,
+        manufacturer: Option<String>,
+        #[graphql(default)] tags: Vec<String>,
+    ) -> async_graphql::Result<Device> {
+        require(ctx, Role::Admin)?;
+        let house = house_name(ctx, house);
+        let meta = SmartDeviceMeta {
+            kind,
+            address,
+            manufacturer,
+            tags,
+        };
+        let app_data = app_data(ctx);
+        app_data
+            .add_device_with_meta(&house, &room, &name, &meta, None)
+            .await
+            .extend()?;
+        let record = app_data.device(&house, &room, &name).await.extend()?;
+
+        Ok(Device {
+            house,
+            room,
+            record,
+        })
+    }
+
+    /// Удаляет устройство, при заданной `version` - только если версия устройства не изменилась
+    async fn remove_device(
+        &self,
+        ctx: &Context<'_>,
+        house: Option<String>,
+        room: String,
+        name: String,
+        version: Option<u64>,
+    ) -> async_graphql::Result<bool> {
+        require(ctx, Role::Admin)?;
+        let house = house_name(ctx, house);
+        app_data(ctx)
+            .remove_device(&house, &room, &name, version)
+            .await
+            .extend()?;
+
+        Ok(true)
+    }
+
+    /// Меняет заданные параметры устройства, при заданной `version` - только если
+    /// версия устройства не изменилась
+    #[allow(clippy::too_many_arguments)]
+    async fn update_device_info(
+        &self,
+        ctx: &Context<'_>,
+        house: Option<String>,
+        room: String,
+        name: String,
+        status: Option<String>,
+        power: Option<f32>,
+        temp: Option<f32>,
+        version: Option<u64>,
+    ) -> async_graphql::Result<Device> {
+        require(ctx, Role::Operator)?;
+        let house = house_name(ctx, house);
+        let update = SmartDeviceInfoUpdate {
+            status,
+            power,
+            temp,
+        };
+        let app_data = app_data(ctx);
+        app_data
+            .update_device_info(&house, &room, &name, &update, version)
+            .await
+            .extend()?;
+        let record = app_data.device(&house, &room, &name).await.extend()?;
+
+        Ok(Device {
+            house,
+            room,
+            record,
+        })
+    }
+}
+
+/// Дом
+pub struct House(SmartHouseRecord);
+
+#[Object]
+impl House {
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn address(&self) -> &str {
+        &self.0.address
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// Версия дома, увеличивается при добавлении и удалении комнат
+    async fn version(&self) -> u64 {
+        self.0.version
+    }
+
+    async fn rooms(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Room>> {
+        let rooms = app_data(ctx).rooms(&self.0.name).await.extend()?;
+
+        Ok(rooms
+            .into_iter()
+            .map(|name| Room {
+                house: self.0.name.clone(),
+                name,
+            })
+            .collect())
+    }
+
+    async fn room(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<Room> {
+        let rooms = app_data(ctx).rooms(&self.0.name).await.extend()?;
+        if !rooms.contains(&name) {
+            return Err(SmartHouseError::RoomNotFoundError(name).extend());
+        }
+
+        Ok(Room {
+            house: self.0.name.clone(),
+            name,
+        })
+    }
+}
+
+/// Комната
+pub struct Room {
+    house: String,
+    name: String,
+}
+
+#[Object]
+impl Room {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Версия комнаты, увеличивается при добавлении и удалении устройств
+    async fn version(&self, ctx: &Context<'_>) -> async_graphql::Result<u64> {
+        app_data(ctx)
+            .room_version(&self.house, &self.name)
+            .await
+            .extend()
+    }
+
+    async fn devices(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Device>> {
+        let records = app_data(ctx)
+            .device_records(&self.house, &self.name)
+            .await
+            .extend()?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| Device {
+                house: self.house.clone(),
+                room: self.name.clone(),
+                record,
+            })
+            .collect())
+    }
+
+    async fn device(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<Device> {
+        let record = app_data(ctx)
+            .device(&self.house, &self.name, &name)
+            .await
+            .extend()?;
+
+        Ok(Device {
+            house: self.house.clone(),
+            room: self.name.clone(),
+            record,
+        })
+    }
+}
+
+/// Устройство
+pub struct Device {
+    house: String,
+    room: String,
+    record: SmartDeviceRecord,
+}
+
+#[Object]
+impl Device {
+    async fn name(&self) -> &str {
+        &self.record.name
+    }
+
+    async fn kind(&self) -> DeviceKind {
+        self.record.kind
+    }
+
+    async fn address(&self) -> Option<&str> {
+        self.record.address.as_deref()
+    }
+
+    async fn manufacturer(&self) -> Option<&str> {
+        self.record.manufacturer.as_deref()
+    }
+
+    async fn tags(&self) -> &[String] {
+        &self.record.tags
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.record.created_at
+    }
+
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.record.updated_at
+    }
+
+    /// Версия устройства, увеличивается при каждом изменении параметров
+    async fn version(&self) -> u64 {
+        self.record.version
+    }
+
+    /// Текущие параметры устройства
+    async fn info(&self, ctx: &Context<'_>) -> async_graphql::Result<DeviceInfo> {
+        let info = app_data(ctx)
+            .device_info(&self.house, &self.room, &self.record.name)
+            .await
+            .extend()?;
+
+        Ok(DeviceInfo(info))
+    }
+
+    /// Показания за период (по умолчанию последний час) с шагом агрегации
+    /// в секундах (по умолчанию 60)
+    async fn history(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        step: Option<u64>,
+    ) -> async_graphql::Result<Vec<DeviceHistoryPoint>> {
+        let query = HistoryQuery { from, to, step };
+
+        app_data(ctx)
+            .device_history(&self.house, &self.room, &self.record.name, &query)
+            .await
+            .extend()
+    }
+}
+
+/// Параметры устройства
+pub struct DeviceInfo(SmartDeviceInfo);
+
+#[Object]
+impl DeviceInfo {
+    async fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    async fn kind(&self) -> DeviceKind {
+        self.0.kind()
+    }
+
+    async fn status(&self) -> &str {
+        self.0.status()
+    }
+
+    async fn power(&self) -> f32 {
+        self.0.power()
+    }
+
+    async fn temp(&self) -> f32 {
+        self.0.temp()
+    }
+}
@@ -0,0 +1,455 @@
+use crate::audit::with_actor;
+use crate::auth::authorize;
+use crate::prelude::{
+    AppData, Authenticator, DeviceKind, Role, SmartDeviceInfo, SmartDeviceInfoUpdate,
+    SmartDeviceMeta, SmartDeviceRecord, SmartHouseError, SmartHouseEvent, SmartHouseReport,
+};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use actix_web::http::StatusCode;
+use actix_web::ResponseError;
+use futures::stream::{self, Stream};
+use proto::smart_home_server::{SmartHome, SmartHomeServer};
+use proto::{
+    AddDeviceRequest, AddDeviceResponse, AddRoomRequest, AddRoomResponse, Device, DeviceInfo,
+    Event, EventType, GetDeviceInfoRequest, GetReportRequest, ListDevicesRequest,
+    ListDevicesResponse, ListRoomsRequest, ListRoomsResponse, RemoveDeviceRequest,
+    RemoveDeviceResponse, RemoveRoomRequest, RemoveRoomResponse, Report, RoomReport,
+    UpdateDeviceInfoRequest, UpdateDeviceInfoResponse, WatchRequest,
+};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{Code, Request, Response, Status};
+
+/// Сообщения и сервис из `proto/smart_home.proto`, в том числе клиент
+/// `proto::smart_home_client::SmartHomeClient`
+pub mod proto {
+    tonic::include_proto!("smart_home.v1");
+}
+
+const UNKNOWN_CLIENT: &str = "unknown";
+/// Заголовок `X-API-Key` в метаданных gRPC, имена метаданных только в нижнем регистре
+const API_KEY_METADATA: &str = "x-api-key";
+
+/// gRPC API умного дома поверх тех же `AppData`, что и у REST API.
+/// С `Authenticator` требует те же ключи API или JWT и роли, что и REST API.
+pub struct SmartHomeGrpc {
+    app_data: Arc<AppData>,
+    authenticator: Option<Arc<Authenticator>>,
+}
+
+impl SmartHomeGrpc {
+    pub fn new(app_data: Arc<AppData>) -> Self {
+        Self {
+            app_data,
+            authenticator: None,
+        }
+    }
+
+    pub fn with_authenticator(mut self, authenticator: Arc<Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    pub fn into_service(self) -> SmartHomeServer<Self> {
+        SmartHomeServer::new(self)
+    }
+
+    /// Пустое название - основной дом приложения
+    fn house<'a>(&'a self, house: &'a str) -> &'a str {
+        match house.is_empty() {
+            true => &self.app_data.name,
+            false => house,
+        }
+    }
+
+    /// Проверяет роль пользователя и возвращает автора изменений для журнала аудита:
+    /// пользователя API или, без аутентификации, IP-адрес клиента
+    async fn authorize<T>(&self, request: &Request<T>, required: Role) -> Result<String, Status> {
+        let Some(authenticator) = &self.authenticator else {
+            return Ok(request
+                .remote_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| UNKNOWN_CLIENT.to_string()));
+        };
+
+        // ключ API и JWT из метаданных проверяются так же, как заголовки HTTP
+        let mut headers = HeaderMap::new();
+        for name in [HeaderName::from_static(API_KEY_METADATA), AUTHORIZATION] {
+            if let Some(value) = request
+                .metadata()
+                .get(name.as_str())
+                .and_then(|value| HeaderValue::from_bytes(value.as_bytes()).ok())
+            {
+                headers.insert(name, value);
+            }
+        }
+
+        let user = authorize(authenticator, &self.app_data, &headers, required).await?;
+
+        Ok(user.name)
+    }
+}
+
+#[tonic::async_trait]
+impl SmartHome for SmartHomeGrpc {
+    async fn list_rooms(
+        &self,
+        request: Request<ListRoomsRequest>,
+    ) -> Result<Response<ListRoomsResponse>, Status> {
+        self.authorize(&request, Role::Viewer).await?;
+        let request = request.into_inner();
+        let house = self.house(&request.house);
+
+        let version = self.app_data.house(house).await?.version;
+        let mut rooms = self.app_data.rooms(house).await?;
+        rooms.sort();
+
+        Ok(Response::new(ListRoomsResponse { rooms, version }))
+    }
+
+    async fn add_room(
+        &self,
+        request: Request<AddRoomRequest>,
+    ) -> Result<Response<AddRoomResponse>, Status> {
+        let actor = self.authorize(&request, Role::Admin).await?;
+        let request = request.into_inner();
+
+        with_actor(
+            actor,
+            self.app_data
+                .add_room(self.house(&request.house), &request.room, None),
+        )
+        .await?;
+
+        Ok(Response::new(AddRoomResponse {}))
+    }
+
+    async fn remove_room(
+        &self,
+        request: Request<RemoveRoomRequest>,
+    ) -> Result<Response<RemoveRoomResponse>, Status> {
+        let actor = self.authorize(&request, Role::Admin).await?;
+        let request = request.into_inner();
+
+        with_actor(
+            actor,
+            self.app_data
+                .remove_room(self.house(&request.house), &request.room, request.version),
+        )
+        .await?;
+
+        Ok(Response::new(RemoveRoomResponse {}))
+    }
+
+    async fn list_devices(
+        &self,
+        request: Request<ListDevicesRequest>,
+    ) -> Result<Response<ListDevicesResponse>, Status> {
+        self.authorize(&request, Role::Viewer).await?;
+        let request = request.into_inner();
+        let house = self.house(&request.house);
+
+        let version = self.app_data.room_version(house, &request.room).await?;
+        let mut records = self.app_data.device_records(house, &request.room).await?;
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Response::new(ListDevicesResponse {
+            devices: records.into_iter().map(Device::from).collect(),
+            version,
+        }))
+    }
+
+    async fn add_device(
+        &self,
+        request: Request<AddDeviceRequest>,
+    ) -> Result<Response<AddDeviceResponse>, Status> {
+        let actor = self.authorize(&request, Role::Admin).await?;
+        let request = request.into_inner();
+        let meta = SmartDeviceMeta {
+            kind: request.kind().into(),
+            address: request.address,
+            manufacturer: request.manufacturer,
+            tags: request.tags,
+        };
+
+        with_actor(
+            actor,
+            self.app_data.add_device_with_meta(
+                self.house(&request.house),
+                &request.room,
+                &request.device,
+                &meta,
+                None,
+            ),
+        )
+        .await?;
+
+        Ok(Response::new(AddDeviceResponse {}))
+    }
+
+    async fn remove_device(
+        &self,
+        request: Request<RemoveDeviceRequest>,
+    ) -> Result<Response<RemoveDeviceResponse>, Status> {
+        let actor = self.authorize(&request, Role::Admin).await?;
+        let request = request.into_inner();
+
+        with_actor(
+            actor,
+            self.app_data.remove_device(
+                self.house(&request.house),
+                &request.room,
+                &request.device,
+                request.version,
+            ),
+        )
+        .await?;
+
+        Ok(Response::new(RemoveDeviceResponse {}))
+    }
+
+    async fn get_device_info(
+        &self,
+        request: Request<GetDeviceInfoRequest>,
+    ) -> Result<Response<DeviceInfo>, Status> {
+        self.authorize(&request, Role::Viewer).await?;
+        let request = request.into_inner();
+
+        let info = self
+            .app_data
+            .device_info(self.house(&request.house), &request.room, &request.device)
+            .await?;
+
+        Ok(Response::new(info.into()))
+    }
+
+    async fn update_device_info(
+        &self,
+        request: Request<UpdateDeviceInfoRequest>,
+    ) -> Result<Response<UpdateDeviceInfoResponse>, Status> {
+        let actor = self.authorize(&request, Role::Operator).await?;
+        let request = request.into_inner();
+        let update = SmartDeviceInfoUpdate {
+            status: request.status,
+            power: request.power,
+            temp: request.temp,
+        };
+
+        let (info, version) = with_actor(
+            actor,
+            self.app_data.update_device_info(
+                self.house(&request.house),
+                &request.room,
+                &request.device,
+                &update,
+                request.version,
+            ),
+        )
+        .await?;
+
+        Ok(Response::new(UpdateDeviceInfoResponse {
+            info: Some(info.into()),
+            version,
+        }))
+    }
+
+    async fn get_report(
+        &self,
+        request: Request<GetReportRequest>,
+    ) -> Result<Response<Report>, Status> {
+        self.authorize(&request, Role::Viewer).await?;
+        let request = request.into_inner();
+
+        let report = self
+            .app_data
+            .house_report(self.house(&request.house))
+            .await?;
+
+        Ok(Response::new(report.into()))
+    }
+
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<Event, Status>> + Send>>;
+
+    /// События до вызова не передаются, отставший подписчик получает
+    /// `EVENT_TYPE_LAGGED` с количеством пропущенных событий
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        self.authorize(&request, Role::Viewer).await?;
+        let house = request.into_inner().house;
+
+        let events = stream::unfold(self.app_data.subscribe(), move |mut receiver| {
+            let house = house.clone();
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) => {
+                            if house.as_deref().is_some_and(|house| house != event.house()) {
+                                continue;
+                            }
+                            return Some((Ok(Event::from(event)), receiver));
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            let event = Event {
+                                r#type: EventType::Lagged.into(),
+                                skipped,
+                                ..Default::default()
+                            };
+                            return Some((Ok(event), receiver));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Код gRPC по статусу HTTP той же ошибки в REST API
+impl From<SmartHouseError> for Status {
+    fn from(err: SmartHouseError) -> Self {
+        let code = match err.status_code() {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::CONFLICT => Code::AlreadyExists,
+            StatusCode::PRECONDITION_FAILED => Code::FailedPrecondition,
+            StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::BAD_GATEWAY => Code::Unavailable,
+            _ => Code::Internal,
+        };
+
+        Status::new(code, err.to_string())
+    }
+}
+
+impl From<DeviceKind> for proto::DeviceKind {
+    fn from(kind: DeviceKind) -> Self {
+        match kind {
+            DeviceKind::Socket => Self::Socket,
+            DeviceKind::Switch => Self::Switch,
+            DeviceKind::Thermometer => Self::Thermometer,
+            DeviceKind::Unknown => Self::Unknown,
+        }
+    }
+}
+
+impl From<proto::DeviceKind> for DeviceKind {
+    fn from(kind: proto::DeviceKind) -> Self {
+        match kind {
+            proto::DeviceKind::Socket => Self::Socket,
+            proto::DeviceKind::Switch => Self::Switch,
+            proto::DeviceKind::Thermometer => Self::Thermometer,
+            proto::DeviceKind::Unknown => Self::Unknown,
+        }
+    }
+}
+
+impl From<SmartDeviceInfo> for DeviceInfo {
+    fn from(info: SmartDeviceInfo) -> Self {
+        Self {
+            kind: proto::DeviceKind::from(info.kind).into(),
+            name: info.name,
+            status: info.status,
+            power: info.power,
+            temp: info.temp,
+        }
+    }
+}
+
+impl From<SmartDeviceRecord> for Device {
+    fn from(record: SmartDeviceRecord) -> Self {
+        Self {
+            kind: proto::DeviceKind::from(record.kind).into(),
+            name: record.name,
+            address: record.address,
+            manufacturer: record.manufacturer,
+            tags: record.tags,
+            version: record.version,
+        }
+    }
+}
+
+impl From<SmartHouseReport> for Report {
+    fn from(report: SmartHouseReport) -> Self {
+        Self {
+            name: report.name,
+            address: report.address,
+            rooms: report
+                .devices
+                .into_iter()
+                .map(|(name, devices)| RoomReport {
+                    name,
+                    devices: devices.into_iter().map(DeviceInfo::from).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<SmartHouseEvent> for Event {
+    fn from(event: SmartHouseEvent) -> Self {
+        let (r#type, house, room, device, info) = match event {
+            SmartHouseEvent::HouseAdded { house } => {
+                (EventType::HouseAdded, house, None, None, None)
+            }
+            SmartHouseEvent::HouseRemoved { house } => {
+                (EventType::HouseRemoved, house, None, None, None)
+            }
+            SmartHouseEvent::RoomAdded { house, room } => {
+                (EventType::RoomAdded, house, Some(room), None, None)
+            }
+            SmartHouseEvent::RoomRemoved { house, room } => {
+                (EventType::RoomRemoved, house, Some(room), None, None)
+            }
+            SmartHouseEvent::DeviceAdded {
+                house,
+                room,
+                device,
+            } => (
+                EventType::DeviceAdded,
+                house,
+                Some(room),
+                Some(device),
+                None,
+            ),
+            SmartHouseEvent::DeviceRemoved {
+                house,
+                room,
+                device,
+            } => (
+                EventType::DeviceRemoved,
+                house,
+                Some(room),
+                Some(device),
+                None,
+            ),
+            SmartHouseEvent::DeviceUpdated {
+                house,
+                room,
+                device,
+                info,
+            } => (
+                EventType::DeviceUpdated,
+                house,
+                Some(room),
+                Some(device),
+                Some(info.into()),
+            ),
+        };
+
+        Self {
+            r#type: r#type.into(),
+            house,
+            room: room.unwrap_or_default(),
+            device: device.unwrap_or_default(),
+            info,
+            skipped: 0,
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crate::idempotency::IdempotencyError;
+use crate::limits::{LimitError, NameKind, NameViolation};
+use crate::prelude::SmartHouseError;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub(crate) const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Машиночитаемый код ошибки, не зависит от языка сообщения
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    HouseNotFound,
+    HouseAlreadyExists,
+    RoomsNotFound,
+    RoomNotFound,
+    RoomAlreadyExists,
+    DevicesNotFound,
+    DeviceNotFound,
+    DeviceAlreadyExists,
+    DeviceInfoUnavailable,
+    DeviceControlFailed,
+    UserNotFound,
+    UserAlreadyExists,
+    Unauthorized,
+    Forbidden,
+    ValidationFailed,
+    InvalidName,
+    RequestTooLarge,
+    RateLimited,
+    PreconditionFailed,
+    InvalidIdempotencyKey,
+    IdempotencyKeyInProgress,
+    IdempotencyKeyReused,
+    InternalError,
+}
+
+/// Тело ответа с ошибкой
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    /// Описание ошибки для человека, может меняться
+    pub message: String,
+    /// Поля ошибки: имена дома, комнаты, устройства или пользователя, текст
+    /// причины `reason`, ограничения (`max_bytes`, `limit`, `retry_after_secs`)
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<serde_json::Value>,
+    /// Идентификатор запроса из заголовка `X-Request-Id`
+    pub request_id: Option<String>,
+}
+
+impl ErrorResponse {
+    pub fn new(err: &SmartHouseError) -> Self {
+        Self {
+            code: err.code(),
+            message: err.to_string(),
+            details: err.details(),
+            request_id: None,
+        }
+    }
+
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        self.request_id = Some(request_id.to_string());
+        self
+    }
+}
+
+/// Ошибка из ответа API, обратное преобразование к `ErrorResponse::new`: вариант
+/// определяется по коду, его поля - по `details`. Текст ошибки без `reason`
+/// в `details` (ответ старой версии сервера) берётся из сообщения целиком.
+impl From<ErrorResponse> for SmartHouseError {
+    fn from(response: ErrorResponse) -> Self {
+        let details = response.details.unwrap_or_default();
+        let detail = |key: &str| {
+            details
+                .get(key)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let number = |key: &str| {
+            details
+                .get(key)
+                .and_then(|value| value.as_u64())
+                .unwrap_or_default()
+        };
+        let reason = details
+            .get("reason")
+            .and_then(|value| value.as_str())
+            .unwrap_or(&response.message)
+            .to_string();
+
+        match response.code {
+            ErrorCode::HouseNotFound => Self::HouseNotFoundError(detail("house")),
+            ErrorCode::HouseAlreadyExists => Self::HouseAlreadyExistsError(detail("house")),
+            ErrorCode::RoomsNotFound => Self::RoomsNotFoundError,
+            ErrorCode::RoomNotFound => Self::RoomNotFoundError(detail("room")),
+            ErrorCode::RoomAlreadyExists => Self::RoomAlreadyExistsError(detail("room")),
+            ErrorCode::DevicesNotFound => Self::DevicesNotFoundError,
+            ErrorCode::DeviceNotFound => {
+                Self::DeviceNotFoundError(detail("room"), detail("device"))
+            }
+            ErrorCode::DeviceAlreadyExists => {
+                Self::DeviceAlreadyExistsError(detail("room"), detail("device"))
+            }
+            ErrorCode::DeviceInfoUnavailable => Self::DeviceInfoProviderError(reason),
+            ErrorCode::DeviceControlFailed => Self::DeviceControlError(reason),
+            ErrorCode::UserNotFound => Self::UserNotFoundError(detail("user")),
+            ErrorCode::UserAlreadyExists => Self::UserAlreadyExistsError(detail("user")),
+            ErrorCode::Unauthorized => Self::UnauthorizedError(reason),
+            ErrorCode::Forbidden => Self::ForbiddenError(reason),
+            ErrorCode::ValidationFailed => Self::ValidationError(reason),
+            ErrorCode::InvalidName => {
+                invalid_name(&details).unwrap_or_else(|| Self::ValidationError(response.message))
+            }
+            ErrorCode::RequestTooLarge => {
+                let max = number("max_bytes") as usize;
+                match detail("limit").as_str() {
+                    "path" => LimitError::PathTooLong(max).into(),
+                    _ => LimitError::BodyTooLarge(max).into(),
+                }
+            }
+            ErrorCode::RateLimited => {
+                LimitError::RateLimited(Duration::from_secs(number("retry_after_secs"))).into()
+            }
+            ErrorCode::PreconditionFailed => Self::PreconditionFailedError(reason),
+            ErrorCode::InvalidIdempotencyKey => IdempotencyError::InvalidKey.into(),
+            ErrorCode::IdempotencyKeyInProgress => {
+                IdempotencyError::InProgress(detail("idempotency_key")).into()
+            }
+            ErrorCode::IdempotencyKeyReused => {
+                IdempotencyError::KeyReused(detail("idempotency_key")).into()
+            }
+            ErrorCode::InternalError => Self::OtherError(reason),
+        }
+    }
+}
+
+/// `LimitError::InvalidName` из `kind`, названия и `reason` в `details`
+fn invalid_name(details: &serde_json::Value) -> Option<SmartHouseError> {
+    let key = details.get("kind")?.as_str()?;
+    let kind = [
+        NameKind::House,
+        NameKind::Room,
+        NameKind::Device,
+        NameKind::User,
+    ]
+    .into_iter()
+    .find(|kind| kind.key() == key)?;
+    let name = details.get(key)?.as_str()?.to_string();
+
+    let reason = match details.get("reason")?.as_str()? {
+        "empty" => NameViolation::Empty,
+        "whitespace" => NameViolation::Whitespace,
+        "too_long" => NameViolation::TooLong(details.get("max_len")?.as_u64()? as usize),
+        "character" => {
+            let mut chars = details.get("character")?.as_str()?.chars();
+            match (chars.next(), chars.next()) {
+                (Some(character), None) => NameViolation::Character(character),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(LimitError::InvalidName { kind, name, reason }.into())
+}
+
+impl SmartHouseError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::HouseNotFoundError(_) => ErrorCode::HouseNotFound,
+            Self::HouseAlreadyExistsError(_) => ErrorCode::HouseAlreadyExists,
+            Self::RoomsNotFoundError => ErrorCode::RoomsNotFound,
+            Self::RoomNotFoundError(_) => ErrorCode::RoomNotFound,
+            Self::RoomAlreadyExistsError(_) => ErrorCode::RoomAlreadyExists,
+            Self::DevicesNotFoundError => ErrorCode::DevicesNotFound,
+            Self::DeviceNotFoundError(_, _) => ErrorCode::DeviceNotFound,
+            Self::DeviceAlreadyExistsError(_, _) => ErrorCode::DeviceAlreadyExists,
+            Self::DeviceInfoProviderError(_) => ErrorCode::DeviceInfoUnavailable,
+            Self::DeviceControlError(_) => ErrorCode::DeviceControlFailed,
+            Self::UserNotFoundError(_) => ErrorCode::UserNotFound,
+            Self::UserAlreadyExistsError(_) => ErrorCode::UserAlreadyExists,
+            Self::UnauthorizedError(_) => ErrorCode::Unauthorized,
+            Self::ForbiddenError(_) => ErrorCode::Forbidden,
+            Self::ValidationError(_) => ErrorCode::ValidationFailed,
+            Self::LimitError(LimitError::InvalidName { .. }) => ErrorCode::InvalidName,
+            Self::LimitError(LimitError::PathTooLong(_) | LimitError::BodyTooLarge(_)) => {
+                ErrorCode::RequestTooLarge
+            }
+            Self::LimitError(LimitError::RateLimited(_)) => ErrorCode::RateLimited,
+            Self::PreconditionFailedError(_) => ErrorCode::PreconditionFailed,
+            Self::IdempotencyError(IdempotencyError::InvalidKey) => {
+                ErrorCode::InvalidIdempotencyKey
+            }
+            Self::IdempotencyError(IdempotencyError::InProgress(_)) => {
+                ErrorCode::IdempotencyKeyInProgress
+            }
+            Self::IdempotencyError(IdempotencyError::KeyReused(_)) => {
+                ErrorCode::IdempotencyKeyReused
+            }
+            Self::ImportError(failure) => failure.error.code(),
+            Self::IoError(_)
+            | Self::ParseError(_)
+            | Self::MongoDBError(_)
+            | Self::OtherError(_) => ErrorCode::InternalError,
+        }
+    }
+
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::HouseNotFoundError(house) | Self::HouseAlreadyExistsError(house) => {
+                Some(json!({ "house": house }))
+            }
+            Self::RoomNotFoundError(room) | Self::RoomAlreadyExistsError(room) => {
+                Some(json!({ "room": room }))
+            }
+            Self::DeviceNotFoundError(room, device)
+            | Self::DeviceAlreadyExistsError(room, device) => {
+                Some(json!({ "room": room, "device": device }))
+            }
+            Self::UserNotFoundError(user) | Self::UserAlreadyExistsError(user) => {
+                Some(json!({ "user": user }))
+            }
+            Self::DeviceInfoProviderError(reason)
+            | Self::DeviceControlError(reason)
+            | Self::UnauthorizedError(reason)
+            | Self::ForbiddenError(reason)
+            | Self::ValidationError(reason)
+            | Self::PreconditionFailedError(reason)
+            | Self::OtherError(reason) => Some(json!({ "reason": reason })),
+            Self::IoError(err) => Some(json!({ "reason": err.to_string() })),
+            Self::ParseError(err) => Some(json!({ "reason": err.to_string() })),
+            Self::MongoDBError(err) => Some(json!({ "reason": err.to_string() })),
+            Self::LimitError(LimitError::InvalidName { kind, name, reason }) => {
+                let mut details = json!({
+                    kind.key(): name,
+                    "kind": kind.key(),
+                    "reason": reason.key(),
+                });
+                match reason {
+                    NameViolation::TooLong(max) => details["max_len"] = json!(max),
+                    NameViolation::Character(character) => {
+                        details["character"] = json!(character.to_string())
+                    }
+                    NameViolation::Empty | NameViolation::Whitespace => (),
+                }
+                Some(details)
+            }
+            Self::LimitError(LimitError::PathTooLong(max)) => {
+                Some(json!({ "max_bytes": max, "limit": "path" }))
+            }
+            Self::LimitError(LimitError::BodyTooLarge(max)) => {
+                Some(json!({ "max_bytes": max, "limit": "body" }))
+            }
+            Self::LimitError(LimitError::RateLimited(retry_after)) => {
+                Some(json!({ "retry_after_secs": retry_after.as_secs() }))
+            }
+            Self::IdempotencyError(
+                IdempotencyError::InProgress(key) | IdempotencyError::KeyReused(key),
+            ) => Some(json!({ "idempotency_key": key })),
+            Self::ImportError(failure) => {
+                let mut details = failure.error.details().unwrap_or_else(|| json!({}));
+                details["applied"] = json!(failure.applied);
+                details["failed"] = json!(failure.failed);
+                Some(details)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Идентификатор запроса, доступен обработчикам как `web::ReqData<RequestId>`
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// Назначает запросу идентификатор (из заголовка `X-Request-Id` клиента или новый),
+/// возвращает его в заголовке ответа и в теле ошибок `SmartHouseError`.
+pub async fn request_id_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| is_valid_request_id(value))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let mut res = next.call(req).await?.map_into_boxed_body();
+
+    let error = res
+        .response()
+        .error()
+        .and_then(|err| err.as_error::<SmartHouseError>())
+        .map(|err| (err.status_code(), ErrorResponse::new(err)));
+    if let Some((status, body)) = error {
+        let body = body.with_request_id(&request_id);
+        // заголовки ошибки, например Retry-After, сохраняются
+        let mut response = HttpResponse::build(status);
+        for (name, value) in res.headers() {
+            if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+                response.append_header((name.clone(), value.clone()));
+            }
+        }
+        res = res.into_response(response.json(body));
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static("x-request-id"), value);
+    }
+
+    Ok(res)
+}
+
+fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id.bytes().all(|byte| byte.is_ascii_graphic())
+}
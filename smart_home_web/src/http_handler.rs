@@ -1,25 +1,104 @@
-use crate::prelude::{AppData, SmartHouseError};
-use actix_web::http::StatusCode;
-use actix_web::{delete, get, post, web, HttpResponse, Responder, ResponseError};
+use crate::auth::API_KEY_HEADER;
+use crate::idempotency::{IdempotencyError, IDEMPOTENCY_KEY_HEADER, MAX_IDEMPOTENCY_KEY_LEN};
+use crate::limits::{LimitError, Limits};
+use crate::prelude::{
+    ApiUser, AppData, AuditRecord, AuditResult, Authenticator, DeviceCommand, DeviceCommandResult,
+    DeviceKind, DeviceSnapshot, DeviceStatus, ErrorCode, ErrorResponse, ExportQuery, HouseSnapshot,
+    ImportChange, ImportConflict, ImportMode, ImportQuery, ImportReport, Role, RoomSnapshot,
+    SmartHouseError, SmartHouseEvent, SnapshotFormat,
+};
+use crate::smart_house_event::sse_stream;
+use actix_web::dev::Payload;
+use actix_web::error::{JsonPayloadError, PayloadError};
+use actix_web::http::header::{EntityTag, HeaderMap};
+use actix_web::http::{header, StatusCode};
+use actix_web::{
+    delete, get, patch, post, put, web, CustomizeResponder, FromRequest, HttpRequest, HttpResponse,
+    Responder, ResponseError,
+};
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use futures::future::LocalBoxFuture;
+use log::warn;
+use prometheus::TEXT_FORMAT;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use utoipa::{OpenApi, ToSchema};
+use std::future::{ready, Ready};
+use std::net::SocketAddr;
+use utoipa::openapi::{
+    path::{ParameterBuilder, ParameterIn, PathItemType},
+    security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Deprecated, ObjectBuilder, Required, SchemaType,
+};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
 
 pub mod prelude {
     pub use crate::http_handler::{
-        delete_device, delete_room, get_device, get_house_report, get_room_devices, get_rooms,
-        post_device, post_room,
+        config, get_events, json_config, EventsQuery, NewSmartHouse, SmartHouseRecord,
+    };
+    pub use crate::http_handler::{
+        delete_device, delete_room, get_device, get_device_history, get_device_record, get_healthz,
+        get_house_export, get_house_report, get_metrics, get_readyz, get_room_device_records,
+        get_room_devices, get_rooms, patch_device, post_device, post_device_command,
+        post_device_off, post_device_on, post_house_import, post_room, put_device,
+    };
+    pub use crate::http_handler::{
+        delete_device_v2, delete_room_v2, get_device_history_v2, get_device_record_v2,
+        get_device_v2, get_room_devices_v2, patch_device_v2, post_device_command_v2,
+        post_device_off_v2, post_device_on_v2, post_device_v2, post_room_v2, put_device_v2,
+        DevicePath, IfMatch, OptionalJson, RoomPath,
+    };
+    pub use crate::http_handler::{
+        delete_house, delete_house_device, delete_house_room, get_house, get_house_device,
+        get_house_device_history, get_house_device_report, get_house_room_devices, get_house_rooms,
+        get_houses, patch_house_device, post_house, post_house_device, post_house_device_command,
+        post_house_room, put_house_device,
+    };
+    pub use crate::http_handler::{
+        delete_user, get_auth_me, get_users, post_auth_token, post_user, ApiUserKey, AuthToken,
+        NewApiUser,
+    };
+    pub use crate::http_handler::{
+        get_audit, ApiDoc, AuditQuery, DeviceHistoryPoint, DeviceQuery, DeviceReading, HealthState,
+        HealthStatus, HistoryQuery, Page, ReadingAggregate, RoomQuery, SmartDeviceInfo,
+        SmartDeviceInfoUpdate, SmartDeviceMeta, SmartDeviceRecord, SmartHouseReport, SortOrder,
     };
-    pub use crate::http_handler::{ApiDoc, SmartDeviceInfo, SmartHouseReport};
 }
 
+const HOUSE_NOT_FOUND: &str = "дом не найден";
+const HOUSE_OR_ROOM_NOT_FOUND: &str = "дом или комната не найдены";
+const HOUSE_ROOM_OR_DEVICE_NOT_FOUND: &str = "дом, комната или устройство не найдены";
 const ROOM_NOT_FOUND: &str = "комната не найдена";
-const DEVICE_NOT_FOUND: &str = "устройство не найдено";
 const ROOM_OR_DEVICE_NOT_FOUND: &str = "комната или устройство не найдены";
 const OK: &str = "OK";
+const CONFLICT_HOUSE_EXISTS: &str = "дом уже существует";
 const CONFLICT_ROOM_EXISTS: &str = "комната уже существует";
 const CONFLICT_DEVICE_EXISTS: &str = "устройство уже существует";
 const INTERNAL_SERVER_ERROR: &str = "внутренняя ошибка сервера";
+const DEVICE_UNAVAILABLE: &str = "устройство недоступно";
+const USER_NOT_FOUND: &str = "пользователь не найден";
+const CONFLICT_USER_EXISTS: &str = "пользователь уже существует";
+const UNAUTHORIZED: &str = "требуется аутентификация";
+const FORBIDDEN: &str = "недостаточно прав";
+const BAD_REQUEST: &str = "некорректные данные";
+const STORAGE_UNAVAILABLE: &str = "хранилище недоступно";
+const PRECONDITION_FAILED: &str = "версия из If-Match не совпадает с текущей";
+
+/// Начальная версия дома, комнаты и устройства, возвращается в `ETag`
+pub(crate) const INITIAL_VERSION: u64 = 1;
+
+const DEPRECATION_HEADER: &str = "Deprecation";
+/// Префиксы устаревших маршрутов v1, замененных маршрутами `/rooms/{room_name}/devices/{device_name}`
+const DEPRECATED_PATHS: [&str; 3] = ["/room/", "/devices/", "/device/"];
+
+const MAX_DEVICE_POWER: f32 = 10000.0;
+const MIN_DEVICE_TEMP: f32 = -100.0;
+const MAX_DEVICE_TEMP: f32 = 100.0;
+const MAX_DEVICE_TAGS: usize = 16;
+const MAX_PAGE_LIMIT: usize = 1000;
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
 
 #[derive(OpenApi)]
 #[openapi(
@@ -28,13 +107,102 @@ const INTERNAL_SERVER_ERROR: &str = "внутренняя ошибка серв
         post_room,
         delete_room,
         get_room_devices,
+        get_room_device_records,
         post_device,
         delete_device,
         get_device,
-        get_house_report
+        get_device_record,
+        get_device_history,
+        put_device,
+        patch_device,
+        post_device_command,
+        post_device_on,
+        post_device_off,
+        post_room_v2,
+        delete_room_v2,
+        get_room_devices_v2,
+        post_device_v2,
+        delete_device_v2,
+        get_device_v2,
+        get_device_record_v2,
+        get_device_history_v2,
+        put_device_v2,
+        patch_device_v2,
+        post_device_command_v2,
+        post_device_on_v2,
+        post_device_off_v2,
+        get_house_report,
+        get_house_export,
+        post_house_import,
+        get_metrics,
+        get_healthz,
+        get_readyz,
+        get_houses,
+        get_house,
+        post_house,
+        delete_house,
+        get_house_rooms,
+        post_house_room,
+        delete_house_room,
+        get_house_room_devices,
+        post_house_device,
+        delete_house_device,
+        get_house_device,
+        get_house_device_history,
+        put_house_device,
+        patch_house_device,
+        post_house_device_command,
+        get_house_device_report,
+        get_events,
+        get_users,
+        post_user,
+        delete_user,
+        get_auth_me,
+        post_auth_token,
+        get_audit
     ),
     components(
-        schemas(SmartDeviceInfo, SmartHouseReport),
+        schemas(
+            DeviceKind,
+            DeviceCommand,
+            DeviceCommandResult,
+            DeviceHistoryPoint,
+            DeviceReading,
+            ReadingAggregate,
+            SmartDeviceInfo,
+            SmartDeviceInfoUpdate,
+            SmartDeviceMeta,
+            SmartDeviceRecord,
+            SmartHouseRecord,
+            NewSmartHouse,
+            SmartHouseReport,
+            HouseSnapshot,
+            RoomSnapshot,
+            DeviceSnapshot,
+            SnapshotFormat,
+            ImportMode,
+            ImportReport,
+            ImportChange,
+            ImportConflict,
+            SmartHouseEvent,
+            SortOrder,
+            HealthState,
+            HealthStatus,
+            Role,
+            ApiUser,
+            NewApiUser,
+            ApiUserKey,
+            AuthToken,
+            AuditRecord,
+            AuditResult,
+            ErrorCode,
+            ErrorResponse
+        ),
+    ),
+    modifiers(&SecurityAddon, &DeprecatedAddon, &IdempotencyAddon),
+    security(
+        ("api_key" = []),
+        ("bearer" = [])
     ),
     tags(
         (name = "Smart Home REST API", description = "Умный дом с умными устройствами")
@@ -42,9 +210,82 @@ const INTERNAL_SERVER_ERROR: &str = "внутренняя ошибка серв
 )]
 pub struct ApiDoc;
 
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+struct DeprecatedAddon;
+
+impl Modify for DeprecatedAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if DEPRECATED_PATHS
+                .iter()
+                .any(|prefix| path.starts_with(prefix))
+            {
+                for operation in item.operations.values_mut() {
+                    operation.deprecated = Some(Deprecated::True);
+                }
+            }
+        }
+    }
+}
+
+struct IdempotencyAddon;
+
+/// Заголовок `Idempotency-Key` принимают все запросы POST и DELETE
+impl Modify for IdempotencyAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let parameter = ParameterBuilder::new()
+            .name(IDEMPOTENCY_KEY_HEADER)
+            .parameter_in(ParameterIn::Header)
+            .required(Required::False)
+            .description(Some(
+                "Ключ идемпотентности: повторный запрос с тем же ключом получает \
+                 сохранённый ответ с заголовком Idempotent-Replayed",
+            ))
+            .schema(Some(
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::String)
+                    .max_length(Some(MAX_IDEMPOTENCY_KEY_LEN)),
+            ))
+            .build();
+
+        for item in openapi.paths.paths.values_mut() {
+            for (kind, operation) in item.operations.iter_mut() {
+                if matches!(kind, PathItemType::Post | PathItemType::Delete) {
+                    operation
+                        .parameters
+                        .get_or_insert_with(Vec::new)
+                        .push(parameter.clone());
+                }
+            }
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, ToSchema)]
 pub struct SmartDeviceInfo {
     pub(crate) name: String,
+    #[serde(default, skip_serializing_if = "DeviceKind::is_unknown")]
+    pub(crate) kind: DeviceKind,
     pub(crate) status: String,
     pub(crate) power: f32,
     pub(crate) temp: f32,
@@ -54,168 +295,2410 @@ impl SmartDeviceInfo {
     pub fn new(name: String, status: String, power: f32, temp: f32) -> Self {
         Self {
             name,
+            kind: DeviceKind::Unknown,
             status,
             power,
             temp,
         }
     }
+
+    pub fn with_kind(mut self, kind: DeviceKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn kind(&self) -> DeviceKind {
+        self.kind
+    }
+
+    pub fn status(&self) -> &str {
+        self.status.as_str()
+    }
+
+    pub fn power(&self) -> f32 {
+        self.power
+    }
+
+    pub fn temp(&self) -> f32 {
+        self.temp
+    }
+}
+
+/// Изменяемые параметры устройства, незаданные поля не изменяются
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SmartDeviceInfoUpdate {
+    #[schema(example = "on")]
+    pub status: Option<String>,
+    pub power: Option<f32>,
+    pub temp: Option<f32>,
+}
+
+impl SmartDeviceInfoUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.status.is_none() && self.power.is_none() && self.temp.is_none()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.status.is_some() && self.power.is_some() && self.temp.is_some()
+    }
+
+    pub fn validate(&self) -> Result<Self, SmartHouseError> {
+        if self.is_empty() {
+            return Err(SmartHouseError::ValidationError(
+                "нет данных для обновления".to_string(),
+            ));
+        }
+
+        let status = match &self.status {
+            Some(status) => Some(status.parse::<DeviceStatus>()?.to_string()),
+            None => None,
+        };
+
+        if let Some(power) = self.power {
+            if !power.is_finite() || !(0.0..=MAX_DEVICE_POWER).contains(&power) {
+                return Err(SmartHouseError::ValidationError(format!(
+                    "мощность {power} вне диапазона 0..{MAX_DEVICE_POWER}"
+                )));
+            }
+        }
+
+        if let Some(temp) = self.temp {
+            if !temp.is_finite() || !(MIN_DEVICE_TEMP..=MAX_DEVICE_TEMP).contains(&temp) {
+                return Err(SmartHouseError::ValidationError(format!(
+                    "температура {temp} вне диапазона {MIN_DEVICE_TEMP}..{MAX_DEVICE_TEMP}"
+                )));
+            }
+        }
+
+        Ok(Self {
+            status,
+            power: self.power,
+            temp: self.temp,
+        })
+    }
+
+    pub(crate) fn apply(&self, info: &mut SmartDeviceInfo) {
+        if let Some(status) = &self.status {
+            info.status.clone_from(status);
+        }
+        if let Some(power) = self.power {
+            info.power = power;
+        }
+        if let Some(temp) = self.temp {
+            info.temp = temp;
+        }
+    }
+}
+
+/// Параметры устройства при добавлении в реестр
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmartDeviceMeta {
+    pub kind: DeviceKind,
+    #[schema(example = "127.0.0.1:54321")]
+    pub address: Option<String>,
+    pub manufacturer: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl SmartDeviceMeta {
+    pub fn new(kind: DeviceKind) -> Self {
+        Self {
+            kind,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_address(mut self, address: &str) -> Self {
+        self.address = Some(address.to_string());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SmartHouseError> {
+        if let Some(address) = &self.address {
+            if address.parse::<SocketAddr>().is_err() {
+                return Err(SmartHouseError::ValidationError(format!(
+                    "некорректный адрес устройства '{address}'"
+                )));
+            }
+        }
+
+        if self.tags.len() > MAX_DEVICE_TAGS {
+            return Err(SmartHouseError::ValidationError(format!(
+                "у устройства может быть не более {MAX_DEVICE_TAGS} тегов"
+            )));
+        }
+
+        if self.tags.iter().any(|tag| tag.trim().is_empty()) {
+            return Err(SmartHouseError::ValidationError(
+                "тег устройства не может быть пустым".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Устройство в реестре умного дома
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct SmartDeviceRecord {
+    pub name: String,
+    pub kind: DeviceKind,
+    pub address: Option<String>,
+    pub manufacturer: Option<String>,
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Версия устройства, увеличивается при каждом изменении параметров, возвращается в `ETag`
+    #[serde(default)]
+    pub version: u64,
+}
+
+impl SmartDeviceRecord {
+    pub fn new(name: &str, meta: &SmartDeviceMeta) -> Self {
+        let now = Utc::now();
+
+        Self {
+            name: name.to_string(),
+            kind: meta.kind,
+            address: meta.address.clone(),
+            manufacturer: meta.manufacturer.clone(),
+            tags: meta.tags.clone(),
+            created_at: now,
+            updated_at: now,
+            version: INITIAL_VERSION,
+        }
+    }
+}
+
+/// Умный дом в реестре
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct SmartHouseRecord {
+    pub name: String,
+    pub address: String,
+    pub created_at: DateTime<Utc>,
+    /// Версия дома, увеличивается при добавлении и удалении комнат, возвращается в `ETag`
+    #[serde(default)]
+    pub version: u64,
+}
+
+impl SmartHouseRecord {
+    pub fn new(name: &str, address: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            address: address.to_string(),
+            created_at: Utc::now(),
+            version: INITIAL_VERSION,
+        }
+    }
+}
+
+/// Параметры дома при добавлении
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct NewSmartHouse {
+    #[schema(example = "ул. Умная, д. 1")]
+    pub address: String,
+}
+
+/// Показание устройства в истории
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeviceReading {
+    pub timestamp: DateTime<Utc>,
+    pub status: String,
+    pub power: f32,
+    pub temp: f32,
+}
+
+impl DeviceReading {
+    pub fn new(timestamp: DateTime<Utc>, info: &SmartDeviceInfo) -> Self {
+        Self {
+            timestamp,
+            status: info.status.clone(),
+            power: info.power,
+            temp: info.temp,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema, SimpleObject)]
+pub struct ReadingAggregate {
+    pub min: f32,
+    pub max: f32,
+    pub avg: f32,
+}
+
+impl ReadingAggregate {
+    pub(crate) fn new(values: impl Iterator<Item = f32>) -> Self {
+        let (mut min, mut max, mut sum, mut count) = (f32::MAX, f32::MIN, 0.0, 0);
+        for value in values {
+            min = min.min(value);
+            max = max.max(value);
+            sum += value;
+            count += 1;
+        }
+
+        match count {
+            0 => Self {
+                min: 0.0,
+                max: 0.0,
+                avg: 0.0,
+            },
+            _ => Self {
+                min,
+                max,
+                avg: sum / count as f32,
+            },
+        }
+    }
+}
+
+/// Агрегированные показания устройства за интервал [from, to)
+#[derive(Clone, Serialize, Deserialize, ToSchema, SimpleObject)]
+pub struct DeviceHistoryPoint {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub count: usize,
+    pub power: ReadingAggregate,
+    pub temp: ReadingAggregate,
+}
+
+/// Период истории (по умолчанию последний час) и шаг агрегации в секундах (по умолчанию 60)
+#[derive(Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub step: Option<u64>,
+}
+
+/// Фильтры журнала аудита: записи не раньше `since` и только автора `actor`,
+/// не более `limit` (1..1000, по умолчанию 100) последних
+#[derive(Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct AuditQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub actor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    pub fn validate(&self) -> Result<Self, SmartHouseError> {
+        validate_limit(self.limit)?;
+
+        Ok(Self {
+            limit: Some(self.limit.unwrap_or(DEFAULT_AUDIT_LIMIT)),
+            ..self.clone()
+        })
+    }
+
+    pub(crate) fn matches(&self, record: &AuditRecord) -> bool {
+        self.since.is_none_or(|since| record.timestamp >= since)
+            && self
+                .actor
+                .as_ref()
+                .is_none_or(|actor| &record.actor == actor)
+    }
+}
+
+/// Порядок сортировки списка по имени
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Постраничная выборка комнат: `limit` (1..1000) и `cursor` из заголовка `X-Next-Cursor`
+/// предыдущей страницы, фильтры по началу (`prefix`) и части (`contains`) имени
+#[derive(Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct RoomQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    pub prefix: Option<String>,
+    pub contains: Option<String>,
+    pub order: Option<SortOrder>,
+}
+
+impl RoomQuery {
+    pub fn validate(&self) -> Result<Self, SmartHouseError> {
+        validate_limit(self.limit)?;
+
+        Ok(Self {
+            cursor: decode_cursor(&self.cursor)?,
+            ..self.clone()
+        })
+    }
+
+    pub fn order(&self) -> SortOrder {
+        self.order.unwrap_or_default()
+    }
+
+    /// Подходит ли имя под фильтры и находится ли оно после курсора
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        name_matches(
+            name,
+            &self.prefix,
+            &self.contains,
+            &self.cursor,
+            self.order(),
+        )
+    }
+}
+
+/// Постраничная выборка устройств, как у комнат, и дополнительно фильтры по типу и статусу
+#[derive(Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    pub prefix: Option<String>,
+    pub contains: Option<String>,
+    pub order: Option<SortOrder>,
+    pub kind: Option<DeviceKind>,
+    #[param(example = "on")]
+    pub status: Option<String>,
+}
+
+impl DeviceQuery {
+    pub fn validate(&self) -> Result<Self, SmartHouseError> {
+        validate_limit(self.limit)?;
+
+        let status = match &self.status {
+            Some(status) => Some(status.parse::<DeviceStatus>()?.to_string()),
+            None => None,
+        };
+
+        Ok(Self {
+            cursor: decode_cursor(&self.cursor)?,
+            status,
+            ..self.clone()
+        })
+    }
+
+    pub fn order(&self) -> SortOrder {
+        self.order.unwrap_or_default()
+    }
+
+    /// Подходит ли устройство с текущим статусом `status` под фильтры и находится ли оно после курсора
+    pub(crate) fn matches(&self, record: &SmartDeviceRecord, status: &str) -> bool {
+        name_matches(
+            &record.name,
+            &self.prefix,
+            &self.contains,
+            &self.cursor,
+            self.order(),
+        ) && self.kind.is_none_or(|kind| kind == record.kind)
+            && self.status.as_ref().is_none_or(|s| s == status)
+    }
+}
+
+/// Страница списка, `next_cursor` задан, если за ней есть ещё элементы
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Страница из `limit + 1` выбранных элементов, лишний элемент означает наличие следующей
+    pub(crate) fn new(mut items: Vec<T>, limit: Option<usize>, name: impl Fn(&T) -> &str) -> Self {
+        let next_cursor = match limit {
+            Some(limit) if items.len() > limit => {
+                items.truncate(limit);
+                items.last().map(|item| encode_cursor(name(item)))
+            }
+            _ => None,
+        };
+
+        Self { items, next_cursor }
+    }
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+impl<T: Serialize> Page<T> {
+    fn response(&self) -> HttpResponse {
+        let mut response = HttpResponse::Ok();
+        if let Some(cursor) = &self.next_cursor {
+            response.insert_header((NEXT_CURSOR_HEADER, cursor.as_str()));
+        }
+
+        response.json(&self.items)
+    }
+}
+
+fn validate_limit(limit: Option<usize>) -> Result<(), SmartHouseError> {
+    match limit {
+        Some(limit) if !(1..=MAX_PAGE_LIMIT).contains(&limit) => {
+            Err(SmartHouseError::ValidationError(format!(
+                "limit {limit} вне диапазона 1..{MAX_PAGE_LIMIT}"
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn name_matches(
+    name: &str,
+    prefix: &Option<String>,
+    contains: &Option<String>,
+    cursor: &Option<String>,
+    order: SortOrder,
+) -> bool {
+    prefix
+        .as_ref()
+        .is_none_or(|prefix| name.starts_with(prefix.as_str()))
+        && contains
+            .as_ref()
+            .is_none_or(|part| name.contains(part.as_str()))
+        && cursor.as_ref().is_none_or(|cursor| match order {
+            SortOrder::Asc => name > cursor.as_str(),
+            SortOrder::Desc => name < cursor.as_str(),
+        })
+}
+
+/// Курсор - имя последнего элемента страницы в шестнадцатеричном виде, чтобы его можно было
+/// передать в заголовке ответа
+fn encode_cursor(name: &str) -> String {
+    name.bytes().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_cursor(cursor: &Option<String>) -> Result<Option<String>, SmartHouseError> {
+    let cursor = match cursor {
+        Some(cursor) => cursor,
+        None => return Ok(None),
+    };
+
+    let invalid = || SmartHouseError::ValidationError(format!("некорректный курсор '{cursor}'"));
+    if cursor.len() % 2 != 0 {
+        return Err(invalid());
+    }
+
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2).unwrap_or_default(), 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid())?;
+
+    String::from_utf8(bytes).map(Some).map_err(|_| invalid())
+}
+
+/// Параметры пользователя API при добавлении
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NewApiUser {
+    pub role: Role,
+}
+
+/// Новый пользователь API и его ключ
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiUserKey {
+    pub user: ApiUser,
+    pub api_key: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuthToken {
+    pub token: String,
+    pub token_type: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Комната в пути запроса
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct RoomPath {
+    pub room_name: String,
+}
+
+/// Устройство в пути запроса, параметры извлекаются по имени, а не по порядку в маршруте
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct DevicePath {
+    pub room_name: String,
+    pub device_name: String,
+}
+
+/// Фильтр событий по дому, по умолчанию события всех домов
+#[derive(Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventsQuery {
+    pub house: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    Ok,
+    Unavailable,
+}
+
+/// Состояние сервера и хранилища
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct HealthStatus {
+    pub status: HealthState,
+    pub storage: HealthState,
+    /// Причина недоступности хранилища
+    pub error: Option<String>,
+}
+
+impl HealthStatus {
+    /// Для проверки готовности общий статус совпадает со статусом хранилища
+    fn new(readiness: bool, storage: Result<(), SmartHouseError>) -> Self {
+        let (storage, error) = match storage {
+            Ok(()) => (HealthState::Ok, None),
+            Err(err) => (HealthState::Unavailable, Some(err.to_string())),
+        };
+
+        Self {
+            status: if readiness { storage } else { HealthState::Ok },
+            storage,
+            error,
+        }
+    }
 }
 
-#[derive(Serialize, ToSchema)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct SmartHouseReport {
     pub(crate) name: String,
     pub(crate) address: String,
     pub(crate) devices: BTreeMap<String, Vec<SmartDeviceInfo>>,
 }
 
+impl SmartHouseReport {
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn address(&self) -> &str {
+        self.address.as_str()
+    }
+
+    /// Параметры устройств по комнатам
+    pub fn devices(&self) -> &BTreeMap<String, Vec<SmartDeviceInfo>> {
+        &self.devices
+    }
+}
+
 /// Список всех комнат
 #[utoipa::path(
     tag = "rooms",
+    params(RoomQuery),
     responses(
-        (status = 200, description = OK, body = [&str]),
-        (status = 500, description = INTERNAL_SERVER_ERROR),
+        (status = 200, description = OK, body = [&str], headers(("X-Next-Cursor" = String, description = "курсор следующей страницы, если она есть"), ("ETag" = String, description = "версия дома"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
     )
 )]
 #[get("/rooms")]
-async fn get_rooms(app_data: web::Data<AppData>) -> Result<impl Responder, SmartHouseError> {
-    Ok(HttpResponse::Ok().json(app_data.rooms().await?))
+async fn get_rooms(
+    query: web::Query<RoomQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let version = app_data.house(&app_data.name).await?.version;
+    let rooms = app_data.find_rooms(&app_data.name, &query).await?;
+
+    Ok(rooms.response().customize().insert_header(etag(version)))
 }
 
-/// Добавить комнату
+/// Добавить комнату (устаревший маршрут, используйте `POST /rooms/{room_name}`)
 #[utoipa::path(
     tag = "rooms",
+    params(RoomPath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
     responses(
         (status = 201, description = OK),
-        (status = 409, description = CONFLICT_ROOM_EXISTS),
-        (status = 500, description = INTERNAL_SERVER_ERROR),
+        (status = 409, description = CONFLICT_ROOM_EXISTS, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
     )
 )]
 #[post("/room/{room_name}")]
 async fn post_room(
-    path: web::Path<String>,
+    path: web::Path<RoomPath>,
+    if_match: IfMatch,
     app_data: web::Data<AppData>,
 ) -> Result<impl Responder, SmartHouseError> {
-    app_data.add_room(&path).await?;
-
-    Ok(HttpResponse::Created())
+    Ok(deprecated(
+        add_room_response(&app_data, &app_data.name, &path.room_name, if_match.0).await?,
+    ))
 }
 
-/// Удалить комнату
+/// Удалить комнату (устаревший маршрут, используйте `DELETE /rooms/{room_name}`)
 #[utoipa::path(
     tag = "rooms",
+    params(RoomPath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
     responses(
         (status = 200, description = OK),
-        (status = 404, description = ROOM_NOT_FOUND),
-        (status = 500, description = INTERNAL_SERVER_ERROR),
+        (status = 404, description = ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
     )
 )]
 #[delete("/room/{room_name}")]
 async fn delete_room(
-    path: web::Path<String>,
+    path: web::Path<RoomPath>,
+    if_match: IfMatch,
     app_data: web::Data<AppData>,
 ) -> Result<impl Responder, SmartHouseError> {
-    app_data.remove_room(&path).await?;
-
-    Ok(HttpResponse::Ok())
+    Ok(deprecated(
+        remove_room_response(&app_data, &app_data.name, &path.room_name, if_match.0).await?,
+    ))
 }
 
-/// Список всех устройств в комнате
+/// Список всех устройств в комнате (устаревший маршрут, используйте `GET /rooms/{room_name}/devices`)
 #[utoipa::path(
     tag = "devices",
+    params(RoomPath, DeviceQuery),
     responses(
-        (status = 200, description = OK, body = [&str]),
-        (status = 404, description = ROOM_NOT_FOUND),
-        (status = 500, description = INTERNAL_SERVER_ERROR),
+        (status = 200, description = OK, body = [&str], headers(("X-Next-Cursor" = String, description = "курсор следующей страницы, если она есть"), ("ETag" = String, description = "версия комнаты"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
     )
 )]
 #[get("/devices/{room_name}")]
 async fn get_room_devices(
-    path: web::Path<String>,
+    path: web::Path<RoomPath>,
+    query: web::Query<DeviceQuery>,
     app_data: web::Data<AppData>,
 ) -> Result<impl Responder, SmartHouseError> {
-    Ok(HttpResponse::Ok().json(app_data.devices(&path).await?))
+    let version = app_data
+        .room_version(&app_data.name, &path.room_name)
+        .await?;
+    let devices = app_data
+        .find_devices(&app_data.name, &path.room_name, &query)
+        .await?
+        .map(|record| record.name);
+
+    Ok(deprecated(
+        devices.response().customize().insert_header(etag(version)),
+    ))
 }
 
-/// Добавить устройство в комнату
+/// Реестр устройств в комнате (устаревший маршрут, используйте `GET /rooms/{room_name}/devices`)
+#[utoipa::path(
+    tag = "devices",
+    params(RoomPath, DeviceQuery),
+    responses(
+        (status = 200, description = OK, body = [SmartDeviceRecord], headers(("X-Next-Cursor" = String, description = "курсор следующей страницы, если она есть"), ("ETag" = String, description = "версия комнаты"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/devices/{room_name}/records")]
+async fn get_room_device_records(
+    path: web::Path<RoomPath>,
+    query: web::Query<DeviceQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(deprecated(
+        device_records_response(&app_data, &app_data.name, &path.room_name, &query).await?,
+    ))
+}
+
+/// Добавить устройство в комнату (устаревший маршрут)
 #[utoipa::path(
     tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body(content = Option<SmartDeviceMeta>),
     responses(
         (status = 201, description = OK),
-        (status = 404, description = ROOM_NOT_FOUND),
-        (status = 409, description = CONFLICT_DEVICE_EXISTS),
-        (status = 500, description = INTERNAL_SERVER_ERROR),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 409, description = CONFLICT_DEVICE_EXISTS, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
     )
 )]
 #[post("/device/{device_name}/room/{room_name}")]
 async fn post_device(
-    path: web::Path<(String, String)>,
+    path: web::Path<DevicePath>,
+    meta: OptionalJson<SmartDeviceMeta>,
+    if_match: IfMatch,
     app_data: web::Data<AppData>,
 ) -> Result<impl Responder, SmartHouseError> {
-    let (room_name, device_name) = path.into_inner();
-    app_data.add_device(&device_name, &room_name).await?;
-
-    Ok(HttpResponse::Created())
+    Ok(deprecated(
+        add_device_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+            meta.into_inner(),
+            if_match.0,
+        )
+        .await?,
+    ))
 }
 
-/// Удалить устройство из комнаты
+/// Удалить устройство из комнаты (устаревший маршрут)
 #[utoipa::path(
     tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
     responses(
         (status = 200, description = OK),
-        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND),
-        (status = 500, description = INTERNAL_SERVER_ERROR),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
     )
 )]
 #[delete("/device/{device_name}/room/{room_name}")]
 async fn delete_device(
-    path: web::Path<(String, String)>,
+    path: web::Path<DevicePath>,
+    if_match: IfMatch,
     app_data: web::Data<AppData>,
 ) -> Result<impl Responder, SmartHouseError> {
-    let (room_name, device_name) = path.into_inner();
-    app_data.remove_device(&device_name, &room_name).await?;
-
-    Ok(HttpResponse::Ok())
+    Ok(deprecated(
+        remove_device_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+            if_match.0,
+        )
+        .await?,
+    ))
 }
 
-/// Статус устройства из источника информации
+/// Статус устройства из источника информации (устаревший маршрут)
 #[utoipa::path(
     tag = "devices",
+    params(DevicePath),
     responses(
-        (status = 200, description = OK, body = SmartDeviceInfo),
-        (status = 404, description = ROOM_NOT_FOUND),
-        (status = 409, description = DEVICE_NOT_FOUND),
-        (status = 500, description = INTERNAL_SERVER_ERROR),
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
     )
 )]
 #[get("/device/{device_name}/room/{room_name}")]
 async fn get_device(
-    path: web::Path<(String, String)>,
+    path: web::Path<DevicePath>,
     app_data: web::Data<AppData>,
 ) -> Result<impl Responder, SmartHouseError> {
-    let (room_name, device_name) = path.into_inner();
-    let device = app_data.device_info(&device_name, &room_name).await?;
-
-    Ok(HttpResponse::Ok().json(device))
+    Ok(deprecated(
+        device_info_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+        )
+        .await?,
+    ))
 }
 
-/// Отчёт о состоянии умного дома
+/// Запись об устройстве из реестра (устаревший маршрут)
 #[utoipa::path(
-    tag = "reports",
+    tag = "devices",
+    params(DevicePath),
     responses(
-        (status = 200, description = OK, body = SmartHouseReport),
-        (status = 500, description = INTERNAL_SERVER_ERROR),
+        (status = 200, description = OK, body = SmartDeviceRecord, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
     )
 )]
-#[get("/house/report")]
-async fn get_house_report(app_data: web::Data<AppData>) -> Result<impl Responder, SmartHouseError> {
-    let house = app_data.house_report().await?;
-
-    Ok(HttpResponse::Ok().json(house))
+#[get("/device/{device_name}/room/{room_name}/record")]
+async fn get_device_record(
+    path: web::Path<DevicePath>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(deprecated(
+        device_record_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+        )
+        .await?,
+    ))
+}
+
+/// История показаний устройства (устаревший маршрут)
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, HistoryQuery),
+    responses(
+        (status = 200, description = OK, body = [DeviceHistoryPoint]),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/device/{device_name}/room/{room_name}/history")]
+async fn get_device_history(
+    path: web::Path<DevicePath>,
+    query: web::Query<HistoryQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(deprecated(
+        device_history_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+            &query,
+        )
+        .await?,
+    ))
+}
+
+/// Заменить все параметры устройства (устаревший маршрут)
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = SmartDeviceInfoUpdate,
+    responses(
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[put("/device/{device_name}/room/{room_name}")]
+async fn put_device(
+    path: web::Path<DevicePath>,
+    update: web::Json<SmartDeviceInfoUpdate>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(deprecated(
+        replace_device_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+            &update,
+            if_match.0,
+        )
+        .await?,
+    ))
+}
+
+/// Изменить отдельные параметры устройства (устаревший маршрут)
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = SmartDeviceInfoUpdate,
+    responses(
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[patch("/device/{device_name}/room/{room_name}")]
+async fn patch_device(
+    path: web::Path<DevicePath>,
+    update: web::Json<SmartDeviceInfoUpdate>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(deprecated(
+        update_device_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+            &update,
+            if_match.0,
+        )
+        .await?,
+    ))
+}
+
+/// Отправить команду устройству (устаревший маршрут)
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = DeviceCommand,
+    responses(
+        (status = 200, description = OK, body = DeviceCommandResult),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 502, description = DEVICE_UNAVAILABLE, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/device/{device_name}/room/{room_name}/command")]
+async fn post_device_command(
+    path: web::Path<DevicePath>,
+    command: web::Json<DeviceCommand>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(deprecated(
+        device_command_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+            &command,
+            if_match.0,
+        )
+        .await?,
+    ))
+}
+
+/// Включить устройство (устаревший маршрут)
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK, body = DeviceCommandResult),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 502, description = DEVICE_UNAVAILABLE, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/device/{device_name}/room/{room_name}/on")]
+async fn post_device_on(
+    path: web::Path<DevicePath>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(deprecated(
+        device_command_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+            &DeviceCommand::On,
+            if_match.0,
+        )
+        .await?,
+    ))
+}
+
+/// Выключить устройство (устаревший маршрут)
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK, body = DeviceCommandResult),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 502, description = DEVICE_UNAVAILABLE, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/device/{device_name}/room/{room_name}/off")]
+async fn post_device_off(
+    path: web::Path<DevicePath>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(deprecated(
+        device_command_response(
+            &app_data,
+            &app_data.name,
+            &path.room_name,
+            &path.device_name,
+            &DeviceCommand::Off,
+            if_match.0,
+        )
+        .await?,
+    ))
+}
+
+/// Добавить комнату
+#[utoipa::path(
+    tag = "rooms",
+    params(RoomPath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 201, description = OK),
+        (status = 409, description = CONFLICT_ROOM_EXISTS, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/rooms/{room_name}")]
+async fn post_room_v2(
+    path: web::Path<RoomPath>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    add_room_response(&app_data, &app_data.name, &path.room_name, if_match.0).await
+}
+
+/// Удалить комнату вместе с устройствами
+#[utoipa::path(
+    tag = "rooms",
+    params(RoomPath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK),
+        (status = 404, description = ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[delete("/rooms/{room_name}")]
+async fn delete_room_v2(
+    path: web::Path<RoomPath>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    remove_room_response(&app_data, &app_data.name, &path.room_name, if_match.0).await
+}
+
+/// Реестр устройств в комнате
+#[utoipa::path(
+    tag = "devices",
+    params(RoomPath, DeviceQuery),
+    responses(
+        (status = 200, description = OK, body = [SmartDeviceRecord], headers(("X-Next-Cursor" = String, description = "курсор следующей страницы, если она есть"), ("ETag" = String, description = "версия комнаты"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/rooms/{room_name}/devices")]
+async fn get_room_devices_v2(
+    path: web::Path<RoomPath>,
+    query: web::Query<DeviceQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    device_records_response(&app_data, &app_data.name, &path.room_name, &query).await
+}
+
+/// Добавить устройство в комнату
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body(content = Option<SmartDeviceMeta>),
+    responses(
+        (status = 201, description = OK),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 409, description = CONFLICT_DEVICE_EXISTS, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/rooms/{room_name}/devices/{device_name}")]
+async fn post_device_v2(
+    path: web::Path<DevicePath>,
+    meta: OptionalJson<SmartDeviceMeta>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    add_device_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+        meta.into_inner(),
+        if_match.0,
+    )
+    .await
+}
+
+/// Удалить устройство из комнаты
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[delete("/rooms/{room_name}/devices/{device_name}")]
+async fn delete_device_v2(
+    path: web::Path<DevicePath>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    remove_device_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+        if_match.0,
+    )
+    .await
+}
+
+/// Статус устройства из источника информации
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath),
+    responses(
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/rooms/{room_name}/devices/{device_name}")]
+async fn get_device_v2(
+    path: web::Path<DevicePath>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    device_info_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+    )
+    .await
+}
+
+/// Запись об устройстве из реестра
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath),
+    responses(
+        (status = 200, description = OK, body = SmartDeviceRecord, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/rooms/{room_name}/devices/{device_name}/record")]
+async fn get_device_record_v2(
+    path: web::Path<DevicePath>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    device_record_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+    )
+    .await
+}
+
+/// История показаний устройства
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, HistoryQuery),
+    responses(
+        (status = 200, description = OK, body = [DeviceHistoryPoint]),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/rooms/{room_name}/devices/{device_name}/history")]
+async fn get_device_history_v2(
+    path: web::Path<DevicePath>,
+    query: web::Query<HistoryQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    device_history_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+        &query,
+    )
+    .await
+}
+
+/// Заменить все параметры устройства
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = SmartDeviceInfoUpdate,
+    responses(
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[put("/rooms/{room_name}/devices/{device_name}")]
+async fn put_device_v2(
+    path: web::Path<DevicePath>,
+    update: web::Json<SmartDeviceInfoUpdate>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    replace_device_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+        &update,
+        if_match.0,
+    )
+    .await
+}
+
+/// Изменить отдельные параметры устройства
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = SmartDeviceInfoUpdate,
+    responses(
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[patch("/rooms/{room_name}/devices/{device_name}")]
+async fn patch_device_v2(
+    path: web::Path<DevicePath>,
+    update: web::Json<SmartDeviceInfoUpdate>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    update_device_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+        &update,
+        if_match.0,
+    )
+    .await
+}
+
+/// Отправить команду устройству
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = DeviceCommand,
+    responses(
+        (status = 200, description = OK, body = DeviceCommandResult),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 502, description = DEVICE_UNAVAILABLE, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/rooms/{room_name}/devices/{device_name}/command")]
+async fn post_device_command_v2(
+    path: web::Path<DevicePath>,
+    command: web::Json<DeviceCommand>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    device_command_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+        &command,
+        if_match.0,
+    )
+    .await
+}
+
+/// Включить устройство
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK, body = DeviceCommandResult),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 502, description = DEVICE_UNAVAILABLE, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/rooms/{room_name}/devices/{device_name}/on")]
+async fn post_device_on_v2(
+    path: web::Path<DevicePath>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    device_command_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+        &DeviceCommand::On,
+        if_match.0,
+    )
+    .await
+}
+
+/// Выключить устройство
+#[utoipa::path(
+    tag = "devices",
+    params(DevicePath, ("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK, body = DeviceCommandResult),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 502, description = DEVICE_UNAVAILABLE, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/rooms/{room_name}/devices/{device_name}/off")]
+async fn post_device_off_v2(
+    path: web::Path<DevicePath>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    device_command_response(
+        &app_data,
+        &app_data.name,
+        &path.room_name,
+        &path.device_name,
+        &DeviceCommand::Off,
+        if_match.0,
+    )
+    .await
+}
+
+/// Ожидаемая версия ресурса из заголовка `If-Match`.
+/// `None`, если заголовка нет или он равен `*`: изменение выполняется без проверки.
+pub struct IfMatch(pub Option<u64>);
+
+impl IfMatch {
+    fn parse(headers: &HeaderMap) -> Result<Self, SmartHouseError> {
+        let Some(value) = headers.get(header::IF_MATCH) else {
+            return Ok(Self(None));
+        };
+        let value = value.to_str().unwrap_or_default().trim();
+        if value == "*" {
+            return Ok(Self(None));
+        }
+
+        let tag: EntityTag = value.parse().map_err(|_| {
+            SmartHouseError::ValidationError(format!(
+                "в заголовке If-Match ожидается одна версия в кавычках, получено '{value}'"
+            ))
+        })?;
+        // If-Match сравнивает версии строго, слабый ETag не совпадает ни с одной
+        match tag.weak {
+            false => match tag.tag().parse() {
+                Ok(version) => Ok(Self(Some(version))),
+                Err(_) => Err(SmartHouseError::PreconditionFailedError(format!(
+                    "версия {tag} не существует"
+                ))),
+            },
+            true => Err(SmartHouseError::PreconditionFailedError(format!(
+                "слабый ETag {tag} не подходит для If-Match"
+            ))),
+        }
+    }
+}
+
+impl FromRequest for IfMatch {
+    type Error = SmartHouseError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::parse(req.headers()))
+    }
+}
+
+/// Необязательное тело запроса в JSON: `None` только для запроса без тела,
+/// некорректное тело - ошибка разбора, как у `web::Json`
+pub struct OptionalJson<T>(pub Option<T>);
+
+impl<T> OptionalJson<T> {
+    pub fn into_inner(self) -> Option<T> {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for OptionalJson<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let content_length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or_default();
+        if content_length == 0 && !req.headers().contains_key(header::TRANSFER_ENCODING) {
+            return Box::pin(ready(Ok(Self(None))));
+        }
+
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move { Ok(Self(Some(json.await?.into_inner()))) })
+    }
+}
+
+/// Заголовок `ETag` с версией ресурса
+fn etag(version: u64) -> header::ETag {
+    header::ETag(EntityTag::new_strong(version.to_string()))
+}
+
+/// Параметры устройства с `ETag`, если устройство есть в реестре
+async fn device_info_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    device: &str,
+) -> Result<HttpResponse, SmartHouseError> {
+    // версия читается первой: параметры могут оказаться новее неё, но не старше
+    let version = app_data.device(house, room, device).await.ok();
+    let info = app_data.device_info(house, room, device).await?;
+
+    let mut response = HttpResponse::Ok();
+    if let Some(record) = version {
+        response.insert_header(etag(record.version));
+    }
+
+    Ok(response.json(info))
+}
+
+// Общие обработчики маршрутов v1, v2 и маршрутов домов: маршрут v1 добавляет
+// к ответу только заголовок `Deprecation`
+
+async fn add_room_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    version: Option<u64>,
+) -> Result<impl Responder, SmartHouseError> {
+    app_data.add_room(house, room, version).await?;
+
+    Ok(HttpResponse::Created())
+}
+
+async fn remove_room_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    version: Option<u64>,
+) -> Result<impl Responder, SmartHouseError> {
+    app_data.remove_room(house, room, version).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+/// Страница реестра устройств комнаты с `ETag` версии комнаты
+async fn device_records_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    query: &DeviceQuery,
+) -> Result<impl Responder, SmartHouseError> {
+    let version = app_data.room_version(house, room).await?;
+    let records = app_data.find_devices(house, room, query).await?;
+
+    Ok(records.response().customize().insert_header(etag(version)))
+}
+
+/// Добавляет устройство, без тела запроса - с параметрами по умолчанию
+async fn add_device_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    device: &str,
+    meta: Option<SmartDeviceMeta>,
+    version: Option<u64>,
+) -> Result<impl Responder, SmartHouseError> {
+    let meta = meta.unwrap_or_default();
+    app_data
+        .add_device_with_meta(house, room, device, &meta, version)
+        .await?;
+
+    Ok(HttpResponse::Created())
+}
+
+async fn remove_device_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    device: &str,
+    version: Option<u64>,
+) -> Result<impl Responder, SmartHouseError> {
+    app_data.remove_device(house, room, device, version).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+async fn device_record_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    device: &str,
+) -> Result<impl Responder, SmartHouseError> {
+    let record = app_data.device(house, room, device).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(record.version))
+        .json(record))
+}
+
+async fn device_history_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    device: &str,
+    query: &HistoryQuery,
+) -> Result<impl Responder, SmartHouseError> {
+    let history = app_data.device_history(house, room, device, query).await?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
+
+/// Заменяет все параметры устройства: `status`, `power` и `temp` обязательны
+async fn replace_device_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    device: &str,
+    update: &SmartDeviceInfoUpdate,
+    version: Option<u64>,
+) -> Result<impl Responder, SmartHouseError> {
+    if !update.is_complete() {
+        return Err(SmartHouseError::ValidationError(
+            "необходимо задать status, power и temp".to_string(),
+        ));
+    }
+
+    update_device_response(app_data, house, room, device, update, version).await
+}
+
+async fn update_device_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    device: &str,
+    update: &SmartDeviceInfoUpdate,
+    version: Option<u64>,
+) -> Result<HttpResponse, SmartHouseError> {
+    let (info, version) = app_data
+        .update_device_info(house, room, device, update, version)
+        .await?;
+
+    Ok(HttpResponse::Ok().insert_header(etag(version)).json(info))
+}
+
+async fn device_command_response(
+    app_data: &AppData,
+    house: &str,
+    room: &str,
+    device: &str,
+    command: &DeviceCommand,
+    version: Option<u64>,
+) -> Result<impl Responder, SmartHouseError> {
+    let result = app_data
+        .send_device_command(house, room, device, command, version)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+/// Ответ устаревшего маршрута v1 с заголовком `Deprecation`
+fn deprecated<R: Responder>(responder: R) -> CustomizeResponder<R> {
+    responder
+        .customize()
+        .insert_header((DEPRECATION_HEADER, "true"))
+}
+
+/// Отчёт о состоянии умного дома
+#[utoipa::path(
+    tag = "reports",
+    responses(
+        (status = 200, description = OK, body = SmartHouseReport),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/house/report")]
+async fn get_house_report(app_data: web::Data<AppData>) -> Result<impl Responder, SmartHouseError> {
+    let house = app_data.house_report(&app_data.name).await?;
+
+    Ok(HttpResponse::Ok().json(house))
+}
+
+/// Конфигурация дома в JSON или YAML для резервного копирования и переноса
+#[utoipa::path(
+    tag = "houses",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = OK, content(
+            ("application/json" = HouseSnapshot),
+            ("application/yaml" = HouseSnapshot),
+        )),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/house/export")]
+async fn get_house_export(
+    req: HttpRequest,
+    query: web::Query<ExportQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let house = query.house.as_deref().unwrap_or(&app_data.name);
+    let format = query.format.unwrap_or_else(|| {
+        SnapshotFormat::from_mime(header_str(&req, header::ACCEPT).unwrap_or_default())
+    });
+    let snapshot = app_data.export_house(house).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(format.serialize(&snapshot)?))
+}
+
+/// Импорт конфигурации дома из JSON или YAML (по заголовку `Content-Type`)
+/// в дом с именем из конфигурации
+#[utoipa::path(
+    tag = "houses",
+    params(ImportQuery),
+    request_body(
+        content = HouseSnapshot,
+        content_type = "application/json",
+        description = "конфигурация дома, также принимается `application/yaml`",
+    ),
+    responses(
+        (status = 200, description = OK, body = ImportReport),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/house/import")]
+async fn post_house_import(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<ImportQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let format =
+        SnapshotFormat::from_mime(header_str(&req, header::CONTENT_TYPE).unwrap_or_default());
+    let snapshot = format.deserialize(&body)?;
+    let report = app_data
+        .import_house(
+            &snapshot,
+            query.mode.unwrap_or_default(),
+            query.dry_run.unwrap_or_default(),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Метрики Prometheus: запросы, операции хранилища, ошибки и сохранённые показания
+/// устройств основного дома (устройства при сборе метрик не опрашиваются)
+#[utoipa::path(
+    tag = "reports",
+    responses(
+        (status = 200, description = OK, body = String, content_type = "text/plain"),
+        (status = 401, description = UNAUTHORIZED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/metrics")]
+async fn get_metrics(app_data: web::Data<AppData>) -> Result<impl Responder, SmartHouseError> {
+    if let Err(err) = app_data.update_device_metrics().await {
+        warn!("Device metrics are not updated: {err}");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(TEXT_FORMAT)
+        .body(app_data.metrics().encode()?))
+}
+
+/// Проверка работоспособности (liveness): сервер отвечает, пока работает процесс,
+/// состояние хранилища только сообщается
+#[utoipa::path(
+    tag = "health",
+    security(),
+    responses(
+        (status = 200, description = OK, body = HealthStatus),
+    )
+)]
+#[get("/healthz")]
+async fn get_healthz(app_data: web::Data<AppData>) -> impl Responder {
+    let storage = app_data.ping_storage().await;
+
+    HttpResponse::Ok().json(HealthStatus::new(false, storage))
+}
+
+/// Готовность принимать запросы (readiness): хранилище доступно
+#[utoipa::path(
+    tag = "health",
+    security(),
+    responses(
+        (status = 200, description = OK, body = HealthStatus),
+        (status = 503, description = STORAGE_UNAVAILABLE, body = HealthStatus),
+    )
+)]
+#[get("/readyz")]
+async fn get_readyz(app_data: web::Data<AppData>) -> impl Responder {
+    let health = HealthStatus::new(true, app_data.ping_storage().await);
+    match health.status {
+        HealthState::Ok => HttpResponse::Ok().json(health),
+        HealthState::Unavailable => HttpResponse::ServiceUnavailable().json(health),
+    }
+}
+
+fn header_str(req: &HttpRequest, name: header::HeaderName) -> Option<&str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Список всех домов
+#[utoipa::path(
+    tag = "houses",
+    responses(
+        (status = 200, description = OK, body = [SmartHouseRecord]),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/houses")]
+async fn get_houses(app_data: web::Data<AppData>) -> Result<impl Responder, SmartHouseError> {
+    Ok(HttpResponse::Ok().json(app_data.houses().await?))
+}
+
+/// Информация о доме
+#[utoipa::path(
+    tag = "houses",
+    responses(
+        (status = 200, description = OK, body = SmartHouseRecord, headers(("ETag" = String, description = "версия дома"))),
+        (status = 404, description = HOUSE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/houses/{house_name}")]
+async fn get_house(
+    path: web::Path<String>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let house = app_data.house(&path).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(house.version))
+        .json(house))
+}
+
+/// Добавить дом
+#[utoipa::path(
+    tag = "houses",
+    request_body(content = Option<NewSmartHouse>),
+    responses(
+        (status = 201, description = OK),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 409, description = CONFLICT_HOUSE_EXISTS, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/houses/{house_name}")]
+async fn post_house(
+    path: web::Path<String>,
+    house: OptionalJson<NewSmartHouse>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let house = house.into_inner().unwrap_or_default();
+    app_data.add_house(&path, &house.address).await?;
+
+    Ok(HttpResponse::Created())
+}
+
+/// Удалить дом вместе с комнатами и устройствами
+#[utoipa::path(
+    tag = "houses",
+    params(("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[delete("/houses/{house_name}")]
+async fn delete_house(
+    path: web::Path<String>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    app_data.remove_house(&path, if_match.0).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+/// Список всех комнат дома
+#[utoipa::path(
+    tag = "houses",
+    params(RoomQuery),
+    responses(
+        (status = 200, description = OK, body = [&str], headers(("X-Next-Cursor" = String, description = "курсор следующей страницы, если она есть"), ("ETag" = String, description = "версия дома"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/houses/{house_name}/rooms")]
+async fn get_house_rooms(
+    path: web::Path<String>,
+    query: web::Query<RoomQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let version = app_data.house(&path).await?.version;
+    let rooms = app_data.find_rooms(&path, &query).await?;
+
+    Ok(rooms.response().customize().insert_header(etag(version)))
+}
+
+/// Добавить комнату в дом
+#[utoipa::path(
+    tag = "houses",
+    params(("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 201, description = OK),
+        (status = 404, description = HOUSE_NOT_FOUND, body = ErrorResponse),
+        (status = 409, description = CONFLICT_ROOM_EXISTS, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/houses/{house_name}/rooms/{room_name}")]
+async fn post_house_room(
+    path: web::Path<(String, String)>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name) = path.into_inner();
+
+    add_room_response(&app_data, &house_name, &room_name, if_match.0).await
+}
+
+/// Удалить комнату из дома
+#[utoipa::path(
+    tag = "houses",
+    params(("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK),
+        (status = 404, description = HOUSE_OR_ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[delete("/houses/{house_name}/rooms/{room_name}")]
+async fn delete_house_room(
+    path: web::Path<(String, String)>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name) = path.into_inner();
+
+    remove_room_response(&app_data, &house_name, &room_name, if_match.0).await
+}
+
+/// Реестр устройств в комнате дома
+#[utoipa::path(
+    tag = "houses",
+    params(DeviceQuery),
+    responses(
+        (status = 200, description = OK, body = [SmartDeviceRecord], headers(("X-Next-Cursor" = String, description = "курсор следующей страницы, если она есть"), ("ETag" = String, description = "версия комнаты"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_OR_ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/houses/{house_name}/rooms/{room_name}/devices")]
+async fn get_house_room_devices(
+    path: web::Path<(String, String)>,
+    query: web::Query<DeviceQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name) = path.into_inner();
+
+    device_records_response(&app_data, &house_name, &room_name, &query).await
+}
+
+/// Добавить устройство в комнату дома
+#[utoipa::path(
+    tag = "houses",
+    params(("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body(content = Option<SmartDeviceMeta>),
+    responses(
+        (status = 201, description = OK),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_OR_ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 409, description = CONFLICT_DEVICE_EXISTS, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/houses/{house_name}/rooms/{room_name}/devices/{device_name}")]
+async fn post_house_device(
+    path: web::Path<(String, String, String)>,
+    meta: OptionalJson<SmartDeviceMeta>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name, device_name) = path.into_inner();
+
+    add_device_response(
+        &app_data,
+        &house_name,
+        &room_name,
+        &device_name,
+        meta.into_inner(),
+        if_match.0,
+    )
+    .await
+}
+
+/// Удалить устройство из комнаты дома
+#[utoipa::path(
+    tag = "houses",
+    params(("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    responses(
+        (status = 200, description = OK),
+        (status = 404, description = HOUSE_ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[delete("/houses/{house_name}/rooms/{room_name}/devices/{device_name}")]
+async fn delete_house_device(
+    path: web::Path<(String, String, String)>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name, device_name) = path.into_inner();
+
+    remove_device_response(&app_data, &house_name, &room_name, &device_name, if_match.0).await
+}
+
+/// Статус устройства дома из источника информации
+#[utoipa::path(
+    tag = "houses",
+    responses(
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 404, description = HOUSE_OR_ROOM_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/houses/{house_name}/rooms/{room_name}/devices/{device_name}")]
+async fn get_house_device(
+    path: web::Path<(String, String, String)>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name, device_name) = path.into_inner();
+
+    device_info_response(&app_data, &house_name, &room_name, &device_name).await
+}
+
+/// История показаний устройства дома
+#[utoipa::path(
+    tag = "houses",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = OK, body = [DeviceHistoryPoint]),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/houses/{house_name}/rooms/{room_name}/devices/{device_name}/history")]
+async fn get_house_device_history(
+    path: web::Path<(String, String, String)>,
+    query: web::Query<HistoryQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name, device_name) = path.into_inner();
+
+    device_history_response(&app_data, &house_name, &room_name, &device_name, &query).await
+}
+
+/// Заменить все параметры устройства дома
+#[utoipa::path(
+    tag = "houses",
+    params(("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = SmartDeviceInfoUpdate,
+    responses(
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[put("/houses/{house_name}/rooms/{room_name}/devices/{device_name}")]
+async fn put_house_device(
+    path: web::Path<(String, String, String)>,
+    update: web::Json<SmartDeviceInfoUpdate>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name, device_name) = path.into_inner();
+
+    replace_device_response(
+        &app_data,
+        &house_name,
+        &room_name,
+        &device_name,
+        &update,
+        if_match.0,
+    )
+    .await
+}
+
+/// Изменить отдельные параметры устройства дома
+#[utoipa::path(
+    tag = "houses",
+    params(("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = SmartDeviceInfoUpdate,
+    responses(
+        (status = 200, description = OK, body = SmartDeviceInfo, headers(("ETag" = String, description = "версия устройства"))),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[patch("/houses/{house_name}/rooms/{room_name}/devices/{device_name}")]
+async fn patch_house_device(
+    path: web::Path<(String, String, String)>,
+    update: web::Json<SmartDeviceInfoUpdate>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name, device_name) = path.into_inner();
+
+    update_device_response(
+        &app_data,
+        &house_name,
+        &room_name,
+        &device_name,
+        &update,
+        if_match.0,
+    )
+    .await
+}
+
+/// Отправить команду устройству дома
+#[utoipa::path(
+    tag = "houses",
+    params(("If-Match" = Option<String>, Header, description = "изменить, только если версия ресурса совпадает")),
+    request_body = DeviceCommand,
+    responses(
+        (status = 200, description = OK, body = DeviceCommandResult),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 404, description = HOUSE_ROOM_OR_DEVICE_NOT_FOUND, body = ErrorResponse),
+        (status = 412, description = PRECONDITION_FAILED, body = ErrorResponse),
+        (status = 502, description = DEVICE_UNAVAILABLE, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/houses/{house_name}/rooms/{room_name}/devices/{device_name}/command")]
+async fn post_house_device_command(
+    path: web::Path<(String, String, String)>,
+    command: web::Json<DeviceCommand>,
+    if_match: IfMatch,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (house_name, room_name, device_name) = path.into_inner();
+
+    device_command_response(
+        &app_data,
+        &house_name,
+        &room_name,
+        &device_name,
+        &command,
+        if_match.0,
+    )
+    .await
+}
+
+/// Отчёт о состоянии дома
+#[utoipa::path(
+    tag = "houses",
+    responses(
+        (status = 200, description = OK, body = SmartHouseReport),
+        (status = 404, description = HOUSE_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/houses/{house_name}/report")]
+async fn get_house_device_report(
+    path: web::Path<String>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(HttpResponse::Ok().json(app_data.house_report(&path).await?))
+}
+
+/// Поток изменений умного дома в формате Server-Sent Events
+#[utoipa::path(
+    tag = "events",
+    params(EventsQuery),
+    responses(
+        (status = 200, description = OK, body = SmartHouseEvent, content_type = "text/event-stream"),
+    )
+)]
+#[get("/events")]
+async fn get_events(
+    query: web::Query<EventsQuery>,
+    app_data: web::Data<AppData>,
+) -> impl Responder {
+    let events = sse_stream(
+        app_data.subscribe(),
+        app_data.shutdown_signal(),
+        query.into_inner().house,
+        app_data.events_heartbeat(),
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
+
+/// Журнал аудита изменений, от новых записей к старым
+#[utoipa::path(
+    tag = "audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = OK, body = [AuditRecord]),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 401, description = UNAUTHORIZED, body = ErrorResponse),
+        (status = 403, description = FORBIDDEN, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/audit")]
+async fn get_audit(
+    query: web::Query<AuditQuery>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    Ok(HttpResponse::Ok().json(app_data.audit_log(&query).await?))
+}
+
+/// Список пользователей API
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, description = OK, body = [ApiUser]),
+        (status = 401, description = UNAUTHORIZED, body = ErrorResponse),
+        (status = 403, description = FORBIDDEN, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[get("/users")]
+async fn get_users(app_data: web::Data<AppData>) -> Result<impl Responder, SmartHouseError> {
+    Ok(HttpResponse::Ok().json(app_data.api_users().await?))
+}
+
+/// Добавить пользователя API, ключ возвращается только в ответе на этот запрос
+#[utoipa::path(
+    tag = "users",
+    request_body = NewApiUser,
+    responses(
+        (status = 201, description = OK, body = ApiUserKey),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 401, description = UNAUTHORIZED, body = ErrorResponse),
+        (status = 403, description = FORBIDDEN, body = ErrorResponse),
+        (status = 409, description = CONFLICT_USER_EXISTS, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[post("/users/{user_name}")]
+async fn post_user(
+    path: web::Path<String>,
+    user: web::Json<NewApiUser>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (user, api_key) = app_data.add_api_user(&path, user.role).await?;
+
+    Ok(HttpResponse::Created().json(ApiUserKey { user, api_key }))
+}
+
+/// Удалить пользователя API
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, description = OK),
+        (status = 401, description = UNAUTHORIZED, body = ErrorResponse),
+        (status = 403, description = FORBIDDEN, body = ErrorResponse),
+        (status = 404, description = USER_NOT_FOUND, body = ErrorResponse),
+        (status = 500, description = INTERNAL_SERVER_ERROR, body = ErrorResponse),
+    )
+)]
+#[delete("/users/{user_name}")]
+async fn delete_user(
+    path: web::Path<String>,
+    app_data: web::Data<AppData>,
+) -> Result<impl Responder, SmartHouseError> {
+    app_data.remove_api_user(&path).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+/// Текущий пользователь API
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, description = OK, body = ApiUser),
+        (status = 401, description = UNAUTHORIZED, body = ErrorResponse),
+    )
+)]
+#[get("/auth/me")]
+async fn get_auth_me(
+    user: Option<web::ReqData<ApiUser>>,
+) -> Result<impl Responder, SmartHouseError> {
+    match user {
+        Some(user) => Ok(HttpResponse::Ok().json(user.into_inner())),
+        None => Err(SmartHouseError::UnauthorizedError(
+            "аутентификация отключена".to_string(),
+        )),
+    }
+}
+
+/// Обменять ключ API на JWT
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, description = OK, body = AuthToken),
+        (status = 400, description = BAD_REQUEST, body = ErrorResponse),
+        (status = 401, description = UNAUTHORIZED, body = ErrorResponse),
+    )
+)]
+#[post("/auth/token")]
+async fn post_auth_token(
+    user: Option<web::ReqData<ApiUser>>,
+    authenticator: Option<web::Data<Authenticator>>,
+) -> Result<impl Responder, SmartHouseError> {
+    let (user, authenticator) = match (user, authenticator) {
+        (Some(user), Some(authenticator)) => (user, authenticator),
+        _ => {
+            return Err(SmartHouseError::UnauthorizedError(
+                "аутентификация отключена".to_string(),
+            ))
+        }
+    };
+    let (token, expires_at) = authenticator.issue_token(&user)?;
+
+    Ok(HttpResponse::Ok().json(AuthToken {
+        token,
+        token_type: "Bearer".to_string(),
+        expires_at,
+    }))
+}
+
+/// Регистрирует все маршруты REST API
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(json_error))
+        .app_data(web::PathConfig::default().error_handler(|err, _| validation_error(err)))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| validation_error(err)))
+        .service(get_rooms)
+        .service(post_room)
+        .service(delete_room)
+        .service(get_room_devices)
+        .service(get_room_device_records)
+        .service(post_device)
+        .service(delete_device)
+        .service(get_device)
+        .service(get_device_record)
+        .service(get_device_history)
+        .service(put_device)
+        .service(patch_device)
+        .service(post_device_command)
+        .service(post_device_on)
+        .service(post_device_off)
+        .service(post_room_v2)
+        .service(delete_room_v2)
+        .service(get_room_devices_v2)
+        .service(post_device_v2)
+        .service(delete_device_v2)
+        .service(get_device_v2)
+        .service(get_device_record_v2)
+        .service(get_device_history_v2)
+        .service(put_device_v2)
+        .service(patch_device_v2)
+        .service(post_device_command_v2)
+        .service(post_device_on_v2)
+        .service(post_device_off_v2)
+        .service(get_house_report)
+        .service(get_house_export)
+        .service(post_house_import)
+        .service(get_metrics)
+        .service(get_healthz)
+        .service(get_readyz)
+        .service(get_houses)
+        .service(get_house)
+        .service(post_house)
+        .service(delete_house)
+        .service(get_house_rooms)
+        .service(post_house_room)
+        .service(delete_house_room)
+        .service(get_house_room_devices)
+        .service(post_house_device)
+        .service(delete_house_device)
+        .service(get_house_device)
+        .service(get_house_device_history)
+        .service(put_house_device)
+        .service(patch_house_device)
+        .service(post_house_device_command)
+        .service(get_house_device_report)
+        .service(get_events)
+        .service(get_users)
+        .service(post_user)
+        .service(delete_user)
+        .service(get_auth_me)
+        .service(post_auth_token)
+        .service(get_audit);
+}
+
+/// Разбор JSON с ограничением тела из `Limits`. `config` оставляет ограничение
+/// actix-web по умолчанию (2 МиБ), сервер подключает этот вариант после `config`.
+pub fn json_config(limits: &Limits) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limits.max_body_bytes)
+        .error_handler(json_error)
+}
+
+/// Ошибки разбора запроса возвращаются в том же формате, что и ошибки API
+fn validation_error(err: impl std::fmt::Display) -> actix_web::Error {
+    SmartHouseError::ValidationError(err.to_string()).into()
+}
+
+/// Тело JSON больше допустимого - `LimitError::BodyTooLarge`, иначе ошибка разбора
+fn json_error(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+    match err {
+        JsonPayloadError::Overflow { limit }
+        | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+            LimitError::BodyTooLarge(limit).into()
+        }
+        JsonPayloadError::Payload(PayloadError::Overflow) => {
+            let limit = req
+                .app_data::<web::Data<AppData>>()
+                .map(|app_data| app_data.limits().max_body_bytes)
+                .unwrap_or_default();
+            LimitError::BodyTooLarge(limit).into()
+        }
+        err => validation_error(err),
+    }
 }
 
 impl ResponseError for SmartHouseError {
     fn status_code(&self) -> StatusCode {
         match self {
+            Self::HouseNotFoundError(_) => StatusCode::NOT_FOUND,
+            Self::HouseAlreadyExistsError(_) => StatusCode::CONFLICT,
             Self::RoomsNotFoundError => StatusCode::NOT_FOUND,
             Self::RoomNotFoundError(_) => StatusCode::NOT_FOUND,
             Self::RoomAlreadyExistsError(_) => StatusCode::CONFLICT,
@@ -225,8 +2708,32 @@ impl ResponseError for SmartHouseError {
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::ParseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::DeviceInfoProviderError(_) => StatusCode::NOT_FOUND,
+            Self::UserNotFoundError(_) => StatusCode::NOT_FOUND,
+            Self::UserAlreadyExistsError(_) => StatusCode::CONFLICT,
+            Self::UnauthorizedError(_) => StatusCode::UNAUTHORIZED,
+            Self::ForbiddenError(_) => StatusCode::FORBIDDEN,
+            Self::DeviceControlError(_) => StatusCode::BAD_GATEWAY,
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::LimitError(LimitError::RateLimited(_)) => StatusCode::TOO_MANY_REQUESTS,
+            Self::LimitError(_) => StatusCode::BAD_REQUEST,
+            Self::PreconditionFailedError(_) => StatusCode::PRECONDITION_FAILED,
+            Self::IdempotencyError(IdempotencyError::InvalidKey) => StatusCode::BAD_REQUEST,
+            Self::IdempotencyError(IdempotencyError::InProgress(_)) => StatusCode::CONFLICT,
+            Self::IdempotencyError(IdempotencyError::KeyReused(_)) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            Self::ImportError(failure) => failure.error.status_code(),
             Self::MongoDBError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::OtherError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::LimitError(LimitError::RateLimited(retry_after)) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.as_secs()));
+        }
+
+        response.json(ErrorResponse::new(self))
+    }
 }
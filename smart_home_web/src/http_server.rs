@@ -1,16 +1,34 @@
+use crate::auth::hash_key;
 use crate::http_handler::prelude::*;
-use crate::prelude::AppData;
-use actix_web::middleware::Logger;
+use crate::prelude::{
+    audit_middleware, auth_middleware, dashboard_config, graphql_config, idempotency_middleware,
+    limits_middleware, metrics_middleware, request_id_middleware, AppConfig, AppData,
+    Authenticator, CorsConfig, MqttBridge, MqttConfig, Role, SmartHomeGrpc, SmartHouseError,
+};
+use actix_web::middleware::{from_fn, Condition, Logger};
 use actix_web::{web, App, HttpServer};
-use log::info;
+use log::{error, info, warn};
 use std::io;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::TcpListenerStream;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+/// Время на остановку по умолчанию, как у actix-web
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct HTTPServer {
     bind_address: String,
     workers: usize,
     app_data: AppData,
+    history_interval: Option<Duration>,
+    authenticator: Option<Authenticator>,
+    shutdown_timeout: Option<Duration>,
+    cors: CorsConfig,
+    grpc_bind_address: Option<String>,
+    mqtt: Option<MqttConfig>,
 }
 
 impl HTTPServer {
@@ -19,39 +37,251 @@ impl HTTPServer {
             bind_address,
             workers,
             app_data,
+            history_interval: None,
+            authenticator: None,
+            shutdown_timeout: None,
+            cors: CorsConfig::default(),
+            grpc_bind_address: None,
+            mqtt: None,
         }
     }
 
+    /// Сервер, хранилище и пользователь-администратор из конфигурации
+    pub async fn from_config(config: &AppConfig) -> Result<Self, SmartHouseError> {
+        let app_data = AppData::from_config(config).await?;
+
+        let authenticator = match &config.auth.admin_api_key {
+            Some(admin_key) => {
+                match app_data
+                    .add_api_user_with_key("admin", Role::Admin, admin_key)
+                    .await
+                {
+                    Ok(_) => (),
+                    // сохранённый ранее (например, в MongoDB) ключ заменяется ключом из конфигурации
+                    Err(SmartHouseError::UserAlreadyExistsError(_)) => {
+                        let stored = app_data
+                            .storage
+                            .api_user_by_key_hash(&hash_key(admin_key))
+                            .await;
+                        if !matches!(stored, Ok(user) if user.name == "admin") {
+                            app_data.set_api_user_key("admin", admin_key).await?;
+                            warn!(
+                                "API key of the 'admin' user is replaced with the configured one"
+                            );
+                        }
+                    }
+                    Err(err) => return Err(err),
+                }
+                let authenticator = Authenticator::new();
+                Some(match &config.auth.jwt_secret {
+                    Some(secret) => authenticator.with_jwt_secret(secret, config.auth.token_ttl()),
+                    None => authenticator,
+                })
+            }
+            None => None,
+        };
+
+        let mut server = Self::new(
+            config.server.bind_address.clone(),
+            config.server.workers,
+            app_data,
+        )
+        .with_shutdown_timeout(config.server.shutdown_timeout())
+        .with_cors(config.cors.clone());
+        if let Some(interval) = config.server.history_interval() {
+            server = server.with_history_interval(interval);
+        }
+        if let Some(authenticator) = authenticator {
+            server = server.with_authenticator(authenticator);
+        }
+        if let Some(address) = &config.server.grpc_bind_address {
+            server = server.with_grpc_bind_address(address.clone());
+        }
+        if config.mqtt.is_enabled() {
+            server = server.with_mqtt(config.mqtt.clone());
+        }
+
+        Ok(server)
+    }
+
+    /// Периодически сохранять показания всех устройств в историю
+    pub fn with_history_interval(mut self, interval: Duration) -> Self {
+        self.history_interval = Some(interval);
+        self
+    }
+
+    /// Требовать аутентификацию и проверять роли пользователей для всех маршрутов API
+    pub fn with_authenticator(mut self, authenticator: Authenticator) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Время на завершение обрабатываемых запросов (и открытых потоков gRPC)
+    /// после сигнала остановки, по умолчанию 30 секунд
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Разрешить вызовы API из браузера со страниц указанных сайтов,
+    /// по умолчанию запрещены
+    pub fn with_cors(mut self, cors: CorsConfig) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Запускать в том же процессе gRPC API на отдельном адресе, по умолчанию не запускается
+    pub fn with_grpc_bind_address(mut self, bind_address: String) -> Self {
+        self.grpc_bind_address = Some(bind_address);
+        self
+    }
+
+    /// Запускать мост MQTT к брокеру из `mqtt.broker_address`, по умолчанию не запускается
+    pub fn with_mqtt(mut self, mqtt: MqttConfig) -> Self {
+        self.mqtt = Some(mqtt);
+        self
+    }
+
+    /// Запускает сервер и ждёт его остановки по SIGINT (Ctrl+C) или SIGTERM:
+    /// новые соединения не принимаются, начатые запросы завершаются
+    /// в течение `shutdown_timeout`
     pub async fn start(self) -> io::Result<()> {
         info!("Server is starting on: {} ...", self.bind_address);
 
         let data = web::Data::new(self.app_data);
+        let authenticator = self.authenticator.map(web::Data::new);
+
+        let history = self.history_interval.map(|interval| {
+            let data = web::Data::clone(&data);
+            actix_web::rt::spawn(async move {
+                let mut interval = actix_web::rt::time::interval(interval);
+                loop {
+                    interval.tick().await;
+                    if let Err(err) = data.record_history().await {
+                        error!("History recording failed: {err}");
+                    }
+                }
+            })
+        });
+
+        let mqtt = match &self.mqtt {
+            Some(config) => {
+                let bridge = MqttBridge::from_config(data.clone().into_inner(), config)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+                Some(actix_web::rt::spawn(bridge.run()))
+            }
+            None => None,
+        };
+
+        let grpc = match &self.grpc_bind_address {
+            Some(address) => {
+                let mut service = SmartHomeGrpc::new(data.clone().into_inner());
+                if let Some(authenticator) = &authenticator {
+                    service = service.with_authenticator(authenticator.clone().into_inner());
+                }
+                let listener = TcpListener::bind(address).await?;
+                info!("gRPC server is starting on: {address} ...");
 
-        HttpServer::new(move || {
-            App::new()
+                let (stop, stopped) = oneshot::channel::<()>();
+                let server = tonic::transport::Server::builder()
+                    .add_service(service.into_service())
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                        let _ = stopped.await;
+                    });
+                Some((stop, actix_web::rt::spawn(server)))
+            }
+            None => None,
+        };
+
+        let shutdown = web::Data::clone(&data);
+        let mut server = HttpServer::new(move || {
+            let mut app = App::new();
+            if let Some(authenticator) = &authenticator {
+                app = app.app_data(web::Data::clone(authenticator));
+            }
+
+            app.wrap(from_fn(audit_middleware))
+                .wrap(from_fn(idempotency_middleware))
+                .wrap(from_fn(auth_middleware))
+                .wrap(from_fn(limits_middleware))
+                .wrap(from_fn(metrics_middleware))
+                .wrap(from_fn(request_id_middleware))
+                .wrap(Condition::new(self.cors.is_enabled(), self.cors.cors()))
                 .wrap(Logger::new(
-                    "%{r}a '%r' %s %b '%{Referer}i' '%{User-Agent}i' %D ms",
+                    "%{r}a '%r' %s %b %{x-request-id}o '%{Referer}i' '%{User-Agent}i' %D ms",
                 ))
                 .service(
                     SwaggerUi::new("/swagger-ui/{_:.*}")
                         .url("/api-docs/openapi.json", ApiDoc::openapi()),
                 )
                 .app_data(web::Data::clone(&data))
-                .service(web::redirect("/", "/swagger-ui/"))
-                .service(get_rooms)
-                .service(post_room)
-                .service(delete_room)
-                .service(get_room_devices)
-                .service(post_device)
-                .service(delete_device)
-                .service(get_device)
-                .service(get_house_report)
+                .configure(dashboard_config)
+                .configure(graphql_config)
+                .configure(config)
+                .app_data(json_config(data.limits()))
         })
         .workers(self.workers)
-        .bind(&self.bind_address)?
-        .run()
-        .await?;
+        .disable_signals();
+        if let Some(timeout) = self.shutdown_timeout {
+            server = server.shutdown_timeout(timeout.as_secs());
+        }
+
+        let server = server.bind(&self.bind_address)?.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(async move {
+            shutdown_signal().await;
+            info!("Shutdown signal received, stopping server ...");
+            shutdown.shutdown();
+            handle.stop(true).await;
+        });
+
+        server.await?;
+        if let Some(history) = history {
+            history.abort();
+        }
+        if let Some(mqtt) = mqtt {
+            mqtt.abort();
+        }
+        if let Some((stop, mut grpc)) = grpc {
+            let _ = stop.send(());
+            // потоки Watch сами не завершаются, поэтому ожидание остановки ограничено
+            let timeout = self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+            match actix_web::rt::time::timeout(timeout, &mut grpc).await {
+                Ok(Ok(Err(err))) => error!("gRPC server failed: {err}"),
+                Ok(Err(err)) => error!("gRPC server task failed: {err}"),
+                Ok(Ok(Ok(()))) => (),
+                Err(_) => {
+                    warn!("gRPC server is not stopped in {timeout:?}, aborting");
+                    grpc.abort();
+                }
+            }
+        }
+        info!("Server stopped");
 
         Ok(())
     }
 }
+
+/// Ожидает SIGINT (Ctrl+C) или, в Unix, SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => (),
+                    _ = sigterm.recv() => (),
+                }
+                return;
+            }
+            Err(err) => error!("SIGTERM handler is not installed: {err}"),
+        }
+    }
+
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        error!("Ctrl+C handler is not installed: {err}");
+        std::future::pending::<()>().await;
+    }
+}
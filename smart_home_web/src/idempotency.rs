@@ -0,0 +1,270 @@
+use crate::limits::LimitError;
+use crate::prelude::{ApiUser, AppData, ErrorResponse, RequestId, SmartHouseError};
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::header::{
+    ContentType, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, DATE, TRANSFER_ENCODING,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes, BytesMut};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use thiserror::Error;
+
+pub(crate) const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+pub(crate) const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+pub(crate) const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Срок блокировки ключа выполняющимся запросом: ключ запроса, прерванного
+/// вместе с процессом, освобождается по его истечении, а не через `DEFAULT_IDEMPOTENCY_TTL`
+pub(crate) const IDEMPOTENCY_LEASE: Duration = Duration::from_secs(60);
+/// Ответы с ключами API и токенами не сохраняются, ключ идемпотентности для них не учитывается
+const SECRET_PATHS: [&str; 2] = ["/users/", "/auth/token"];
+pub(crate) const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Ошибка запроса с ключом идемпотентности, `SmartHouseError::IdempotencyError`
+#[derive(Debug, Error)]
+pub enum IdempotencyError {
+    #[error("ключ идемпотентности должен состоять из 1-255 видимых ASCII-символов")]
+    InvalidKey,
+    #[error("запрос с ключом идемпотентности '{0}' ещё выполняется")]
+    InProgress(String),
+    #[error("ключ идемпотентности '{0}' уже использован для другого запроса")]
+    KeyReused(String),
+}
+
+/// Ответ на запрос с ключом идемпотентности, повторяется для повторных запросов
+#[derive(Clone, Debug)]
+pub struct IdempotentResponse {
+    pub status: u16,
+    /// Заголовки ответа без `Content-Length`, `Transfer-Encoding` и `Date`
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Запрос с ключом идемпотентности
+#[derive(Clone, Debug)]
+pub struct IdempotencyRecord {
+    /// Ключ из заголовка `Idempotency-Key` с именем пользователя API, если он известен
+    pub key: String,
+    /// Хеш метода, пути, параметров и тела запроса
+    pub fingerprint: String,
+    /// Пока запрос выполняется - конец блокировки ключа, после ответа - конец его хранения
+    pub expires_at: DateTime<Utc>,
+    /// `None`, пока запрос выполняется
+    pub response: Option<IdempotentResponse>,
+}
+
+impl IdempotencyRecord {
+    pub fn new(key: String, fingerprint: String, ttl: Duration) -> Self {
+        Self {
+            key,
+            fingerprint,
+            expires_at: Utc::now() + ttl,
+            response: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
+
+impl IdempotentResponse {
+    fn new(response: &HttpResponse<()>, body: Vec<u8>) -> Self {
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                ![CONTENT_LENGTH, TRANSFER_ENCODING, DATE].contains(name)
+                    && name.as_str() != "x-request-id"
+            })
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+
+        Self {
+            status: response.status().as_u16(),
+            headers,
+            body,
+        }
+    }
+
+    fn with_content_type(mut self, content_type: ContentType) -> Self {
+        self.headers
+            .retain(|(name, _)| name != CONTENT_TYPE.as_str());
+        self.headers
+            .push((CONTENT_TYPE.to_string(), content_type.to_string()));
+        self
+    }
+
+    /// Повтор сохранённого ответа с заголовком `Idempotent-Replayed: true`
+    fn replay(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let mut response = HttpResponse::build(status);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::from_str(value),
+            ) {
+                response.append_header((name, value));
+            }
+        }
+        response.insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"));
+
+        response.body(self.body.clone())
+    }
+}
+
+/// Выполняет запросы POST и DELETE с заголовком `Idempotency-Key` не более одного раза
+/// за время хранения ключа: повторный запрос с тем же ключом получает сохранённый ответ
+/// (кроме ответов 5xx, после которых запрос можно повторить). Ключ с другим методом,
+/// путём или телом запроса отклоняется с 422, ещё не завершённый запрос - с 409
+/// (не дольше `IDEMPOTENCY_LEASE`). Запросы пользователей и токенов не сохраняются.
+/// Ключи разных пользователей API не пересекаются, поэтому middleware должен быть
+/// обёрнут `auth_middleware`.
+pub async fn idempotency_middleware(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let app_data = req.app_data::<web::Data<AppData>>().cloned();
+    let (Some(app_data), Some(ttl), true, Some(key)) = (
+        app_data.as_ref(),
+        app_data
+            .as_ref()
+            .and_then(|app_data| app_data.idempotency_ttl()),
+        [Method::POST, Method::DELETE].contains(req.method())
+            && !SECRET_PATHS.iter().any(|path| req.path().starts_with(path)),
+        req.headers().get(IDEMPOTENCY_KEY_HEADER).cloned(),
+    ) else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    let key = match key.to_str() {
+        Ok(key) if is_valid_key(key) => key.to_string(),
+        _ => return Ok(req.error_response(IdempotencyError::InvalidKey)),
+    };
+
+    let body = match read_body(&mut req).await {
+        Ok(body) => body,
+        Err(PayloadError::Overflow) => {
+            let max_body_bytes = app_data.limits().max_body_bytes;
+            return Ok(req.error_response(LimitError::BodyTooLarge(max_body_bytes)));
+        }
+        Err(err) => return Ok(req.error_response(err)),
+    };
+    let fingerprint = fingerprint(&req, &body);
+    req.set_payload(Payload::from(body));
+
+    let scoped_key = match req.extensions().get::<ApiUser>() {
+        Some(user) => format!("{}:{key}", user.name),
+        None => key.clone(),
+    };
+    let record =
+        IdempotencyRecord::new(scoped_key.clone(), fingerprint, IDEMPOTENCY_LEASE.min(ttl));
+    match app_data.start_idempotent_request(&record).await {
+        Ok(None) => (),
+        Ok(Some(existing)) if existing.fingerprint != record.fingerprint => {
+            return Ok(req.error_response(IdempotencyError::KeyReused(key)));
+        }
+        Ok(Some(existing)) => {
+            return Ok(match existing.response {
+                Some(response) => req.into_response(response.replay()),
+                None => req.error_response(IdempotencyError::InProgress(key)),
+            });
+        }
+        Err(err) => return Ok(req.error_response(err)),
+    }
+
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|request_id| request_id.0.clone());
+    let res = match next.call(req).await {
+        Ok(res) => res.map_into_boxed_body(),
+        Err(err) => {
+            app_data.remove_idempotent_request(&scoped_key).await;
+            return Err(err);
+        }
+    };
+
+    if res.status().is_server_error() {
+        app_data.remove_idempotent_request(&scoped_key).await;
+        return Ok(res);
+    }
+
+    let (req, res) = res.into_parts();
+    let error = res
+        .error()
+        .and_then(|err| err.as_error::<SmartHouseError>())
+        .map(ErrorResponse::new);
+    let (res, body) = res.into_parts();
+    let body = match body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            app_data.remove_idempotent_request(&scoped_key).await;
+            return Err(SmartHouseError::OtherError(err.to_string()).into());
+        }
+    };
+
+    // тело ошибки заменяется `request_id_middleware` уже после сохранения ответа,
+    // поэтому сохраняется итоговый вид ошибки с идентификатором исходного запроса
+    let stored = match error {
+        Some(error) => {
+            let error = match &request_id {
+                Some(request_id) => error.with_request_id(request_id),
+                None => error,
+            };
+            let body = serde_json::to_vec(&error).unwrap_or_default();
+            IdempotentResponse::new(&res, body).with_content_type(ContentType::json())
+        }
+        None => IdempotentResponse::new(&res, body.to_vec()),
+    };
+    app_data
+        .complete_idempotent_request(&scoped_key, stored, ttl)
+        .await;
+
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(body))))
+}
+
+/// 1-255 видимых ASCII-символов
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_IDEMPOTENCY_KEY_LEN
+        && key.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+async fn read_body(req: &mut ServiceRequest) -> Result<Bytes, PayloadError> {
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+
+    Ok(body.freeze())
+}
+
+/// Хеш метода, пути с параметрами и тела запроса
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(b" ");
+    hasher.update(req.uri().to_string());
+    hasher.update(b"\n");
+    hasher.update(body);
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+impl From<IdempotencyError> for Error {
+    fn from(err: IdempotencyError) -> Self {
+        SmartHouseError::from(err).into()
+    }
+}
@@ -1,23 +1,69 @@
 mod app;
+mod app_config;
+mod audit;
+mod auth;
+mod dashboard;
+mod device_control;
 mod device_info_provider;
+mod graphql;
+pub mod grpc;
+mod http_error;
 pub mod http_handler;
 mod http_server;
+mod idempotency;
+mod limits;
+mod metrics;
+mod mqtt_bridge;
+mod network_device_info_provider;
 pub mod smart_device;
 mod smart_house;
+mod smart_house_event;
+mod smart_house_snapshot;
 mod smart_house_storage;
+mod smart_house_storage_audit;
+mod smart_house_storage_history;
+mod smart_house_storage_idempotency;
 mod smart_house_storage_memory;
 mod smart_house_storage_mock;
 mod smart_house_storage_mongodb;
+mod smart_house_storage_users;
 mod smart_socket;
 mod smart_switch;
 mod smart_thermometer;
 
 pub mod prelude {
     pub use crate::app::AppData;
+    pub use crate::app_config::{
+        AppConfig, AuthConfig, CliArgs, CorsConfig, DeviceInfoProviderKind, MqttConfig,
+        ServerConfig, StorageBackend, StorageConfig,
+    };
+    pub use crate::audit::{audit_middleware, AuditRecord, AuditResult};
+    pub use crate::auth::{auth_middleware, ApiUser, Authenticator, Role};
+    pub use crate::dashboard::dashboard_config;
+    pub use crate::device_control::{DeviceCommand, DeviceCommandResult, DeviceController};
     pub use crate::device_info_provider::{BorrowingDeviceInfoProvider, OwningDeviceInfoProvider};
+    pub use crate::graphql::{graphql_config, graphql_schema, graphql_schema_sdl, SmartHomeSchema};
+    pub use crate::grpc::SmartHomeGrpc;
+    pub use crate::http_error::{request_id_middleware, ErrorCode, ErrorResponse, RequestId};
     pub use crate::http_handler::prelude::*;
     pub use crate::http_server::HTTPServer;
+    pub use crate::idempotency::{
+        idempotency_middleware, IdempotencyError, IdempotencyRecord, IdempotentResponse,
+    };
+    pub use crate::limits::{
+        limits_middleware, LimitError, Limits, NameKind, NameViolation, RateLimiter,
+    };
+    pub use crate::metrics::{metrics_middleware, Metrics};
+    pub use crate::mqtt_bridge::MqttBridge;
+    pub use crate::network_device_info_provider::{
+        NetworkDevice, NetworkDeviceInfoProvider, SmartDeviceInfoProvider,
+    };
     pub use crate::smart_device::prelude::*;
     pub use crate::smart_house::{SmartHouse, SmartHouseError};
+    pub use crate::smart_house_event::SmartHouseEvent;
+    pub use crate::smart_house_snapshot::{
+        DeviceSnapshot, ExportQuery, HouseSnapshot, ImportChange, ImportConflict, ImportFailure,
+        ImportMode, ImportQuery, ImportReport, RoomSnapshot, SnapshotFormat,
+    };
     pub use crate::smart_house_storage::prelude::*;
 }
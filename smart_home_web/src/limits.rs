@@ -0,0 +1,337 @@
+use crate::prelude::{AppData, SmartHouseError};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage};
+use dashmap::DashMap;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const DEFAULT_MAX_NAME_LEN: usize = 64;
+const DEFAULT_MAX_PATH_LEN: usize = 1024;
+const DEFAULT_MAX_BODY_BYTES: usize = 256 * 1024;
+const DEFAULT_REQUESTS_PER_SEC: f64 = 50.0;
+const DEFAULT_BURST: u32 = 100;
+/// Кроме букв и цифр в названиях допускаются только эти символы
+const NAME_PUNCTUATION: &str = " -_.,()#№";
+/// При большем числе клиентов из памяти удаляются корзины, успевшие наполниться,
+/// а если и этого мало - корзины, дольше всех не получавшие запросов
+const MAX_RATE_LIMITED_CLIENTS: usize = 10000;
+/// Корзины проверяются не чаще этого интервала, пока клиентов меньше двойного предела
+const RATE_LIMITER_PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+const UNKNOWN_CLIENT: &str = "unknown";
+
+/// Ограничения на запросы к API и на названия домов, комнат, устройств и пользователей
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Максимальная длина названия в символах
+    pub max_name_len: usize,
+    /// Максимальная длина пути запроса в байтах (с учётом URL-кодирования)
+    pub max_path_len: usize,
+    /// Максимальный размер тела запроса в байтах
+    pub max_body_bytes: usize,
+    /// Средняя допустимая частота запросов одного клиента, 0 - без ограничения
+    pub requests_per_sec: f64,
+    /// Сколько запросов клиент может отправить сразу сверх средней частоты
+    pub burst: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_name_len: DEFAULT_MAX_NAME_LEN,
+            max_path_len: DEFAULT_MAX_PATH_LEN,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            requests_per_sec: DEFAULT_REQUESTS_PER_SEC,
+            burst: DEFAULT_BURST,
+        }
+    }
+}
+
+impl Limits {
+    /// Проверяет сами ограничения
+    pub fn validate(&self) -> Result<(), SmartHouseError> {
+        if self.max_name_len == 0 || self.max_path_len == 0 || self.max_body_bytes == 0 {
+            return Err(SmartHouseError::ValidationError(
+                "ограничения длины названий, пути и тела запроса должны быть больше 0".to_string(),
+            ));
+        }
+        if !self.requests_per_sec.is_finite() || self.requests_per_sec < 0.0 {
+            return Err(SmartHouseError::ValidationError(
+                "частота запросов должна быть неотрицательным числом".to_string(),
+            ));
+        }
+        if self.requests_per_sec > 0.0 && self.burst == 0 {
+            return Err(SmartHouseError::ValidationError(
+                "при ограничении частоты запросов burst должен быть больше 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Название не пустое, без пробелов по краям, не длиннее `max_name_len` символов
+    /// и состоит из букв, цифр и символов ` -_.,()#№`
+    pub fn validate_name(&self, kind: NameKind, name: &str) -> Result<(), SmartHouseError> {
+        let reason = if name.trim().is_empty() {
+            NameViolation::Empty
+        } else if name.chars().count() > self.max_name_len {
+            NameViolation::TooLong(self.max_name_len)
+        } else if name.trim() != name {
+            NameViolation::Whitespace
+        } else if let Some(c) = name
+            .chars()
+            .find(|c| !c.is_alphanumeric() && !NAME_PUNCTUATION.contains(*c))
+        {
+            NameViolation::Character(c)
+        } else {
+            return Ok(());
+        };
+
+        // слишком длинное название не возвращается клиенту целиком
+        let name = match name.char_indices().nth(self.max_name_len) {
+            Some((end, _)) => format!("{}…", &name[..end]),
+            None => name.to_string(),
+        };
+
+        Err(LimitError::InvalidName { kind, name, reason }.into())
+    }
+}
+
+/// Чьё название проверяется
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameKind {
+    House,
+    Room,
+    Device,
+    User,
+}
+
+impl NameKind {
+    /// Ключ в `details` ответа с ошибкой
+    pub fn key(&self) -> &'static str {
+        match self {
+            NameKind::House => "house",
+            NameKind::Room => "room",
+            NameKind::Device => "device",
+            NameKind::User => "user",
+        }
+    }
+}
+
+impl fmt::Display for NameKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameKind::House => write!(f, "дома"),
+            NameKind::Room => write!(f, "комнаты"),
+            NameKind::Device => write!(f, "устройства"),
+            NameKind::User => write!(f, "пользователя"),
+        }
+    }
+}
+
+/// Чем название не подходит
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum NameViolation {
+    #[error("пустое")]
+    Empty,
+    #[error("длиннее {0} символов")]
+    TooLong(usize),
+    #[error("начинается или заканчивается пробелом")]
+    Whitespace,
+    #[error("недопустимый символ '{0}'")]
+    Character(char),
+}
+
+impl NameViolation {
+    /// Причина `reason` в `details` ответа с ошибкой
+    pub fn key(&self) -> &'static str {
+        match self {
+            NameViolation::Empty => "empty",
+            NameViolation::TooLong(_) => "too_long",
+            NameViolation::Whitespace => "whitespace",
+            NameViolation::Character(_) => "character",
+        }
+    }
+}
+
+/// Нарушение ограничений запроса, `SmartHouseError::LimitError`
+#[derive(Debug, Error)]
+pub enum LimitError {
+    #[error("недопустимое название {kind} '{name}': {reason}")]
+    InvalidName {
+        kind: NameKind,
+        name: String,
+        reason: NameViolation,
+    },
+    #[error("путь запроса длиннее {0} байт")]
+    PathTooLong(usize),
+    #[error("тело запроса больше {0} байт")]
+    BodyTooLarge(usize),
+    #[error("слишком много запросов, повторите через {} с", .0.as_secs())]
+    RateLimited(Duration),
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Ограничение частоты запросов по алгоритму token bucket, корзина у каждого клиента своя
+pub struct RateLimiter {
+    requests_per_sec: f64,
+    burst: f64,
+    buckets: DashMap<String, TokenBucket>,
+    last_prune: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            requests_per_sec: limits.requests_per_sec,
+            burst: limits.burst.into(),
+            buckets: DashMap::new(),
+            last_prune: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Забирает токен из корзины клиента, при пустой корзине возвращает время
+    /// до появления следующего токена (не меньше секунды)
+    pub fn acquire(&self, client: &str) -> Result<(), Duration> {
+        if self.requests_per_sec <= 0.0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        self.prune(now);
+
+        let mut bucket = self
+            .buckets
+            .entry(client.to_string())
+            .or_insert_with(|| TokenBucket {
+                tokens: self.burst,
+                updated: now,
+            });
+        bucket.tokens = self.refill(&bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let wait = (1.0 - bucket.tokens) / self.requests_per_sec;
+        Err(Duration::from_secs(wait.ceil().max(1.0) as u64))
+    }
+
+    /// Количество клиентов, для которых хранятся корзины
+    pub fn clients(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Удаляет лишние корзины не чаще `RATE_LIMITER_PRUNE_INTERVAL`, но сразу при
+    /// двойном превышении предела, так что полный обход приходится на тысячи запросов
+    fn prune(&self, now: Instant) {
+        let clients = self.buckets.len();
+        if clients <= MAX_RATE_LIMITED_CLIENTS {
+            return;
+        }
+        // корзины проверяет только один запрос, остальные не ждут
+        let Ok(mut last_prune) = self.last_prune.try_lock() else {
+            return;
+        };
+        if clients < 2 * MAX_RATE_LIMITED_CLIENTS
+            && now.duration_since(*last_prune) < RATE_LIMITER_PRUNE_INTERVAL
+        {
+            return;
+        }
+        *last_prune = now;
+
+        self.buckets
+            .retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        let excess = self.buckets.len().saturating_sub(MAX_RATE_LIMITED_CLIENTS);
+        if excess == 0 {
+            return;
+        }
+
+        let mut oldest: Vec<_> = self
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.updated, bucket.key().clone()))
+            .collect();
+        oldest.select_nth_unstable_by_key(excess - 1, |(updated, _)| *updated);
+        for (_, client) in &oldest[..excess] {
+            self.buckets.remove(client);
+        }
+    }
+
+    fn refill(&self, bucket: &TokenBucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.requests_per_sec).min(self.burst)
+    }
+}
+
+/// IP-адрес клиента по адресу соединения
+pub(crate) fn client_ip(req: &ServiceRequest) -> String {
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| UNKNOWN_CLIENT.to_string())
+}
+
+/// Отклоняет запросы сверх допустимой частоты (клиент определяется по IP-адресу
+/// соединения), со слишком длинным путём и со слишком большим телом.
+/// Должен оборачивать `auth_middleware`, чтобы перебор ключей тоже ограничивался.
+pub async fn limits_middleware(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(app_data) = req.app_data::<web::Data<AppData>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let limits = app_data.limits();
+
+    let client = client_ip(&req);
+    if let Err(retry_after) = app_data.rate_limiter().acquire(&client) {
+        return Ok(req.error_response(LimitError::RateLimited(retry_after)));
+    }
+
+    if req.path().len() > limits.max_path_len {
+        return Ok(req.error_response(LimitError::PathTooLong(limits.max_path_len)));
+    }
+
+    let max_body_bytes = limits.max_body_bytes;
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > max_body_bytes) {
+        return Ok(req.error_response(LimitError::BodyTooLarge(max_body_bytes)));
+    }
+
+    // тело без Content-Length (chunked) обрывается, как только превысит ограничение
+    let mut received = 0;
+    let payload = req.take_payload().map(move |chunk| {
+        let chunk = chunk?;
+        received += chunk.len();
+        if received > max_body_bytes {
+            return Err(PayloadError::Overflow);
+        }
+        Ok(chunk)
+    });
+    req.set_payload(Payload::from(payload.boxed_local()));
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+impl From<LimitError> for Error {
+    fn from(err: LimitError) -> Self {
+        SmartHouseError::from(err).into()
+    }
+}
@@ -0,0 +1,237 @@
+use crate::prelude::{AppData, DeviceStatus, HouseDevices, SmartHouseError};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use prometheus::{
+    histogram_opts, opts, Encoder, GaugeVec, HistogramVec, IntCounterVec, Registry, TextEncoder,
+};
+use std::time::{Duration, Instant};
+
+const NAMESPACE: &str = "smart_home";
+/// Операции хранилища в памяти занимают микросекунды, а MongoDB - миллисекунды
+const STORAGE_BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Метрики приложения в формате Prometheus, у каждого `AppData` свой реестр
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    storage_duration: HistogramVec,
+    errors: IntCounterVec,
+    device_power: GaugeVec,
+    device_temp: GaugeVec,
+    device_on: GaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let http_requests = IntCounterVec::new(
+            opts!("http_requests_total", "Количество HTTP-запросов").namespace(NAMESPACE),
+            &["method", "route", "status"],
+        )
+        .expect("корректное описание метрики");
+        let http_request_duration = HistogramVec::new(
+            histogram_opts!(
+                "http_request_duration_seconds",
+                "Длительность обработки HTTP-запросов"
+            )
+            .namespace(NAMESPACE),
+            &["method", "route"],
+        )
+        .expect("корректное описание метрики");
+        let storage_duration = HistogramVec::new(
+            histogram_opts!(
+                "storage_operation_duration_seconds",
+                "Длительность операций хранилища",
+                STORAGE_BUCKETS.to_vec()
+            )
+            .namespace(NAMESPACE),
+            &["operation"],
+        )
+        .expect("корректное описание метрики");
+        let errors = IntCounterVec::new(
+            opts!("errors_total", "Количество ошибок в ответах API по видам").namespace(NAMESPACE),
+            &["error"],
+        )
+        .expect("корректное описание метрики");
+        let device_power = GaugeVec::new(
+            opts!("device_power_watts", "Мощность устройства").namespace(NAMESPACE),
+            &["house", "room", "device"],
+        )
+        .expect("корректное описание метрики");
+        let device_temp = GaugeVec::new(
+            opts!("device_temperature_celsius", "Температура устройства").namespace(NAMESPACE),
+            &["house", "room", "device"],
+        )
+        .expect("корректное описание метрики");
+        let device_on = GaugeVec::new(
+            opts!(
+                "device_on",
+                "Устройство включено (1) или выключено (0), для неизвестного статуса нет значения"
+            )
+            .namespace(NAMESPACE),
+            &["house", "room", "device"],
+        )
+        .expect("корректное описание метрики");
+
+        let registry = Registry::new();
+        for collector in [
+            Box::new(http_requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(http_request_duration.clone()),
+            Box::new(storage_duration.clone()),
+            Box::new(errors.clone()),
+            Box::new(device_power.clone()),
+            Box::new(device_temp.clone()),
+            Box::new(device_on.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("метрики зарегистрированы один раз");
+        }
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            storage_duration,
+            errors,
+            device_power,
+            device_temp,
+            device_on,
+        }
+    }
+
+    pub(crate) fn observe_request(
+        &self,
+        method: &str,
+        route: &str,
+        status: u16,
+        duration: Duration,
+    ) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[method, route])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub(crate) fn observe_error(&self, err: &SmartHouseError) {
+        self.errors.with_label_values(&[err.variant()]).inc();
+    }
+
+    pub(crate) fn observe_storage(&self, operation: &str, duration: Duration) {
+        self.storage_duration
+            .with_label_values(&[operation])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Заменяет показания устройств сохранёнными параметрами, удалённые устройства
+    /// и устройства без параметров пропадают
+    pub(crate) fn set_devices(&self, house_devices: &HouseDevices) {
+        self.device_power.reset();
+        self.device_temp.reset();
+        self.device_on.reset();
+
+        let house = house_devices.house.name.as_str();
+        for device in &house_devices.devices {
+            let Some(info) = &device.info else {
+                continue;
+            };
+            let labels = [house, device.room.as_str(), device.record.name.as_str()];
+            self.device_power
+                .with_label_values(&labels)
+                .set(info.power.into());
+            self.device_temp
+                .with_label_values(&labels)
+                .set(info.temp.into());
+            match info.status.parse::<DeviceStatus>() {
+                Ok(DeviceStatus::On) => self.device_on.with_label_values(&labels).set(1.0),
+                Ok(DeviceStatus::Off) => self.device_on.with_label_values(&labels).set(0.0),
+                _ => (),
+            }
+        }
+    }
+
+    /// Все метрики в текстовом формате Prometheus
+    pub fn encode(&self) -> Result<String, SmartHouseError> {
+        let mut buffer = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            return Err(SmartHouseError::OtherError(err.to_string()));
+        }
+
+        String::from_utf8(buffer).map_err(|err| SmartHouseError::OtherError(err.to_string()))
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SmartHouseError {
+    /// Имя варианта ошибки для метрик
+    pub fn variant(&self) -> &'static str {
+        match self {
+            Self::HouseNotFoundError(_) => "HouseNotFoundError",
+            Self::HouseAlreadyExistsError(_) => "HouseAlreadyExistsError",
+            Self::RoomsNotFoundError => "RoomsNotFoundError",
+            Self::RoomNotFoundError(_) => "RoomNotFoundError",
+            Self::RoomAlreadyExistsError(_) => "RoomAlreadyExistsError",
+            Self::DevicesNotFoundError => "DevicesNotFoundError",
+            Self::DeviceNotFoundError(_, _) => "DeviceNotFoundError",
+            Self::DeviceAlreadyExistsError(_, _) => "DeviceAlreadyExistsError",
+            Self::IoError(_) => "IoError",
+            Self::ParseError(_) => "ParseError",
+            Self::DeviceInfoProviderError(_) => "DeviceInfoProviderError",
+            Self::UserNotFoundError(_) => "UserNotFoundError",
+            Self::UserAlreadyExistsError(_) => "UserAlreadyExistsError",
+            Self::UnauthorizedError(_) => "UnauthorizedError",
+            Self::ForbiddenError(_) => "ForbiddenError",
+            Self::DeviceControlError(_) => "DeviceControlError",
+            Self::ValidationError(_) => "ValidationError",
+            Self::LimitError(_) => "LimitError",
+            Self::PreconditionFailedError(_) => "PreconditionFailedError",
+            Self::IdempotencyError(_) => "IdempotencyError",
+            Self::ImportError(_) => "ImportError",
+            Self::MongoDBError(_) => "MongoDBError",
+            Self::OtherError(_) => "OtherError",
+        }
+    }
+}
+
+/// Считает запросы и их длительность по шаблонам маршрутов и ошибки `SmartHouseError`
+/// по видам. Должен оборачиваться `request_id_middleware`, который заменяет тело ошибок.
+pub async fn metrics_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+
+    let res = next.call(req).await?.map_into_boxed_body();
+
+    if let Some(app_data) = res.request().app_data::<web::Data<AppData>>() {
+        let route = res
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+        let metrics = app_data.metrics();
+        metrics.observe_request(&method, &route, res.status().as_u16(), start.elapsed());
+
+        if let Some(err) = res
+            .response()
+            .error()
+            .and_then(|err| err.as_error::<SmartHouseError>())
+        {
+            metrics.observe_error(err);
+        }
+    }
+
+    Ok(res)
+}
@@ -0,0 +1,322 @@
+use crate::audit::with_actor;
+use crate::prelude::{AppData, DeviceCommand, MqttConfig, SmartHouseError, SmartHouseEvent};
+use log::{info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS, Transport};
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+const STATE_TOPIC: &str = "state";
+const SET_TOPIC: &str = "set";
+/// Автор изменений по командам из MQTT в журнале аудита
+const MQTT_ACTOR: &str = "mqtt";
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const REQUESTS_CAPACITY: usize = 64;
+
+/// Мост между домом и брокером MQTT: публикует параметры устройств дома в
+/// `{prefix}/{room}/{device}/state` (JSON, с флагом retain) и выполняет команды
+/// из `{prefix}/{room}/{device}/set`: `on`, `off`, температуру числом или команду
+/// в JSON, как в `POST /device/{device}/room/{room}/command`.
+/// `#`, `+`, `/` и `%` в названиях комнат и устройств кодируются в топиках
+/// как `%23`, `%2B`, `%2F` и `%25`.
+pub struct MqttBridge {
+    app_data: Arc<AppData>,
+    options: MqttOptions,
+    house: String,
+    topic_prefix: String,
+    publish_interval: Option<Duration>,
+}
+
+impl MqttBridge {
+    /// Мост основного дома приложения с префиксом топиков `home`
+    pub fn new(app_data: Arc<AppData>, client_id: &str, host: &str, port: u16) -> Self {
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(KEEP_ALIVE);
+
+        Self {
+            house: app_data.name.clone(),
+            app_data,
+            options,
+            topic_prefix: MqttConfig::default().topic_prefix,
+            publish_interval: None,
+        }
+    }
+
+    pub fn from_config(
+        app_data: Arc<AppData>,
+        config: &MqttConfig,
+    ) -> Result<Self, SmartHouseError> {
+        let (host, port) = config
+            .broker_address
+            .as_deref()
+            .and_then(|address| address.rsplit_once(':'))
+            .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            .ok_or_else(|| {
+                SmartHouseError::ValidationError("mqtt.broker_address не задан".to_string())
+            })?;
+
+        let mut bridge = Self::new(app_data, &config.client_id, host, port)
+            .with_topic_prefix(&config.topic_prefix)
+            .with_publish_interval(config.publish_interval());
+        if let Some(house) = &config.house {
+            bridge = bridge.with_house(house);
+        }
+        if let Some(username) = &config.username {
+            bridge =
+                bridge.with_credentials(username, config.password.as_deref().unwrap_or_default());
+        }
+        if config.tls {
+            bridge = bridge.with_transport(tls_transport(config)?);
+        }
+
+        Ok(bridge)
+    }
+
+    pub fn with_house(mut self, house: &str) -> Self {
+        self.house = house.to_string();
+        self
+    }
+
+    pub fn with_topic_prefix(mut self, prefix: &str) -> Self {
+        self.topic_prefix = prefix.to_string();
+        self
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.options.set_credentials(username, password);
+        self
+    }
+
+    /// Подключение к брокеру, например `Transport::tls_with_default_config()`
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.options.set_transport(transport);
+        self
+    }
+
+    /// Период повторной публикации всех устройств, например для показаний,
+    /// которые устройства меняют сами. `None` - только при изменениях.
+    pub fn with_publish_interval(mut self, interval: Option<Duration>) -> Self {
+        self.publish_interval = interval;
+        self
+    }
+
+    /// Работает до отмены задачи, при обрыве связи переподключается к брокеру
+    pub async fn run(self) {
+        info!(
+            "MQTT bridge is connecting to: {}:{} ...",
+            self.options.broker_address().0,
+            self.options.broker_address().1
+        );
+        let (client, eventloop) = AsyncClient::new(self.options.clone(), REQUESTS_CAPACITY);
+        let bridge = Arc::new(self);
+
+        tokio::join!(
+            Arc::clone(&bridge).poll(client.clone(), eventloop),
+            bridge.publish_events(&client),
+            bridge.publish_periodically(&client),
+        );
+    }
+
+    /// Обрабатывает сообщения брокера: после каждого подключения подписывается на
+    /// команды и публикует все устройства, команды выполняет параллельно
+    async fn poll(self: Arc<Self>, client: AsyncClient, mut eventloop: EventLoop) {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("MQTT bridge is connected");
+                    let bridge = Arc::clone(&self);
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        let filter = format!("{}/+/+/{SET_TOPIC}", bridge.topic_prefix);
+                        if let Err(err) = client.subscribe(filter, QoS::AtLeastOnce).await {
+                            warn!("MQTT subscription failed: {err}");
+                        }
+                        bridge.publish_all(&client).await;
+                    });
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let bridge = Arc::clone(&self);
+                    tokio::spawn(async move { bridge.execute(publish).await });
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    warn!("MQTT connection failed: {err}");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Публикует изменения устройств дома по мере их появления
+    async fn publish_events(&self, client: &AsyncClient) {
+        let mut events = self.app_data.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(event) if event.house() == self.house => match event {
+                    SmartHouseEvent::DeviceAdded { room, device, .. } => {
+                        self.publish_device(client, &room, &device).await;
+                    }
+                    SmartHouseEvent::DeviceUpdated {
+                        room, device, info, ..
+                    } => {
+                        let payload = serde_json::to_vec(&info).unwrap_or_default();
+                        self.publish(client, &room, &device, payload).await;
+                    }
+                    // пустое сообщение с retain удаляет сохранённое брокером состояние
+                    SmartHouseEvent::DeviceRemoved { room, device, .. } => {
+                        self.publish(client, &room, &device, Vec::new()).await;
+                    }
+                    _ => (),
+                },
+                Ok(_) => (),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("MQTT bridge skipped {skipped} events, republishing all devices");
+                    self.publish_all(client).await;
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    async fn publish_periodically(&self, client: &AsyncClient) {
+        let Some(period) = self.publish_interval else {
+            return;
+        };
+
+        let mut interval = tokio::time::interval(period);
+        // сразу после подключения все устройства уже публикуются
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            self.publish_all(client).await;
+        }
+    }
+
+    async fn publish_all(&self, client: &AsyncClient) {
+        let rooms = match self.app_data.rooms(&self.house).await {
+            Ok(rooms) => rooms,
+            Err(err) => {
+                warn!("MQTT bridge cannot list rooms: {err}");
+                return;
+            }
+        };
+
+        for room in rooms {
+            let Ok(devices) = self.app_data.devices(&self.house, &room).await else {
+                continue;
+            };
+            for device in devices {
+                self.publish_device(client, &room, &device).await;
+            }
+        }
+    }
+
+    /// Публикует текущие параметры устройства, в том числе опрошенные по сети
+    async fn publish_device(&self, client: &AsyncClient, room: &str, device: &str) {
+        match self.app_data.device_info(&self.house, room, device).await {
+            Ok(info) => {
+                let payload = serde_json::to_vec(&info).unwrap_or_default();
+                self.publish(client, room, device, payload).await;
+            }
+            Err(err) => warn!("MQTT bridge cannot get device '{device}' info: {err}"),
+        }
+    }
+
+    async fn publish(&self, client: &AsyncClient, room: &str, device: &str, payload: Vec<u8>) {
+        let topic = self.topic(room, device, STATE_TOPIC);
+        if let Err(err) = client.publish(topic, QoS::AtLeastOnce, true, payload).await {
+            warn!("MQTT publish failed: {err}");
+        }
+    }
+
+    /// Выполняет команду из `.../set`, новое состояние публикуется событием `DeviceUpdated`
+    async fn execute(&self, publish: Publish) {
+        let Some((room, device)) = self.parse_set_topic(&publish.topic) else {
+            return;
+        };
+
+        let result = match parse_command(&publish.payload) {
+            Ok(command) => {
+                with_actor(
+                    MQTT_ACTOR.to_string(),
+                    self.app_data
+                        .send_device_command(&self.house, &room, &device, &command, None),
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!("MQTT command for device '{device}' in room '{room}' failed: {err}");
+        }
+    }
+
+    fn topic(&self, room: &str, device: &str, suffix: &str) -> String {
+        format!(
+            "{}/{}/{}/{suffix}",
+            self.topic_prefix,
+            encode_level(room),
+            encode_level(device)
+        )
+    }
+
+    /// Комната и устройство из топика `{prefix}/{room}/{device}/set`
+    fn parse_set_topic(&self, topic: &str) -> Option<(String, String)> {
+        let levels = topic
+            .strip_prefix(&self.topic_prefix)?
+            .strip_prefix('/')?
+            .strip_suffix(SET_TOPIC)?
+            .strip_suffix('/')?;
+        let (room, device) = levels.split_once('/')?;
+
+        Some((decode_level(room), decode_level(device)))
+    }
+}
+
+/// `on`, `off`, температура числом или `DeviceCommand` в JSON
+fn parse_command(payload: &[u8]) -> Result<DeviceCommand, SmartHouseError> {
+    let text = String::from_utf8_lossy(payload);
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("on") {
+        return Ok(DeviceCommand::On);
+    }
+    if text.eq_ignore_ascii_case("off") {
+        return Ok(DeviceCommand::Off);
+    }
+    if let Ok(value) = text.parse::<f32>() {
+        return Ok(DeviceCommand::SetTemp { value });
+    }
+
+    serde_json::from_str(text)
+        .map_err(|_| SmartHouseError::ValidationError(format!("неизвестная команда '{text}'")))
+}
+
+/// TLS с сертификатами системы или с центром сертификации и сертификатом клиента
+/// из файлов конфигурации
+fn tls_transport(config: &MqttConfig) -> Result<Transport, SmartHouseError> {
+    let Some(ca_file) = &config.ca_file else {
+        return Ok(Transport::tls_with_default_config());
+    };
+    let client_auth = match (&config.client_cert_file, &config.client_key_file) {
+        (Some(cert), Some(key)) => Some((fs::read(cert)?, fs::read(key)?)),
+        _ => None,
+    };
+
+    Ok(Transport::tls(fs::read(ca_file)?, client_auth, None))
+}
+
+/// Символы, недопустимые в уровне топика, и `%`
+fn encode_level(name: &str) -> String {
+    name.replace('%', "%25")
+        .replace('#', "%23")
+        .replace('+', "%2B")
+        .replace('/', "%2F")
+}
+
+fn decode_level(level: &str) -> String {
+    urlencoding::decode(level)
+        .map(|name| name.into_owned())
+        .unwrap_or_else(|_| level.to_string())
+}
@@ -0,0 +1,233 @@
+use crate::prelude::{
+    DeviceKind, DeviceStatus, SmartDevice, SmartDeviceInfo, SmartHouseError, SmartSocket,
+    SmartSwitch, SmartThermometer,
+};
+use crate::smart_device::{parse_status, parse_value, DEFAULT_DEVICE_TIMEOUT};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(5);
+
+type DeviceKey = (String, String, String);
+
+/// Источник актуальной информации об устройствах (в отличие от хранилища)
+#[async_trait]
+pub trait SmartDeviceInfoProvider {
+    fn contains(&self, house: &str, room: &str, device: &str) -> bool;
+
+    /// Сбрасывает закэшированную информацию, например после управляющей команды
+    fn invalidate(&self, _house: &str, _room: &str, _device: &str) {}
+
+    /// Начинает опрашивать устройство, добавленное в реестр с известным типом и адресом
+    fn register_device(
+        &self,
+        _house: &str,
+        _room: &str,
+        _device: &str,
+        _kind: DeviceKind,
+        _address: &str,
+    ) {
+    }
+
+    /// Прекращает опрашивать устройство и сбрасывает закэшированную информацию о нём
+    fn unregister_device(&self, _house: &str, _room: &str, _device: &str) {}
+
+    async fn device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceInfo, SmartHouseError>;
+}
+
+#[derive(Clone)]
+pub struct NetworkDevice {
+    pub kind: DeviceKind,
+    pub address: String,
+}
+
+/// Опрашивает устройства по сети: розетки и выключатели по TCP, термометры по UDP.
+/// Ответы кэшируются на время `ttl`, каждый запрос к устройству ограничен `timeout`.
+pub struct NetworkDeviceInfoProvider {
+    devices: DashMap<DeviceKey, NetworkDevice>,
+    cache: DashMap<DeviceKey, (Instant, SmartDeviceInfo)>,
+    ttl: Duration,
+    timeout: Duration,
+}
+
+impl NetworkDeviceInfoProvider {
+    pub fn new(ttl: Duration, timeout: Duration) -> Self {
+        Self {
+            devices: DashMap::new(),
+            cache: DashMap::new(),
+            ttl,
+            timeout,
+        }
+    }
+
+    pub fn add_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        kind: DeviceKind,
+        address: &str,
+    ) {
+        let key = device_key(house, room, device);
+        self.cache.remove(&key);
+        self.devices.insert(
+            key,
+            NetworkDevice {
+                kind,
+                address: address.to_string(),
+            },
+        );
+    }
+
+    pub fn remove_device(&self, house: &str, room: &str, device: &str) -> Option<NetworkDevice> {
+        let key = device_key(house, room, device);
+        self.cache.remove(&key);
+        self.devices.remove(&key).map(|(_, device)| device)
+    }
+
+    pub fn device(&self, house: &str, room: &str, device: &str) -> Option<NetworkDevice> {
+        self.devices
+            .get(&device_key(house, room, device))
+            .map(|device| device.clone())
+    }
+
+    async fn query(
+        &self,
+        name: &str,
+        device: &NetworkDevice,
+    ) -> Result<SmartDeviceInfo, SmartHouseError> {
+        let info = match device.kind {
+            DeviceKind::Socket => {
+                let status = self
+                    .send_command::<SmartSocket>(&device.address, "status")
+                    .await?;
+                let power = self
+                    .send_command::<SmartSocket>(&device.address, "power")
+                    .await?;
+                SmartDeviceInfo::new(
+                    name.to_string(),
+                    parse_status(&status, SmartHouseError::DeviceInfoProviderError)?,
+                    parse_value(&power, SmartHouseError::DeviceInfoProviderError)?,
+                    0.0,
+                )
+            }
+            DeviceKind::Switch => {
+                let status = self
+                    .send_command::<SmartSwitch>(&device.address, "status")
+                    .await?;
+                SmartDeviceInfo::new(
+                    name.to_string(),
+                    parse_status(&status, SmartHouseError::DeviceInfoProviderError)?,
+                    0.0,
+                    0.0,
+                )
+            }
+            DeviceKind::Thermometer => {
+                let temp = self
+                    .send_command::<SmartThermometer>(&device.address, "temp")
+                    .await?;
+                SmartDeviceInfo::new(
+                    name.to_string(),
+                    DeviceStatus::Unknown.to_string(),
+                    0.0,
+                    parse_value(&temp, SmartHouseError::DeviceInfoProviderError)?,
+                )
+            }
+            DeviceKind::Unknown => {
+                return Err(SmartHouseError::DeviceInfoProviderError(format!(
+                    "неизвестный тип устройства '{name}'"
+                )))
+            }
+        };
+
+        Ok(info.with_kind(device.kind))
+    }
+
+    async fn send_command<D: SmartDevice>(
+        &self,
+        addr: &str,
+        command: &str,
+    ) -> Result<String, SmartHouseError> {
+        match time::timeout(self.timeout, D::send_command(addr, command)).await {
+            Ok(result) => result,
+            Err(_) => Err(SmartHouseError::DeviceInfoProviderError(format!(
+                "устройство '{addr}' не ответило за {:?}",
+                self.timeout
+            ))),
+        }
+    }
+}
+
+impl Default for NetworkDeviceInfoProvider {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL, DEFAULT_DEVICE_TIMEOUT)
+    }
+}
+
+#[async_trait]
+impl SmartDeviceInfoProvider for NetworkDeviceInfoProvider {
+    fn contains(&self, house: &str, room: &str, device: &str) -> bool {
+        self.devices.contains_key(&device_key(house, room, device))
+    }
+
+    fn invalidate(&self, house: &str, room: &str, device: &str) {
+        self.cache.remove(&device_key(house, room, device));
+    }
+
+    fn register_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        kind: DeviceKind,
+        address: &str,
+    ) {
+        self.add_device(house, room, device, kind, address);
+    }
+
+    fn unregister_device(&self, house: &str, room: &str, device: &str) {
+        self.remove_device(house, room, device);
+    }
+
+    async fn device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceInfo, SmartHouseError> {
+        let key = device_key(house, room, device);
+
+        if let Some(cached) = self.cache.get(&key) {
+            let (updated, info) = cached.value();
+            if updated.elapsed() < self.ttl {
+                return Ok(info.clone());
+            }
+        }
+
+        let network_device = match self.devices.get(&key) {
+            Some(network_device) => network_device.clone(),
+            None => {
+                return Err(SmartHouseError::DeviceInfoProviderError(
+                    SmartHouseError::DeviceNotFoundError(room.to_string(), device.to_string())
+                        .to_string(),
+                ))
+            }
+        };
+
+        let info = self.query(device, &network_device).await?;
+        self.cache.insert(key, (Instant::now(), info.clone()));
+
+        Ok(info)
+    }
+}
+
+fn device_key(house: &str, room: &str, device: &str) -> DeviceKey {
+    (house.to_string(), room.to_string(), device.to_string())
+}
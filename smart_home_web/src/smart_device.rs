@@ -1,11 +1,17 @@
 use crate::smart_house::SmartHouseError;
+use async_graphql::Enum;
 use async_trait::async_trait;
 use atomic_enum::atomic_enum;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
+use utoipa::ToSchema;
 
 pub mod prelude {
+    pub use crate::smart_device::DeviceKind;
     pub use crate::smart_device::DeviceStatus;
     pub use crate::smart_device::SmartDevice;
     pub use crate::smart_socket::SmartSocket;
@@ -13,6 +19,9 @@ pub mod prelude {
     pub use crate::smart_thermometer::SmartThermometer;
 }
 
+/// Время ожидания ответа устройства по сети по умолчанию
+pub(crate) const DEFAULT_DEVICE_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[atomic_enum]
 pub enum DeviceStatus {
     Off,
@@ -30,6 +39,37 @@ impl fmt::Display for DeviceStatus {
     }
 }
 
+impl FromStr for DeviceStatus {
+    type Err = SmartHouseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" | "выключено" => Ok(DeviceStatus::Off),
+            "on" | "включено" => Ok(DeviceStatus::On),
+            "unknown" | "неизвестно" => Ok(DeviceStatus::Unknown),
+            _ => Err(SmartHouseError::ValidationError(format!(
+                "неизвестный статус устройства '{s}'"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceKind {
+    Socket,
+    Switch,
+    Thermometer,
+    #[default]
+    Unknown,
+}
+
+impl DeviceKind {
+    pub fn is_unknown(&self) -> bool {
+        *self == DeviceKind::Unknown
+    }
+}
+
 #[async_trait]
 pub trait SmartDevice {
     async fn listen(&'static self, addr: &str) -> Result<(), SmartHouseError> {
@@ -79,9 +119,8 @@ pub trait SmartDevice {
         let result = self.exec_command(&command);
         println!("'{}'", result);
 
-        let write_result = stream.write_all(result.as_bytes()).await;
-        if write_result.is_err() {
-            eprintln!("SMART_DEVICE: write error: {}", write_result.unwrap_err());
+        if let Err(err) = stream.write_all(result.as_bytes()).await {
+            eprintln!("SMART_DEVICE: write error: {err}");
         }
     }
 
@@ -112,3 +151,24 @@ pub trait SmartDevice {
         String::from("OK")
     }
 }
+
+/// Статус из ответа устройства, ошибка разбора - вариантом `error`
+pub(crate) fn parse_status(
+    status: &str,
+    error: fn(String) -> SmartHouseError,
+) -> Result<String, SmartHouseError> {
+    match status.parse::<DeviceStatus>() {
+        Ok(status) => Ok(status.to_string()),
+        Err(err) => Err(error(err.to_string())),
+    }
+}
+
+/// Число из ответа устройства, ошибка разбора - вариантом `error`
+pub(crate) fn parse_value(
+    value: &str,
+    error: fn(String) -> SmartHouseError,
+) -> Result<f32, SmartHouseError> {
+    value
+        .parse::<f32>()
+        .map_err(|err| error(format!("'{value}' не число: {err}")))
+}
@@ -1,4 +1,7 @@
 use crate::device_info_provider::DeviceInfoProvider;
+use crate::idempotency::IdempotencyError;
+use crate::limits::LimitError;
+use crate::smart_house_snapshot::ImportFailure;
 use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
@@ -135,6 +138,10 @@ impl SmartHouse {
 
 #[derive(Debug, Error)]
 pub enum SmartHouseError {
+    #[error("дом '{0}' не найден")]
+    HouseNotFoundError(String),
+    #[error("дом '{0}' уже существует")]
+    HouseAlreadyExistsError(String),
     #[error("комнаты не найдены")]
     RoomsNotFoundError,
     #[error("комната '{0}' не найдена")]
@@ -153,6 +160,26 @@ pub enum SmartHouseError {
     ParseError(#[from] std::num::ParseIntError),
     #[error("ошибка получения информации об устройстве: {0}")]
     DeviceInfoProviderError(String),
+    #[error("пользователь '{0}' не найден")]
+    UserNotFoundError(String),
+    #[error("пользователь '{0}' уже существует")]
+    UserAlreadyExistsError(String),
+    #[error("требуется аутентификация: {0}")]
+    UnauthorizedError(String),
+    #[error("недостаточно прав: {0}")]
+    ForbiddenError(String),
+    #[error("ошибка управления устройством: {0}")]
+    DeviceControlError(String),
+    #[error("некорректные данные: {0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    LimitError(#[from] LimitError),
+    #[error("ресурс изменён: {0}")]
+    PreconditionFailedError(String),
+    #[error("{0}")]
+    IdempotencyError(#[from] IdempotencyError),
+    #[error("импорт прерван: {}", .0.error)]
+    ImportError(Box<ImportFailure>),
     #[error("ошибка MongoDB: {0}")]
     MongoDBError(#[from] mongodb::error::Error),
     #[error("внутренняя ошибка: {0}")]
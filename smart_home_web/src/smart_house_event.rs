@@ -0,0 +1,134 @@
+use crate::prelude::SmartDeviceInfo;
+use actix_web::web::Bytes;
+use futures::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tokio::time::{self, Instant, MissedTickBehavior};
+use utoipa::ToSchema;
+
+/// Изменение умного дома, рассылаемое подписчикам `/events`
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SmartHouseEvent {
+    HouseAdded {
+        house: String,
+    },
+    HouseRemoved {
+        house: String,
+    },
+    RoomAdded {
+        house: String,
+        room: String,
+    },
+    RoomRemoved {
+        house: String,
+        room: String,
+    },
+    DeviceAdded {
+        house: String,
+        room: String,
+        device: String,
+    },
+    DeviceRemoved {
+        house: String,
+        room: String,
+        device: String,
+    },
+    DeviceUpdated {
+        house: String,
+        room: String,
+        device: String,
+        info: SmartDeviceInfo,
+    },
+}
+
+impl SmartHouseEvent {
+    pub fn house(&self) -> &str {
+        match self {
+            Self::HouseAdded { house }
+            | Self::HouseRemoved { house }
+            | Self::RoomAdded { house, .. }
+            | Self::RoomRemoved { house, .. }
+            | Self::DeviceAdded { house, .. }
+            | Self::DeviceRemoved { house, .. }
+            | Self::DeviceUpdated { house, .. } => house,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HouseAdded { .. } => "house_added",
+            Self::HouseRemoved { .. } => "house_removed",
+            Self::RoomAdded { .. } => "room_added",
+            Self::RoomRemoved { .. } => "room_removed",
+            Self::DeviceAdded { .. } => "device_added",
+            Self::DeviceRemoved { .. } => "device_removed",
+            Self::DeviceUpdated { .. } => "device_updated",
+        }
+    }
+
+    /// Сообщение в формате Server-Sent Events
+    pub(crate) fn to_sse(&self) -> Bytes {
+        let data = serde_json::to_string(self).unwrap_or_default();
+
+        Bytes::from(format!("event: {}\ndata: {data}\n\n", self.name()))
+    }
+}
+
+/// Поток событий в формате Server-Sent Events, при заданном `house` только события этого дома.
+/// Отставший подписчик получает комментарий о количестве пропущенных событий, без событий
+/// каждые `heartbeat` отправляется пустой комментарий. Поток завершается, когда `shutdown`
+/// становится `true`.
+pub(crate) fn sse_stream(
+    receiver: broadcast::Receiver<SmartHouseEvent>,
+    shutdown: watch::Receiver<bool>,
+    house: Option<String>,
+    heartbeat: Duration,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let mut interval = time::interval_at(Instant::now() + heartbeat, heartbeat);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    stream::unfold(
+        (receiver, shutdown, interval),
+        move |(mut receiver, mut shutdown, mut interval)| {
+            let house = house.clone();
+            async move {
+                loop {
+                    if *shutdown.borrow_and_update() {
+                        return None;
+                    }
+
+                    let result = tokio::select! {
+                        result = receiver.recv() => result,
+                        _ = interval.tick() => {
+                            let comment = Bytes::from_static(b": heartbeat\n\n");
+                            return Some((Ok(comment), (receiver, shutdown, interval)));
+                        }
+                        changed = shutdown.changed() => match changed {
+                            Ok(()) => continue,
+                            Err(_) => return None,
+                        },
+                    };
+                    match result {
+                        Ok(event) => {
+                            if house.as_deref().is_some_and(|house| house != event.house()) {
+                                continue;
+                            }
+                            interval.reset();
+                            return Some((Ok(event.to_sse()), (receiver, shutdown, interval)));
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            let comment =
+                                Bytes::from(format!(": пропущено событий: {skipped}\n\n"));
+                            return Some((Ok(comment), (receiver, shutdown, interval)));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        },
+    )
+}
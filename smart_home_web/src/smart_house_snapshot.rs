@@ -0,0 +1,563 @@
+use crate::prelude::{
+    HouseDevices, SmartDeviceInfo, SmartDeviceInfoUpdate, SmartDeviceMeta, SmartDeviceRecord,
+    SmartHouseError,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use utoipa::{IntoParams, ToSchema};
+
+/// Конфигурация дома для резервного копирования и переноса:
+/// комнаты, устройства и их последние параметры
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HouseSnapshot {
+    pub name: String,
+    pub address: String,
+    #[serde(default)]
+    pub rooms: Vec<RoomSnapshot>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RoomSnapshot {
+    pub name: String,
+    #[serde(default)]
+    pub devices: Vec<DeviceSnapshot>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceSnapshot {
+    pub name: String,
+    #[serde(default)]
+    pub meta: SmartDeviceMeta,
+    /// Параметры устройства, незаданные поля не изменяются при импорте
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<SmartDeviceInfoUpdate>,
+}
+
+/// Формат конфигурации дома
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+impl SnapshotFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Yaml => "application/yaml",
+        }
+    }
+
+    /// Формат по заголовку `Content-Type` или `Accept`, по умолчанию JSON
+    pub fn from_mime(mime: &str) -> Self {
+        if mime.contains("yaml") {
+            Self::Yaml
+        } else {
+            Self::Json
+        }
+    }
+
+    pub fn serialize(&self, snapshot: &HouseSnapshot) -> Result<String, SmartHouseError> {
+        let body = match self {
+            Self::Json => serde_json::to_string_pretty(snapshot).map_err(|err| err.to_string()),
+            Self::Yaml => serde_yaml::to_string(snapshot).map_err(|err| err.to_string()),
+        };
+
+        body.map_err(SmartHouseError::OtherError)
+    }
+
+    pub fn deserialize(&self, body: &[u8]) -> Result<HouseSnapshot, SmartHouseError> {
+        let snapshot = match self {
+            Self::Json => serde_json::from_slice(body).map_err(|err| err.to_string()),
+            Self::Yaml => serde_yaml::from_slice(body).map_err(|err| err.to_string()),
+        };
+
+        snapshot.map_err(|err| {
+            SmartHouseError::ValidationError(format!("некорректная конфигурация дома: {err}"))
+        })
+    }
+}
+
+/// Режим импорта конфигурации
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Добавляет недостающие комнаты и устройства, существующие устройства не изменяются,
+    /// а их отличия от конфигурации возвращаются как конфликты
+    #[default]
+    Merge,
+    /// Приводит дом в точное соответствие с конфигурацией: лишние комнаты и устройства
+    /// удаляются, устройства с другими параметрами пересоздаются (их история теряется)
+    Replace,
+}
+
+/// Параметры экспорта конфигурации дома
+#[derive(Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct ExportQuery {
+    /// Дом, по умолчанию основной дом приложения
+    pub house: Option<String>,
+    /// Формат ответа, по умолчанию по заголовку `Accept` или JSON
+    pub format: Option<SnapshotFormat>,
+}
+
+/// Параметры импорта конфигурации дома
+#[derive(Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct ImportQuery {
+    pub mode: Option<ImportMode>,
+    /// Только проверить конфигурацию и вернуть план изменений
+    pub dry_run: Option<bool>,
+}
+
+/// Комната (без `device`) или устройство, затронутые импортом
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ImportChange {
+    pub room: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+/// Отличие конфигурации от текущего состояния дома, которое не было применено
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ImportConflict {
+    /// Комната, `None` для параметров самого дома
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    pub reason: String,
+}
+
+/// Результат (или план при `dry_run`) импорта конфигурации дома
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    pub house: String,
+    pub mode: ImportMode,
+    pub dry_run: bool,
+    /// Дом создан импортом
+    pub house_created: bool,
+    pub created: Vec<ImportChange>,
+    /// Устройства, пересозданные или с обновлёнными параметрами
+    pub updated: Vec<ImportChange>,
+    pub removed: Vec<ImportChange>,
+    pub conflicts: Vec<ImportConflict>,
+}
+
+/// Шаг применения импорта, выполняются по порядку
+pub(crate) enum ImportAction {
+    AddHouse {
+        address: String,
+    },
+    AddRoom {
+        room: String,
+    },
+    RemoveRoom {
+        room: String,
+    },
+    AddDevice {
+        room: String,
+        device: String,
+        meta: SmartDeviceMeta,
+    },
+    RemoveDevice {
+        room: String,
+        device: String,
+    },
+    UpdateDeviceInfo {
+        room: String,
+        device: String,
+        update: SmartDeviceInfoUpdate,
+    },
+}
+
+/// Импорт, прерванный ошибкой хранилища: уже применённая часть отчёта
+/// и изменение, на котором импорт остановлен
+#[derive(Debug)]
+pub struct ImportFailure {
+    pub applied: ImportReport,
+    /// Комната или устройство, `None` при ошибке создания самого дома
+    pub failed: Option<ImportChange>,
+    pub error: SmartHouseError,
+}
+
+impl ImportAction {
+    /// Комната или устройство, которые изменяет шаг, `None` для самого дома
+    pub(crate) fn change(&self) -> Option<ImportChange> {
+        match self {
+            Self::AddHouse { .. } => None,
+            Self::AddRoom { room } | Self::RemoveRoom { room } => Some(ImportChange {
+                room: room.clone(),
+                device: None,
+            }),
+            Self::AddDevice { room, device, .. }
+            | Self::RemoveDevice { room, device }
+            | Self::UpdateDeviceInfo { room, device, .. } => Some(device_change(room, device)),
+        }
+    }
+}
+
+impl ImportReport {
+    /// Часть отчёта, применённая до невыполненных шагов `pending`:
+    /// изменения, для которых не осталось ни одного шага
+    pub(crate) fn applied(&self, pending: &[ImportAction]) -> Self {
+        let house_pending = pending
+            .iter()
+            .any(|action| matches!(action, ImportAction::AddHouse { .. }));
+        let pending: Vec<ImportChange> = pending.iter().filter_map(ImportAction::change).collect();
+        let applied = |changes: &[ImportChange]| {
+            changes
+                .iter()
+                .filter(|change| !pending.contains(change))
+                .cloned()
+                .collect()
+        };
+
+        Self {
+            house: self.house.clone(),
+            mode: self.mode,
+            dry_run: self.dry_run,
+            house_created: self.house_created && !house_pending,
+            created: applied(&self.created),
+            updated: applied(&self.updated),
+            removed: applied(&self.removed),
+            conflicts: self.conflicts.clone(),
+        }
+    }
+}
+
+pub(crate) struct ImportPlan {
+    pub(crate) actions: Vec<ImportAction>,
+    pub(crate) report: ImportReport,
+}
+
+impl HouseSnapshot {
+    /// Конфигурация дома по его комнатам и устройствам из хранилища
+    pub(crate) fn new(rooms: Vec<String>, house_devices: HouseDevices) -> Self {
+        let mut snapshot_rooms: BTreeMap<String, Vec<DeviceSnapshot>> =
+            rooms.into_iter().map(|room| (room, Vec::new())).collect();
+        for device in house_devices.devices {
+            snapshot_rooms
+                .entry(device.room)
+                .or_default()
+                .push(DeviceSnapshot {
+                    name: device.record.name.clone(),
+                    meta: record_meta(&device.record),
+                    info: device.info.as_ref().map(info_update),
+                });
+        }
+
+        Self {
+            name: house_devices.house.name,
+            address: house_devices.house.address,
+            rooms: snapshot_rooms
+                .into_iter()
+                .map(|(name, devices)| RoomSnapshot { name, devices })
+                .collect(),
+        }
+    }
+
+    /// Проверяет имена и параметры, возвращает конфигурацию с нормализованными статусами
+    pub fn validate(&self) -> Result<Self, SmartHouseError> {
+        if self.name.trim().is_empty() {
+            return Err(SmartHouseError::ValidationError(
+                "название дома не может быть пустым".to_string(),
+            ));
+        }
+
+        let mut room_names = HashSet::new();
+        let mut rooms = Vec::with_capacity(self.rooms.len());
+        for room in &self.rooms {
+            if room.name.trim().is_empty() {
+                return Err(SmartHouseError::ValidationError(
+                    "название комнаты не может быть пустым".to_string(),
+                ));
+            }
+            if !room_names.insert(room.name.as_str()) {
+                return Err(SmartHouseError::ValidationError(format!(
+                    "комната '{}' указана несколько раз",
+                    room.name
+                )));
+            }
+
+            let mut device_names = HashSet::new();
+            let mut devices = Vec::with_capacity(room.devices.len());
+            for device in &room.devices {
+                if device.name.trim().is_empty() {
+                    return Err(SmartHouseError::ValidationError(format!(
+                        "название устройства в комнате '{}' не может быть пустым",
+                        room.name
+                    )));
+                }
+                if !device_names.insert(device.name.as_str()) {
+                    return Err(SmartHouseError::ValidationError(format!(
+                        "устройство '{}' указано в комнате '{}' несколько раз",
+                        device.name, room.name
+                    )));
+                }
+                device.meta.validate()?;
+
+                devices.push(DeviceSnapshot {
+                    name: device.name.clone(),
+                    meta: device.meta.clone(),
+                    info: device
+                        .info
+                        .as_ref()
+                        .map(|info| info.validate())
+                        .transpose()?,
+                });
+            }
+
+            rooms.push(RoomSnapshot {
+                name: room.name.clone(),
+                devices,
+            });
+        }
+
+        Ok(Self {
+            name: self.name.clone(),
+            address: self.address.clone(),
+            rooms,
+        })
+    }
+
+    /// План импорта проверенной конфигурации поверх текущего состояния дома
+    /// (`None`, если дома ещё нет)
+    pub(crate) fn plan(
+        &self,
+        current: Option<&HouseSnapshot>,
+        mode: ImportMode,
+        dry_run: bool,
+    ) -> ImportPlan {
+        let mut plan = ImportPlan {
+            actions: Vec::new(),
+            report: ImportReport {
+                house: self.name.clone(),
+                mode,
+                dry_run,
+                house_created: current.is_none(),
+                created: Vec::new(),
+                updated: Vec::new(),
+                removed: Vec::new(),
+                conflicts: Vec::new(),
+            },
+        };
+
+        let current = match current {
+            Some(current) => current,
+            None => {
+                plan.actions.push(ImportAction::AddHouse {
+                    address: self.address.clone(),
+                });
+                for room in &self.rooms {
+                    plan.add_room(room);
+                }
+                return plan;
+            }
+        };
+
+        if current.address != self.address {
+            plan.report.conflicts.push(ImportConflict {
+                room: None,
+                device: None,
+                reason: format!(
+                    "адрес дома '{}' отличается от '{}' и не изменяется при импорте",
+                    current.address, self.address
+                ),
+            });
+        }
+
+        for room in &self.rooms {
+            let current_room = match current.room(&room.name) {
+                Some(current_room) => current_room,
+                None => {
+                    plan.add_room(room);
+                    continue;
+                }
+            };
+
+            for device in &room.devices {
+                match current_room.device(&device.name) {
+                    Some(current_device) => plan.update_device(&room.name, device, current_device),
+                    None => plan.add_device(&room.name, device),
+                }
+            }
+        }
+
+        // лишнее удаляется после добавлений, чтобы прерванный импорт не оставил дом пустым
+        if mode == ImportMode::Replace {
+            for current_room in &current.rooms {
+                match self.room(&current_room.name) {
+                    Some(room) => {
+                        for device in &current_room.devices {
+                            if room.device(&device.name).is_none() {
+                                plan.remove_device(&room.name, &device.name);
+                            }
+                        }
+                    }
+                    None => plan.remove_room(&current_room.name),
+                }
+            }
+        }
+
+        plan
+    }
+
+    fn room(&self, name: &str) -> Option<&RoomSnapshot> {
+        self.rooms.iter().find(|room| room.name == name)
+    }
+}
+
+impl RoomSnapshot {
+    fn device(&self, name: &str) -> Option<&DeviceSnapshot> {
+        self.devices.iter().find(|device| device.name == name)
+    }
+}
+
+impl ImportPlan {
+    fn add_room(&mut self, room: &RoomSnapshot) {
+        self.actions.push(ImportAction::AddRoom {
+            room: room.name.clone(),
+        });
+        self.report.created.push(ImportChange {
+            room: room.name.clone(),
+            device: None,
+        });
+
+        for device in &room.devices {
+            self.add_device(&room.name, device);
+        }
+    }
+
+    fn remove_room(&mut self, room: &str) {
+        self.actions.push(ImportAction::RemoveRoom {
+            room: room.to_string(),
+        });
+        self.report.removed.push(ImportChange {
+            room: room.to_string(),
+            device: None,
+        });
+    }
+
+    fn add_device(&mut self, room: &str, device: &DeviceSnapshot) {
+        self.push_device(room, device);
+        self.report.created.push(device_change(room, &device.name));
+    }
+
+    fn remove_device(&mut self, room: &str, device: &str) {
+        self.actions.push(ImportAction::RemoveDevice {
+            room: room.to_string(),
+            device: device.to_string(),
+        });
+        self.report.removed.push(device_change(room, device));
+    }
+
+    fn update_device(&mut self, room: &str, device: &DeviceSnapshot, current: &DeviceSnapshot) {
+        let meta_changed = !same_meta(&device.meta, &current.meta);
+        let info_changed = device
+            .info
+            .as_ref()
+            .is_some_and(|info| !same_info(info, current.info.as_ref()));
+        if !meta_changed && !info_changed {
+            return;
+        }
+
+        match self.report.mode {
+            ImportMode::Merge => {
+                let reason = if meta_changed {
+                    "параметры устройства отличаются от сохранённых"
+                } else {
+                    "состояние устройства отличается от сохранённого"
+                };
+                self.report.conflicts.push(ImportConflict {
+                    room: Some(room.to_string()),
+                    device: Some(device.name.clone()),
+                    reason: reason.to_string(),
+                });
+            }
+            ImportMode::Replace if meta_changed => {
+                self.actions.push(ImportAction::RemoveDevice {
+                    room: room.to_string(),
+                    device: device.name.clone(),
+                });
+                self.push_device(room, device);
+                self.report.updated.push(device_change(room, &device.name));
+            }
+            ImportMode::Replace => {
+                if let Some(update) = &device.info {
+                    self.actions.push(ImportAction::UpdateDeviceInfo {
+                        room: room.to_string(),
+                        device: device.name.clone(),
+                        update: update.clone(),
+                    });
+                }
+                self.report.updated.push(device_change(room, &device.name));
+            }
+        }
+    }
+
+    fn push_device(&mut self, room: &str, device: &DeviceSnapshot) {
+        self.actions.push(ImportAction::AddDevice {
+            room: room.to_string(),
+            device: device.name.clone(),
+            meta: device.meta.clone(),
+        });
+        if let Some(update) = &device.info {
+            self.actions.push(ImportAction::UpdateDeviceInfo {
+                room: room.to_string(),
+                device: device.name.clone(),
+                update: update.clone(),
+            });
+        }
+    }
+}
+
+fn device_change(room: &str, device: &str) -> ImportChange {
+    ImportChange {
+        room: room.to_string(),
+        device: Some(device.to_string()),
+    }
+}
+
+fn record_meta(record: &SmartDeviceRecord) -> SmartDeviceMeta {
+    SmartDeviceMeta {
+        kind: record.kind,
+        address: record.address.clone(),
+        manufacturer: record.manufacturer.clone(),
+        tags: record.tags.clone(),
+    }
+}
+
+fn info_update(info: &SmartDeviceInfo) -> SmartDeviceInfoUpdate {
+    SmartDeviceInfoUpdate {
+        status: Some(info.status.clone()),
+        power: Some(info.power),
+        temp: Some(info.temp),
+    }
+}
+
+fn same_meta(a: &SmartDeviceMeta, b: &SmartDeviceMeta) -> bool {
+    a.kind == b.kind
+        && a.address == b.address
+        && a.manufacturer == b.manufacturer
+        && a.tags == b.tags
+}
+
+/// Заданные в `update` поля совпадают с текущими параметрами устройства
+fn same_info(update: &SmartDeviceInfoUpdate, current: Option<&SmartDeviceInfoUpdate>) -> bool {
+    let current = match current {
+        Some(current) => current,
+        None => return update.is_empty(),
+    };
+
+    (update.status.is_none() || update.status == current.status)
+        && (update.power.is_none() || update.power == current.power)
+        && (update.temp.is_none() || update.temp == current.temp)
+}
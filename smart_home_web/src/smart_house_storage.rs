@@ -1,31 +1,132 @@
-use crate::prelude::SmartHouseError;
+use crate::prelude::{
+    DeviceQuery, RoomQuery, SmartDeviceMeta, SmartDeviceRecord, SmartHouseError, SmartHouseRecord,
+};
 use crate::smart_house_storage::prelude::*;
 use async_trait::async_trait;
 
 pub mod prelude {
     pub use crate::smart_house_storage::SmartHouseStorage;
+    pub use crate::smart_house_storage_audit::AuditLogStorage;
+    pub use crate::smart_house_storage_history::DeviceHistoryStorage;
+    pub use crate::smart_house_storage_idempotency::IdempotencyStorage;
     pub use crate::smart_house_storage_memory::SmartHouseStorageMemory;
-    pub use crate::smart_house_storage_mock::MockDeviceInfoProvider;
+    pub use crate::smart_house_storage_mock::{HouseDevices, MockDeviceInfoProvider, RoomDevice};
     pub use crate::smart_house_storage_mongodb::SmartHouseStorageMongoDB;
+    pub use crate::smart_house_storage_users::ApiUserStorage;
 }
 
 #[async_trait]
 pub trait SmartHouseStorage {
-    async fn rooms(&self) -> Result<Vec<String>, SmartHouseError>;
+    /// Проверяет доступность хранилища
+    async fn ping(&self) -> Result<(), SmartHouseError>;
 
-    async fn add_room(&self, room: &str) -> Result<(), SmartHouseError>;
+    async fn houses(&self) -> Result<Vec<SmartHouseRecord>, SmartHouseError>;
 
-    async fn remove_room(&self, room: &str) -> Result<(), SmartHouseError>;
+    async fn house(&self, house: &str) -> Result<SmartHouseRecord, SmartHouseError>;
 
-    async fn devices(&self, room: &str) -> Result<Vec<String>, SmartHouseError>;
+    async fn add_house(&self, house: &str, address: &str) -> Result<(), SmartHouseError>;
 
-    async fn add_device(&self, room: &str, device: &str) -> Result<(), SmartHouseError>;
+    /// Удаляет дом вместе со всеми комнатами, устройствами и их историей.
+    /// Если задана `version`, дом удаляется только при совпадении его версии.
+    async fn remove_house(&self, house: &str, version: Option<u64>) -> Result<(), SmartHouseError>;
 
-    async fn remove_device(&self, room: &str, device: &str) -> Result<(), SmartHouseError>;
+    async fn rooms(&self, house: &str) -> Result<Vec<String>, SmartHouseError>;
+
+    /// Комнаты дома, подходящие под фильтры запроса, отсортированные по имени,
+    /// не более `query.limit`
+    async fn find_rooms(
+        &self,
+        house: &str,
+        query: &RoomQuery,
+    ) -> Result<Vec<String>, SmartHouseError>;
+
+    /// Добавляет комнату, при заданной `version` - только при совпадении версии дома
+    async fn add_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError>;
+
+    /// Удаляет комнату, при заданной `version` - только при совпадении версии комнаты
+    async fn remove_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError>;
+
+    /// Версия комнаты, увеличивается при добавлении и удалении устройств
+    async fn room_version(&self, house: &str, room: &str) -> Result<u64, SmartHouseError>;
+
+    async fn devices(&self, house: &str, room: &str) -> Result<Vec<String>, SmartHouseError>;
+
+    async fn device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceRecord, SmartHouseError>;
+
+    async fn device_records(
+        &self,
+        house: &str,
+        room: &str,
+    ) -> Result<Vec<SmartDeviceRecord>, SmartHouseError>;
+
+    /// Устройства комнаты, подходящие под фильтры запроса, отсортированные по имени,
+    /// не более `query.limit`
+    async fn find_devices(
+        &self,
+        house: &str,
+        room: &str,
+        query: &DeviceQuery,
+    ) -> Result<Vec<SmartDeviceRecord>, SmartHouseError>;
+
+    /// Добавляет устройство, при заданной `version` - только при совпадении версии комнаты
+    async fn add_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        meta: &SmartDeviceMeta,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError>;
+
+    /// Удаляет устройство, при заданной `version` - только при совпадении версии устройства
+    async fn remove_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError>;
+}
+
+/// Проверяет ожидаемую версию ресурса из `If-Match`, `resource` описывает ресурс в ошибке
+pub(crate) fn check_version(
+    expected: Option<u64>,
+    current: u64,
+    resource: impl FnOnce() -> String,
+) -> Result<(), SmartHouseError> {
+    match expected {
+        Some(expected) if expected != current => Err(SmartHouseError::PreconditionFailedError(
+            format!("{}, текущая версия {current}", resource()),
+        )),
+        _ => Ok(()),
+    }
 }
 
 #[async_trait]
-pub trait SmartHouseDeviceStorage: SmartHouseStorage + MockDeviceInfoProvider {}
+pub trait SmartHouseDeviceStorage:
+    SmartHouseStorage
+    + MockDeviceInfoProvider
+    + DeviceHistoryStorage
+    + ApiUserStorage
+    + AuditLogStorage
+    + IdempotencyStorage
+{
+}
 
 #[async_trait]
 impl SmartHouseDeviceStorage for SmartHouseStorageMemory {}
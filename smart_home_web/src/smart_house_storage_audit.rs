@@ -0,0 +1,111 @@
+use crate::prelude::{
+    AuditQuery, AuditRecord, AuditResult, SmartHouseError, SmartHouseStorageMemory,
+    SmartHouseStorageMongoDB,
+};
+use async_trait::async_trait;
+use chrono::DateTime;
+use futures::stream::TryStreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+/// Журнал аудита изменений
+#[async_trait]
+pub trait AuditLogStorage {
+    async fn add_audit_record(&self, record: &AuditRecord) -> Result<(), SmartHouseError>;
+
+    /// Записи, подходящие под фильтры запроса, от новых к старым, не более `query.limit`
+    async fn audit_records(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>, SmartHouseError>;
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CollectionAuditRecord {
+    pub(crate) timestamp: i64,
+    pub(crate) actor: String,
+    pub(crate) operation: String,
+    pub(crate) target: String,
+    pub(crate) result: AuditResult,
+    pub(crate) error: Option<String>,
+}
+
+#[async_trait]
+impl AuditLogStorage for SmartHouseStorageMemory {
+    async fn add_audit_record(&self, record: &AuditRecord) -> Result<(), SmartHouseError> {
+        let mut audit = self.audit.lock().unwrap_or_else(|err| err.into_inner());
+
+        if audit.len() >= self.audit_capacity {
+            audit.pop_front();
+        }
+        audit.push_back(record.clone());
+
+        Ok(())
+    }
+
+    async fn audit_records(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>, SmartHouseError> {
+        let audit = self.audit.lock().unwrap_or_else(|err| err.into_inner());
+
+        let records = audit
+            .iter()
+            .rev()
+            .filter(|record| query.matches(record))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+
+        Ok(records)
+    }
+}
+
+#[async_trait]
+impl AuditLogStorage for SmartHouseStorageMongoDB {
+    async fn add_audit_record(&self, record: &AuditRecord) -> Result<(), SmartHouseError> {
+        self.collection_audit
+            .insert_one(CollectionAuditRecord {
+                timestamp: record.timestamp.timestamp_millis(),
+                actor: record.actor.clone(),
+                operation: record.operation.clone(),
+                target: record.target.clone(),
+                result: record.result,
+                error: record.error.clone(),
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn audit_records(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>, SmartHouseError> {
+        let mut filter = doc! {};
+        if let Some(since) = query.since {
+            filter.insert("timestamp", doc! {"$gte": since.timestamp_millis()});
+        }
+        if let Some(actor) = &query.actor {
+            filter.insert("actor", actor);
+        }
+
+        let mut find = self
+            .collection_audit
+            .find(filter)
+            .sort(doc! {"timestamp": -1});
+        if let Some(limit) = query.limit {
+            find = find.limit(limit as i64);
+        }
+
+        let records = find
+            .await?
+            .try_collect::<Vec<CollectionAuditRecord>>()
+            .await?
+            .into_iter()
+            .filter_map(|record| {
+                Some(AuditRecord {
+                    timestamp: DateTime::from_timestamp_millis(record.timestamp)?,
+                    actor: record.actor,
+                    operation: record.operation,
+                    target: record.target,
+                    result: record.result,
+                    error: record.error,
+                })
+            })
+            .collect();
+
+        Ok(records)
+    }
+}
@@ -0,0 +1,148 @@
+use crate::prelude::{
+    DeviceReading, SmartHouseError, SmartHouseStorageMemory, SmartHouseStorageMongoDB,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[async_trait]
+pub trait DeviceHistoryStorage {
+    async fn add_reading(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        reading: &DeviceReading,
+    ) -> Result<(), SmartHouseError>;
+
+    /// Показания за период [from, to), упорядоченные по времени
+    async fn readings(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DeviceReading>, SmartHouseError>;
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CollectionReading {
+    pub(crate) house_name: String,
+    pub(crate) room_name: String,
+    pub(crate) device_name: String,
+    pub(crate) timestamp: i64,
+    pub(crate) status: String,
+    pub(crate) power: f32,
+    pub(crate) temp: f32,
+}
+
+#[async_trait]
+impl DeviceHistoryStorage for SmartHouseStorageMemory {
+    async fn add_reading(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        reading: &DeviceReading,
+    ) -> Result<(), SmartHouseError> {
+        let mut readings = self
+            .history
+            .entry(Self::device_key(house, room, device))
+            .or_insert_with(|| VecDeque::with_capacity(self.history_capacity));
+
+        if readings.len() >= self.history_capacity {
+            readings.pop_front();
+        }
+
+        // показания могут прийти не по порядку, буфер остаётся отсортированным
+        let index = readings.partition_point(|r| r.timestamp <= reading.timestamp);
+        readings.insert(index, reading.clone());
+
+        Ok(())
+    }
+
+    async fn readings(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DeviceReading>, SmartHouseError> {
+        let readings = match self.history.get(&Self::device_key(house, room, device)) {
+            Some(readings) => readings
+                .iter()
+                .filter(|reading| reading.timestamp >= from && reading.timestamp < to)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(readings)
+    }
+}
+
+#[async_trait]
+impl DeviceHistoryStorage for SmartHouseStorageMongoDB {
+    async fn add_reading(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        reading: &DeviceReading,
+    ) -> Result<(), SmartHouseError> {
+        self.collection_history
+            .insert_one(CollectionReading {
+                house_name: house.to_string(),
+                room_name: room.to_string(),
+                device_name: device.to_string(),
+                timestamp: reading.timestamp.timestamp_millis(),
+                status: reading.status.clone(),
+                power: reading.power,
+                temp: reading.temp,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn readings(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DeviceReading>, SmartHouseError> {
+        let cursor = self
+            .collection_history
+            .find(doc! {
+                "house_name": house,
+                "room_name": room,
+                "device_name": device,
+                "timestamp": {"$gte": from.timestamp_millis(), "$lt": to.timestamp_millis()},
+            })
+            .sort(doc! {"timestamp": 1})
+            .await?;
+
+        let readings = cursor
+            .try_collect::<Vec<CollectionReading>>()
+            .await?
+            .into_iter()
+            .filter_map(|reading| {
+                Some(DeviceReading {
+                    timestamp: DateTime::from_timestamp_millis(reading.timestamp)?,
+                    status: reading.status,
+                    power: reading.power,
+                    temp: reading.temp,
+                })
+            })
+            .collect();
+
+        Ok(readings)
+    }
+}
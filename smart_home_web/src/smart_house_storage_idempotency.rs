@@ -0,0 +1,202 @@
+use crate::prelude::{
+    IdempotencyRecord, IdempotentResponse, SmartHouseError, SmartHouseStorageMemory,
+    SmartHouseStorageMongoDB,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::mapref::entry::Entry;
+use mongodb::bson::spec::BinarySubtype;
+use mongodb::bson::{doc, to_bson, Binary, DateTime as BsonDateTime};
+use mongodb::error::{ErrorKind, WriteError, WriteFailure};
+use serde::{Deserialize, Serialize};
+
+/// При большем числе ключей из памяти удаляются истёкшие
+const MAX_IDEMPOTENCY_KEYS: usize = 10000;
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+/// Ответы на запросы с ключом идемпотентности
+#[async_trait]
+pub trait IdempotencyStorage {
+    /// Сохраняет запрос как выполняющийся, если ключа нет, он истёк или истекла
+    /// блокировка ключа прерванным запросом, иначе возвращает уже сохранённый запрос
+    async fn start_idempotent_request(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, SmartHouseError>;
+
+    /// Сохраняет ответ на запрос и продлевает хранение ключа до `expires_at`
+    async fn complete_idempotent_request(
+        &self,
+        key: &str,
+        response: &IdempotentResponse,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), SmartHouseError>;
+
+    /// Забывает ключ, чтобы запрос можно было повторить
+    async fn remove_idempotent_request(&self, key: &str) -> Result<(), SmartHouseError>;
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CollectionIdempotencyRecord {
+    #[serde(rename = "_id")]
+    pub(crate) key: String,
+    pub(crate) fingerprint: String,
+    /// Дата BSON для TTL-индекса
+    pub(crate) expires_at: BsonDateTime,
+    pub(crate) response: Option<CollectionIdempotentResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CollectionIdempotentResponse {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Binary,
+}
+
+impl From<&IdempotentResponse> for CollectionIdempotentResponse {
+    fn from(response: &IdempotentResponse) -> Self {
+        Self {
+            status: response.status,
+            headers: response.headers.clone(),
+            body: Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: response.body.clone(),
+            },
+        }
+    }
+}
+
+impl CollectionIdempotencyRecord {
+    fn record(self) -> IdempotencyRecord {
+        IdempotencyRecord {
+            key: self.key,
+            fingerprint: self.fingerprint,
+            expires_at: DateTime::from_timestamp_millis(self.expires_at.timestamp_millis())
+                .unwrap_or_default(),
+            response: self.response.map(|response| IdempotentResponse {
+                status: response.status,
+                headers: response.headers,
+                body: response.body.bytes,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl IdempotencyStorage for SmartHouseStorageMemory {
+    async fn start_idempotent_request(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, SmartHouseError> {
+        let now = Utc::now();
+        if self.idempotency.len() > MAX_IDEMPOTENCY_KEYS {
+            self.idempotency.retain(|_, record| !record.is_expired(now));
+        }
+
+        match self.idempotency.entry(record.key.clone()) {
+            Entry::Occupied(existing) if !existing.get().is_expired(now) => {
+                Ok(Some(existing.get().clone()))
+            }
+            Entry::Occupied(mut expired) => {
+                expired.insert(record.clone());
+                Ok(None)
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(record.clone());
+                Ok(None)
+            }
+        }
+    }
+
+    async fn complete_idempotent_request(
+        &self,
+        key: &str,
+        response: &IdempotentResponse,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), SmartHouseError> {
+        if let Some(mut record) = self.idempotency.get_mut(key) {
+            record.response = Some(response.clone());
+            record.expires_at = expires_at;
+        }
+
+        Ok(())
+    }
+
+    async fn remove_idempotent_request(&self, key: &str) -> Result<(), SmartHouseError> {
+        self.idempotency.remove(key);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IdempotencyStorage for SmartHouseStorageMongoDB {
+    async fn start_idempotent_request(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, SmartHouseError> {
+        let now = BsonDateTime::now();
+        // TTL-индекс удаляет истёкшие ключи не сразу
+        self.collection_idempotency
+            .delete_one(doc! {"_id": &record.key, "expires_at": {"$lte": now}})
+            .await?;
+
+        let inserted = self
+            .collection_idempotency
+            .insert_one(CollectionIdempotencyRecord {
+                key: record.key.clone(),
+                fingerprint: record.fingerprint.clone(),
+                expires_at: BsonDateTime::from_millis(record.expires_at.timestamp_millis()),
+                response: None,
+            })
+            .await;
+        match inserted {
+            Ok(_) => Ok(None),
+            Err(err) if is_duplicate_key(&err) => {
+                let existing = self
+                    .collection_idempotency
+                    .find_one(doc! {"_id": &record.key})
+                    .await?;
+                Ok(existing.map(CollectionIdempotencyRecord::record))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn complete_idempotent_request(
+        &self,
+        key: &str,
+        response: &IdempotentResponse,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), SmartHouseError> {
+        let response = to_bson(&CollectionIdempotentResponse::from(response))
+            .map_err(|err| SmartHouseError::OtherError(err.to_string()))?;
+        let expires_at = BsonDateTime::from_millis(expires_at.timestamp_millis());
+        self.collection_idempotency
+            .update_one(
+                doc! {"_id": key},
+                doc! {"$set": {"response": response, "expires_at": expires_at}},
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_idempotent_request(&self, key: &str) -> Result<(), SmartHouseError> {
+        self.collection_idempotency
+            .delete_one(doc! {"_id": key})
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(WriteError {
+            code: DUPLICATE_KEY_CODE,
+            ..
+        }))
+    )
+}
@@ -1,19 +1,118 @@
-use crate::http_handler::SmartDeviceInfo;
-use crate::prelude::{SmartHouseError, SmartHouseStorage};
+use crate::http_handler::{SmartDeviceInfo, INITIAL_VERSION};
+use crate::prelude::{
+    ApiUser, AuditRecord, DeviceQuery, DeviceReading, DeviceStatus, IdempotencyRecord, RoomQuery,
+    SmartDeviceMeta, SmartDeviceRecord, SmartHouseError, SmartHouseRecord, SmartHouseStorage,
+    SortOrder,
+};
+use crate::smart_house_storage::check_version;
 use async_trait::async_trait;
-use dashmap::{DashMap, DashSet};
-use std::collections::HashMap;
+use dashmap::mapref::one::{Ref, RefMut};
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+const HISTORY_CAPACITY: usize = 1024;
+const AUDIT_CAPACITY: usize = 10000;
+
+pub(crate) type RoomKey = (String, String);
+pub(crate) type DeviceKey = (String, String, String);
 
 pub struct SmartHouseStorageMemory {
-    pub(crate) devices: DashMap<String, DashSet<String>>,
-    pub(crate) devices_info: HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>>,
+    pub(crate) houses: DashMap<String, SmartHouseRecord>,
+    pub(crate) devices: DashMap<RoomKey, DashMap<String, SmartDeviceRecord>>,
+    /// Версии комнат, блокируются только после `devices`
+    pub(crate) room_versions: DashMap<RoomKey, u64>,
+    pub(crate) devices_info: DashMap<RoomKey, DashMap<String, SmartDeviceInfo>>,
+    pub(crate) history: DashMap<DeviceKey, VecDeque<DeviceReading>>,
+    pub(crate) history_capacity: usize,
+    pub(crate) users: DashMap<String, (ApiUser, String)>,
+    /// Журнал аудита от старых записей к новым, не более `audit_capacity`
+    pub(crate) audit: Mutex<VecDeque<AuditRecord>>,
+    pub(crate) audit_capacity: usize,
+    pub(crate) idempotency: DashMap<String, IdempotencyRecord>,
 }
 
 impl SmartHouseStorageMemory {
     pub fn new() -> Self {
         Self {
+            houses: DashMap::new(),
             devices: DashMap::new(),
-            devices_info: HashMap::new(),
+            room_versions: DashMap::new(),
+            devices_info: DashMap::new(),
+            history: DashMap::new(),
+            history_capacity: HISTORY_CAPACITY,
+            users: DashMap::new(),
+            audit: Mutex::new(VecDeque::new()),
+            audit_capacity: AUDIT_CAPACITY,
+            idempotency: DashMap::new(),
+        }
+    }
+
+    /// Количество хранимых показаний на каждое устройство
+    pub fn with_history_capacity(mut self, capacity: usize) -> Self {
+        self.history_capacity = capacity.max(1);
+        self
+    }
+
+    /// Количество хранимых записей журнала аудита, старые записи вытесняются новыми
+    pub fn with_audit_capacity(mut self, capacity: usize) -> Self {
+        self.audit_capacity = capacity.max(1);
+        self
+    }
+
+    pub(crate) fn room_key(house: &str, room: &str) -> RoomKey {
+        (house.to_string(), room.to_string())
+    }
+
+    pub(crate) fn device_key(house: &str, room: &str, device: &str) -> DeviceKey {
+        (house.to_string(), room.to_string(), device.to_string())
+    }
+
+    fn check_house(&self, house: &str) -> Result<(), SmartHouseError> {
+        match self.houses.contains_key(house) {
+            true => Ok(()),
+            false => Err(SmartHouseError::HouseNotFoundError(house.to_string())),
+        }
+    }
+
+    pub(crate) fn room_devices(
+        &self,
+        house: &str,
+        room: &str,
+    ) -> Result<Ref<'_, RoomKey, DashMap<String, SmartDeviceRecord>>, SmartHouseError> {
+        self.check_house(house)?;
+
+        match self.devices.get(&Self::room_key(house, room)) {
+            Some(devices) => Ok(devices),
+            None => Err(SmartHouseError::RoomNotFoundError(room.to_string())),
+        }
+    }
+
+    /// Увеличивает версию дома после изменения списка его комнат
+    fn bump_house_version(&self, house: &str) {
+        if let Some(mut record) = self.houses.get_mut(house) {
+            record.version += 1;
+        }
+    }
+
+    /// Увеличивает версию комнаты после изменения списка её устройств
+    fn bump_room_version(&self, house: &str, room: &str) {
+        *self
+            .room_versions
+            .entry(Self::room_key(house, room))
+            .or_insert(INITIAL_VERSION) += 1;
+    }
+
+    fn room_devices_mut(
+        &self,
+        house: &str,
+        room: &str,
+    ) -> Result<RefMut<'_, RoomKey, DashMap<String, SmartDeviceRecord>>, SmartHouseError> {
+        self.check_house(house)?;
+
+        match self.devices.get_mut(&Self::room_key(house, room)) {
+            Some(devices) => Ok(devices),
+            None => Err(SmartHouseError::RoomNotFoundError(room.to_string())),
         }
     }
 }
@@ -26,66 +125,268 @@ impl Default for SmartHouseStorageMemory {
 
 #[async_trait]
 impl SmartHouseStorage for SmartHouseStorageMemory {
-    async fn rooms(&self) -> Result<Vec<String>, SmartHouseError> {
-        let rooms = self.devices.iter().map(|s| s.key().clone()).collect();
+    async fn ping(&self) -> Result<(), SmartHouseError> {
+        Ok(())
+    }
+
+    async fn houses(&self) -> Result<Vec<SmartHouseRecord>, SmartHouseError> {
+        let houses = self.houses.iter().map(|s| s.value().clone()).collect();
+
+        Ok(houses)
+    }
+
+    async fn house(&self, house: &str) -> Result<SmartHouseRecord, SmartHouseError> {
+        match self.houses.get(house) {
+            Some(record) => Ok(record.clone()),
+            None => Err(SmartHouseError::HouseNotFoundError(house.to_string())),
+        }
+    }
+
+    async fn add_house(&self, house: &str, address: &str) -> Result<(), SmartHouseError> {
+        if self.houses.contains_key(house) {
+            return Err(SmartHouseError::HouseAlreadyExistsError(house.to_string()));
+        }
+
+        self.houses
+            .insert(house.to_string(), SmartHouseRecord::new(house, address));
+
+        Ok(())
+    }
+
+    async fn remove_house(&self, house: &str, version: Option<u64>) -> Result<(), SmartHouseError> {
+        let mut current = None;
+        let removed = self.houses.remove_if(house, |_, record| {
+            current = Some(record.version);
+            check_version(version, record.version, String::new).is_ok()
+        });
+        if removed.is_none() {
+            return match current {
+                Some(current) => check_version(version, current, || format!("дом '{house}'")),
+                None => Err(SmartHouseError::HouseNotFoundError(house.to_string())),
+            };
+        }
+
+        self.devices.retain(|(h, _), _| h != house);
+        self.room_versions.retain(|(h, _), _| h != house);
+        self.devices_info.retain(|(h, _), _| h != house);
+        self.history.retain(|(h, _, _), _| h != house);
+
+        Ok(())
+    }
+
+    async fn rooms(&self, house: &str) -> Result<Vec<String>, SmartHouseError> {
+        self.check_house(house)?;
+
+        let rooms = self
+            .devices
+            .iter()
+            .filter(|s| s.key().0 == house)
+            .map(|s| s.key().1.clone())
+            .collect();
+
+        Ok(rooms)
+    }
+
+    async fn find_rooms(
+        &self,
+        house: &str,
+        query: &RoomQuery,
+    ) -> Result<Vec<String>, SmartHouseError> {
+        let mut rooms: Vec<String> = self
+            .rooms(house)
+            .await?
+            .into_iter()
+            .filter(|room| query.matches(room))
+            .collect();
+
+        rooms.sort();
+        if query.order() == SortOrder::Desc {
+            rooms.reverse();
+        }
+        rooms.truncate(query.limit.unwrap_or(usize::MAX));
 
         Ok(rooms)
     }
 
-    async fn add_room(&self, room: &str) -> Result<(), SmartHouseError> {
-        if self.devices.contains_key(room) {
+    async fn add_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        match self.houses.get(house) {
+            Some(record) => check_version(version, record.version, || format!("дом '{house}'"))?,
+            None => return Err(SmartHouseError::HouseNotFoundError(house.to_string())),
+        }
+
+        let key = Self::room_key(house, room);
+        if self.devices.contains_key(&key) {
             return Err(SmartHouseError::RoomAlreadyExistsError(room.to_string()));
         }
 
-        self.devices.insert(room.to_string(), DashSet::new());
+        self.devices.insert(key.clone(), DashMap::new());
+        self.room_versions.insert(key, INITIAL_VERSION);
+        self.bump_house_version(house);
 
         Ok(())
     }
 
-    async fn remove_room(&self, room: &str) -> Result<(), SmartHouseError> {
-        match self.devices.contains_key(room) {
-            true => self.devices.remove(room),
-            false => return Err(SmartHouseError::RoomNotFoundError(room.to_string())),
-        };
+    async fn remove_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.check_house(house)?;
+
+        let key = Self::room_key(house, room);
+        let mut result = Err(SmartHouseError::RoomNotFoundError(room.to_string()));
+        self.devices.remove_if(&key, |key, _| {
+            let current = self.room_versions.get(key).map_or(INITIAL_VERSION, |v| *v);
+            result = check_version(version, current, || format!("комната '{room}'"));
+            result.is_ok()
+        });
+        result?;
+
+        self.room_versions.remove(&key);
+        self.devices_info.remove(&key);
+        self.history.retain(|(h, r, _), _| h != house || r != room);
+        self.bump_house_version(house);
 
         Ok(())
     }
 
-    async fn devices(&self, room: &str) -> Result<Vec<String>, SmartHouseError> {
-        match self.devices.get(room) {
-            Some(devices) => Ok(devices.iter().map(|s| s.to_string()).collect()),
-            None => Err(SmartHouseError::RoomNotFoundError(room.to_string())),
-        }
+    async fn room_version(&self, house: &str, room: &str) -> Result<u64, SmartHouseError> {
+        let key = Self::room_key(house, room);
+        let _devices = self.room_devices(house, room)?;
+        let version = self.room_versions.get(&key).map_or(INITIAL_VERSION, |v| *v);
+
+        Ok(version)
     }
 
-    async fn add_device(&self, room: &str, device: &str) -> Result<(), SmartHouseError> {
-        let device_room = match self.devices.get_mut(room) {
-            Some(device_room) => device_room,
-            None => return Err(SmartHouseError::RoomNotFoundError(room.to_string())),
+    async fn devices(&self, house: &str, room: &str) -> Result<Vec<String>, SmartHouseError> {
+        let devices = self.room_devices(house, room)?;
+
+        let devices = devices.iter().map(|s| s.key().to_string()).collect();
+
+        Ok(devices)
+    }
+
+    async fn device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceRecord, SmartHouseError> {
+        let device_room = self.room_devices(house, room)?;
+
+        let record = match device_room.get(device) {
+            Some(record) => record.clone(),
+            None => {
+                return Err(SmartHouseError::DeviceNotFoundError(
+                    room.to_string(),
+                    device.to_string(),
+                ))
+            }
         };
 
-        if !device_room.insert(device.to_string()) {
+        Ok(record)
+    }
+
+    async fn device_records(
+        &self,
+        house: &str,
+        room: &str,
+    ) -> Result<Vec<SmartDeviceRecord>, SmartHouseError> {
+        let devices = self.room_devices(house, room)?;
+
+        let records = devices.iter().map(|s| s.value().clone()).collect();
+
+        Ok(records)
+    }
+
+    async fn find_devices(
+        &self,
+        house: &str,
+        room: &str,
+        query: &DeviceQuery,
+    ) -> Result<Vec<SmartDeviceRecord>, SmartHouseError> {
+        let devices = self.room_devices(house, room)?;
+        let devices_info = self.devices_info.get(&Self::room_key(house, room));
+        let unknown = DeviceStatus::Unknown.to_string();
+
+        let mut records: Vec<SmartDeviceRecord> = devices
+            .iter()
+            .filter(|s| {
+                let info = devices_info.as_ref().and_then(|info| info.get(s.key()));
+                let status = info.as_ref().map_or(unknown.as_str(), |info| info.status());
+                query.matches(s.value(), status)
+            })
+            .map(|s| s.value().clone())
+            .collect();
+
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        if query.order() == SortOrder::Desc {
+            records.reverse();
+        }
+        records.truncate(query.limit.unwrap_or(usize::MAX));
+
+        Ok(records)
+    }
+
+    async fn add_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        meta: &SmartDeviceMeta,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        let device_room = self.room_devices_mut(house, room)?;
+        let current = self
+            .room_versions
+            .get(&Self::room_key(house, room))
+            .map_or(INITIAL_VERSION, |v| *v);
+        check_version(version, current, || format!("комната '{room}'"))?;
+
+        if device_room.contains_key(device) {
             return Err(SmartHouseError::DeviceAlreadyExistsError(
                 room.to_string(),
                 device.to_string(),
             ));
         }
 
+        device_room.insert(device.to_string(), SmartDeviceRecord::new(device, meta));
+        self.bump_room_version(house, room);
+
         Ok(())
     }
 
-    async fn remove_device(&self, room: &str, device: &str) -> Result<(), SmartHouseError> {
-        let device_room = match self.devices.get_mut(room) {
-            Some(device_room) => device_room,
-            None => return Err(SmartHouseError::RoomNotFoundError(room.to_string())),
-        };
+    async fn remove_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        let device_room = self.room_devices_mut(house, room)?;
 
-        if device_room.remove(device).is_none() {
-            return Err(SmartHouseError::DeviceNotFoundError(
-                room.to_string(),
-                device.to_string(),
-            ));
+        let mut result = Err(SmartHouseError::DeviceNotFoundError(
+            room.to_string(),
+            device.to_string(),
+        ));
+        device_room.remove_if(device, |_, record| {
+            result = check_version(version, record.version, || {
+                format!("устройство '{device}' в комнате '{room}'")
+            });
+            result.is_ok()
+        });
+        result?;
+        if let Some(info) = self.devices_info.get(&Self::room_key(house, room)) {
+            info.remove(device);
         }
+        self.history.remove(&Self::device_key(house, room, device));
+        self.bump_room_version(house, room);
 
         Ok(())
     }
@@ -1,44 +1,94 @@
+use crate::http_handler::INITIAL_VERSION;
 use crate::prelude::{
-    SmartDeviceInfo, SmartHouseError, SmartHouseStorage, SmartHouseStorageMemory,
+    DeviceStatus, SmartDeviceInfo, SmartDeviceInfoUpdate, SmartDeviceMeta, SmartDeviceRecord,
+    SmartHouseError, SmartHouseRecord, SmartHouseStorage, SmartHouseStorageMemory,
     SmartHouseStorageMongoDB,
 };
-use crate::smart_house_storage_mongodb::{CollectionDevice, CollectionRoom};
+use crate::smart_house_storage::check_version;
+use crate::smart_house_storage_mongodb::{with_version, CollectionDevice, CollectionRoom};
 use async_trait::async_trait;
-use dashmap::{DashMap, DashSet};
-use mongodb::bson::doc;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, to_bson};
+use mongodb::options::ReturnDocument;
+use serde::Deserialize;
 use std::collections::HashMap;
 
+/// Дом со всеми устройствами, полученный одним запросом к хранилищу
+pub struct HouseDevices {
+    pub house: SmartHouseRecord,
+    pub devices: Vec<RoomDevice>,
+}
+
+/// Устройство в комнате с сохранёнными параметрами, если они есть
+pub struct RoomDevice {
+    pub room: String,
+    pub record: SmartDeviceRecord,
+    pub info: Option<SmartDeviceInfo>,
+}
+
 #[async_trait]
 pub trait MockDeviceInfoProvider: SmartHouseStorage {
+    /// Создаёт дом (если его ещё нет) и заполняет его комнатами и устройствами
     async fn init(
         &mut self,
+        house: &str,
+        address: &str,
         devices_info: HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>>,
     ) -> Result<(), SmartHouseError>;
 
     async fn device_info(
         &self,
+        house: &str,
         room: &str,
         device: &str,
     ) -> Result<SmartDeviceInfo, SmartHouseError>;
+
+    async fn update_device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        update: &SmartDeviceInfoUpdate,
+        version: Option<u64>,
+    ) -> Result<(SmartDeviceInfo, u64), SmartHouseError>;
+
+    /// Дом, все его устройства и их параметры одним запросом, устройства упорядочены
+    /// по комнате и имени
+    async fn house_devices(&self, house: &str) -> Result<HouseDevices, SmartHouseError>;
 }
 
 #[async_trait]
 impl MockDeviceInfoProvider for SmartHouseStorageMemory {
     async fn init(
         &mut self,
+        house: &str,
+        address: &str,
         devices_info: HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>>,
     ) -> Result<(), SmartHouseError> {
-        self.devices_info = devices_info;
-
-        self.devices = DashMap::new();
-        for (room, devices) in self.devices_info.iter() {
-            self.devices.insert(room.to_string(), DashSet::new());
-            for device in devices.keys() {
-                self.devices
-                    .get_mut(*room)
-                    .unwrap()
-                    .insert(device.to_string());
+        match self.add_house(house, address).await {
+            Ok(()) => (),
+            Err(SmartHouseError::HouseAlreadyExistsError(_)) => {
+                self.devices.retain(|(h, _), _| h != house);
+                self.room_versions.retain(|(h, _), _| h != house);
+                self.devices_info.retain(|(h, _), _| h != house);
+            }
+            Err(err) => return Err(err),
+        }
+
+        for (room, devices) in devices_info {
+            let room_devices = DashMap::new();
+            let room_devices_info = DashMap::new();
+            for (device, device_info) in devices {
+                let meta = SmartDeviceMeta::new(device_info.kind);
+                room_devices.insert(device.to_string(), SmartDeviceRecord::new(device, &meta));
+                room_devices_info.insert(device.to_string(), device_info);
             }
+            self.devices
+                .insert(Self::room_key(house, room), room_devices);
+            self.devices_info
+                .insert(Self::room_key(house, room), room_devices_info);
         }
 
         Ok(())
@@ -46,22 +96,18 @@ impl MockDeviceInfoProvider for SmartHouseStorageMemory {
 
     async fn device_info(
         &self,
+        house: &str,
         room: &str,
         device: &str,
     ) -> Result<SmartDeviceInfo, SmartHouseError> {
-        if !match self.devices.get(room) {
-            Some(room) => room,
-            None => return Err(SmartHouseError::RoomNotFoundError(room.to_string())),
-        }
-        .contains(device)
-        {
+        if !self.room_devices(house, room)?.contains_key(device) {
             return Err(SmartHouseError::DeviceNotFoundError(
                 room.to_string(),
                 device.to_string(),
             ));
         };
 
-        let room_device = match self.devices_info.get(room) {
+        let room_device = match self.devices_info.get(&Self::room_key(house, room)) {
             Some(room_device) => room_device,
             None => {
                 return Err(SmartHouseError::DeviceInfoProviderError(
@@ -82,16 +128,128 @@ impl MockDeviceInfoProvider for SmartHouseStorageMemory {
 
         Ok(device_info.clone())
     }
+
+    async fn update_device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        update: &SmartDeviceInfoUpdate,
+        version: Option<u64>,
+    ) -> Result<(SmartDeviceInfo, u64), SmartHouseError> {
+        let room_devices = self.room_devices(house, room)?;
+        let mut record = match room_devices.get_mut(device) {
+            Some(record) => record,
+            None => {
+                return Err(SmartHouseError::DeviceNotFoundError(
+                    room.to_string(),
+                    device.to_string(),
+                ))
+            }
+        };
+        check_version(version, record.version, || {
+            format!("устройство '{device}' в комнате '{room}'")
+        })?;
+        record.version += 1;
+        record.updated_at = Utc::now();
+
+        let room_device = self
+            .devices_info
+            .entry(Self::room_key(house, room))
+            .or_default();
+        let mut device_info = room_device.entry(device.to_string()).or_insert_with(|| {
+            SmartDeviceInfo::new(
+                device.to_string(),
+                DeviceStatus::Unknown.to_string(),
+                0.0,
+                0.0,
+            )
+        });
+        update.apply(&mut device_info);
+
+        Ok((device_info.clone(), record.version))
+    }
+
+    async fn house_devices(&self, house: &str) -> Result<HouseDevices, SmartHouseError> {
+        let record = match self.houses.get(house) {
+            Some(record) => record.clone(),
+            None => return Err(SmartHouseError::HouseNotFoundError(house.to_string())),
+        };
+
+        let mut devices = Vec::new();
+        for room_devices in self.devices.iter().filter(|s| s.key().0 == house) {
+            let room = &room_devices.key().1;
+            let devices_info = self.devices_info.get(room_devices.key());
+            for device in room_devices.value().iter() {
+                devices.push(RoomDevice {
+                    room: room.clone(),
+                    record: device.value().clone(),
+                    info: devices_info
+                        .as_ref()
+                        .and_then(|info| info.get(device.key()).map(|info| info.clone())),
+                });
+            }
+        }
+        sort_room_devices(&mut devices);
+
+        Ok(HouseDevices {
+            house: record,
+            devices,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct CollectionHouseDevices {
+    name: String,
+    address: String,
+    created_at: DateTime<Utc>,
+    #[serde(default)]
+    version: u64,
+    devices: Vec<CollectionDevice>,
+}
+
+fn sort_room_devices(devices: &mut [RoomDevice]) {
+    devices.sort_by(|a, b| (&a.room, &a.record.name).cmp(&(&b.room, &b.record.name)));
 }
 
 #[async_trait]
 impl MockDeviceInfoProvider for SmartHouseStorageMongoDB {
     async fn init(
         &mut self,
+        house: &str,
+        address: &str,
         devices_info: HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>>,
     ) -> Result<(), SmartHouseError> {
-        if self.collection_rooms.count_documents(doc! {}).await? > 0
-            || self.collection_devices.count_documents(doc! {}).await? > 0
+        match self.add_house(house, address).await {
+            Ok(()) | Err(SmartHouseError::HouseAlreadyExistsError(_)) => (),
+            Err(err) => return Err(err),
+        }
+
+        // Комнаты, устройства и история, сохранённые до появления нескольких домов,
+        // относятся к дому по умолчанию
+        let legacy = doc! {"house_name": {"$exists": false}};
+        let backfill = doc! {"$set": {"house_name": house}};
+        self.collection_rooms
+            .update_many(legacy.clone(), backfill.clone())
+            .await?;
+        self.collection_devices
+            .update_many(legacy.clone(), backfill.clone())
+            .await?;
+        self.collection_history
+            .update_many(legacy, backfill)
+            .await?;
+
+        if self
+            .collection_rooms
+            .count_documents(doc! {"house_name": house})
+            .await?
+            > 0
+            || self
+                .collection_devices
+                .count_documents(doc! {"house_name": house})
+                .await?
+                > 0
         {
             return Ok(());
         }
@@ -99,7 +257,9 @@ impl MockDeviceInfoProvider for SmartHouseStorageMongoDB {
         let rooms: Vec<CollectionRoom> = devices_info
             .keys()
             .map(|room| CollectionRoom {
+                house_name: house.to_string(),
                 name: room.to_string(),
+                version: INITIAL_VERSION,
             })
             .collect();
         self.collection_rooms.insert_many(rooms).await?;
@@ -107,12 +267,10 @@ impl MockDeviceInfoProvider for SmartHouseStorageMongoDB {
         let devices: Vec<CollectionDevice> = devices_info
             .iter()
             .flat_map(|(room, devices)| {
-                devices
-                    .iter()
-                    .map(move |(_, device_info)| CollectionDevice {
-                        room_name: room.to_string(),
-                        device: device_info.clone(),
-                    })
+                devices.values().map(move |device_info| {
+                    let meta = SmartDeviceMeta::new(device_info.kind);
+                    CollectionDevice::new(house, room, device_info.clone(), &meta)
+                })
             })
             .collect();
         self.collection_devices.insert_many(devices).await?;
@@ -122,37 +280,141 @@ impl MockDeviceInfoProvider for SmartHouseStorageMongoDB {
 
     async fn device_info(
         &self,
+        house: &str,
         room: &str,
         device: &str,
     ) -> Result<SmartDeviceInfo, SmartHouseError> {
-        if self
-            .collection_rooms
-            .count_documents(doc! {"name": room})
+        self.check_room(house, room).await?;
+
+        let device_info = match self
+            .collection_devices
+            .find_one(doc! {"house_name": house, "room_name": room, "device.name": device})
             .await?
-            == 0
         {
-            return Err(SmartHouseError::RoomNotFoundError(room.to_string()));
+            Some(device) => device.device,
+            None => {
+                return Err(SmartHouseError::DeviceNotFoundError(
+                    room.to_string(),
+                    device.to_string(),
+                ))
+            }
+        };
+
+        Ok(device_info)
+    }
+
+    async fn update_device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        update: &SmartDeviceInfoUpdate,
+        version: Option<u64>,
+    ) -> Result<(SmartDeviceInfo, u64), SmartHouseError> {
+        self.check_room(house, room).await?;
+
+        let mut fields = doc! {};
+        if let Some(status) = &update.status {
+            fields.insert("device.status", status);
+        }
+        if let Some(power) = update.power {
+            fields.insert("device.power", power);
         }
+        if let Some(temp) = update.temp {
+            fields.insert("device.temp", temp);
+        }
+        let updated_at =
+            to_bson(&Utc::now()).map_err(|err| SmartHouseError::OtherError(err.to_string()))?;
+        fields.insert("updated_at", updated_at);
 
-        if self
+        let filter = doc! {"house_name": house, "room_name": room, "device.name": device};
+        let updated = match self
             .collection_devices
-            .count_documents(doc! {"room_name": room, "device.name": device})
+            .find_one_and_update(
+                with_version(filter.clone(), version),
+                doc! {"$set": fields, "$inc": {"version": 1_i64}},
+            )
+            .return_document(ReturnDocument::After)
             .await?
-            == 0
         {
-            return Err(SmartHouseError::DeviceNotFoundError(
-                room.to_string(),
-                device.to_string(),
-            ));
-        }
+            Some(updated) => updated,
+            None => {
+                // устройство есть, но не совпала версия
+                if let Some(record) = self.collection_devices.find_one(filter).await? {
+                    check_version(version, record.version, || {
+                        format!("устройство '{device}' в комнате '{room}'")
+                    })?;
+                }
+                return Err(SmartHouseError::DeviceNotFoundError(
+                    room.to_string(),
+                    device.to_string(),
+                ));
+            }
+        };
 
-        let device_info = self
-            .collection_devices
-            .find_one(doc! {"room_name": room, "device.name": device})
+        Ok((updated.device, updated.version))
+    }
+    async fn house_devices(&self, house: &str) -> Result<HouseDevices, SmartHouseError> {
+        let pipeline = [
+            doc! {"$match": {"name": house}},
+            // устройства берутся через комнаты дома: устройства, оставшиеся от комнат,
+            // удалённых прежними версиями без удаления устройств, не попадают в отчёт
+            doc! {"$lookup": {
+                "from": "rooms",
+                "let": {"house": "$name"},
+                "as": "rooms",
+                "pipeline": [
+                    {"$match": {"$expr": {"$eq": ["$house_name", "$$house"]}}},
+                    {"$lookup": {
+                        "from": "devices",
+                        "let": {"house": "$house_name", "room": "$name"},
+                        "pipeline": [{"$match": {"$expr": {"$and": [
+                            {"$eq": ["$house_name", "$$house"]},
+                            {"$eq": ["$room_name", "$$room"]},
+                        ]}}}],
+                        "as": "devices",
+                    }},
+                ],
+            }},
+            doc! {"$set": {"devices": {"$reduce": {
+                "input": "$rooms.devices",
+                "initialValue": [],
+                "in": {"$concatArrays": ["$$value", "$$this"]},
+            }}}},
+            doc! {"$unset": "rooms"},
+        ];
+
+        let house_devices = match self
+            .collection_houses
+            .aggregate(pipeline)
+            .with_type::<CollectionHouseDevices>()
             .await?
-            .unwrap()
-            .device;
+            .try_next()
+            .await?
+        {
+            Some(house_devices) => house_devices,
+            None => return Err(SmartHouseError::HouseNotFoundError(house.to_string())),
+        };
 
-        Ok(device_info)
+        let mut devices: Vec<RoomDevice> = house_devices
+            .devices
+            .into_iter()
+            .map(|device| RoomDevice {
+                room: device.room_name.clone(),
+                info: Some(device.device.clone()),
+                record: device.record(),
+            })
+            .collect();
+        sort_room_devices(&mut devices);
+
+        Ok(HouseDevices {
+            house: SmartHouseRecord {
+                name: house_devices.name,
+                address: house_devices.address,
+                created_at: house_devices.created_at,
+                version: house_devices.version,
+            },
+            devices,
+        })
     }
 }
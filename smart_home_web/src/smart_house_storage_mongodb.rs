@@ -1,25 +1,112 @@
-use crate::prelude::{DeviceStatus, SmartDeviceInfo, SmartHouseError, SmartHouseStorage};
+use crate::http_handler::INITIAL_VERSION;
+use crate::prelude::{
+    DeviceKind, DeviceQuery, DeviceStatus, RoomQuery, SmartDeviceInfo, SmartDeviceMeta,
+    SmartDeviceRecord, SmartHouseError, SmartHouseRecord, SmartHouseStorage, SortOrder,
+};
+use crate::smart_house_storage::check_version;
+use crate::smart_house_storage_audit::CollectionAuditRecord;
+use crate::smart_house_storage_history::CollectionReading;
+use crate::smart_house_storage_idempotency::CollectionIdempotencyRecord;
+use crate::smart_house_storage_users::CollectionUser;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use futures::stream::TryStreamExt;
-use mongodb::bson::doc;
-use mongodb::{Client, Collection};
+use log::warn;
+use mongodb::bson::{doc, to_bson, Bson, Document, Regex};
+use mongodb::options::IndexOptions;
+use mongodb::{Client, Collection, Database, IndexModel};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const MAX_CONNECT_DELAY: Duration = Duration::from_secs(30);
 
 pub struct SmartHouseStorageMongoDB {
+    db: Database,
+    pub(crate) collection_houses: Collection<CollectionHouse>,
     pub(crate) collection_rooms: Collection<CollectionRoom>,
     pub(crate) collection_devices: Collection<CollectionDevice>,
+    pub(crate) collection_history: Collection<CollectionReading>,
+    pub(crate) collection_users: Collection<CollectionUser>,
+    pub(crate) collection_audit: Collection<CollectionAuditRecord>,
+    pub(crate) collection_idempotency: Collection<CollectionIdempotencyRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CollectionHouse {
+    pub(crate) name: String,
+    pub(crate) address: String,
+    pub(crate) created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub(crate) version: u64,
+}
+
+impl CollectionHouse {
+    fn record(self) -> SmartHouseRecord {
+        SmartHouseRecord {
+            name: self.name,
+            address: self.address,
+            created_at: self.created_at,
+            version: self.version,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct CollectionRoom {
+    pub(crate) house_name: String,
     pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) version: u64,
 }
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct CollectionDevice {
+    pub(crate) house_name: String,
     pub(crate) room_name: String,
     pub(crate) device: SmartDeviceInfo,
+    #[serde(default)]
+    pub(crate) meta: SmartDeviceMeta,
+    #[serde(default)]
+    pub(crate) created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub(crate) updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub(crate) version: u64,
+}
+
+impl CollectionDevice {
+    pub(crate) fn new(
+        house: &str,
+        room: &str,
+        device: SmartDeviceInfo,
+        meta: &SmartDeviceMeta,
+    ) -> Self {
+        let now = Utc::now();
+
+        Self {
+            house_name: house.to_string(),
+            room_name: room.to_string(),
+            device: device.with_kind(meta.kind),
+            meta: meta.clone(),
+            created_at: now,
+            updated_at: now,
+            version: INITIAL_VERSION,
+        }
+    }
+
+    pub(crate) fn record(self) -> SmartDeviceRecord {
+        SmartDeviceRecord {
+            name: self.device.name,
+            kind: self.meta.kind,
+            address: self.meta.address,
+            manufacturer: self.meta.manufacturer,
+            tags: self.meta.tags,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            version: self.version,
+        }
+    }
 }
 
 impl SmartHouseStorageMongoDB {
@@ -35,16 +122,254 @@ impl SmartHouseStorageMongoDB {
         };
 
         Ok(Self {
+            collection_houses: db.collection("houses"),
             collection_rooms: db.collection("rooms"),
             collection_devices: db.collection("devices"),
+            collection_history: db.collection("history"),
+            collection_users: db.collection("users"),
+            collection_audit: db.collection("audit"),
+            collection_idempotency: db.collection("idempotency"),
+            db,
         })
     }
+
+    /// Подключается к MongoDB и проверяет соединение. При неудаче делает ещё попытки,
+    /// всего не более `attempts`, удваивая паузу между ними начиная с `delay`.
+    pub async fn connect(
+        uri: &str,
+        attempts: u32,
+        delay: Duration,
+    ) -> Result<Self, SmartHouseError> {
+        let mut delay = delay;
+        let mut attempt = 1;
+        loop {
+            let result = match Self::new(uri).await {
+                Ok(storage) => match storage.ping().await {
+                    Ok(()) => storage.create_indexes().await.map(|()| storage),
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(storage) => return Ok(storage),
+                Err(err) if attempt < attempts => {
+                    warn!(
+                        "MongoDB connection attempt {attempt}/{attempts} failed: {err}, \
+                         retrying in {delay:?}"
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_CONNECT_DELAY);
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// TTL-индекс удаляет истёкшие ключи идемпотентности
+    async fn create_indexes(&self) -> Result<(), SmartHouseError> {
+        self.collection_idempotency
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {"expires_at": 1})
+                    .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+                    .build(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn check_house(&self, house: &str) -> Result<(), SmartHouseError> {
+        if self
+            .collection_houses
+            .count_documents(doc! {"name": house})
+            .await?
+            == 0
+        {
+            return Err(SmartHouseError::HouseNotFoundError(house.to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn check_room(&self, house: &str, room: &str) -> Result<(), SmartHouseError> {
+        self.check_house(house).await?;
+
+        if self
+            .collection_rooms
+            .count_documents(doc! {"house_name": house, "name": room})
+            .await?
+            == 0
+        {
+            return Err(SmartHouseError::RoomNotFoundError(room.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Добавляет к фильтру ожидаемую версию документа, если она задана.
+/// У документов, созданных до появления версий, поля нет - их версия 0.
+pub(crate) fn with_version(mut filter: Document, version: Option<u64>) -> Document {
+    match version {
+        Some(0) => filter.insert("version", doc! {"$in": [0_i64, Bson::Null]}),
+        Some(version) => filter.insert("version", version as i64),
+        None => None,
+    };
+
+    filter
+}
+
+/// Увеличение версии документа для `update_one`
+pub(crate) fn inc_version() -> Document {
+    doc! {"$inc": {"version": 1_i64}}
+}
+
+/// Условия на имя `field` для `$and`: префикс, подстрока и положение после курсора
+fn name_conditions(
+    field: &str,
+    prefix: &Option<String>,
+    contains: &Option<String>,
+    cursor: &Option<String>,
+    order: SortOrder,
+) -> Vec<Document> {
+    let mut conditions = Vec::new();
+
+    if let Some(prefix) = prefix {
+        conditions.push(doc! {field: regex(&format!("^{}", escape_regex(prefix)))});
+    }
+    if let Some(contains) = contains {
+        conditions.push(doc! {field: regex(&escape_regex(contains))});
+    }
+    if let Some(cursor) = cursor {
+        let operator = match order {
+            SortOrder::Asc => "$gt",
+            SortOrder::Desc => "$lt",
+        };
+        conditions.push(doc! {field: {operator: cursor}});
+    }
+
+    conditions
+}
+
+fn regex(pattern: &str) -> Regex {
+    Regex {
+        pattern: pattern.to_string(),
+        options: String::new(),
+    }
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn sort_direction(order: SortOrder) -> i32 {
+    match order {
+        SortOrder::Asc => 1,
+        SortOrder::Desc => -1,
+    }
 }
 
 #[async_trait]
 impl SmartHouseStorage for SmartHouseStorageMongoDB {
-    async fn rooms(&self) -> Result<Vec<String>, SmartHouseError> {
-        let cursor = self.collection_rooms.find(doc! {}).await?;
+    async fn ping(&self) -> Result<(), SmartHouseError> {
+        self.db.run_command(doc! {"ping": 1}).await?;
+
+        Ok(())
+    }
+
+    async fn houses(&self) -> Result<Vec<SmartHouseRecord>, SmartHouseError> {
+        let cursor = self.collection_houses.find(doc! {}).await?;
+
+        let houses = cursor
+            .try_collect::<Vec<CollectionHouse>>()
+            .await?
+            .into_iter()
+            .map(CollectionHouse::record)
+            .collect();
+
+        Ok(houses)
+    }
+
+    async fn house(&self, house: &str) -> Result<SmartHouseRecord, SmartHouseError> {
+        match self
+            .collection_houses
+            .find_one(doc! {"name": house})
+            .await?
+        {
+            Some(house) => Ok(house.record()),
+            None => Err(SmartHouseError::HouseNotFoundError(house.to_string())),
+        }
+    }
+
+    async fn add_house(&self, house: &str, address: &str) -> Result<(), SmartHouseError> {
+        if self
+            .collection_houses
+            .count_documents(doc! {"name": house})
+            .await?
+            > 0
+        {
+            return Err(SmartHouseError::HouseAlreadyExistsError(house.to_string()));
+        }
+
+        self.collection_houses
+            .insert_one(CollectionHouse {
+                name: house.to_string(),
+                address: address.to_string(),
+                created_at: Utc::now(),
+                version: INITIAL_VERSION,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_house(&self, house: &str, version: Option<u64>) -> Result<(), SmartHouseError> {
+        let deleted = self
+            .collection_houses
+            .delete_one(with_version(doc! {"name": house}, version))
+            .await?;
+        if deleted.deleted_count == 0 {
+            return match self
+                .collection_houses
+                .find_one(doc! {"name": house})
+                .await?
+            {
+                Some(record) => check_version(version, record.version, || format!("дом '{house}'")),
+                None => Err(SmartHouseError::HouseNotFoundError(house.to_string())),
+            };
+        }
+
+        self.collection_history
+            .delete_many(doc! {"house_name": house})
+            .await?;
+        self.collection_devices
+            .delete_many(doc! {"house_name": house})
+            .await?;
+        self.collection_rooms
+            .delete_many(doc! {"house_name": house})
+            .await?;
+
+        Ok(())
+    }
+
+    async fn rooms(&self, house: &str) -> Result<Vec<String>, SmartHouseError> {
+        self.check_house(house).await?;
+
+        let cursor = self
+            .collection_rooms
+            .find(doc! {"house_name": house})
+            .await?;
 
         let rooms = cursor
             .try_collect::<Vec<CollectionRoom>>()
@@ -56,55 +381,147 @@ impl SmartHouseStorage for SmartHouseStorageMongoDB {
         Ok(rooms)
     }
 
-    async fn add_room(&self, room: &str) -> Result<(), SmartHouseError> {
+    async fn find_rooms(
+        &self,
+        house: &str,
+        query: &RoomQuery,
+    ) -> Result<Vec<String>, SmartHouseError> {
+        self.check_house(house).await?;
+
+        let mut filter = doc! {"house_name": house};
+        let conditions = name_conditions(
+            "name",
+            &query.prefix,
+            &query.contains,
+            &query.cursor,
+            query.order(),
+        );
+        if !conditions.is_empty() {
+            filter.insert("$and", conditions);
+        }
+
+        let mut find = self
+            .collection_rooms
+            .find(filter)
+            .sort(doc! {"name": sort_direction(query.order())});
+        if let Some(limit) = query.limit {
+            find = find.limit(limit as i64);
+        }
+
+        let rooms = find
+            .await?
+            .try_collect::<Vec<CollectionRoom>>()
+            .await?
+            .into_iter()
+            .map(|room| room.name)
+            .collect();
+
+        Ok(rooms)
+    }
+
+    async fn add_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.check_house(house).await?;
+
         if self
             .collection_rooms
-            .count_documents(doc! {"name": room})
+            .count_documents(doc! {"house_name": house, "name": room})
             .await?
             > 0
         {
             return Err(SmartHouseError::RoomAlreadyExistsError(room.to_string()));
         }
 
+        let updated = self
+            .collection_houses
+            .update_one(with_version(doc! {"name": house}, version), inc_version())
+            .await?;
+        if updated.matched_count == 0 {
+            // дом есть, но не совпала версия
+            if let Some(record) = self
+                .collection_houses
+                .find_one(doc! {"name": house})
+                .await?
+            {
+                check_version(version, record.version, || format!("дом '{house}'"))?;
+            }
+            return Err(SmartHouseError::HouseNotFoundError(house.to_string()));
+        }
         self.collection_rooms
             .insert_one(CollectionRoom {
+                house_name: house.to_string(),
                 name: room.to_string(),
+                version: INITIAL_VERSION,
             })
             .await?;
 
         Ok(())
     }
 
-    async fn remove_room(&self, room: &str) -> Result<(), SmartHouseError> {
-        if self
+    async fn remove_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.check_house(house).await?;
+
+        let deleted = self
             .collection_rooms
-            .count_documents(doc! {"name": room})
-            .await?
-            == 0
-        {
-            return Err(SmartHouseError::RoomNotFoundError(room.to_string()));
+            .delete_one(with_version(
+                doc! {"house_name": house, "name": room},
+                version,
+            ))
+            .await?;
+        if deleted.deleted_count == 0 {
+            return match self
+                .collection_rooms
+                .find_one(doc! {"house_name": house, "name": room})
+                .await?
+            {
+                Some(record) => {
+                    check_version(version, record.version, || format!("комната '{room}'"))
+                }
+                None => Err(SmartHouseError::RoomNotFoundError(room.to_string())),
+            };
         }
 
-        self.collection_rooms
-            .delete_one(doc! {"name": room})
+        self.collection_history
+            .delete_many(doc! {"house_name": house, "room_name": room})
+            .await?;
+        self.collection_devices
+            .delete_many(doc! {"house_name": house, "room_name": room})
+            .await?;
+        self.collection_houses
+            .update_one(doc! {"name": house}, inc_version())
             .await?;
 
         Ok(())
     }
 
-    async fn devices(&self, room: &str) -> Result<Vec<String>, SmartHouseError> {
-        if self
+    async fn room_version(&self, house: &str, room: &str) -> Result<u64, SmartHouseError> {
+        self.check_house(house).await?;
+
+        match self
             .collection_rooms
-            .count_documents(doc! {"name": room})
+            .find_one(doc! {"house_name": house, "name": room})
             .await?
-            == 0
         {
-            return Err(SmartHouseError::RoomNotFoundError(room.to_string()));
+            Some(record) => Ok(record.version),
+            None => Err(SmartHouseError::RoomNotFoundError(room.to_string())),
         }
+    }
+
+    async fn devices(&self, house: &str, room: &str) -> Result<Vec<String>, SmartHouseError> {
+        self.check_room(house, room).await?;
 
         let cursor = self
             .collection_devices
-            .find(doc! {"room_name": room})
+            .find(doc! {"house_name": house, "room_name": room})
             .await?;
 
         let devices = cursor
@@ -117,19 +534,113 @@ impl SmartHouseStorage for SmartHouseStorageMongoDB {
         Ok(devices)
     }
 
-    async fn add_device(&self, room: &str, device: &str) -> Result<(), SmartHouseError> {
-        if self
-            .collection_rooms
-            .count_documents(doc! {"name": room})
+    async fn device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceRecord, SmartHouseError> {
+        self.check_room(house, room).await?;
+
+        match self
+            .collection_devices
+            .find_one(doc! {"house_name": house, "room_name": room, "device.name": device})
             .await?
-            == 0
         {
-            return Err(SmartHouseError::RoomNotFoundError(room.to_string()));
+            Some(device) => Ok(device.record()),
+            None => Err(SmartHouseError::DeviceNotFoundError(
+                room.to_string(),
+                device.to_string(),
+            )),
         }
+    }
+
+    async fn device_records(
+        &self,
+        house: &str,
+        room: &str,
+    ) -> Result<Vec<SmartDeviceRecord>, SmartHouseError> {
+        self.check_room(house, room).await?;
+
+        let cursor = self
+            .collection_devices
+            .find(doc! {"house_name": house, "room_name": room})
+            .await?;
+
+        let records = cursor
+            .try_collect::<Vec<CollectionDevice>>()
+            .await?
+            .into_iter()
+            .map(|device| device.record())
+            .collect();
+
+        Ok(records)
+    }
+
+    async fn find_devices(
+        &self,
+        house: &str,
+        room: &str,
+        query: &DeviceQuery,
+    ) -> Result<Vec<SmartDeviceRecord>, SmartHouseError> {
+        self.check_room(house, room).await?;
+
+        let mut filter = doc! {"house_name": house, "room_name": room};
+        let conditions = name_conditions(
+            "device.name",
+            &query.prefix,
+            &query.contains,
+            &query.cursor,
+            query.order(),
+        );
+        if !conditions.is_empty() {
+            filter.insert("$and", conditions);
+        }
+        if let Some(kind) = query.kind {
+            let kind =
+                to_bson(&kind).map_err(|err| SmartHouseError::OtherError(err.to_string()))?;
+            // у устройств, добавленных до появления реестра, тип не записан
+            match query.kind == Some(DeviceKind::Unknown) {
+                true => filter.insert("meta.kind", doc! {"$in": [kind, Bson::Null]}),
+                false => filter.insert("meta.kind", kind),
+            };
+        }
+        if let Some(status) = &query.status {
+            filter.insert("device.status", status);
+        }
+
+        let mut find = self
+            .collection_devices
+            .find(filter)
+            .sort(doc! {"device.name": sort_direction(query.order())});
+        if let Some(limit) = query.limit {
+            find = find.limit(limit as i64);
+        }
+
+        let records = find
+            .await?
+            .try_collect::<Vec<CollectionDevice>>()
+            .await?
+            .into_iter()
+            .map(|device| device.record())
+            .collect();
+
+        Ok(records)
+    }
+
+    async fn add_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        meta: &SmartDeviceMeta,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.check_room(house, room).await?;
 
         if self
             .collection_devices
-            .count_documents(doc! {"room_name": room, "device.name": device})
+            .count_documents(doc! {"house_name": house, "room_name": room, "device.name": device})
             .await?
             > 0
         {
@@ -146,40 +657,61 @@ impl SmartHouseStorage for SmartHouseStorageMongoDB {
         let power = rand::thread_rng().gen_range(10.0..3000.0);
         let temp = rand::thread_rng().gen_range(18.0..30.0);
 
+        let filter = doc! {"house_name": house, "name": room};
+        let updated = self
+            .collection_rooms
+            .update_one(with_version(filter.clone(), version), inc_version())
+            .await?;
+        if updated.matched_count == 0 {
+            // комната есть, но не совпала версия
+            if let Some(record) = self.collection_rooms.find_one(filter).await? {
+                check_version(version, record.version, || format!("комната '{room}'"))?;
+            }
+            return Err(SmartHouseError::RoomNotFoundError(room.to_string()));
+        }
         self.collection_devices
-            .insert_one(CollectionDevice {
-                room_name: room.to_string(),
-                device: SmartDeviceInfo::new(device.to_string(), status, power, temp),
-            })
+            .insert_one(CollectionDevice::new(
+                house,
+                room,
+                SmartDeviceInfo::new(device.to_string(), status, power, temp),
+                meta,
+            ))
             .await?;
 
         Ok(())
     }
 
-    async fn remove_device(&self, room: &str, device: &str) -> Result<(), SmartHouseError> {
-        if self
-            .collection_rooms
-            .count_documents(doc! {"name": room})
-            .await?
-            == 0
-        {
-            return Err(SmartHouseError::RoomNotFoundError(room.to_string()));
-        }
+    async fn remove_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        self.check_room(house, room).await?;
 
-        if self
+        let filter = doc! {"house_name": house, "room_name": room, "device.name": device};
+        let deleted = self
             .collection_devices
-            .count_documents(doc! {"room_name": room, "device.name": device})
-            .await?
-            == 0
-        {
-            return Err(SmartHouseError::DeviceNotFoundError(
-                room.to_string(),
-                device.to_string(),
-            ));
+            .delete_one(with_version(filter.clone(), version))
+            .await?;
+        if deleted.deleted_count == 0 {
+            return match self.collection_devices.find_one(filter).await? {
+                Some(record) => check_version(version, record.version, || {
+                    format!("устройство '{device}' в комнате '{room}'")
+                }),
+                None => Err(SmartHouseError::DeviceNotFoundError(
+                    room.to_string(),
+                    device.to_string(),
+                )),
+            };
         }
 
-        self.collection_devices
-            .delete_one(doc! {"room_name": room, "device.name": device})
+        self.collection_history
+            .delete_many(doc! {"house_name": house, "room_name": room, "device_name": device})
+            .await?;
+        self.collection_rooms
+            .update_one(doc! {"house_name": house, "name": room}, inc_version())
             .await?;
 
         Ok(())
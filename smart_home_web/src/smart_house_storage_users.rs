@@ -0,0 +1,178 @@
+use crate::prelude::{
+    ApiUser, Role, SmartHouseError, SmartHouseStorageMemory, SmartHouseStorageMongoDB,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+/// Пользователи API, ключи хранятся только в виде хэша
+#[async_trait]
+pub trait ApiUserStorage {
+    async fn api_users(&self) -> Result<Vec<ApiUser>, SmartHouseError>;
+
+    async fn api_user(&self, name: &str) -> Result<ApiUser, SmartHouseError>;
+
+    async fn api_user_by_key_hash(&self, key_hash: &str) -> Result<ApiUser, SmartHouseError>;
+
+    async fn add_api_user(&self, user: &ApiUser, key_hash: &str) -> Result<(), SmartHouseError>;
+
+    /// Заменяет хэш ключа пользователя, старый ключ перестаёт действовать
+    async fn set_api_user_key(&self, name: &str, key_hash: &str) -> Result<(), SmartHouseError>;
+
+    async fn remove_api_user(&self, name: &str) -> Result<(), SmartHouseError>;
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CollectionUser {
+    pub(crate) name: String,
+    pub(crate) role: Role,
+    pub(crate) key_hash: String,
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+impl CollectionUser {
+    fn user(self) -> ApiUser {
+        ApiUser {
+            name: self.name,
+            role: self.role,
+            created_at: self.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl ApiUserStorage for SmartHouseStorageMemory {
+    async fn api_users(&self) -> Result<Vec<ApiUser>, SmartHouseError> {
+        let users = self.users.iter().map(|s| s.value().0.clone()).collect();
+
+        Ok(users)
+    }
+
+    async fn api_user(&self, name: &str) -> Result<ApiUser, SmartHouseError> {
+        match self.users.get(name) {
+            Some(user) => Ok(user.0.clone()),
+            None => Err(SmartHouseError::UserNotFoundError(name.to_string())),
+        }
+    }
+
+    async fn api_user_by_key_hash(&self, key_hash: &str) -> Result<ApiUser, SmartHouseError> {
+        match self.users.iter().find(|s| s.value().1 == key_hash) {
+            Some(user) => Ok(user.value().0.clone()),
+            None => Err(SmartHouseError::UnauthorizedError(
+                "неизвестный ключ API".to_string(),
+            )),
+        }
+    }
+
+    async fn add_api_user(&self, user: &ApiUser, key_hash: &str) -> Result<(), SmartHouseError> {
+        if self.users.contains_key(&user.name) {
+            return Err(SmartHouseError::UserAlreadyExistsError(user.name.clone()));
+        }
+
+        self.users
+            .insert(user.name.clone(), (user.clone(), key_hash.to_string()));
+
+        Ok(())
+    }
+
+    async fn set_api_user_key(&self, name: &str, key_hash: &str) -> Result<(), SmartHouseError> {
+        match self.users.get_mut(name) {
+            Some(mut user) => {
+                user.1 = key_hash.to_string();
+                Ok(())
+            }
+            None => Err(SmartHouseError::UserNotFoundError(name.to_string())),
+        }
+    }
+
+    async fn remove_api_user(&self, name: &str) -> Result<(), SmartHouseError> {
+        match self.users.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(SmartHouseError::UserNotFoundError(name.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl ApiUserStorage for SmartHouseStorageMongoDB {
+    async fn api_users(&self) -> Result<Vec<ApiUser>, SmartHouseError> {
+        let cursor = self.collection_users.find(doc! {}).await?;
+
+        let users = cursor
+            .try_collect::<Vec<CollectionUser>>()
+            .await?
+            .into_iter()
+            .map(|user| user.user())
+            .collect();
+
+        Ok(users)
+    }
+
+    async fn api_user(&self, name: &str) -> Result<ApiUser, SmartHouseError> {
+        match self.collection_users.find_one(doc! {"name": name}).await? {
+            Some(user) => Ok(user.user()),
+            None => Err(SmartHouseError::UserNotFoundError(name.to_string())),
+        }
+    }
+
+    async fn api_user_by_key_hash(&self, key_hash: &str) -> Result<ApiUser, SmartHouseError> {
+        match self
+            .collection_users
+            .find_one(doc! {"key_hash": key_hash})
+            .await?
+        {
+            Some(user) => Ok(user.user()),
+            None => Err(SmartHouseError::UnauthorizedError(
+                "неизвестный ключ API".to_string(),
+            )),
+        }
+    }
+
+    async fn add_api_user(&self, user: &ApiUser, key_hash: &str) -> Result<(), SmartHouseError> {
+        if self
+            .collection_users
+            .count_documents(doc! {"name": &user.name})
+            .await?
+            > 0
+        {
+            return Err(SmartHouseError::UserAlreadyExistsError(user.name.clone()));
+        }
+
+        self.collection_users
+            .insert_one(CollectionUser {
+                name: user.name.clone(),
+                role: user.role,
+                key_hash: key_hash.to_string(),
+                created_at: user.created_at,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_api_user_key(&self, name: &str, key_hash: &str) -> Result<(), SmartHouseError> {
+        let result = self
+            .collection_users
+            .update_one(doc! {"name": name}, doc! {"$set": {"key_hash": key_hash}})
+            .await?;
+
+        match result.matched_count {
+            0 => Err(SmartHouseError::UserNotFoundError(name.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    async fn remove_api_user(&self, name: &str) -> Result<(), SmartHouseError> {
+        let result = self
+            .collection_users
+            .delete_one(doc! {"name": name})
+            .await?;
+
+        match result.deleted_count {
+            0 => Err(SmartHouseError::UserNotFoundError(name.to_string())),
+            _ => Ok(()),
+        }
+    }
+}
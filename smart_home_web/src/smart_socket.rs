@@ -54,6 +54,7 @@ impl SmartDevice for SmartSocket {
                 "device is now OFF".to_string()
             }
             "power" => format!("{:.2}", self.power.load(SeqCst)),
+            "status" => self.status.load(SeqCst).to_string(),
             "info" => {
                 format!(
                     "name: {}, room: {}, status: {}, power: {:.2} pW",
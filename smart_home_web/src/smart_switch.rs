@@ -41,6 +41,7 @@ impl SmartDevice for SmartSwitch {
                 self.status.store(DeviceStatus::Off, SeqCst);
                 "device is now OFF".to_string()
             }
+            "status" => self.status.load(SeqCst).to_string(),
             "info" => {
                 format!(
                     "name: {}, room: {}, status: {}",
@@ -100,6 +100,7 @@ impl SmartDevice for SmartThermometer {
                     self.temp.load(SeqCst)
                 )
             }
+            "temp" => format!("{:.2}", self.temp.load(SeqCst)),
             _ => match command.parse::<f32>() {
                 Ok(value) => {
                     self.temp.store(value, SeqCst);
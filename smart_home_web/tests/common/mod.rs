@@ -17,6 +17,10 @@ pub const SWITCH_2: &str = "Выключатель-2";
 pub const SOCKET_ADDR: &str = "127.0.0.1:54321";
 pub const THERMOMETER_ADDR: &str = "127.0.0.1:12345";
 pub const SWITCH_ADDR: &str = "127.0.0.1:31254";
+pub const NETWORK_SOCKET_ADDR: &str = "127.0.0.1:54322";
+pub const NETWORK_THERMOMETER_ADDR: &str = "127.0.0.1:12346";
+pub const NETWORK_SWITCH_ADDR: &str = "127.0.0.1:31255";
+pub const UNAVAILABLE_ADDR: &str = "127.0.0.1:9";
 
 pub(crate) fn new_house() -> SmartHouse {
     SmartHouse::new(
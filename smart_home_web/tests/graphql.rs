@@ -0,0 +1,367 @@
+use actix_web::middleware::from_fn;
+use actix_web::{http::StatusCode, test, web, App};
+use serde_json::{json, Value};
+use smart_home_web::prelude::{
+    audit_middleware, auth_middleware, graphql_config, graphql_schema_sdl, AppData, AuditQuery,
+    Authenticator, DeviceStatus, Role, SmartDeviceInfo, SmartHouseStorageMemory,
+};
+use std::collections::HashMap;
+
+const HOUSE_NAME: &str = "Мой умный дом (graphql)";
+const HOUSE_ADDRESS: &str = "ул. Умных домов, д.4, кв.5";
+const KITCHEN: &str = "Кухня";
+const BEDROOM: &str = "Спальня";
+const HALLWAY: &str = "Прихожая";
+const SOCKET_1: &str = "Розетка-1";
+const THERMOMETER_1: &str = "Термометр-1";
+const SWITCH_1: &str = "Выключатель-1";
+
+#[actix_web::test]
+async fn test_graphql_schema_export() {
+    // schema.graphql обновляется командой `cargo run --example graphql_schema > schema.graphql`
+    let exported = include_str!("../schema.graphql");
+    assert_eq!(exported, graphql_schema_sdl(), "schema.graphql устарел");
+
+    let app_data = web::Data::new(new_house_graphql().await);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&app_data))
+            .app_data(web::Data::new(Authenticator::new()))
+            .wrap(from_fn(auth_middleware))
+            .configure(graphql_config),
+    )
+    .await;
+
+    // схема доступна без аутентификации
+    let req = test::TestRequest::get()
+        .uri("/graphql/schema.graphql")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, exported.as_bytes());
+}
+
+#[actix_web::test]
+async fn test_graphql_query() {
+    let app_data = web::Data::new(new_house_graphql().await);
+
+    let query = r#"{
+        houses { name address }
+        house {
+            rooms {
+                name
+                devices { name kind info { status power temp } }
+            }
+            room(name: "Спальня") { device(name: "Термометр-1") { info { temp } } }
+        }
+    }"#;
+    let resp = graphql_call(&app_data, query, json!({}), None).await;
+    assert_eq!(
+        resp,
+        json!({"data": {
+            "houses": [{"name": HOUSE_NAME, "address": HOUSE_ADDRESS}],
+            "house": {
+                "rooms": [
+                    {"name": KITCHEN, "devices": [{
+                        "name": SOCKET_1,
+                        "kind": "UNKNOWN",
+                        "info": {"status": DeviceStatus::On.to_string(), "power": 1500.0, "temp": 0.0},
+                    }]},
+                    {"name": BEDROOM, "devices": [{
+                        "name": THERMOMETER_1,
+                        "kind": "UNKNOWN",
+                        "info": {"status": DeviceStatus::Unknown.to_string(), "power": 0.0, "temp": 22.5},
+                    }]},
+                ],
+                "room": {"device": {"info": {"temp": 22.5}}},
+            },
+        }})
+    );
+
+    // клиент выбирает только нужные поля
+    let resp = graphql_call(&app_data, "{ house { name } }", json!({}), None).await;
+    assert_eq!(resp, json!({"data": {"house": {"name": HOUSE_NAME}}}));
+
+    let resp = graphql_call(
+        &app_data,
+        "query($name: String) { house(name: $name) { name } }",
+        json!({"name": "Чужой дом"}),
+        None,
+    )
+    .await;
+    assert_eq!(resp["data"], Value::Null);
+    assert_eq!(
+        resp["errors"][0]["extensions"],
+        json!({"code": "house_not_found", "details": {"house": "Чужой дом"}})
+    );
+    let resp = graphql_call(
+        &app_data,
+        "{ house { room(name: \"Чердак\") { name } } }",
+        json!({}),
+        None,
+    )
+    .await;
+    assert_eq!(resp["errors"][0]["extensions"]["code"], "room_not_found");
+}
+
+#[actix_web::test]
+async fn test_graphql_mutations() {
+    let app_data = web::Data::new(new_house_graphql().await);
+
+    let resp = graphql_call(
+        &app_data,
+        "mutation($room: String!) { addRoom(name: $room) { name version } }",
+        json!({"room": HALLWAY}),
+        None,
+    )
+    .await;
+    assert_eq!(
+        resp,
+        json!({"data": {"addRoom": {"name": HALLWAY, "version": 1}}})
+    );
+    let resp = graphql_call(
+        &app_data,
+        "mutation($room: String!) { addRoom(name: $room) { name } }",
+        json!({"room": HALLWAY}),
+        None,
+    )
+    .await;
+    assert_eq!(
+        resp["errors"][0]["extensions"]["code"],
+        "room_already_exists"
+    );
+
+    let add_device = r#"mutation($room: String!, $device: String!) {
+        addDevice(room: $room, name: $device, kind: SWITCH, tags: ["свет"]) {
+            name kind tags version
+        }
+    }"#;
+    let resp = graphql_call(
+        &app_data,
+        add_device,
+        json!({"room": HALLWAY, "device": SWITCH_1}),
+        None,
+    )
+    .await;
+    assert_eq!(
+        resp,
+        json!({"data": {"addDevice": {"name": SWITCH_1, "kind": "SWITCH", "tags": ["свет"], "version": 1}}})
+    );
+
+    let update = r#"mutation($room: String!, $device: String!, $version: Int) {
+        updateDeviceInfo(room: $room, name: $device, status: "on", power: 50.0, version: $version) {
+            version
+            info { status power }
+            history { count power { max } }
+        }
+    }"#;
+    let variables = json!({"room": HALLWAY, "device": SWITCH_1, "version": 1});
+    let resp = graphql_call(&app_data, update, variables.clone(), None).await;
+    assert_eq!(
+        resp,
+        json!({"data": {"updateDeviceInfo": {
+            "version": 2,
+            "info": {"status": DeviceStatus::On.to_string(), "power": 50.0},
+            "history": [{"count": 1, "power": {"max": 50.0}}],
+        }}})
+    );
+    let resp = graphql_call(&app_data, update, variables, None).await;
+    assert_eq!(
+        resp["errors"][0]["extensions"]["code"],
+        "precondition_failed"
+    );
+
+    let resp = graphql_call(
+        &app_data,
+        "mutation($room: String!, $device: String!) { removeDevice(room: $room, name: $device) }",
+        json!({"room": HALLWAY, "device": SWITCH_1}),
+        None,
+    )
+    .await;
+    assert_eq!(resp, json!({"data": {"removeDevice": true}}));
+    let resp = graphql_call(
+        &app_data,
+        "mutation($room: String!) { removeRoom(name: $room) }",
+        json!({"room": HALLWAY}),
+        None,
+    )
+    .await;
+    assert_eq!(resp, json!({"data": {"removeRoom": true}}));
+    assert_eq!(
+        app_data.rooms(HOUSE_NAME).await.unwrap(),
+        [KITCHEN, BEDROOM]
+    );
+
+    let resp = graphql_call(
+        &app_data,
+        "mutation { addHouse(name: \"Дача\", address: \"пос. Дачный\") { name rooms { name } } }",
+        json!({}),
+        None,
+    )
+    .await;
+    assert_eq!(
+        resp,
+        json!({"data": {"addHouse": {"name": "Дача", "rooms": []}}})
+    );
+    let resp = graphql_call(
+        &app_data,
+        "mutation { removeHouse(name: \"Дача\") }",
+        json!({}),
+        None,
+    )
+    .await;
+    assert_eq!(resp, json!({"data": {"removeHouse": true}}));
+
+    let resp = graphql_call(
+        &app_data,
+        "mutation { addRoom(name: \" \") { name } }",
+        json!({}),
+        None,
+    )
+    .await;
+    assert_eq!(resp["errors"][0]["extensions"]["code"], "invalid_name");
+}
+
+#[actix_web::test]
+async fn test_graphql_auth() {
+    let app_data = web::Data::new(new_house_graphql().await);
+    app_data
+        .add_api_user_with_key("admin", Role::Admin, "admin-key")
+        .await
+        .unwrap();
+    app_data
+        .add_api_user_with_key("operator", Role::Operator, "operator-key")
+        .await
+        .unwrap();
+    app_data
+        .add_api_user_with_key("viewer", Role::Viewer, "viewer-key")
+        .await
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&app_data))
+            .app_data(web::Data::new(Authenticator::new()))
+            .wrap(from_fn(audit_middleware))
+            .wrap(from_fn(auth_middleware))
+            .configure(graphql_config),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri("/graphql")
+        .set_json(json!({"query": "{ house { name } }"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = graphql_call(
+        &app_data,
+        "{ house { name } }",
+        json!({}),
+        Some("viewer-key"),
+    )
+    .await;
+    assert_eq!(resp, json!({"data": {"house": {"name": HOUSE_NAME}}}));
+
+    // изменения требуют тех же ролей, что и в REST API
+    let add_room = "mutation { addRoom(name: \"Прихожая\") { name } }";
+    let resp = graphql_call(&app_data, add_room, json!({}), Some("viewer-key")).await;
+    assert_eq!(resp["errors"][0]["extensions"]["code"], "forbidden");
+    let resp = graphql_call(&app_data, add_room, json!({}), Some("operator-key")).await;
+    assert_eq!(resp["errors"][0]["extensions"]["code"], "forbidden");
+    let resp = graphql_call(&app_data, add_room, json!({}), Some("admin-key")).await;
+    assert_eq!(resp, json!({"data": {"addRoom": {"name": HALLWAY}}}));
+
+    let update = r#"mutation { updateDeviceInfo(room: "Кухня", name: "Розетка-1", power: 10.0) {
+        info { power }
+    } }"#;
+    let resp = graphql_call(&app_data, update, json!({}), Some("viewer-key")).await;
+    assert_eq!(resp["errors"][0]["extensions"]["code"], "forbidden");
+    let resp = graphql_call(&app_data, update, json!({}), Some("operator-key")).await;
+    assert_eq!(
+        resp,
+        json!({"data": {"updateDeviceInfo": {"info": {"power": 10.0}}}})
+    );
+
+    // изменения записываются в журнал аудита от имени пользователя
+    let records = app_data.audit_log(&AuditQuery::default()).await.unwrap();
+    let records: Vec<(&str, &str)> = records
+        .iter()
+        .take(2)
+        .map(|record| (record.operation.as_str(), record.actor.as_str()))
+        .collect();
+    assert_eq!(
+        records,
+        [("update_device_info", "operator"), ("add_room", "admin")]
+    );
+}
+
+/// Выполняет запрос GraphQL и возвращает тело ответа. С ключом API включается
+/// аутентификация и журнал аудита.
+async fn graphql_call(
+    app_data: &web::Data<AppData>,
+    query: &str,
+    variables: Value,
+    api_key: Option<&str>,
+) -> Value {
+    let mut req = test::TestRequest::post()
+        .uri("/graphql")
+        .set_json(json!({"query": query, "variables": variables}));
+    let app = App::new()
+        .app_data(web::Data::clone(app_data))
+        .wrap(from_fn(audit_middleware))
+        .wrap(from_fn(auth_middleware))
+        .configure(graphql_config);
+    let app = match api_key {
+        Some(api_key) => {
+            req = req.insert_header(("X-API-Key", api_key));
+            test::init_service(app.app_data(web::Data::new(Authenticator::new()))).await
+        }
+        None => test::init_service(app).await,
+    };
+
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    test::read_body_json(resp).await
+}
+
+async fn new_house_graphql() -> AppData {
+    let mut app_data = AppData::new(
+        HOUSE_NAME.to_string(),
+        HOUSE_ADDRESS.to_string(),
+        Box::new(SmartHouseStorageMemory::new()),
+    );
+    app_data.init(generate_mock_devices()).await.unwrap();
+
+    app_data
+}
+
+fn generate_mock_devices() -> HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>> {
+    HashMap::from([
+        (
+            KITCHEN,
+            HashMap::from([(
+                SOCKET_1,
+                SmartDeviceInfo::new(
+                    SOCKET_1.to_string(),
+                    DeviceStatus::On.to_string(),
+                    1500.0,
+                    0.0,
+                ),
+            )]),
+        ),
+        (
+            BEDROOM,
+            HashMap::from([(
+                THERMOMETER_1,
+                SmartDeviceInfo::new(
+                    THERMOMETER_1.to_string(),
+                    DeviceStatus::Unknown.to_string(),
+                    0.0,
+                    22.5,
+                ),
+            )]),
+        ),
+    ])
+}
@@ -0,0 +1,365 @@
+use smart_home_web::grpc::proto::smart_home_client::SmartHomeClient;
+use smart_home_web::grpc::proto::{
+    AddDeviceRequest, AddRoomRequest, DeviceKind as ProtoDeviceKind, EventType,
+    GetDeviceInfoRequest, GetReportRequest, ListDevicesRequest, ListRoomsRequest,
+    RemoveDeviceRequest, RemoveRoomRequest, UpdateDeviceInfoRequest, WatchRequest,
+};
+use smart_home_web::prelude::{
+    AppData, AuditQuery, Authenticator, DeviceStatus, Role, SmartDeviceInfo, SmartHomeGrpc,
+    SmartHouseStorageMemory,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::StreamExt;
+use tonic::transport::{Channel, Server};
+use tonic::{Code, Request};
+
+const HOUSE_NAME: &str = "Мой умный дом (grpc)";
+const HOUSE_ADDRESS: &str = "ул. Умных домов, д.3, кв.4";
+const KITCHEN: &str = "Кухня";
+const BEDROOM: &str = "Спальня";
+const HALLWAY: &str = "Прихожая";
+const SOCKET_1: &str = "Розетка-1";
+const THERMOMETER_1: &str = "Термометр-1";
+const SWITCH_1: &str = "Выключатель-1";
+
+#[tokio::test]
+async fn test_grpc_rooms() {
+    let (mut client, _) = start_grpc(|service| service).await;
+
+    let rooms = client
+        .list_rooms(ListRoomsRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(rooms.rooms, [KITCHEN, BEDROOM]);
+
+    let add = AddRoomRequest {
+        house: String::new(),
+        room: HALLWAY.to_string(),
+    };
+    client.add_room(add.clone()).await.unwrap();
+    let status = client.add_room(add).await.unwrap_err();
+    assert_eq!(status.code(), Code::AlreadyExists);
+
+    let list = ListRoomsRequest {
+        house: HOUSE_NAME.to_string(),
+    };
+    let updated = client.list_rooms(list.clone()).await.unwrap().into_inner();
+    assert_eq!(updated.rooms, [KITCHEN, HALLWAY, BEDROOM]);
+    assert!(updated.version > rooms.version);
+
+    // устаревшая версия дома не подходит для удаления комнаты
+    let remove = |version| RemoveRoomRequest {
+        house: String::new(),
+        room: HALLWAY.to_string(),
+        version,
+    };
+    let status = client.remove_room(remove(Some(0))).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    client.remove_room(remove(None)).await.unwrap();
+    let status = client.remove_room(remove(None)).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let status = client
+        .add_room(AddRoomRequest {
+            house: String::new(),
+            room: " ".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let status = client
+        .list_rooms(ListRoomsRequest {
+            house: "Чужой дом".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_grpc_devices() {
+    let (mut client, _) = start_grpc(|service| service).await;
+
+    client
+        .add_device(AddDeviceRequest {
+            room: KITCHEN.to_string(),
+            device: SWITCH_1.to_string(),
+            kind: ProtoDeviceKind::Switch.into(),
+            manufacturer: Some("Умные вещи".to_string()),
+            tags: vec!["свет".to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let list = ListDevicesRequest {
+        house: String::new(),
+        room: KITCHEN.to_string(),
+    };
+    let devices = client
+        .list_devices(list.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    let names: Vec<&str> = devices.devices.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, [SWITCH_1, SOCKET_1]);
+    assert_eq!(devices.devices[0].kind(), ProtoDeviceKind::Switch);
+    assert_eq!(
+        devices.devices[0].manufacturer.as_deref(),
+        Some("Умные вещи")
+    );
+    assert_eq!(devices.devices[0].tags, ["свет"]);
+
+    let device = |device: &str| GetDeviceInfoRequest {
+        house: String::new(),
+        room: KITCHEN.to_string(),
+        device: device.to_string(),
+    };
+    let info = client
+        .get_device_info(device(SOCKET_1))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(info.name, SOCKET_1);
+    assert_eq!(info.status, DeviceStatus::On.to_string());
+    assert_eq!(info.power, 1500.0);
+
+    let version = devices.devices[1].version;
+    let update = |version| UpdateDeviceInfoRequest {
+        room: KITCHEN.to_string(),
+        device: SOCKET_1.to_string(),
+        status: Some(DeviceStatus::Off.to_string()),
+        power: Some(0.0),
+        version,
+        ..Default::default()
+    };
+    let updated = client
+        .update_device_info(update(Some(version)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(updated.version, version + 1);
+    let updated_info = updated.info.unwrap();
+    assert_eq!(updated_info.status, DeviceStatus::Off.to_string());
+    assert_eq!(updated_info.power, 0.0);
+    let status = client
+        .update_device_info(update(Some(version)))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    let status = client
+        .update_device_info(UpdateDeviceInfoRequest {
+            status: Some("сломано".to_string()),
+            ..update(None)
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let remove = RemoveDeviceRequest {
+        room: KITCHEN.to_string(),
+        device: SWITCH_1.to_string(),
+        ..Default::default()
+    };
+    client.remove_device(remove.clone()).await.unwrap();
+    let status = client.remove_device(remove).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let status = client
+        .list_devices(ListDevicesRequest {
+            house: String::new(),
+            room: HALLWAY.to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_grpc_report() {
+    let (mut client, _) = start_grpc(|service| service).await;
+
+    let report = client
+        .get_report(GetReportRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(report.name, HOUSE_NAME);
+    assert_eq!(report.address, HOUSE_ADDRESS);
+    let rooms: Vec<(&str, Vec<&str>)> = report
+        .rooms
+        .iter()
+        .map(|room| {
+            let devices = room.devices.iter().map(|d| d.name.as_str()).collect();
+            (room.name.as_str(), devices)
+        })
+        .collect();
+    assert_eq!(
+        rooms,
+        [(KITCHEN, vec![SOCKET_1]), (BEDROOM, vec![THERMOMETER_1])]
+    );
+    assert_eq!(report.rooms[1].devices[0].temp, 22.5);
+}
+
+#[tokio::test]
+async fn test_grpc_watch() {
+    let (mut client, _) = start_grpc(|service| service).await;
+
+    let mut all = client
+        .watch(WatchRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let mut other = client
+        .watch(WatchRequest {
+            house: Some("Чужой дом".to_string()),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    client
+        .add_room(AddRoomRequest {
+            house: String::new(),
+            room: HALLWAY.to_string(),
+        })
+        .await
+        .unwrap();
+    client
+        .update_device_info(UpdateDeviceInfoRequest {
+            room: KITCHEN.to_string(),
+            device: SOCKET_1.to_string(),
+            power: Some(100.0),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let event = all.next().await.unwrap().unwrap();
+    assert_eq!(event.r#type(), EventType::RoomAdded);
+    assert_eq!(
+        (event.house.as_str(), event.room.as_str()),
+        (HOUSE_NAME, HALLWAY)
+    );
+    let event = all.next().await.unwrap().unwrap();
+    assert_eq!(event.r#type(), EventType::DeviceUpdated);
+    assert_eq!(event.device, SOCKET_1);
+    assert_eq!(event.info.unwrap().power, 100.0);
+
+    // события другого дома не приходят
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), other.next())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_grpc_auth() {
+    let (mut client, app_data) =
+        start_grpc(|service| service.with_authenticator(Arc::new(Authenticator::new()))).await;
+    app_data
+        .add_api_user_with_key("admin", Role::Admin, "admin-key")
+        .await
+        .unwrap();
+    app_data
+        .add_api_user_with_key("viewer", Role::Viewer, "viewer-key")
+        .await
+        .unwrap();
+    let with_key = |key: &str, room: &str| {
+        let mut request = Request::new(AddRoomRequest {
+            house: String::new(),
+            room: room.to_string(),
+        });
+        request
+            .metadata_mut()
+            .insert("x-api-key", key.parse().unwrap());
+        request
+    };
+
+    let status = client.list_rooms(ListRoomsRequest::default()).await;
+    assert_eq!(status.unwrap_err().code(), Code::Unauthenticated);
+    let status = client.add_room(with_key("wrong-key", HALLWAY)).await;
+    assert_eq!(status.unwrap_err().code(), Code::Unauthenticated);
+    let status = client.add_room(with_key("viewer-key", HALLWAY)).await;
+    assert_eq!(status.unwrap_err().code(), Code::PermissionDenied);
+
+    client
+        .add_room(with_key("admin-key", HALLWAY))
+        .await
+        .unwrap();
+    let mut request = Request::new(ListRoomsRequest::default());
+    request
+        .metadata_mut()
+        .insert("x-api-key", "viewer-key".parse().unwrap());
+    let rooms = client.list_rooms(request).await.unwrap().into_inner();
+    assert!(rooms.rooms.contains(&HALLWAY.to_string()));
+
+    // изменения записываются в журнал аудита от имени пользователя
+    let records = app_data.audit_log(&AuditQuery::default()).await.unwrap();
+    assert_eq!(records[0].operation, "add_room");
+    assert_eq!(records[0].actor, "admin");
+}
+
+/// Запускает gRPC API на свободном порту и подключает к нему клиент
+async fn start_grpc(
+    configure: impl FnOnce(SmartHomeGrpc) -> SmartHomeGrpc,
+) -> (SmartHomeClient<Channel>, Arc<AppData>) {
+    let mut app_data = AppData::new(
+        HOUSE_NAME.to_string(),
+        HOUSE_ADDRESS.to_string(),
+        Box::new(SmartHouseStorageMemory::new()),
+    );
+    app_data.init(generate_mock_devices()).await.unwrap();
+    let app_data = Arc::new(app_data);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = configure(SmartHomeGrpc::new(Arc::clone(&app_data)));
+    tokio::spawn(
+        Server::builder()
+            .add_service(service.into_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let client = SmartHomeClient::connect(format!("http://{addr}"))
+        .await
+        .unwrap();
+
+    (client, app_data)
+}
+
+fn generate_mock_devices() -> HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>> {
+    HashMap::from([
+        (
+            KITCHEN,
+            HashMap::from([(
+                SOCKET_1,
+                SmartDeviceInfo::new(
+                    SOCKET_1.to_string(),
+                    DeviceStatus::On.to_string(),
+                    1500.0,
+                    0.0,
+                ),
+            )]),
+        ),
+        (
+            BEDROOM,
+            HashMap::from([(
+                THERMOMETER_1,
+                SmartDeviceInfo::new(
+                    THERMOMETER_1.to_string(),
+                    DeviceStatus::Unknown.to_string(),
+                    0.0,
+                    22.5,
+                ),
+            )]),
+        ),
+    ])
+}
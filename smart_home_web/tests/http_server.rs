@@ -1,8 +1,27 @@
-use actix_web::{http::Method, http::StatusCode, test, web, web::Bytes, App};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::ServiceResponse;
+use actix_web::middleware::from_fn;
+use actix_web::{http::Method, http::StatusCode, test, web, web::Bytes, App, ResponseError};
+use chrono::{Duration, Utc};
+use clap::Parser;
 use smart_home_web::http_handler::prelude::*;
-use smart_home_web::prelude::{AppData, DeviceStatus, SmartHouseError, SmartHouseStorageMemory};
+use smart_home_web::prelude::{
+    audit_middleware, auth_middleware, dashboard_config, idempotency_middleware, limits_middleware,
+    metrics_middleware, request_id_middleware, ApiUser, AppConfig, AppData, AuditRecord,
+    AuditResult, Authenticator, CliArgs, CorsConfig, DeviceCommand, DeviceCommandResult,
+    DeviceKind, DeviceSnapshot, DeviceStatus, ErrorCode, ErrorResponse, HouseSnapshot,
+    IdempotencyError, IdempotencyRecord, IdempotentResponse, ImportChange, ImportFailure,
+    ImportMode, ImportReport, LimitError, Limits, NameKind, NameViolation,
+    NetworkDeviceInfoProvider, RateLimiter, Role, RoomSnapshot, SmartDevice, SmartHouseError,
+    SmartHouseEvent, SmartHouseStorageMemory, SmartHouseStorageMongoDB, SmartSocket,
+    SmartThermometer, StorageBackend,
+};
 use std::collections::HashMap;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::time::Duration as StdDuration;
 use urlencoding::encode;
+use utoipa::OpenApi;
 
 const HOUSE_NAME: &str = "Мой умный дом (http)";
 const HOUSE_ADDRESS: &str = "ул. Умных домов, д.2, кв.3";
@@ -14,8 +33,11 @@ const THERMOMETER_1: &str = "Термометр-1";
 const THERMOMETER_2: &str = "Термометр-2";
 const SOCKET_1: &str = "Розетка-1";
 const SOCKET_2: &str = "Розетка-2";
+const SOCKET_3: &str = "Розетка-3";
 const SWITCH_1: &str = "Выключатель-1";
 const SWITCH_2: &str = "Выключатель-2";
+const UNAVAILABLE_MONGO_DB_URI: &str =
+    "mongodb://127.0.0.1:1/smart_home?serverSelectionTimeoutMS=100";
 
 #[actix_web::test]
 async fn test_http_rooms() {
@@ -136,6 +158,64 @@ async fn test_http_room_remove_device() {
     test_http_helper(data, &path, Method::GET, StatusCode::OK, expected).await;
 }
 
+#[actix_web::test]
+async fn test_http_room_readd_device() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let path = format!("/device/{}/room/{}", &encode(SOCKET_1), &encode(KITCHEN));
+
+    for (method, status) in [
+        (Method::DELETE, StatusCode::OK),
+        (Method::POST, StatusCode::CREATED),
+    ] {
+        test_http_helper(data.clone(), &path, method, status, "".to_string()).await;
+    }
+
+    let req = test::TestRequest::get().uri(&path);
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(info["power"], 0.0);
+    assert_ne!(info["status"], DeviceStatus::On.to_string());
+}
+
+#[actix_web::test]
+async fn test_http_network_device_registration() {
+    let provider =
+        NetworkDeviceInfoProvider::new(StdDuration::from_secs(60), StdDuration::from_secs(1));
+    let app_data = new_house_http()
+        .await
+        .unwrap()
+        .with_device_info_provider(Box::new(provider));
+    let data = web::Data::new(app_data);
+    let path = format!("/rooms/{}/devices/{}", &encode(KITCHEN), &encode(SOCKET_3));
+
+    let req = test::TestRequest::post()
+        .uri(&path)
+        .set_json(serde_json::json!({"kind": "socket", "address": "127.0.0.1:1"}));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    // устройство опрашивается по сети сразу после добавления
+    let req = test::TestRequest::get().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let unavailable: SmartDeviceInfo = test::read_body_json(resp).await;
+    assert!(unavailable.name().contains("недоступно"));
+
+    let req = test::TestRequest::delete().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = test::TestRequest::post().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    // без адреса устройство больше не опрашивается
+    let req = test::TestRequest::get().uri(&path);
+    let resp = test_http_call_helper(data, req).await;
+    let info: SmartDeviceInfo = test::read_body_json(resp).await;
+    assert!(!info.name().contains("недоступно"));
+}
+
 #[actix_web::test]
 async fn test_http_house_report() {
     let app_data = new_house_http().await.unwrap();
@@ -217,38 +297,2799 @@ async fn test_http_house_report() {
     .await;
 }
 
-async fn test_http_helper(
-    app_data: web::Data<AppData>,
-    path: &str,
-    method: Method,
-    status_code: StatusCode,
-    expected: String,
-) {
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::clone(&app_data))
-            .service(get_rooms)
-            .service(post_room)
-            .service(delete_room)
-            .service(get_room_devices)
-            .service(post_device)
-            .service(delete_device)
-            .service(get_device)
-            .service(get_house_report),
+#[actix_web::test]
+async fn test_http_room_put_device() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let path = format!("/device/{}/room/{}", &encode(SOCKET_2), &encode(KITCHEN));
+
+    let body = serde_json::json!({"status": "on", "power": 333.444, "temp": 0.0});
+    let expected_json = serde_json::json!({
+        "name": SOCKET_2,
+        "status": DeviceStatus::On.to_string(),
+        "power": 333.444,
+        "temp": 0.0
+    });
+    test_http_json_helper(
+        data.clone(),
+        &path,
+        Method::PUT,
+        body,
+        StatusCode::OK,
+        expected_json.to_string(),
     )
     .await;
 
-    let req = match method {
-        Method::GET => test::TestRequest::get().uri(path).to_request(),
-        Method::POST => test::TestRequest::post().uri(path).to_request(),
-        Method::DELETE => test::TestRequest::delete().uri(path).to_request(),
-        _ => unreachable!(),
-    };
-    let resp = test::call_service(&app, req).await;
-    assert_eq!(resp.status(), status_code);
+    test_http_helper(
+        data.clone(),
+        &path,
+        Method::GET,
+        StatusCode::OK,
+        expected_json.to_string(),
+    )
+    .await;
 
-    let body = test::read_body(resp).await;
-    assert_eq!(body, Bytes::from(expected));
+    let body = serde_json::json!({"status": "off"});
+    let req = test::TestRequest::put().uri(&path).set_json(body);
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_http_room_patch_device() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let path = format!(
+        "/device/{}/room/{}",
+        &encode(THERMOMETER_1),
+        &encode(LIVING_ROOM)
+    );
+
+    let body = serde_json::json!({"temp": 23.5});
+    let expected_json = serde_json::json!({
+        "name": THERMOMETER_1,
+        "status": DeviceStatus::Unknown.to_string(),
+        "power": 0.0,
+        "temp": 23.5
+    });
+    test_http_json_helper(
+        data.clone(),
+        &path,
+        Method::PATCH,
+        body,
+        StatusCode::OK,
+        expected_json.to_string(),
+    )
+    .await;
+
+    for body in [
+        serde_json::json!({}),
+        serde_json::json!({"temp": 1000.0}),
+        serde_json::json!({"power": -1.0}),
+        serde_json::json!({"status": "qqq"}),
+        serde_json::json!({"volume": 10}),
+    ] {
+        let req = test::TestRequest::patch().uri(&path).set_json(body);
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let path = format!(
+        "/device/{}/room/{}",
+        &encode(THERMOMETER_1),
+        &encode(KITCHEN)
+    );
+    let req = test::TestRequest::patch()
+        .uri(&path)
+        .set_json(serde_json::json!({"temp": 23.5}));
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_http_room_add_device_record() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let path = format!("/device/{}/room/{}", &encode(SOCKET_3), &encode(KITCHEN));
+
+    let req = test::TestRequest::post()
+        .uri(&path)
+        .set_json(serde_json::json!({
+            "kind": "socket",
+            "address": "127.0.0.1:54321",
+            "manufacturer": "Otus",
+            "tags": ["кухня", "чайник"]
+        }));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let req = test::TestRequest::get().uri(&format!("{path}/record"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let record: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(record["name"], SOCKET_3);
+    assert_eq!(record["kind"], "socket");
+    assert_eq!(record["address"], "127.0.0.1:54321");
+    assert_eq!(record["manufacturer"], "Otus");
+    assert_eq!(record["tags"], serde_json::json!(["кухня", "чайник"]));
+    assert_eq!(record["created_at"], record["updated_at"]);
+
+    let req = test::TestRequest::get().uri(&format!("/devices/{}/records", &encode(KITCHEN)));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let records: Vec<SmartDeviceRecord> = test::read_body_json(resp).await;
+    let names: Vec<_> = records.iter().map(|record| record.name.as_str()).collect();
+    assert_eq!(names, vec![SWITCH_1, SOCKET_1, SOCKET_2, SOCKET_3]);
+    assert_eq!(records[3].kind, DeviceKind::Socket);
+    assert_eq!(records[0].kind, DeviceKind::Unknown);
+
+    let req = test::TestRequest::get().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let info: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(info["kind"], "socket");
+
+    let req = test::TestRequest::patch()
+        .uri(&path)
+        .set_json(serde_json::json!({"power": 5.0}));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = test::TestRequest::get().uri(&format!("{path}/record"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let patched: SmartDeviceRecord = test::read_body_json(resp).await;
+    assert!(patched.updated_at > patched.created_at);
+    assert_eq!(patched.version, record["version"].as_u64().unwrap() + 1);
+
+    let path = format!("/device/{}/room/{}", &encode(SWITCH_2), &encode(KITCHEN));
+    let req = test::TestRequest::post()
+        .uri(&path)
+        .set_json(serde_json::json!({"kind": "switch", "address": "localhost"}));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // некорректное тело не заменяется параметрами по умолчанию
+    let path = format!("/rooms/{}/devices/{}", &encode(KITCHEN), &encode(SWITCH_2));
+    let bodies = [
+        "{\"kind\": ".to_string(),
+        serde_json::json!({"kind": "чайник"}).to_string(),
+        serde_json::json!({"kind": "switch", "color": "red"}).to_string(),
+    ];
+    for body in bodies {
+        let req = test::TestRequest::post()
+            .uri(&path)
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body.clone());
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{body}");
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.code, ErrorCode::ValidationFailed);
+    }
+
+    // без тела устройство добавляется с параметрами по умолчанию
+    let req = test::TestRequest::post().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let req = test::TestRequest::get().uri(&format!("{path}/record"));
+    let resp = test_http_call_helper(data, req).await;
+    let record: SmartDeviceRecord = test::read_body_json(resp).await;
+    assert_eq!(record.kind, DeviceKind::Unknown);
+}
+
+#[actix_web::test]
+async fn test_http_device_history() {
+    let app_data = new_house_http().await.unwrap();
+    let now = Utc::now();
+    let readings = [
+        (0, 100.0),
+        (10, 200.0),
+        (70, 300.0),
+        (80, 500.0),
+        (130, 600.0),
+    ];
+    for (secs, power) in readings {
+        let info = SmartDeviceInfo::new(
+            SOCKET_1.to_string(),
+            DeviceStatus::On.to_string(),
+            power,
+            0.0,
+        );
+        let reading = DeviceReading::new(now - Duration::seconds(200 - secs), &info);
+        app_data
+            .storage
+            .add_reading(HOUSE_NAME, KITCHEN, SOCKET_1, &reading)
+            .await
+            .unwrap();
+    }
+    let data = web::Data::new(app_data);
+
+    let from = now - Duration::seconds(200);
+    let path = format!(
+        "/device/{}/room/{}/history?from={}&step=60",
+        &encode(SOCKET_1),
+        &encode(KITCHEN),
+        &encode(&from.to_rfc3339())
+    );
+    let req = test::TestRequest::get().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let history: Vec<DeviceHistoryPoint> = test::read_body_json(resp).await;
+    let counts: Vec<_> = history.iter().map(|point| point.count).collect();
+    assert_eq!(counts, vec![2, 2, 1]);
+    assert_eq!(history[0].from, from);
+    assert_eq!(history[0].power.min, 100.0);
+    assert_eq!(history[0].power.max, 200.0);
+    assert_eq!(history[0].power.avg, 150.0);
+    assert_eq!(history[1].power.avg, 400.0);
+    assert_eq!(history[2].power.max, 600.0);
+
+    let path = format!(
+        "/device/{}/room/{}/history",
+        &encode(SOCKET_1),
+        &encode(KITCHEN)
+    );
+    let req = test::TestRequest::patch()
+        .uri(&path.replace("/history", ""))
+        .set_json(serde_json::json!({"power": 700.0}));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri(&format!("{path}?step=3600"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let history: Vec<DeviceHistoryPoint> = test::read_body_json(resp).await;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].count, 6);
+    assert_eq!(history[0].power.max, 700.0);
+
+    for step in ["0", "100000000000000000", "18446744073709551615"] {
+        let req = test::TestRequest::get().uri(&format!("{path}?step={step}"));
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{step}");
+    }
+
+    let path = format!(
+        "/device/{}/room/{}/history",
+        &encode(SOCKET_3),
+        &encode(KITCHEN)
+    );
+    let req = test::TestRequest::get().uri(&path);
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_http_readd_device_history() {
+    let app_data = new_house_http().await.unwrap();
+    let info = SmartDeviceInfo::new(
+        SOCKET_1.to_string(),
+        DeviceStatus::On.to_string(),
+        100.0,
+        0.0,
+    );
+    let reading = DeviceReading::new(Utc::now(), &info);
+    app_data
+        .storage
+        .add_reading(HOUSE_NAME, KITCHEN, SOCKET_1, &reading)
+        .await
+        .unwrap();
+    let data = web::Data::new(app_data);
+    let path = format!("/device/{}/room/{}", &encode(SOCKET_1), &encode(KITCHEN));
+
+    for (method, status) in [
+        (Method::DELETE, StatusCode::OK),
+        (Method::POST, StatusCode::CREATED),
+    ] {
+        test_http_helper(data.clone(), &path, method, status, "".to_string()).await;
+    }
+
+    let req = test::TestRequest::get().uri(&format!("{path}/history"));
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let history: Vec<DeviceHistoryPoint> = test::read_body_json(resp).await;
+    assert!(history.is_empty());
+}
+
+#[actix_web::test]
+async fn test_http_houses() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let other = "Дача";
+    let house_path = "/houses/".to_owned() + &encode(other);
+    let room_path = house_path.clone() + "/rooms/" + &encode(KITCHEN);
+    let device_path = room_path.clone() + "/devices/" + &encode(SOCKET_1);
+
+    let req = test::TestRequest::post()
+        .uri(&house_path)
+        .set_json(serde_json::json!({"address": "СНТ Умное"}));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let req = test::TestRequest::post().uri(&house_path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    // некорректное тело - ошибка, а не дом без адреса
+    let bad_path = "/houses/".to_owned() + &encode("Гараж");
+    for body in ["{\"address\": ", "{\"adress\": \"ул. Новая\"}"] {
+        let req = test::TestRequest::post()
+            .uri(&bad_path)
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body);
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{body}");
+    }
+    let req = test::TestRequest::get().uri(&bad_path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let req = test::TestRequest::get().uri("/houses");
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let houses: Vec<SmartHouseRecord> = test::read_body_json(resp).await;
+    let names: Vec<_> = houses.iter().map(|house| house.name.as_str()).collect();
+    assert_eq!(names, vec![other, HOUSE_NAME]);
+    assert_eq!(houses[0].address, "СНТ Умное");
+
+    // комнаты и устройства разных домов не пересекаются
+    test_http_helper(
+        data.clone(),
+        &(house_path.clone() + "/rooms"),
+        Method::GET,
+        StatusCode::OK,
+        "[]".to_string(),
+    )
+    .await;
+    test_http_helper(
+        data.clone(),
+        &room_path,
+        Method::POST,
+        StatusCode::CREATED,
+        "".to_string(),
+    )
+    .await;
+    test_http_helper(
+        data.clone(),
+        &device_path,
+        Method::POST,
+        StatusCode::CREATED,
+        "".to_string(),
+    )
+    .await;
+    test_http_error_helper(
+        data.clone(),
+        &device_path,
+        Method::POST,
+        SmartHouseError::DeviceAlreadyExistsError(KITCHEN.to_string(), SOCKET_1.to_string()),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri(&(room_path.clone() + "/devices"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let records: Vec<SmartDeviceRecord> = test::read_body_json(resp).await;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].name, SOCKET_1);
+
+    let expected = format!("[\"{SWITCH_1}\",\"{SOCKET_1}\",\"{SOCKET_2}\"]");
+    let path = "/devices/".to_owned() + &encode(KITCHEN);
+    test_http_helper(data.clone(), &path, Method::GET, StatusCode::OK, expected).await;
+
+    let req = test::TestRequest::get().uri(&(house_path.clone() + "/report"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(report["name"], other);
+    assert_eq!(report["address"], "СНТ Умное");
+
+    let req = test::TestRequest::patch()
+        .uri(&device_path)
+        .set_json(serde_json::json!({"power": 42.0}));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info: SmartDeviceInfo = test::read_body_json(resp).await;
+    assert_eq!(info.power(), 42.0);
+
+    let req = test::TestRequest::put()
+        .uri(&device_path)
+        .set_json(serde_json::json!({"power": 43.0}));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::get().uri(&(device_path.clone() + "/history"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let history: Vec<DeviceHistoryPoint> = test::read_body_json(resp).await;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].power.max, 42.0);
+
+    let req = test::TestRequest::post()
+        .uri(&(device_path.clone() + "/command"))
+        .set_json(DeviceCommand::On);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let path = "/houses/".to_owned() + &encode("Нет такого") + "/rooms";
+    let expected = SmartHouseError::HouseNotFoundError("Нет такого".to_string());
+    test_http_error_helper(data.clone(), &path, Method::GET, expected).await;
+
+    let path = "/houses/".to_owned() + &encode(HOUSE_NAME);
+    let req = test::TestRequest::delete().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    test_http_helper(
+        data.clone(),
+        &device_path,
+        Method::DELETE,
+        StatusCode::OK,
+        "".to_string(),
+    )
+    .await;
+    test_http_helper(
+        data.clone(),
+        &house_path,
+        Method::DELETE,
+        StatusCode::OK,
+        "".to_string(),
+    )
+    .await;
+    test_http_error_helper(
+        data.clone(),
+        &room_path,
+        Method::DELETE,
+        SmartHouseError::HouseNotFoundError(other.to_string()),
+    )
+    .await;
+}
+
+#[actix_web::test]
+async fn test_http_device_command() {
+    let socket_addr = "127.0.0.1:54323";
+    let thermometer_addr = "127.0.0.1:12347";
+    let socket = SmartSocket::new(
+        SOCKET_3.to_string(),
+        KITCHEN.to_string(),
+        DeviceStatus::Off,
+        0.0,
+    );
+    actix_web::rt::spawn(async move { socket.listen(socket_addr).await });
+    let thermometer = SmartThermometer::new(THERMOMETER_2.to_string(), KITCHEN.to_string(), 20.0);
+    actix_web::rt::spawn(async move { thermometer.listen(thermometer_addr).await });
+    actix_web::rt::time::sleep(std::time::Duration::from_secs_f32(0.5)).await;
+
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let socket_path = format!("/device/{}/room/{}", &encode(SOCKET_3), &encode(KITCHEN));
+    let thermometer_path = format!(
+        "/device/{}/room/{}",
+        &encode(THERMOMETER_2),
+        &encode(KITCHEN)
+    );
+    let unavailable_path = format!("/device/{}/room/{}", &encode(SWITCH_2), &encode(KITCHEN));
+    for (path, json) in [
+        (
+            &socket_path,
+            serde_json::json!({"kind": "socket", "address": socket_addr}),
+        ),
+        (
+            &thermometer_path,
+            serde_json::json!({"kind": "thermometer", "address": thermometer_addr}),
+        ),
+        (
+            &unavailable_path,
+            serde_json::json!({"kind": "switch", "address": "127.0.0.1:9"}),
+        ),
+    ] {
+        let req = test::TestRequest::post().uri(path).set_json(json);
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    let req = test::TestRequest::post().uri(&format!("{socket_path}/on"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let result: DeviceCommandResult = test::read_body_json(resp).await;
+    assert_eq!(result.command, DeviceCommand::On);
+    assert_eq!(result.response, "device is now ON");
+    assert_eq!(result.status, Some(DeviceStatus::On.to_string()));
+    assert!(result.power.unwrap() > 0.0);
+
+    // новое состояние сохраняется в хранилище
+    let req = test::TestRequest::get().uri(&socket_path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let info: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(info["status"], DeviceStatus::On.to_string());
+
+    let req = test::TestRequest::post().uri(&format!("{socket_path}/off"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let result: DeviceCommandResult = test::read_body_json(resp).await;
+    assert_eq!(result.status, Some(DeviceStatus::Off.to_string()));
+    assert_eq!(result.power, Some(0.0));
+
+    let req = test::TestRequest::post()
+        .uri(&format!("{thermometer_path}/command"))
+        .set_json(serde_json::json!({"command": "set_temp", "value": 25.5}));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let result: DeviceCommandResult = test::read_body_json(resp).await;
+    assert_eq!(result.kind, DeviceKind::Thermometer);
+    assert_eq!(result.temp, Some(25.5));
+
+    for (path, json, status) in [
+        (
+            &thermometer_path,
+            serde_json::json!({"command": "set_temp", "value": 500.0}),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            &socket_path,
+            serde_json::json!({"command": "temp"}),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            &socket_path,
+            serde_json::json!({"command": "explode"}),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            &unavailable_path,
+            serde_json::json!({"command": "on"}),
+            StatusCode::BAD_GATEWAY,
+        ),
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("{path}/command"))
+            .set_json(json);
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), status);
+    }
+
+    // показания от устройства сохраняются без второй записи аудита
+    let records = data.audit_log(&AuditQuery::default()).await.unwrap();
+    assert!(records
+        .iter()
+        .all(|record| record.operation != "update_device_info"));
+
+    // у устройства без адреса управлять нечем
+    let path = format!("/device/{}/room/{}/on", &encode(SOCKET_1), &encode(KITCHEN));
+    let req = test::TestRequest::post().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let path = format!("/device/{}/room/{}/on", &encode(SOCKET_3), &encode(BEDROOM));
+    let req = test::TestRequest::post().uri(&path);
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_http_events() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+
+    let req = test::TestRequest::get().uri("/events");
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+    let mut events = resp.into_body();
+
+    let path = "/events?house=".to_owned() + &encode(HOUSE_NAME);
+    let req = test::TestRequest::get().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let mut house_events = resp.into_body();
+
+    let path = "/houses/".to_owned() + &encode("Дача");
+    test_http_helper(
+        data.clone(),
+        &path,
+        Method::POST,
+        StatusCode::CREATED,
+        "".to_string(),
+    )
+    .await;
+    let path = "/room/".to_owned() + &encode(HALLWAY);
+    test_http_helper(
+        data.clone(),
+        &path,
+        Method::POST,
+        StatusCode::CREATED,
+        "".to_string(),
+    )
+    .await;
+    let path = format!("/device/{}/room/{}", &encode(SOCKET_1), &encode(HALLWAY));
+    test_http_helper(
+        data.clone(),
+        &path,
+        Method::POST,
+        StatusCode::CREATED,
+        "".to_string(),
+    )
+    .await;
+    let req = test::TestRequest::patch()
+        .uri(&path)
+        .set_json(serde_json::json!({"power": 42.0}));
+    assert_eq!(
+        test_http_call_helper(data.clone(), req).await.status(),
+        StatusCode::OK
+    );
+    test_http_helper(
+        data.clone(),
+        &path,
+        Method::DELETE,
+        StatusCode::OK,
+        "".to_string(),
+    )
+    .await;
+
+    // ошибочные запросы событий не порождают
+    let path = "/room/".to_owned() + &encode(HALLWAY);
+    test_http_helper(
+        data.clone(),
+        &path,
+        Method::DELETE,
+        StatusCode::OK,
+        "".to_string(),
+    )
+    .await;
+    test_http_error_helper(
+        data.clone(),
+        &path,
+        Method::DELETE,
+        SmartHouseError::RoomNotFoundError(HALLWAY.to_string()),
+    )
+    .await;
+
+    let expected = [
+        (
+            "house_added",
+            serde_json::json!({"type": "house_added", "house": "Дача"}),
+        ),
+        (
+            "room_added",
+            serde_json::json!({"type": "room_added", "house": HOUSE_NAME, "room": HALLWAY}),
+        ),
+        (
+            "device_added",
+            serde_json::json!({"type": "device_added", "house": HOUSE_NAME, "room": HALLWAY, "device": SOCKET_1}),
+        ),
+        (
+            "device_updated",
+            serde_json::json!({"type": "device_updated", "house": HOUSE_NAME, "room": HALLWAY, "device": SOCKET_1}),
+        ),
+        (
+            "device_removed",
+            serde_json::json!({"type": "device_removed", "house": HOUSE_NAME, "room": HALLWAY, "device": SOCKET_1}),
+        ),
+        (
+            "room_removed",
+            serde_json::json!({"type": "room_removed", "house": HOUSE_NAME, "room": HALLWAY}),
+        ),
+    ];
+    for (name, json) in &expected {
+        let (event, data) = next_event_helper(&mut events).await;
+        assert_eq!(&event, name);
+        if *name == "device_updated" {
+            assert_eq!(data["info"]["power"], 42.0);
+            assert_eq!(data["device"], json["device"]);
+        } else {
+            assert_eq!(&data, json);
+        }
+    }
+
+    // подписчик на дом по умолчанию не получает события других домов
+    let (event, _) = next_event_helper(&mut house_events).await;
+    assert_eq!(event, "room_added");
+}
+
+#[actix_web::test]
+async fn test_http_events_heartbeat_and_shutdown() {
+    let app_data = new_house_http()
+        .await
+        .unwrap()
+        .with_events_heartbeat(StdDuration::from_millis(100));
+    let data = web::Data::new(app_data);
+
+    let req = test::TestRequest::get().uri("/events");
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let mut events = resp.into_body();
+
+    let chunk = actix_web::rt::time::timeout(
+        StdDuration::from_secs(1),
+        poll_fn(|cx| Pin::new(&mut events).poll_next(cx)),
+    )
+    .await
+    .expect("heartbeat timeout")
+    .unwrap()
+    .unwrap();
+    assert!(chunk.starts_with(b":"));
+
+    // при остановке сервера поток событий завершается сам
+    data.shutdown();
+    let chunk = actix_web::rt::time::timeout(
+        StdDuration::from_secs(1),
+        poll_fn(|cx| Pin::new(&mut events).poll_next(cx)),
+    )
+    .await
+    .expect("stream is not finished");
+    assert!(chunk.is_none());
+}
+
+async fn next_event_helper(body: &mut BoxBody) -> (String, serde_json::Value) {
+    let chunk = actix_web::rt::time::timeout(
+        std::time::Duration::from_secs(1),
+        poll_fn(|cx| Pin::new(&mut *body).poll_next(cx)),
+    )
+    .await
+    .expect("event timeout")
+    .unwrap()
+    .unwrap();
+    let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+
+    let event = chunk
+        .lines()
+        .find_map(|line| line.strip_prefix("event: "))
+        .unwrap();
+    let data = chunk
+        .lines()
+        .find_map(|line| line.strip_prefix("data: "))
+        .unwrap();
+
+    (event.to_string(), serde_json::from_str(data).unwrap())
+}
+
+#[actix_web::test]
+async fn test_http_auth() {
+    let app_data = new_house_http().await.unwrap();
+    app_data
+        .add_api_user_with_key("admin", Role::Admin, "admin-key")
+        .await
+        .unwrap();
+    let data = web::Data::new(app_data);
+    let authenticator =
+        web::Data::new(Authenticator::new().with_jwt_secret("secret", StdDuration::from_secs(60)));
+    let call = |req: test::TestRequest| {
+        test_http_auth_call_helper(data.clone(), authenticator.clone(), req)
+    };
+    let device_path = format!("/device/{}/room/{}", &encode(SOCKET_1), &encode(KITCHEN));
+
+    for uri in ["/rooms", "/metrics"] {
+        let resp = call(test::TestRequest::get().uri(uri)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{uri}");
+    }
+    let req = test::TestRequest::get()
+        .uri("/rooms")
+        .insert_header(("X-API-Key", "wrong"));
+    assert_eq!(call(req).await.status(), StatusCode::UNAUTHORIZED);
+    let req = test::TestRequest::get()
+        .uri("/rooms")
+        .insert_header(("X-API-Key", "admin-key"));
+    assert_eq!(call(req).await.status(), StatusCode::OK);
+
+    let mut keys = HashMap::new();
+    for (name, role) in [("viewer", "viewer"), ("operator", "operator")] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{name}"))
+            .insert_header(("X-API-Key", "admin-key"))
+            .set_json(serde_json::json!({ "role": role }));
+        let resp = call(req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let user: ApiUserKey = test::read_body_json(resp).await;
+        assert_eq!(user.user.name, name);
+        keys.insert(name, user.api_key);
+    }
+
+    // viewer только читает, operator управляет устройствами, admin меняет состав дома
+    let cases = [
+        (
+            "viewer",
+            test::TestRequest::get().uri("/rooms"),
+            StatusCode::OK,
+        ),
+        (
+            "viewer",
+            test::TestRequest::get().uri("/metrics"),
+            StatusCode::OK,
+        ),
+        (
+            "viewer",
+            test::TestRequest::get().uri("/users"),
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "viewer",
+            test::TestRequest::post().uri("/room/on"),
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "viewer",
+            test::TestRequest::patch()
+                .uri(&device_path)
+                .set_json(serde_json::json!({"power": 1.0})),
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "operator",
+            test::TestRequest::patch()
+                .uri(&device_path)
+                .set_json(serde_json::json!({"power": 1.0})),
+            StatusCode::OK,
+        ),
+        (
+            "operator",
+            test::TestRequest::post().uri("/room/on"),
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "operator",
+            test::TestRequest::delete().uri(&device_path),
+            StatusCode::FORBIDDEN,
+        ),
+    ];
+    for (name, req, status) in cases {
+        let req = req.insert_header(("X-API-Key", keys[name].as_str()));
+        assert_eq!(call(req).await.status(), status, "{name}");
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/users")
+        .insert_header(("X-API-Key", "admin-key"));
+    let resp = call(req).await;
+    let users: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(users.as_array().unwrap().len(), 3);
+    assert_eq!(users[2]["name"], "viewer");
+    assert_eq!(users[2]["role"], "viewer");
+    assert!(users[2].get("api_key").is_none());
+
+    let req = test::TestRequest::post()
+        .uri("/auth/token")
+        .insert_header(("X-API-Key", keys["viewer"].as_str()));
+    let resp = call(req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token: AuthToken = test::read_body_json(resp).await;
+    let bearer = format!("Bearer {}", token.token);
+
+    let req = test::TestRequest::get()
+        .uri("/auth/me")
+        .insert_header(("Authorization", bearer.as_str()));
+    let resp = call(req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let user: ApiUser = test::read_body_json(resp).await;
+    assert_eq!(user.role, Role::Viewer);
+    let req = test::TestRequest::post()
+        .uri("/room/on")
+        .insert_header(("Authorization", bearer.as_str()));
+    assert_eq!(call(req).await.status(), StatusCode::FORBIDDEN);
+    let req = test::TestRequest::get()
+        .uri("/rooms")
+        .insert_header(("Authorization", "Bearer qqq"));
+    assert_eq!(call(req).await.status(), StatusCode::UNAUTHORIZED);
+
+    // удалённый пользователь теряет доступ и по ключу, и по JWT
+    let req = test::TestRequest::delete()
+        .uri("/users/viewer")
+        .insert_header(("X-API-Key", "admin-key"));
+    assert_eq!(call(req).await.status(), StatusCode::OK);
+    let req = test::TestRequest::get()
+        .uri("/rooms")
+        .insert_header(("X-API-Key", keys["viewer"].as_str()));
+    assert_eq!(call(req).await.status(), StatusCode::UNAUTHORIZED);
+    let req = test::TestRequest::get()
+        .uri("/rooms")
+        .insert_header(("Authorization", bearer.as_str()));
+    assert_eq!(call(req).await.status(), StatusCode::UNAUTHORIZED);
+
+    // после замены ключа действует только новый ключ
+    data.set_api_user_key("admin", "new-admin-key")
+        .await
+        .unwrap();
+    for (key, status) in [
+        ("admin-key", StatusCode::UNAUTHORIZED),
+        ("new-admin-key", StatusCode::OK),
+    ] {
+        let req = test::TestRequest::get()
+            .uri("/rooms")
+            .insert_header(("X-API-Key", key));
+        assert_eq!(call(req).await.status(), status, "{key}");
+    }
+    assert!(matches!(
+        data.set_api_user_key("nobody", "key").await,
+        Err(SmartHouseError::UserNotFoundError(_))
+    ));
+}
+
+#[actix_web::test]
+async fn test_http_routes_v1_v2() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let room = encode(HALLWAY);
+
+    let req = test::TestRequest::post().uri(&format!("/rooms/{room}"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert!(resp.headers().get("Deprecation").is_none());
+
+    // v2: комната, затем устройство
+    let path = format!("/rooms/{room}/devices/{}", encode(SWITCH_2));
+    let req = test::TestRequest::post().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    // v1: устройство, затем комната, ответ помечен как устаревший
+    let path = format!("/device/{}/room/{room}", encode(SOCKET_3));
+    let req = test::TestRequest::post().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(resp.headers().get("Deprecation").unwrap(), "true");
+
+    // имена в v1 не переставляются: комнаты с именем устройства нет
+    let path = format!("/device/{room}/room/{}", encode(SOCKET_3));
+    let req = test::TestRequest::post().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let req = test::TestRequest::get().uri(&format!("/rooms/{room}/devices"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let records: Vec<SmartDeviceRecord> = test::read_body_json(resp).await;
+    let names: Vec<&str> = records.iter().map(|record| record.name.as_str()).collect();
+    assert_eq!(names, [SWITCH_2, SOCKET_3]);
+
+    let expected = format!("[\"{SWITCH_2}\",\"{SOCKET_3}\"]");
+    let path = format!("/devices/{room}");
+    test_http_helper(data.clone(), &path, Method::GET, StatusCode::OK, expected).await;
+
+    for path in [
+        format!("/rooms/{room}/devices/{}/record", encode(SOCKET_3)),
+        format!("/device/{}/room/{room}/record", encode(SOCKET_3)),
+    ] {
+        let req = test::TestRequest::get().uri(&path);
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{path}");
+        let record: SmartDeviceRecord = test::read_body_json(resp).await;
+        assert_eq!(record.name, SOCKET_3);
+    }
+
+    let path = format!("/rooms/{room}/devices/{}", encode(SOCKET_3));
+    let req = test::TestRequest::delete().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let path = format!("/device/{}/room/{room}", encode(SWITCH_2));
+    let req = test::TestRequest::delete().uri(&path);
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri(&format!("/rooms/{room}/devices"));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    let records: Vec<SmartDeviceRecord> = test::read_body_json(resp).await;
+    assert!(records.is_empty());
+
+    let req = test::TestRequest::delete().uri(&format!("/rooms/{room}"));
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_http_routes_openapi() {
+    let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    let paths = &doc["paths"];
+
+    for (path, method, deprecated) in [
+        ("/rooms", "get", false),
+        ("/room/{room_name}", "post", true),
+        ("/devices/{room_name}", "get", true),
+        ("/device/{device_name}/room/{room_name}", "post", true),
+        ("/device/{device_name}/room/{room_name}/on", "post", true),
+        ("/rooms/{room_name}", "post", false),
+        ("/rooms/{room_name}/devices", "get", false),
+        ("/rooms/{room_name}/devices/{device_name}", "post", false),
+        ("/rooms/{room_name}/devices/{device_name}/on", "post", false),
+    ] {
+        let operation = &paths[path][method];
+        assert!(operation.is_object(), "{method} {path}");
+        assert_eq!(
+            operation["deprecated"].as_bool().unwrap_or_default(),
+            deprecated,
+            "{method} {path}"
+        );
+
+        let mut params: Vec<&str> = operation["parameters"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|param| param["in"] == "path")
+            .map(|param| param["name"].as_str().unwrap())
+            .collect();
+        params.sort();
+        let mut expected: Vec<&str> = path
+            .split('/')
+            .filter_map(|part| part.strip_prefix('{')?.strip_suffix('}'))
+            .collect();
+        expected.sort();
+        assert_eq!(params, expected, "{method} {path}");
+    }
+}
+
+#[actix_web::test]
+async fn test_http_errors() {
+    let app_data = new_house_http().await.unwrap();
+    app_data
+        .add_api_user_with_key("admin", Role::Admin, "admin-key")
+        .await
+        .unwrap();
+    let data = web::Data::new(app_data);
+    let authenticator = web::Data::new(Authenticator::new());
+    let call = |req: test::TestRequest| {
+        test_http_request_id_call_helper(data.clone(), authenticator.clone(), req)
+    };
+
+    // ошибка middleware аутентификации: идентификатор запроса назначается сервером
+    let resp = call(test::TestRequest::get().uri("/rooms")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let request_id = resp.headers().get("X-Request-Id").unwrap().clone();
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.code, ErrorCode::Unauthorized);
+    assert!(body.details.unwrap()["reason"].is_string());
+    assert_eq!(body.request_id.unwrap(), request_id.to_str().unwrap());
+
+    // ошибка обработчика: идентификатор запроса берётся у клиента
+    let req = test::TestRequest::get()
+        .uri(&format!("/houses/{}", encode("Нет такого")))
+        .insert_header(("X-API-Key", "admin-key"))
+        .insert_header(("X-Request-Id", "req-42"));
+    let resp = call(req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers().get("X-Request-Id").unwrap(), "req-42");
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "code": "house_not_found",
+            "message": SmartHouseError::HouseNotFoundError("Нет такого".to_string()).to_string(),
+            "details": {"house": "Нет такого"},
+            "request_id": "req-42"
+        })
+    );
+
+    // ошибка разбора тела запроса
+    let path = format!("/rooms/{}/devices/{}", encode(KITCHEN), encode(SOCKET_1));
+    let req = test::TestRequest::patch()
+        .uri(&path)
+        .insert_header(("X-API-Key", "admin-key"))
+        .insert_header(("Content-Type", "application/json"))
+        .set_payload("{\"power\": ");
+    let resp = call(req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.code, ErrorCode::ValidationFailed);
+    assert!(body.request_id.is_some());
+
+    let req = test::TestRequest::get()
+        .uri("/rooms")
+        .insert_header(("X-API-Key", "admin-key"));
+    let resp = call(req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().contains_key("X-Request-Id"));
+
+    let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    assert!(doc["components"]["schemas"]["ErrorResponse"].is_object());
+    assert!(doc["components"]["schemas"]["ErrorCode"].is_object());
+    assert_eq!(
+        doc["paths"]["/houses/{house_name}"]["get"]["responses"]["404"]["content"]
+            ["application/json"]["schema"]["$ref"],
+        "#/components/schemas/ErrorResponse"
+    );
+}
+
+#[actix_web::test]
+async fn test_http_import_error_details() {
+    let kitchen = ImportChange {
+        room: KITCHEN.to_string(),
+        device: None,
+    };
+    let kitchen_socket = ImportChange {
+        room: KITCHEN.to_string(),
+        device: Some(SOCKET_1.to_string()),
+    };
+    let err = SmartHouseError::ImportError(Box::new(ImportFailure {
+        applied: ImportReport {
+            house: HOUSE_NAME.to_string(),
+            mode: ImportMode::Replace,
+            dry_run: false,
+            house_created: false,
+            created: vec![kitchen.clone()],
+            updated: Vec::new(),
+            removed: Vec::new(),
+            conflicts: Vec::new(),
+        },
+        failed: Some(kitchen_socket.clone()),
+        error: SmartHouseError::DeviceAlreadyExistsError(KITCHEN.to_string(), SOCKET_1.to_string()),
+    }));
+    assert_eq!(err.status_code(), StatusCode::CONFLICT);
+
+    // код и детали исходной ошибки дополняются применённой частью импорта
+    let response = ErrorResponse::new(&err);
+    assert_eq!(response.code, ErrorCode::DeviceAlreadyExists);
+    let details = response.details.unwrap();
+    assert_eq!(details["room"], KITCHEN);
+    assert_eq!(details["device"], SOCKET_1);
+    let applied: ImportReport = serde_json::from_value(details["applied"].clone()).unwrap();
+    assert_eq!(applied.created, [kitchen]);
+    let failed: ImportChange = serde_json::from_value(details["failed"].clone()).unwrap();
+    assert_eq!(failed, kitchen_socket);
+}
+
+#[actix_web::test]
+async fn test_http_error_response_into_error() {
+    let errors = [
+        SmartHouseError::HouseNotFoundError(HOUSE_NAME.to_string()),
+        SmartHouseError::RoomAlreadyExistsError(KITCHEN.to_string()),
+        SmartHouseError::RoomsNotFoundError,
+        SmartHouseError::DeviceNotFoundError(KITCHEN.to_string(), SOCKET_1.to_string()),
+        SmartHouseError::DeviceControlError("нет связи".to_string()),
+        SmartHouseError::UserAlreadyExistsError("admin".to_string()),
+        SmartHouseError::ForbiddenError("нужна роль admin".to_string()),
+        SmartHouseError::PreconditionFailedError("версия 3".to_string()),
+        SmartHouseError::OtherError("сбой".to_string()),
+        SmartHouseError::UnauthorizedError("неизвестный ключ API".to_string()),
+        SmartHouseError::ValidationError("некорректный шаг".to_string()),
+        LimitError::InvalidName {
+            kind: NameKind::Room,
+            name: "Кухня/2".to_string(),
+            reason: NameViolation::Character('/'),
+        }
+        .into(),
+        LimitError::InvalidName {
+            kind: NameKind::Device,
+            name: "Розетка".to_string(),
+            reason: NameViolation::TooLong(64),
+        }
+        .into(),
+        LimitError::InvalidName {
+            kind: NameKind::House,
+            name: " Дом".to_string(),
+            reason: NameViolation::Whitespace,
+        }
+        .into(),
+        LimitError::InvalidName {
+            kind: NameKind::User,
+            name: String::new(),
+            reason: NameViolation::Empty,
+        }
+        .into(),
+        LimitError::PathTooLong(2048).into(),
+        LimitError::BodyTooLarge(1024).into(),
+        LimitError::RateLimited(StdDuration::from_secs(7)).into(),
+        IdempotencyError::KeyReused("key-1".to_string()).into(),
+    ];
+
+    for err in errors {
+        let response = ErrorResponse::new(&err);
+        let restored = SmartHouseError::from(response.clone());
+        assert_eq!(restored.code(), err.code(), "{err}");
+        assert_eq!(restored.to_string(), err.to_string());
+        assert_eq!(restored.details(), response.details, "{err}");
+    }
+
+    let err: SmartHouseError = LimitError::InvalidName {
+        kind: NameKind::Room,
+        name: "Кухня/2".to_string(),
+        reason: NameViolation::Character('/'),
+    }
+    .into();
+    let details = err.details().unwrap();
+    assert_eq!(details["room"], "Кухня/2");
+    assert_eq!(details["kind"], "room");
+    assert_eq!(details["reason"], "character");
+    assert_eq!(details["character"], "/");
+
+    // без полей в `details` сообщение не разбирается, а сохраняется целиком
+    let response = ErrorResponse {
+        code: ErrorCode::InvalidName,
+        message: "недопустимое название".to_string(),
+        details: None,
+        request_id: None,
+    };
+    let err = SmartHouseError::from(response);
+    assert!(
+        matches!(err, SmartHouseError::ValidationError(message) if message == "недопустимое название")
+    );
+    let response = ErrorResponse {
+        code: ErrorCode::DeviceControlFailed,
+        message: "нет связи".to_string(),
+        details: None,
+        request_id: None,
+    };
+    let err = SmartHouseError::from(response);
+    assert!(matches!(err, SmartHouseError::DeviceControlError(message) if message == "нет связи"));
+}
+
+#[actix_web::test]
+async fn test_http_pagination() {
+    let app_data = new_house_http().await.unwrap();
+    app_data
+        .add_room(HOUSE_NAME, "Кладовая", None)
+        .await
+        .unwrap();
+    app_data
+        .add_room(HOUSE_NAME, "Кабинет", None)
+        .await
+        .unwrap();
+    app_data
+        .add_device_with_meta(
+            HOUSE_NAME,
+            KITCHEN,
+            SOCKET_3,
+            &SmartDeviceMeta::new(DeviceKind::Socket),
+            None,
+        )
+        .await
+        .unwrap();
+    let data = web::Data::new(app_data);
+
+    // постраничный обход по курсору
+    let mut pages = Vec::new();
+    let mut uri = "/rooms?limit=2".to_string();
+    loop {
+        let (rooms, cursor) = test_http_page_helper(data.clone(), &uri).await;
+        pages.push(rooms);
+        match cursor {
+            Some(cursor) => uri = format!("/rooms?limit=2&cursor={cursor}"),
+            None => break,
+        }
+    }
+    assert_eq!(
+        pages,
+        [
+            vec![LIVING_ROOM, "Кабинет"],
+            vec!["Кладовая", KITCHEN],
+            vec![BEDROOM]
+        ]
+    );
+
+    let uri = format!("/rooms?prefix={}&order=desc", encode("К"));
+    let (rooms, cursor) = test_http_page_helper(data.clone(), &uri).await;
+    assert_eq!(rooms, [KITCHEN, "Кладовая", "Кабинет"]);
+    assert_eq!(cursor, None);
+
+    let uri = format!("/rooms?contains={}", encode("ла"));
+    let (rooms, _) = test_http_page_helper(data.clone(), &uri).await;
+    assert_eq!(rooms, ["Кладовая"]);
+
+    let uri = format!("/houses/{}/rooms?limit=1", encode(HOUSE_NAME));
+    let (rooms, cursor) = test_http_page_helper(data.clone(), &uri).await;
+    assert_eq!(rooms, [LIVING_ROOM]);
+    assert!(cursor.is_some());
+
+    // фильтры устройств по типу и статусу
+    let devices_path = format!("/rooms/{}/devices", encode(KITCHEN));
+    for (query, expected) in [
+        ("kind=socket", vec![SOCKET_3]),
+        ("status=on", vec![SWITCH_1, SOCKET_1]),
+        ("status=unknown", vec![SOCKET_3]),
+        ("status=off&kind=unknown", vec![SOCKET_2]),
+        ("order=desc", vec![SOCKET_3, SOCKET_2, SOCKET_1, SWITCH_1]),
+    ] {
+        let uri = format!("{devices_path}?{query}");
+        let (devices, _) = test_http_page_helper(data.clone(), &uri).await;
+        assert_eq!(devices, expected, "{query}");
+    }
+
+    let uri = format!("/devices/{}?limit=1&order=desc", encode(KITCHEN));
+    let (devices, cursor) = test_http_page_helper(data.clone(), &uri).await;
+    assert_eq!(devices, [SOCKET_3]);
+    let uri = format!(
+        "/devices/{}?limit=1&order=desc&cursor={}",
+        encode(KITCHEN),
+        cursor.unwrap()
+    );
+    let (devices, _) = test_http_page_helper(data.clone(), &uri).await;
+    assert_eq!(devices, [SOCKET_2]);
+
+    for uri in [
+        "/rooms?limit=0".to_string(),
+        "/rooms?limit=1001".to_string(),
+        "/rooms?cursor=zz".to_string(),
+        "/rooms?order=random".to_string(),
+        "/rooms?unknown=1".to_string(),
+        format!("{devices_path}?status=broken"),
+        format!("{devices_path}?kind=lamp"),
+    ] {
+        let req = test::TestRequest::get().uri(&uri);
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{uri}");
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.code, ErrorCode::ValidationFailed, "{uri}");
+    }
+}
+
+#[actix_web::test]
+async fn test_http_health() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    for uri in ["/healthz", "/readyz"] {
+        let req = test::TestRequest::get().uri(uri);
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+        let health: HealthStatus = test::read_body_json(resp).await;
+        assert_eq!(health.status, HealthState::Ok);
+        assert_eq!(health.storage, HealthState::Ok);
+        assert!(health.error.is_none());
+    }
+
+    // клиент MongoDB подключается лениво, поэтому ошибка видна только при проверке
+    let storage = SmartHouseStorageMongoDB::new(UNAVAILABLE_MONGO_DB_URI)
+        .await
+        .unwrap();
+    let data = web::Data::new(AppData::new(
+        HOUSE_NAME.to_string(),
+        HOUSE_ADDRESS.to_string(),
+        Box::new(storage),
+    ));
+
+    let req = test::TestRequest::get().uri("/healthz");
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let health: HealthStatus = test::read_body_json(resp).await;
+    assert_eq!(health.status, HealthState::Ok);
+    assert_eq!(health.storage, HealthState::Unavailable);
+    assert!(health.error.is_some());
+
+    let req = test::TestRequest::get().uri("/readyz");
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let health: HealthStatus = test::read_body_json(resp).await;
+    assert_eq!(health.status, HealthState::Unavailable);
+    assert_eq!(health.storage, HealthState::Unavailable);
+
+    let start = std::time::Instant::now();
+    let delay = StdDuration::from_millis(50);
+    let result = SmartHouseStorageMongoDB::connect(UNAVAILABLE_MONGO_DB_URI, 3, delay).await;
+    assert!(result.is_err());
+    // пауза удваивается: 50 мс после первой попытки и 100 мс после второй
+    assert!(start.elapsed() >= delay * 3);
+}
+
+/// Проверяется с MongoDB, если задана переменная `MONGO_DB_URI`
+#[actix_web::test]
+async fn test_http_mongodb_remove_room() {
+    let Ok(uri) = std::env::var("MONGO_DB_URI") else {
+        return;
+    };
+    let house = "Мой умный дом (mongodb)";
+    let storage = SmartHouseStorageMongoDB::new(&uri).await.unwrap();
+    let mut app_data = AppData::new(
+        house.to_string(),
+        HOUSE_ADDRESS.to_string(),
+        Box::new(storage),
+    );
+    let _ = app_data.storage.remove_house(house, None).await;
+    app_data.init(generate_mock_devices()).await.unwrap();
+
+    app_data.remove_room(house, KITCHEN, None).await.unwrap();
+
+    // устройства удалённой комнаты не попадают ни в отчёт, ни в конфигурацию
+    let report = app_data.house_report(house).await.unwrap();
+    assert!(!report.devices().contains_key(KITCHEN));
+    assert!(report.devices().contains_key(LIVING_ROOM));
+    let snapshot = app_data.export_house(house).await.unwrap();
+    assert!(snapshot.rooms.iter().all(|room| room.name != KITCHEN));
+
+    // комната с тем же названием создаётся пустой
+    app_data.add_room(house, KITCHEN, None).await.unwrap();
+    assert!(app_data.devices(house, KITCHEN).await.unwrap().is_empty());
+    let report = app_data.house_report(house).await.unwrap();
+    assert!(report
+        .devices()
+        .get(KITCHEN)
+        .is_none_or(|devices| devices.is_empty()));
+
+    app_data.storage.remove_house(house, None).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_http_config() {
+    let config = AppConfig::from_toml("").unwrap();
+    assert_eq!(config.server.bind_address, "127.0.0.1:8000");
+    assert_eq!(config.storage.backend, StorageBackend::Memory);
+    assert_eq!(config.houses.len(), 1);
+    assert!(config.validate().is_ok());
+
+    let text = format!(
+        r#"
+[server]
+bind_address = "127.0.0.1:8081"
+workers = 4
+
+[[houses]]
+name = "{HOUSE_NAME}"
+address = "{HOUSE_ADDRESS}"
+
+[[houses.rooms]]
+name = "{KITCHEN}"
+
+[[houses.rooms.devices]]
+name = "{SOCKET_1}"
+meta = {{ kind = "socket", address = "127.0.0.1:54321" }}
+info = {{ status = "on", power = 100.0, temp = 0.0 }}
+
+[[houses]]
+name = "Дача"
+address = "СНТ Умное"
+"#
+    );
+    let path = std::env::temp_dir().join(format!("smart_home_{}.toml", uuid::Uuid::new_v4()));
+    std::fs::write(&path, &text).unwrap();
+
+    // файл заменяет базовую конфигурацию, а флаги - значения из файла
+    let args = CliArgs::try_parse_from([
+        "http_server",
+        "--config",
+        path.to_str().unwrap(),
+        "--workers",
+        "8",
+        "--history-interval",
+        "0",
+    ])
+    .unwrap();
+    let config = AppConfig::default().with_args(&args).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(config.server.bind_address, "127.0.0.1:8081");
+    assert_eq!(config.server.workers, 8);
+    assert!(config.server.history_interval().is_none());
+    assert_eq!(config.default_house().name, HOUSE_NAME);
+    assert_eq!(config.houses.len(), 2);
+
+    let args = CliArgs::try_parse_from(["http_server", "--mongo-db-uri", "mongodb://db/home"]);
+    let mongo = AppConfig::default().with_args(&args.unwrap()).unwrap();
+    assert_eq!(mongo.storage.backend, StorageBackend::MongoDB);
+
+    for (text, args) in [
+        (
+            r#"[server]
+bind_address = "8000""#,
+            vec![],
+        ),
+        ("", vec!["--storage", "mongodb"]),
+        ("", vec!["--mongo-db-uri", "http://db/home"]),
+        ("", vec!["--workers", "0"]),
+        ("", vec!["--jwt-secret", "secret"]),
+        ("houses = []", vec![]),
+        ("[limits]\nburst = 0", vec![]),
+        ("", vec!["--rate-limit=-1"]),
+        (
+            r#"[[houses]]
+name = "Дом"
+address = "1"
+[[houses]]
+name = "Дом"
+address = "2""#,
+            vec![],
+        ),
+    ] {
+        let args = CliArgs::try_parse_from(std::iter::once("http_server").chain(args)).unwrap();
+        let result = AppConfig::from_toml(text).and_then(|config| config.with_args(&args));
+        assert!(
+            matches!(result, Err(SmartHouseError::ValidationError(_))),
+            "{text} {args:?}"
+        );
+    }
+    assert!(AppConfig::from_toml("[server]\nport = 8000").is_err());
+    assert!(AppConfig::from_toml("[storage]\nbackend = \"redis\"").is_err());
+
+    let app_data = AppData::from_config(&config).await.unwrap();
+    assert_eq!(app_data.rooms(HOUSE_NAME).await.unwrap(), vec![KITCHEN]);
+    assert_eq!(
+        app_data.devices(HOUSE_NAME, KITCHEN).await.unwrap(),
+        vec![SOCKET_1]
+    );
+
+    let data = web::Data::new(app_data);
+    let req = test::TestRequest::get().uri(&format!(
+        "/rooms/{}/devices/{}",
+        encode(KITCHEN),
+        encode(SOCKET_1)
+    ));
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri(&format!("/houses/{}", encode("Дача")));
+    let resp = test_http_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_http_limits() {
+    let limits = Limits {
+        max_name_len: 16,
+        max_path_len: 128,
+        max_body_bytes: 256,
+        requests_per_sec: 1.0,
+        burst: 3,
+    };
+    let app_data = new_house_http().await.unwrap().with_limits(limits.clone());
+    let data = web::Data::new(app_data);
+
+    for (room, reason) in [
+        ("Очень-длинная-комната", "длиннее 16 символов"),
+        (" Кухня", "начинается или заканчивается пробелом"),
+        ("Кухня<script>", "недопустимый символ '<'"),
+    ] {
+        let req = test::TestRequest::post().uri(&format!("/rooms/{}", encode(room)));
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{room}");
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.code, ErrorCode::InvalidName);
+        assert!(body.message.ends_with(reason), "{}", body.message);
+    }
+    let err = limits
+        .validate_name(NameKind::Device, "Очень-длинное-устройство")
+        .unwrap_err();
+    assert_eq!(
+        err.details(),
+        Some(serde_json::json!({
+            "device": "Очень-длинное-ус…",
+            "kind": "device",
+            "reason": "too_long",
+            "max_len": 16,
+        }))
+    );
+    assert!(limits.validate_name(NameKind::House, HOUSE_NAME).is_err());
+    assert!(Limits::default()
+        .validate_name(NameKind::House, HOUSE_NAME)
+        .is_ok());
+
+    let snapshot = HouseSnapshot {
+        name: "Дом".to_string(),
+        address: HOUSE_ADDRESS.to_string(),
+        rooms: vec![RoomSnapshot {
+            name: "Комната/1".to_string(),
+            devices: Vec::new(),
+        }],
+    };
+    let result = data.import_house(&snapshot, ImportMode::Merge, true).await;
+    assert!(matches!(
+        result,
+        Err(SmartHouseError::LimitError(LimitError::InvalidName {
+            kind: NameKind::Room,
+            ..
+        }))
+    ));
+
+    // путь и тело проверяются до обработчика, ответ в общем формате ошибок
+    let peer = "10.0.0.1:1000".parse().unwrap();
+    let req = test::TestRequest::get()
+        .uri(&format!("/rooms/{}/devices", encode(&"к".repeat(64))))
+        .peer_addr(peer);
+    let resp = test_http_limits_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.code, ErrorCode::RequestTooLarge);
+    assert!(body.request_id.is_some());
+
+    let req = test::TestRequest::put()
+        .uri(&format!(
+            "/rooms/{}/devices/{}",
+            encode(KITCHEN),
+            encode(SOCKET_1)
+        ))
+        .peer_addr(peer)
+        .set_json(serde_json::json!({ "status": "on", "padding": "x".repeat(512) }));
+    let resp = test_http_limits_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.code, ErrorCode::RequestTooLarge);
+    assert_eq!(
+        body.details,
+        Some(serde_json::json!({ "max_bytes": 256, "limit": "body" }))
+    );
+
+    // корзина клиента на 3 запроса пуста, у другого клиента своя корзина
+    let req = test::TestRequest::get().uri("/rooms").peer_addr(peer);
+    let resp = test_http_limits_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = test::TestRequest::get().uri("/rooms").peer_addr(peer);
+    let resp = test_http_limits_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.headers().get("Retry-After").unwrap(), "1");
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.code, ErrorCode::RateLimited);
+    assert_eq!(
+        body.details,
+        Some(serde_json::json!({ "retry_after_secs": 1 }))
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/rooms")
+        .peer_addr("10.0.0.2:1000".parse().unwrap());
+    let resp = test_http_limits_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // тело больше ограничения actix-web (2 МиБ), но в пределах max_body_bytes, разбирается
+    let limits = Limits {
+        max_body_bytes: 4 * 1024 * 1024,
+        ..Limits::default()
+    };
+    let data = web::Data::new(new_house_http().await.unwrap().with_limits(limits));
+    let path = format!("/rooms/{}/devices/{}", &encode(KITCHEN), &encode(SOCKET_1));
+    let req = test::TestRequest::patch()
+        .uri(&path)
+        .set_json(serde_json::json!({"status": "x".repeat(3 * 1024 * 1024)}));
+    let resp = test_http_limits_call_helper(data, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let error: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(error.code, ErrorCode::ValidationFailed);
+
+    // корзины клиентов не растут без предела
+    let limiter = RateLimiter::new(&Limits {
+        requests_per_sec: 0.001,
+        ..Limits::default()
+    });
+    for client in 0..25000 {
+        assert!(limiter.acquire(&client.to_string()).is_ok());
+    }
+    assert!(limiter.clients() <= 20000);
+    assert!(limiter.acquire("24999").is_ok());
+
+    let limiter = RateLimiter::new(&Limits {
+        requests_per_sec: 20.0,
+        burst: 1,
+        ..Limits::default()
+    });
+    assert!(limiter.acquire("client").is_ok());
+    assert!(limiter.acquire("client").is_err());
+    actix_web::rt::time::sleep(StdDuration::from_millis(60)).await;
+    assert!(limiter.acquire("client").is_ok());
+    let unlimited = RateLimiter::new(&Limits {
+        requests_per_sec: 0.0,
+        ..Limits::default()
+    });
+    assert!((0..1000).all(|_| unlimited.acquire("client").is_ok()));
+}
+
+#[actix_web::test]
+async fn test_http_dashboard() {
+    let app_data = new_house_http().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(app_data))
+            .configure(dashboard_config)
+            .configure(config),
+    )
+    .await;
+
+    for uri in ["/", "/dashboard"] {
+        let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert!(resp.status().is_redirection(), "{uri}");
+        assert_eq!(resp.headers().get("Location").unwrap(), "/dashboard/");
+    }
+
+    let req = test::TestRequest::get().uri("/dashboard/").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp
+        .headers()
+        .get("Content-Type")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let etag = resp.headers().get("ETag").unwrap().clone();
+    let body = test::read_body(resp).await;
+    let html = std::str::from_utf8(&body).unwrap();
+    assert!(html.contains("app.js") && html.contains("style.css"));
+
+    let req = test::TestRequest::get()
+        .uri("/dashboard/index.html")
+        .insert_header(("If-None-Match", etag))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    for (uri, content_type) in [
+        ("/dashboard/app.js", "javascript"),
+        ("/dashboard/style.css", "text/css"),
+    ] {
+        let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+        let header = resp
+            .headers()
+            .get("Content-Type")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(header.contains(content_type), "{uri}: {header}");
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/dashboard/missing.js")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_http_cors() {
+    const ORIGIN: &str = "https://panel.example.com";
+
+    let app_data = web::Data::new(new_house_http().await.unwrap());
+    let authenticator = web::Data::new(Authenticator::new());
+    let cors = CorsConfig {
+        allowed_origins: vec![ORIGIN.to_string()],
+        ..CorsConfig::default()
+    };
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&app_data))
+            .app_data(web::Data::clone(&authenticator))
+            .wrap(from_fn(auth_middleware))
+            .wrap(cors.cors())
+            .configure(config),
+    )
+    .await;
+
+    // предварительный запрос браузера проходит без ключа API
+    let req = test::TestRequest::default()
+        .method(Method::OPTIONS)
+        .uri("/rooms")
+        .insert_header(("Origin", ORIGIN))
+        .insert_header(("Access-Control-Request-Method", "POST"))
+        .insert_header((
+            "Access-Control-Request-Headers",
+            "x-api-key, content-type, if-match, idempotency-key",
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let headers = resp.headers();
+    assert_eq!(headers.get("Access-Control-Allow-Origin").unwrap(), ORIGIN);
+    let methods = headers
+        .get("Access-Control-Allow-Methods")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(methods.contains("PATCH"), "{methods}");
+
+    let req = test::TestRequest::get()
+        .uri("/rooms")
+        .insert_header(("Origin", ORIGIN))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        resp.headers().get("Access-Control-Allow-Origin").unwrap(),
+        ORIGIN
+    );
+    let exposed = resp
+        .headers()
+        .get("Access-Control-Expose-Headers")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_lowercase();
+    for header in ["x-request-id", "etag", "idempotent-replayed"] {
+        assert!(exposed.contains(header), "{exposed}");
+    }
+
+    let req = test::TestRequest::default()
+        .method(Method::OPTIONS)
+        .uri("/rooms")
+        .insert_header(("Origin", "https://evil.example.com"))
+        .insert_header(("Access-Control-Request-Method", "GET"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get("Access-Control-Allow-Origin").is_none());
+
+    assert!(!CorsConfig::default().is_enabled());
+    for origins in [vec!["*"], vec![ORIGIN, "http://localhost:3000"]] {
+        let args = CliArgs::try_parse_from(["http_server", "--cors-origins", &origins.join(",")]);
+        let config = AppConfig::default().with_args(&args.unwrap()).unwrap();
+        assert_eq!(config.cors.allowed_origins, origins);
+    }
+    for origin in ["panel.example.com", "https://panel.example.com/", ""] {
+        let args = CliArgs::try_parse_from(["http_server", "--cors-origins", origin]).unwrap();
+        assert!(AppConfig::default().with_args(&args).is_err(), "{origin}");
+    }
+}
+
+#[actix_web::test]
+async fn test_http_metrics() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&data))
+            .wrap(from_fn(metrics_middleware))
+            .wrap(from_fn(request_id_middleware))
+            .configure(config),
+    )
+    .await;
+
+    for uri in ["/rooms", "/rooms", "/devices/nowhere"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        test::call_service(&app, req).await;
+    }
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp
+        .headers()
+        .get("Content-Type")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+    let requests = [("method", "GET"), ("route", "/rooms"), ("status", "200")];
+    assert_eq!(
+        test_metric_value(&body, "smart_home_http_requests_total", &requests),
+        Some(2.0)
+    );
+    let requests = [("route", "/devices/{room_name}"), ("status", "404")];
+    assert_eq!(
+        test_metric_value(&body, "smart_home_http_requests_total", &requests),
+        Some(1.0)
+    );
+    let duration = [("method", "GET"), ("route", "/rooms")];
+    assert_eq!(
+        test_metric_value(
+            &body,
+            "smart_home_http_request_duration_seconds_count",
+            &duration
+        ),
+        Some(2.0)
+    );
+    let errors = [("error", "RoomNotFoundError")];
+    assert_eq!(
+        test_metric_value(&body, "smart_home_errors_total", &errors),
+        Some(1.0)
+    );
+    let storage = [("operation", "find_rooms")];
+    assert_eq!(
+        test_metric_value(
+            &body,
+            "smart_home_storage_operation_duration_seconds_count",
+            &storage
+        ),
+        Some(2.0)
+    );
+
+    let socket = [
+        ("house", HOUSE_NAME),
+        ("room", KITCHEN),
+        ("device", SOCKET_1),
+    ];
+    let power = test_metric_value(&body, "smart_home_device_power_watts", &socket).unwrap();
+    assert!((power - 111.222).abs() < 1e-3);
+    assert_eq!(
+        test_metric_value(&body, "smart_home_device_on", &socket),
+        Some(1.0)
+    );
+    let switch = [("room", BEDROOM), ("device", SWITCH_1)];
+    assert_eq!(
+        test_metric_value(&body, "smart_home_device_on", &switch),
+        Some(0.0)
+    );
+    let thermometer = [("room", BEDROOM), ("device", THERMOMETER_2)];
+    let temp =
+        test_metric_value(&body, "smart_home_device_temperature_celsius", &thermometer).unwrap();
+    assert!((temp - 22.33).abs() < 1e-3);
+    assert_eq!(
+        test_metric_value(&body, "smart_home_device_on", &thermometer),
+        None
+    );
+}
+
+#[actix_web::test]
+async fn test_http_export_import() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+
+    let req = test::TestRequest::get().uri("/house/export");
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let snapshot: HouseSnapshot = test::read_body_json(resp).await;
+    assert_eq!(snapshot.name, HOUSE_NAME);
+    assert_eq!(snapshot.address, HOUSE_ADDRESS);
+    let rooms: Vec<&str> = snapshot.rooms.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(rooms, [LIVING_ROOM, KITCHEN, BEDROOM]);
+    let kitchen = &snapshot.rooms[1];
+    let devices: Vec<&str> = kitchen.devices.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(devices, [SWITCH_1, SOCKET_1, SOCKET_2]);
+    let info = kitchen.devices[1].info.as_ref().unwrap();
+    assert_eq!(info.status, Some(DeviceStatus::On.to_string()));
+    assert_eq!(info.power, Some(111.222));
+
+    for req in [
+        test::TestRequest::get().uri("/house/export?format=yaml"),
+        test::TestRequest::get()
+            .uri("/house/export")
+            .insert_header(("Accept", "application/yaml")),
+    ] {
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get("Content-Type").unwrap(),
+            "application/yaml"
+        );
+        let body = test::read_body(resp).await;
+        let yaml: HouseSnapshot = serde_yaml::from_slice(&body).unwrap();
+        assert_eq!(
+            serde_json::to_value(&yaml).unwrap(),
+            serde_json::to_value(&snapshot).unwrap()
+        );
+    }
+
+    // повторный импорт экспортированной конфигурации ничего не меняет
+    let report = test_http_import_helper(data.clone(), "", "application/json", &snapshot).await;
+    assert!(!report.house_created);
+    assert!(report.created.is_empty() && report.updated.is_empty());
+    assert!(report.removed.is_empty() && report.conflicts.is_empty());
+
+    let mut changed = snapshot.clone();
+    changed.rooms.retain(|room| room.name != BEDROOM);
+    changed.rooms[1].devices[1].meta = SmartDeviceMeta::new(DeviceKind::Socket);
+    changed.rooms.push(RoomSnapshot {
+        name: HALLWAY.to_string(),
+        devices: vec![DeviceSnapshot {
+            name: SOCKET_3.to_string(),
+            meta: SmartDeviceMeta::new(DeviceKind::Socket),
+            info: Some(SmartDeviceInfoUpdate {
+                status: Some("on".to_string()),
+                power: Some(10.0),
+                temp: None,
+            }),
+        }],
+    });
+    let hallway = ImportChange {
+        room: HALLWAY.to_string(),
+        device: None,
+    };
+    let hallway_socket = ImportChange {
+        room: HALLWAY.to_string(),
+        device: Some(SOCKET_3.to_string()),
+    };
+    let kitchen_socket = ImportChange {
+        room: KITCHEN.to_string(),
+        device: Some(SOCKET_1.to_string()),
+    };
+
+    for mode in ["merge", "replace"] {
+        let query = format!("?mode={mode}&dry_run=true");
+        let report = test_http_import_helper(data.clone(), &query, "", &changed).await;
+        assert!(report.dry_run);
+        assert_eq!(report.created, [hallway.clone(), hallway_socket.clone()]);
+    }
+    let expected = format!("[\"{LIVING_ROOM}\",\"{KITCHEN}\",\"{BEDROOM}\"]");
+    test_http_helper(
+        data.clone(),
+        "/rooms",
+        Method::GET,
+        StatusCode::OK,
+        expected,
+    )
+    .await;
+
+    let report = test_http_import_helper(data.clone(), "?mode=merge", "", &changed).await;
+    assert_eq!(report.mode, ImportMode::Merge);
+    assert_eq!(report.created, [hallway.clone(), hallway_socket.clone()]);
+    assert!(report.updated.is_empty() && report.removed.is_empty());
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].room.as_deref(), Some(KITCHEN));
+    assert_eq!(report.conflicts[0].device.as_deref(), Some(SOCKET_1));
+    let expected = format!("[\"{LIVING_ROOM}\",\"{KITCHEN}\",\"{HALLWAY}\",\"{BEDROOM}\"]");
+    test_http_helper(
+        data.clone(),
+        "/rooms",
+        Method::GET,
+        StatusCode::OK,
+        expected,
+    )
+    .await;
+    let info = data
+        .device_info(HOUSE_NAME, HALLWAY, SOCKET_3)
+        .await
+        .unwrap();
+    assert_eq!(info.status(), DeviceStatus::On.to_string());
+    assert_eq!(info.power(), 10.0);
+
+    let mut events = data.subscribe();
+    let report = test_http_import_helper(data.clone(), "?mode=replace", "", &changed).await;
+    assert!(report.created.is_empty() && report.conflicts.is_empty());
+    // лишняя комната удаляется последней, после всех добавлений
+    let mut last_event = None;
+    while let Ok(event) = events.try_recv() {
+        last_event = Some(event);
+    }
+    assert!(matches!(
+        last_event,
+        Some(SmartHouseEvent::RoomRemoved { room, .. }) if room == BEDROOM
+    ));
+    assert_eq!(report.updated, [kitchen_socket]);
+    assert_eq!(
+        report.removed,
+        [ImportChange {
+            room: BEDROOM.to_string(),
+            device: None,
+        }]
+    );
+    let expected = format!("[\"{LIVING_ROOM}\",\"{KITCHEN}\",\"{HALLWAY}\"]");
+    test_http_helper(
+        data.clone(),
+        "/rooms",
+        Method::GET,
+        StatusCode::OK,
+        expected,
+    )
+    .await;
+    let record = data.device(HOUSE_NAME, KITCHEN, SOCKET_1).await.unwrap();
+    assert_eq!(record.kind, DeviceKind::Socket);
+
+    // импорт в новый дом из YAML
+    let mut new_house = changed.clone();
+    new_house.name = "Дача".to_string();
+    new_house.address = "СНТ Умное".to_string();
+    let req = test::TestRequest::post()
+        .uri("/house/import")
+        .insert_header(("Content-Type", "application/yaml"))
+        .set_payload(serde_yaml::to_string(&new_house).unwrap());
+    let resp = test_http_call_helper(data.clone(), req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: ImportReport = test::read_body_json(resp).await;
+    assert!(report.house_created);
+    assert_eq!(report.created.len(), 10);
+    let exported = data.export_house("Дача").await.unwrap();
+    assert_eq!(exported.address, "СНТ Умное");
+    assert_eq!(exported.rooms.len(), 3);
+
+    let mut duplicate = snapshot.clone();
+    duplicate.rooms.push(duplicate.rooms[0].clone());
+    let mut bad_meta = snapshot.clone();
+    bad_meta.rooms[0].devices[0].meta.address = Some("not an address".to_string());
+    for body in [
+        serde_json::to_string(&duplicate).unwrap(),
+        serde_json::to_string(&bad_meta).unwrap(),
+        "{\"name\": \"дом\"}".to_string(),
+        "not json".to_string(),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/house/import")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body.clone());
+        let resp = test_http_call_helper(data.clone(), req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{body}");
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.code, ErrorCode::ValidationFailed);
+    }
+
+    test_http_error_helper(
+        data,
+        "/house/export?house=nowhere",
+        Method::GET,
+        SmartHouseError::HouseNotFoundError("nowhere".to_string()),
+    )
+    .await;
+}
+
+#[actix_web::test]
+async fn test_http_etag() {
+    let app_data = new_house_http().await.unwrap();
+    let data = web::Data::new(app_data);
+    let call = |req: test::TestRequest| test_http_call_helper(data.clone(), req);
+    let etag = |resp: &ServiceResponse| {
+        resp.headers()
+            .get("ETag")
+            .map(|etag| etag.to_str().unwrap().to_string())
+    };
+
+    // версия устройства в ETag параметров и записи реестра
+    let path = format!(
+        "/rooms/{}/devices/{}",
+        encode(LIVING_ROOM),
+        encode(THERMOMETER_1)
+    );
+    let resp = call(test::TestRequest::get().uri(&format!("{path}/record"))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(etag(&resp).as_deref(), Some("\"1\""));
+    let record: SmartDeviceRecord = test::read_body_json(resp).await;
+    assert_eq!(record.version, 1);
+    let resp = call(test::TestRequest::get().uri(&path)).await;
+    assert_eq!(etag(&resp).as_deref(), Some("\"1\""));
+
+    let patch = |version: Option<&str>, temp: f32| {
+        let req = test::TestRequest::patch()
+            .uri(&path)
+            .set_json(serde_json::json!({"temp": temp}));
+        match version {
+            Some(version) => req.insert_header(("If-Match", version)),
+            None => req,
+        }
+    };
+    let resp = call(patch(Some("\"1\""), 21.0)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(etag(&resp).as_deref(), Some("\"2\""));
+
+    // устаревшая версия: изменение не применяется
+    let resp = call(patch(Some("\"1\""), 30.0)).await;
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.code, ErrorCode::PreconditionFailed);
+    let info = data
+        .device_info(HOUSE_NAME, LIVING_ROOM, THERMOMETER_1)
+        .await
+        .unwrap();
+    assert_eq!(info.temp(), 21.0);
+
+    for version in ["W/\"2\"", "\"abc\""] {
+        let resp = call(patch(Some(version), 30.0)).await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    }
+    for version in ["2", "\"2\", \"3\""] {
+        let resp = call(patch(Some(version), 30.0)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // без заголовка или с `*` изменение выполняется без проверки
+    let resp = call(patch(Some("*"), 22.0)).await;
+    assert_eq!(etag(&resp).as_deref(), Some("\"3\""));
+    let resp = call(patch(None, 23.0)).await;
+    assert_eq!(etag(&resp).as_deref(), Some("\"4\""));
+
+    // PUT устаревшего маршрута v1
+    let v1_path = format!(
+        "/device/{}/room/{}",
+        encode(THERMOMETER_1),
+        encode(LIVING_ROOM)
+    );
+    let body = serde_json::json!({"status": "on", "power": 1.0, "temp": 20.0});
+    let req = test::TestRequest::put()
+        .uri(&v1_path)
+        .insert_header(("If-Match", "\"3\""))
+        .set_json(&body);
+    assert_eq!(call(req).await.status(), StatusCode::PRECONDITION_FAILED);
+    let req = test::TestRequest::put()
+        .uri(&v1_path)
+        .insert_header(("If-Match", "\"4\""))
+        .set_json(&body);
+    let resp = call(req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(etag(&resp).as_deref(), Some("\"5\""));
+
+    let req = test::TestRequest::delete()
+        .uri(&path)
+        .insert_header(("If-Match", "\"4\""));
+    assert_eq!(call(req).await.status(), StatusCode::PRECONDITION_FAILED);
+
+    // команда устройству с устаревшей версией не отправляется
+    let req = test::TestRequest::post()
+        .uri(&format!("{path}/on"))
+        .insert_header(("If-Match", "\"4\""));
+    assert_eq!(call(req).await.status(), StatusCode::PRECONDITION_FAILED);
+
+    // версия комнаты меняется при добавлении и удалении устройств
+    let room_path = format!("/rooms/{}", encode(KITCHEN));
+    let resp = call(test::TestRequest::get().uri(&format!("{room_path}/devices"))).await;
+    let room_version = etag(&resp).unwrap();
+    let req = test::TestRequest::post().uri(&format!("{room_path}/devices/{}", encode(SOCKET_3)));
+    assert_eq!(call(req).await.status(), StatusCode::CREATED);
+    let resp = call(test::TestRequest::get().uri(&format!("{room_path}/devices"))).await;
+    assert_ne!(etag(&resp).unwrap(), room_version);
+    let new_room_version = etag(&resp).unwrap();
+
+    let resp = call(test::TestRequest::get().uri("/rooms")).await;
+    let house_version = etag(&resp).unwrap();
+    let req = test::TestRequest::delete()
+        .uri(&room_path)
+        .insert_header(("If-Match", room_version.as_str()));
+    assert_eq!(call(req).await.status(), StatusCode::PRECONDITION_FAILED);
+    let req = test::TestRequest::delete()
+        .uri(&room_path)
+        .insert_header(("If-Match", new_room_version.as_str()));
+    assert_eq!(call(req).await.status(), StatusCode::OK);
+    let resp = call(test::TestRequest::get().uri("/rooms")).await;
+    assert_ne!(etag(&resp).unwrap(), house_version);
+
+    // комната добавляется только при совпадении версии дома, устройство - версии комнаты
+    let attic_path = format!("/rooms/{}", encode("Чердак"));
+    let new_house_version = etag(&resp).unwrap();
+    let req = test::TestRequest::post()
+        .uri(&attic_path)
+        .insert_header(("If-Match", house_version.as_str()));
+    assert_eq!(call(req).await.status(), StatusCode::PRECONDITION_FAILED);
+    let req = test::TestRequest::post()
+        .uri(&attic_path)
+        .insert_header(("If-Match", new_house_version.as_str()));
+    assert_eq!(call(req).await.status(), StatusCode::CREATED);
+    let req = test::TestRequest::post()
+        .uri(&format!("{attic_path}/devices/{}", encode(SOCKET_3)))
+        .insert_header(("If-Match", "\"2\""));
+    assert_eq!(call(req).await.status(), StatusCode::PRECONDITION_FAILED);
+    let req = test::TestRequest::post()
+        .uri(&format!("{attic_path}/devices/{}", encode(SOCKET_3)))
+        .insert_header(("If-Match", "\"1\""));
+    assert_eq!(call(req).await.status(), StatusCode::CREATED);
+
+    // версия дома меняется при добавлении и удалении комнат
+    let house_path = format!("/houses/{}", encode("Дача"));
+    assert_eq!(
+        call(test::TestRequest::post().uri(&house_path))
+            .await
+            .status(),
+        StatusCode::CREATED
+    );
+    let resp = call(test::TestRequest::get().uri(&house_path)).await;
+    assert_eq!(etag(&resp).as_deref(), Some("\"1\""));
+    let req = test::TestRequest::post().uri(&format!("{house_path}/rooms/{}", encode(HALLWAY)));
+    assert_eq!(call(req).await.status(), StatusCode::CREATED);
+    let resp = call(test::TestRequest::get().uri(&format!("{house_path}/rooms"))).await;
+    assert_eq!(etag(&resp).as_deref(), Some("\"2\""));
+    let req = test::TestRequest::delete()
+        .uri(&house_path)
+        .insert_header(("If-Match", "\"1\""));
+    let resp = call(req).await;
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(
+        body.message,
+        SmartHouseError::PreconditionFailedError("дом 'Дача', текущая версия 2".to_string())
+            .to_string()
+    );
+    let req = test::TestRequest::delete()
+        .uri(&house_path)
+        .insert_header(("If-Match", "\"2\""));
+    assert_eq!(call(req).await.status(), StatusCode::OK);
+    let req = test::TestRequest::delete()
+        .uri(&house_path)
+        .insert_header(("If-Match", "\"2\""));
+    assert_eq!(call(req).await.status(), StatusCode::NOT_FOUND);
+
+    let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    let patch_doc = &doc["paths"]["/rooms/{room_name}/devices/{device_name}"]["patch"];
+    assert!(patch_doc["responses"]["412"].is_object());
+    assert!(patch_doc["responses"]["200"]["headers"]["ETag"].is_object());
+    assert!(patch_doc["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|param| param["name"] == "If-Match" && param["in"] == "header"));
+    for (path, method) in [
+        ("/rooms/{room_name}", "post"),
+        ("/rooms/{room_name}/devices/{device_name}", "post"),
+        ("/rooms/{room_name}/devices/{device_name}/command", "post"),
+    ] {
+        assert!(doc["paths"][path][method]["responses"]["412"].is_object());
+    }
+}
+
+#[actix_web::test]
+async fn test_http_audit() {
+    let app_data = new_house_http().await.unwrap();
+    app_data
+        .add_api_user_with_key("admin", Role::Admin, "admin-key")
+        .await
+        .unwrap();
+    app_data
+        .add_api_user_with_key("operator", Role::Operator, "operator-key")
+        .await
+        .unwrap();
+    let data = web::Data::new(app_data);
+    let authenticator = web::Data::new(Authenticator::new());
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&data))
+            .app_data(web::Data::clone(&authenticator))
+            .wrap(from_fn(audit_middleware))
+            .wrap(from_fn(auth_middleware))
+            .configure(config),
+    )
+    .await;
+    let start = Utc::now();
+
+    let hallway = format!("/rooms/{}", encode(HALLWAY));
+    let nowhere = format!("/rooms/{}", encode("Чердак"));
+    for (req, status) in [
+        (test::TestRequest::post().uri(&hallway), StatusCode::CREATED),
+        (test::TestRequest::delete().uri(&hallway), StatusCode::OK),
+        (
+            test::TestRequest::delete().uri(&nowhere),
+            StatusCode::NOT_FOUND,
+        ),
+    ] {
+        let req = req.insert_header(("X-API-Key", "admin-key")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+    let req = test::TestRequest::patch()
+        .uri(&format!(
+            "/rooms/{}/devices/{}",
+            encode(KITCHEN),
+            encode(SOCKET_1)
+        ))
+        .insert_header(("X-API-Key", "operator-key"))
+        .set_json(serde_json::json!({"status": "off"}))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let audit = |query: &str, key: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/audit{query}"))
+            .insert_header(("X-API-Key", key))
+            .to_request()
+    };
+    let resp = test::call_service(&app, audit("?actor=admin", "admin-key")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let records: Vec<AuditRecord> = test::read_body_json(resp).await;
+    let operations: Vec<(&str, &str, AuditResult)> = records
+        .iter()
+        .map(|r| (r.operation.as_str(), r.target.as_str(), r.result))
+        .collect();
+    let target = |room: &str| format!("{HOUSE_NAME}/{room}");
+    assert_eq!(
+        operations,
+        [
+            ("remove_room", target("Чердак").as_str(), AuditResult::Error),
+            ("remove_room", target(HALLWAY).as_str(), AuditResult::Ok),
+            ("add_room", target(HALLWAY).as_str(), AuditResult::Ok),
+        ]
+    );
+    assert_eq!(
+        records[0].error.as_deref(),
+        Some(
+            SmartHouseError::RoomNotFoundError("Чердак".to_string())
+                .to_string()
+                .as_str()
+        )
+    );
+    assert!(records.iter().all(|r| r.timestamp >= start));
+
+    // изменения самого сервера записываются от имени system
+    let resp = test::call_service(&app, audit("?actor=system", "admin-key")).await;
+    let records: Vec<AuditRecord> = test::read_body_json(resp).await;
+    let users: Vec<&str> = records.iter().map(|r| r.target.as_str()).collect();
+    assert_eq!(users, ["operator", "admin"]);
+
+    let since = encode(&start.to_rfc3339()).into_owned();
+    let resp =
+        test::call_service(&app, audit(&format!("?since={since}&limit=1"), "admin-key")).await;
+    let records: Vec<AuditRecord> = test::read_body_json(resp).await;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].actor, "operator");
+    assert_eq!(records[0].operation, "update_device_info");
+    assert_eq!(
+        records[0].target,
+        format!("{HOUSE_NAME}/{KITCHEN}/{SOCKET_1}")
+    );
+
+    let future = encode(&(Utc::now() + Duration::hours(1)).to_rfc3339()).into_owned();
+    let resp = test::call_service(&app, audit(&format!("?since={future}"), "admin-key")).await;
+    let records: Vec<AuditRecord> = test::read_body_json(resp).await;
+    assert!(records.is_empty());
+
+    for query in ["?limit=0", "?since=yesterday", "?user=admin"] {
+        let resp = test::call_service(&app, audit(query, "admin-key")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+    let resp = test::call_service(&app, audit("", "operator-key")).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // без аутентификации автор изменений - IP-адрес клиента
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&data))
+            .wrap(from_fn(audit_middleware))
+            .wrap(from_fn(auth_middleware))
+            .configure(config),
+    )
+    .await;
+    let req = test::TestRequest::post()
+        .uri(&hallway)
+        .peer_addr("192.168.1.10:40000".parse().unwrap())
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::CREATED
+    );
+    let resp = test::call_service(&app, audit("?actor=192.168.1.10", "")).await;
+    let records: Vec<AuditRecord> = test::read_body_json(resp).await;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].operation, "add_room");
+}
+
+#[actix_web::test]
+async fn test_http_idempotency() {
+    let app_data = new_house_http().await.unwrap();
+    app_data
+        .add_api_user_with_key("admin", Role::Admin, "admin-key")
+        .await
+        .unwrap();
+    let data = web::Data::new(app_data);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&data))
+            .wrap(from_fn(audit_middleware))
+            .wrap(from_fn(idempotency_middleware))
+            .wrap(from_fn(auth_middleware))
+            .wrap(from_fn(request_id_middleware))
+            .configure(config),
+    )
+    .await;
+    let request = |method: Method, uri: &str, key: Option<&str>| {
+        let req = test::TestRequest::default().method(method).uri(uri);
+        match key {
+            Some(key) => req.insert_header(("Idempotency-Key", key)),
+            None => req,
+        }
+        .to_request()
+    };
+    let replayed = |resp: &ServiceResponse| {
+        resp.headers()
+            .get("Idempotent-Replayed")
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+
+    // повторный запрос получает исходный ответ, а не 409
+    let hallway = format!("/rooms/{}", encode(HALLWAY));
+    let resp = test::call_service(&app, request(Method::POST, &hallway, Some("add-1"))).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(replayed(&resp), None);
+    let etag = resp.headers().get("ETag").cloned();
+    let body = test::read_body(resp).await;
+    let resp = test::call_service(&app, request(Method::POST, &hallway, Some("add-1"))).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(replayed(&resp).as_deref(), Some("true"));
+    assert_eq!(resp.headers().get("ETag").cloned(), etag);
+    assert_eq!(test::read_body(resp).await, body);
+    let resp = test::call_service(&app, request(Method::POST, &hallway, None)).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    // ключ нельзя использовать для другого запроса
+    let attic = format!("/rooms/{}", encode("Чердак"));
+    let resp = test::call_service(&app, request(Method::POST, &attic, Some("add-1"))).await;
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.code, ErrorCode::IdempotencyKeyReused);
+    assert_eq!(
+        body.details,
+        Some(serde_json::json!({"idempotency_key": "add-1"}))
+    );
+
+    // ошибки тоже повторяются, с идентификатором исходного запроса
+    let resp = test::call_service(&app, request(Method::DELETE, &attic, Some("remove-1"))).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let error: ErrorResponse = test::read_body_json(resp).await;
+    data.add_room(HOUSE_NAME, "Чердак", None).await.unwrap();
+    let resp = test::call_service(&app, request(Method::DELETE, &attic, Some("remove-1"))).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(replayed(&resp).as_deref(), Some("true"));
+    let replayed_error: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(replayed_error.code, ErrorCode::RoomNotFound);
+    assert_eq!(replayed_error.request_id, error.request_id);
+    let resp = test::call_service(&app, request(Method::DELETE, &attic, Some("remove-2"))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, request(Method::DELETE, &attic, Some("remove-2"))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(replayed(&resp).as_deref(), Some("true"));
+
+    // повтор не считается изменением
+    let audited = data
+        .audit_log(&AuditQuery::default())
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.operation == "remove_room")
+        .count();
+    assert_eq!(audited, 2);
+
+    for key in ["", "ключ", "two words", &"k".repeat(256)] {
+        let req = test::TestRequest::post()
+            .uri(&hallway)
+            .insert_header(("Idempotency-Key", key))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{key}");
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.code, ErrorCode::InvalidIdempotencyKey);
+    }
+
+    // GET не учитывает ключ
+    let resp = test::call_service(&app, request(Method::GET, "/rooms", Some("add-1"))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(replayed(&resp), None);
+
+    // ответы с ключами API не сохраняются
+    for status in [StatusCode::CREATED, StatusCode::CONFLICT] {
+        let req = test::TestRequest::post()
+            .uri("/users/tester")
+            .insert_header(("Idempotency-Key", "user-1"))
+            .set_json(serde_json::json!({ "role": "viewer" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), status);
+        assert_eq!(replayed(&resp), None);
+    }
+
+    // выполняющийся запрос с тем же ключом, блокировка прерванного запроса истекает
+    let record = IdempotencyRecord::new(
+        "in-progress".to_string(),
+        "fingerprint".to_string(),
+        StdDuration::from_millis(50),
+    );
+    assert!(data
+        .storage
+        .start_idempotent_request(&record)
+        .await
+        .unwrap()
+        .is_none());
+    let existing = data
+        .storage
+        .start_idempotent_request(&record)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(existing.response.is_none());
+    tokio::time::sleep(StdDuration::from_millis(100)).await;
+    assert!(data
+        .storage
+        .start_idempotent_request(&record)
+        .await
+        .unwrap()
+        .is_none());
+
+    // ответ хранится дольше блокировки
+    let response = IdempotentResponse {
+        status: 201,
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+    data.storage
+        .complete_idempotent_request("in-progress", &response, Utc::now() + Duration::hours(1))
+        .await
+        .unwrap();
+    tokio::time::sleep(StdDuration::from_millis(100)).await;
+    let existing = data
+        .storage
+        .start_idempotent_request(&record)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(existing.response.unwrap().status, 201);
+
+    // ключи разных пользователей не пересекаются
+    let authenticator = web::Data::new(Authenticator::new());
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&data))
+            .app_data(web::Data::clone(&authenticator))
+            .wrap(from_fn(idempotency_middleware))
+            .wrap(from_fn(auth_middleware))
+            .configure(config),
+    )
+    .await;
+    let req = test::TestRequest::post()
+        .uri(&attic)
+        .insert_header(("X-API-Key", "admin-key"))
+        .insert_header(("Idempotency-Key", "add-1"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(replayed(&resp), None);
+
+    // после истечения ключа запрос выполняется заново
+    let data = web::Data::new(
+        new_house_http()
+            .await
+            .unwrap()
+            .with_idempotency_ttl(Some(StdDuration::from_millis(50))),
+    );
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&data))
+            .wrap(from_fn(idempotency_middleware))
+            .configure(config),
+    )
+    .await;
+    let resp = test::call_service(&app, request(Method::POST, &hallway, Some("add-1"))).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    tokio::time::sleep(StdDuration::from_millis(100)).await;
+    let resp = test::call_service(&app, request(Method::POST, &hallway, Some("add-1"))).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    assert_eq!(replayed(&resp), None);
+
+    // без срока хранения заголовок не учитывается
+    let data = web::Data::new(new_house_http().await.unwrap().with_idempotency_ttl(None));
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&data))
+            .wrap(from_fn(idempotency_middleware))
+            .configure(config),
+    )
+    .await;
+    for status in [StatusCode::CREATED, StatusCode::CONFLICT] {
+        let resp = test::call_service(&app, request(Method::POST, &hallway, Some("add-1"))).await;
+        assert_eq!(resp.status(), status);
+    }
+}
+
+async fn test_http_helper(
+    app_data: web::Data<AppData>,
+    path: &str,
+    method: Method,
+    status_code: StatusCode,
+    expected: String,
+) {
+    let req = match method {
+        Method::GET => test::TestRequest::get().uri(path),
+        Method::POST => test::TestRequest::post().uri(path),
+        Method::DELETE => test::TestRequest::delete().uri(path),
+        _ => unreachable!(),
+    };
+    let resp = test_http_call_helper(app_data, req).await;
+    assert_eq!(resp.status(), status_code);
+
+    let body = test::read_body(resp).await;
+    assert_eq!(body, Bytes::from(expected));
+}
+
+async fn test_http_json_helper(
+    app_data: web::Data<AppData>,
+    path: &str,
+    method: Method,
+    json: serde_json::Value,
+    status_code: StatusCode,
+    expected: String,
+) {
+    let req = match method {
+        Method::PUT => test::TestRequest::put(),
+        Method::PATCH => test::TestRequest::patch(),
+        _ => unreachable!(),
+    }
+    .uri(path)
+    .set_json(json);
+    let resp = test_http_call_helper(app_data, req).await;
+    assert_eq!(resp.status(), status_code);
+
+    let body = test::read_body(resp).await;
+    assert_eq!(body, Bytes::from(expected));
+}
+
+async fn test_http_error_helper(
+    app_data: web::Data<AppData>,
+    path: &str,
+    method: Method,
+    expected: SmartHouseError,
+) {
+    let req = test::TestRequest::default().uri(path).method(method);
+    let resp = test_http_call_helper(app_data, req).await;
+    assert_eq!(resp.status(), expected.status_code());
+
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.code, expected.code());
+    assert_eq!(body.message, expected.to_string());
+    assert_eq!(body.details, expected.details());
+}
+
+async fn test_http_import_helper(
+    app_data: web::Data<AppData>,
+    query: &str,
+    content_type: &str,
+    snapshot: &HouseSnapshot,
+) -> ImportReport {
+    let mut req = test::TestRequest::post()
+        .uri(&format!("/house/import{query}"))
+        .set_json(snapshot);
+    if !content_type.is_empty() {
+        req = req.insert_header(("Content-Type", content_type));
+    }
+    let resp = test_http_call_helper(app_data, req).await;
+    assert_eq!(resp.status(), StatusCode::OK, "{query}");
+
+    test::read_body_json(resp).await
+}
+
+/// Значение метрики с указанными метками из текстового формата Prometheus
+fn test_metric_value(body: &str, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+    body.lines()
+        .filter(|line| line.starts_with(&format!("{name}{{")))
+        .find(|line| {
+            labels
+                .iter()
+                .all(|(label, value)| line.contains(&format!("{label}=\"{value}\"")))
+        })
+        .and_then(|line| line.rsplit(' ').next())
+        .map(|value| value.parse().unwrap())
+}
+
+/// Имена из страницы списка (строк или записей) и курсор следующей страницы
+async fn test_http_page_helper(
+    app_data: web::Data<AppData>,
+    uri: &str,
+) -> (Vec<String>, Option<String>) {
+    let req = test::TestRequest::get().uri(uri);
+    let resp = test_http_call_helper(app_data, req).await;
+    assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+
+    let cursor = resp
+        .headers()
+        .get("X-Next-Cursor")
+        .map(|cursor| cursor.to_str().unwrap().to_string());
+    let items: Vec<serde_json::Value> = test::read_body_json(resp).await;
+    let names = items
+        .iter()
+        .map(|item| {
+            item.as_str()
+                .unwrap_or_else(|| item["name"].as_str().unwrap())
+        })
+        .map(str::to_string)
+        .collect();
+
+    (names, cursor)
+}
+
+async fn test_http_call_helper(
+    app_data: web::Data<AppData>,
+    req: test::TestRequest,
+) -> ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&app_data))
+            .configure(config),
+    )
+    .await;
+
+    test::call_service(&app, req.to_request()).await
+}
+
+async fn test_http_auth_call_helper(
+    app_data: web::Data<AppData>,
+    authenticator: web::Data<Authenticator>,
+    req: test::TestRequest,
+) -> ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&app_data))
+            .app_data(web::Data::clone(&authenticator))
+            .wrap(from_fn(auth_middleware))
+            .configure(config),
+    )
+    .await;
+
+    test::call_service(&app, req.to_request()).await
+}
+
+async fn test_http_request_id_call_helper(
+    app_data: web::Data<AppData>,
+    authenticator: web::Data<Authenticator>,
+    req: test::TestRequest,
+) -> ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&app_data))
+            .app_data(web::Data::clone(&authenticator))
+            .wrap(from_fn(auth_middleware))
+            .wrap(from_fn(request_id_middleware))
+            .configure(config),
+    )
+    .await;
+
+    test::call_service(&app, req.to_request()).await
+}
+
+async fn test_http_limits_call_helper(
+    app_data: web::Data<AppData>,
+    req: test::TestRequest,
+) -> ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::clone(&app_data))
+            .wrap(from_fn(limits_middleware))
+            .wrap(from_fn(request_id_middleware))
+            .configure(config)
+            .app_data(json_config(app_data.limits())),
+    )
+    .await;
+
+    test::call_service(&app, req.to_request()).await
 }
 
 async fn new_house_http() -> Result<AppData, SmartHouseError> {
@@ -257,7 +3098,7 @@ async fn new_house_http() -> Result<AppData, SmartHouseError> {
         HOUSE_ADDRESS.to_string(),
         Box::new(SmartHouseStorageMemory::new()),
     );
-    app_data.storage.init(generate_mock_devices()).await?;
+    app_data.init(generate_mock_devices()).await?;
 
     Ok(app_data)
 }
@@ -1,6 +1,7 @@
 use crate::common::*;
 use smart_home_web::prelude::*;
 use std::sync::atomic::Ordering::SeqCst;
+use std::time::Duration;
 use tokio::time;
 
 mod common;
@@ -308,3 +309,92 @@ async fn test_switch_client_server_async() {
     let result = SmartSwitch::send_command(SWITCH_ADDR, "qqq").await;
     assert_eq!(result.unwrap(), "unknown command");
 }
+
+// тест опроса устройств по сети с кэшированием
+#[tokio::test]
+async fn test_network_device_info_provider() {
+    run_socket_server(NETWORK_SOCKET_ADDR);
+    run_thermometer_server(NETWORK_THERMOMETER_ADDR);
+    run_switch_server(NETWORK_SWITCH_ADDR);
+    time::sleep(time::Duration::from_secs_f32(0.5)).await;
+
+    let provider =
+        NetworkDeviceInfoProvider::new(Duration::from_secs_f32(0.5), Duration::from_secs(1));
+    provider.add_device(
+        HOUSE_NAME,
+        LIVING_ROOM,
+        SOCKET_1,
+        DeviceKind::Socket,
+        NETWORK_SOCKET_ADDR,
+    );
+    provider.add_device(
+        HOUSE_NAME,
+        BEDROOM,
+        THERMOMETER_1,
+        DeviceKind::Thermometer,
+        NETWORK_THERMOMETER_ADDR,
+    );
+    provider.add_device(
+        HOUSE_NAME,
+        KITCHEN,
+        SOCKET_2,
+        DeviceKind::Switch,
+        NETWORK_SWITCH_ADDR,
+    );
+    provider.add_device(
+        HOUSE_NAME,
+        KITCHEN,
+        SOCKET_3,
+        DeviceKind::Socket,
+        UNAVAILABLE_ADDR,
+    );
+
+    assert!(provider.contains(HOUSE_NAME, LIVING_ROOM, SOCKET_1));
+    assert!(!provider.contains(HOUSE_NAME, KITCHEN, SOCKET_1));
+    assert!(!provider.contains("Другой дом", LIVING_ROOM, SOCKET_1));
+
+    let info = provider
+        .device_info(HOUSE_NAME, LIVING_ROOM, SOCKET_1)
+        .await
+        .unwrap();
+    assert_eq!(info.status(), DeviceStatus::Off.to_string());
+    assert_eq!(info.power(), 0.0);
+
+    let info = provider
+        .device_info(HOUSE_NAME, KITCHEN, SOCKET_2)
+        .await
+        .unwrap();
+    assert_eq!(info.status(), DeviceStatus::Off.to_string());
+
+    let info = provider
+        .device_info(HOUSE_NAME, BEDROOM, THERMOMETER_1)
+        .await
+        .unwrap();
+    assert_eq!(info.temp(), 22.33);
+
+    let result = SmartThermometer::send_command(NETWORK_THERMOMETER_ADDR, "25.5").await;
+    assert_eq!(result.unwrap(), "25.50");
+
+    // значение из кэша до истечения ttl
+    let info = provider
+        .device_info(HOUSE_NAME, BEDROOM, THERMOMETER_1)
+        .await
+        .unwrap();
+    assert_eq!(info.temp(), 22.33);
+
+    time::sleep(time::Duration::from_secs_f32(0.6)).await;
+    let info = provider
+        .device_info(HOUSE_NAME, BEDROOM, THERMOMETER_1)
+        .await
+        .unwrap();
+    assert_eq!(info.temp(), 25.5);
+
+    assert!(provider
+        .device_info(HOUSE_NAME, KITCHEN, SOCKET_3)
+        .await
+        .is_err());
+    assert!(provider
+        .device_info(HOUSE_NAME, KITCHEN, SOCKET_1)
+        .await
+        .is_err());
+}
@@ -0,0 +1,497 @@
+use bytes::BytesMut;
+use clap::Parser;
+use rumqttc::{
+    matches, read, AsyncClient, ConnAck, ConnectReturnCode, Event, MqttOptions, Packet, PingResp,
+    PubAck, Publish, QoS, SubAck, SubscribeReasonCode,
+};
+use serde_json::Value;
+use smart_home_web::http_handler::prelude::SmartDeviceMeta;
+use smart_home_web::prelude::{
+    AppConfig, AppData, AuditQuery, CliArgs, DeviceKind, DeviceStatus, MqttBridge, MqttConfig,
+    SmartDeviceInfo, SmartHouseError, SmartHouseStorageMemory, SmartSocket, SmartThermometer,
+};
+use smart_home_web::smart_device::SmartDevice;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+const HOUSE_NAME: &str = "Мой умный дом (mqtt)";
+const HOUSE_ADDRESS: &str = "ул. Умных домов, д.5, кв.6";
+const KITCHEN: &str = "Кухня";
+const BEDROOM: &str = "Спальня";
+const SOCKET_1: &str = "Розетка-1";
+const SOCKET_2: &str = "Розетка #2";
+const THERMOMETER_1: &str = "Термометр-1";
+const THERMOMETER_2: &str = "Термометр-2";
+const SOCKET_ADDR: &str = "127.0.0.1:54324";
+const THERMOMETER_ADDR: &str = "127.0.0.1:12348";
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn test_mqtt_bridge() {
+    let socket = SmartSocket::new(
+        SOCKET_2.to_string(),
+        KITCHEN.to_string(),
+        DeviceStatus::Off,
+        0.0,
+    );
+    tokio::spawn(async move { socket.listen(SOCKET_ADDR).await });
+    let thermometer = SmartThermometer::new(THERMOMETER_2.to_string(), BEDROOM.to_string(), 20.0);
+    tokio::spawn(async move { thermometer.listen(THERMOMETER_ADDR).await });
+    tokio::time::sleep(Duration::from_secs_f32(0.5)).await;
+
+    let app_data = Arc::new(new_house_mqtt().await);
+    app_data
+        .add_device_with_meta(
+            HOUSE_NAME,
+            KITCHEN,
+            SOCKET_2,
+            &SmartDeviceMeta::new(DeviceKind::Socket).with_address(SOCKET_ADDR),
+            None,
+        )
+        .await
+        .unwrap();
+    app_data
+        .add_device_with_meta(
+            HOUSE_NAME,
+            BEDROOM,
+            THERMOMETER_2,
+            &SmartDeviceMeta::new(DeviceKind::Thermometer).with_address(THERMOMETER_ADDR),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let port = start_broker().await;
+    let (client, mut messages) = connect_client(port, "home/#").await;
+    tokio::spawn(
+        MqttBridge::new(Arc::clone(&app_data), "smart_home_web", "127.0.0.1", port)
+            .with_house(HOUSE_NAME)
+            .run(),
+    );
+
+    // после подключения публикуются все устройства дома
+    let state = messages
+        .wait_state("home/Кухня/Розетка-1/state", |_| true)
+        .await;
+    assert_eq!(state["name"], SOCKET_1);
+    assert_eq!(state["status"], DeviceStatus::On.to_string());
+    assert_eq!(state["power"], 1500.0);
+    let state = messages
+        .wait_state("home/Спальня/Термометр-1/state", |_| true)
+        .await;
+    assert_eq!(state["temp"], 22.5);
+
+    // `#` недопустим в топике и кодируется
+    let socket_topic = "home/Кухня/Розетка %232";
+    messages
+        .wait_state(&format!("{socket_topic}/state"), |_| true)
+        .await;
+
+    client
+        .publish(format!("{socket_topic}/set"), QoS::AtLeastOnce, false, "ON")
+        .await
+        .unwrap();
+    let state = messages
+        .wait_state(&format!("{socket_topic}/state"), |state| {
+            state["status"] == DeviceStatus::On.to_string()
+        })
+        .await;
+    assert!(state["power"].as_f64().unwrap() > 0.0);
+
+    client
+        .publish(
+            "home/Спальня/Термометр-2/set",
+            QoS::AtLeastOnce,
+            false,
+            "25.5",
+        )
+        .await
+        .unwrap();
+    messages
+        .wait_state("home/Спальня/Термометр-2/state", |state| {
+            state["temp"] == 25.5
+        })
+        .await;
+
+    // неизвестные команды и устройства пропускаются, мост продолжает работать
+    for (topic, payload) in [
+        (format!("{socket_topic}/set"), "explode"),
+        ("home/Кухня/Чайник/set".to_string(), "on"),
+    ] {
+        client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await
+            .unwrap();
+    }
+    client
+        .publish(
+            format!("{socket_topic}/set"),
+            QoS::AtLeastOnce,
+            false,
+            r#"{"command": "off"}"#,
+        )
+        .await
+        .unwrap();
+    messages
+        .wait_state(&format!("{socket_topic}/state"), |state| {
+            state["status"] == DeviceStatus::Off.to_string()
+        })
+        .await;
+
+    // команды записываются в журнал аудита от имени моста
+    let records = app_data
+        .audit_log(&AuditQuery {
+            actor: Some("mqtt".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let commands = records
+        .iter()
+        .filter(|record| record.operation == "send_device_command" && record.error.is_none())
+        .count();
+    assert_eq!(commands, 3);
+
+    // при удалении устройства сохранённое брокером состояние очищается
+    app_data
+        .remove_device(HOUSE_NAME, KITCHEN, SOCKET_1, None)
+        .await
+        .unwrap();
+    messages
+        .wait("home/Кухня/Розетка-1/state", |payload| {
+            payload.is_empty()
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_mqtt_bridge_config() {
+    let app_data = Arc::new(new_house_mqtt().await);
+
+    let config = MqttConfig::default();
+    assert!(!config.is_enabled());
+    assert!(MqttBridge::from_config(Arc::clone(&app_data), &config).is_err());
+
+    let config = MqttConfig {
+        broker_address: Some("localhost:1883".to_string()),
+        ..Default::default()
+    };
+    assert!(config.is_enabled());
+    assert!(MqttBridge::from_config(Arc::clone(&app_data), &config).is_ok());
+
+    let config = MqttConfig {
+        broker_address: Some("localhost".to_string()),
+        ..Default::default()
+    };
+    assert!(MqttBridge::from_config(app_data, &config).is_err());
+
+    let args = CliArgs::try_parse_from(["http_server", "--mqtt-broker-address", "broker:1883"]);
+    let config = AppConfig::default().with_args(&args.unwrap()).unwrap();
+    assert_eq!(config.mqtt.broker_address.as_deref(), Some("broker:1883"));
+    assert_eq!(
+        config.mqtt.publish_interval(),
+        Some(Duration::from_secs(60))
+    );
+    let config = AppConfig::from_toml("[mqtt]\npublish_interval_secs = 0").unwrap();
+    assert!(config.mqtt.publish_interval().is_none());
+
+    for text in [
+        "[mqtt]\nbroker_address = \"broker\"",
+        "[mqtt]\nclient_id = \"\"",
+        "[mqtt]\ntopic_prefix = \"home/#\"",
+        "[mqtt]\ntopic_prefix = \"home/\"",
+        "[mqtt]\npassword = \"secret\"",
+        "[mqtt]\nca_file = \"ca.pem\"",
+        "[mqtt]\ntls = true\nclient_cert_file = \"client.pem\"",
+        "[mqtt]\ntls = true\nclient_cert_file = \"client.pem\"\nclient_key_file = \"client.key\"",
+    ] {
+        let result = AppConfig::from_toml(text).and_then(|config| config.validate());
+        assert!(
+            matches!(result, Err(SmartHouseError::ValidationError(_))),
+            "{text}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_mqtt_bridge_credentials() {
+    let app_data = Arc::new(new_house_mqtt().await);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let args = CliArgs::try_parse_from([
+        "http_server",
+        "--mqtt-broker-address",
+        &format!("127.0.0.1:{port}"),
+        "--mqtt-username",
+        "bridge",
+        "--mqtt-password",
+        "secret",
+    ]);
+    let config = AppConfig::default().with_args(&args.unwrap()).unwrap();
+    let bridge = MqttBridge::from_config(Arc::clone(&app_data), &config.mqtt).unwrap();
+    let task = tokio::spawn(bridge.run());
+
+    // брокер получает имя и пароль в пакете CONNECT
+    let (mut stream, _) = tokio::time::timeout(WAIT_TIMEOUT, listener.accept())
+        .await
+        .expect("connect timeout")
+        .unwrap();
+    let mut buffer = BytesMut::new();
+    let connect = loop {
+        match read(&mut buffer, MAX_PACKET_SIZE) {
+            Ok(Packet::Connect(connect)) => break connect,
+            Err(rumqttc::Error::InsufficientBytes(_)) => {
+                assert!(stream.read_buf(&mut buffer).await.unwrap() > 0);
+            }
+            other => panic!("unexpected packet: {other:?}"),
+        }
+    };
+    task.abort();
+    let login = connect.login.unwrap();
+    assert_eq!(login.username, "bridge");
+    assert_eq!(login.password, "secret");
+
+    // файл центра сертификации читается при создании моста
+    let config = MqttConfig {
+        broker_address: Some("localhost:8883".to_string()),
+        tls: true,
+        ca_file: Some("missing-ca.pem".into()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        MqttBridge::from_config(app_data, &config),
+        Err(SmartHouseError::IoError(_))
+    ));
+}
+
+/// Сообщения, полученные клиентом, и последнее сообщение в каждом топике
+struct Messages {
+    receiver: UnboundedReceiver<Publish>,
+    latest: HashMap<String, Publish>,
+}
+
+impl Messages {
+    /// Ждёт состояние устройства в JSON, для которого выполняется условие
+    async fn wait_state(&mut self, topic: &str, condition: impl Fn(&Value) -> bool) -> Value {
+        let publish = self
+            .wait(topic, |payload| {
+                serde_json::from_slice(payload).is_ok_and(|state| condition(&state))
+            })
+            .await;
+
+        serde_json::from_slice(&publish.payload).unwrap()
+    }
+
+    /// Ждёт сообщение в топике, начиная с последнего уже полученного
+    async fn wait(&mut self, topic: &str, condition: impl Fn(&[u8]) -> bool) -> Publish {
+        if let Some(publish) = self.latest.get(topic) {
+            if condition(&publish.payload) {
+                return publish.clone();
+            }
+        }
+
+        let wait = async {
+            loop {
+                let publish = self.receiver.recv().await.unwrap();
+                self.latest.insert(publish.topic.clone(), publish.clone());
+                if publish.topic == topic && condition(&publish.payload) {
+                    return publish;
+                }
+            }
+        };
+
+        tokio::time::timeout(WAIT_TIMEOUT, wait)
+            .await
+            .unwrap_or_else(|_| panic!("не дождались сообщения в '{topic}'"))
+    }
+}
+
+/// Клиент, подписанный на `filter`, и полученные им сообщения
+async fn connect_client(port: u16, filter: &str) -> (AsyncClient, Messages) {
+    let (client, mut eventloop) =
+        AsyncClient::new(MqttOptions::new("test_client", "127.0.0.1", port), 16);
+    client.subscribe(filter, QoS::AtLeastOnce).await.unwrap();
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    if sender.send(publish).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => (),
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    });
+
+    let messages = Messages {
+        receiver,
+        latest: HashMap::new(),
+    };
+    (client, messages)
+}
+
+/// Сообщения с флагом retain и подписки клиентов встроенного брокера
+#[derive(Default)]
+struct Broker {
+    retained: HashMap<String, Publish>,
+    subscriptions: Vec<(String, UnboundedSender<BytesMut>)>,
+}
+
+/// Минимальный брокер MQTT 3.1.1 для тестов: подписки с `+` и `#`, сообщения
+/// с флагом retain, подписчикам сообщения доставляются с QoS 0
+async fn start_broker() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = Arc::new(Mutex::new(Broker::default()));
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_client(stream, Arc::clone(&broker)));
+        }
+    });
+
+    port
+}
+
+async fn serve_client(stream: TcpStream, broker: Arc<Mutex<Broker>>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (outgoing, mut packets) = mpsc::unbounded_channel::<BytesMut>();
+    tokio::spawn(async move {
+        while let Some(packet) = packets.recv().await {
+            if writer.write_all(&packet).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut buffer = BytesMut::new();
+    loop {
+        let packet = match read(&mut buffer, MAX_PACKET_SIZE) {
+            Ok(packet) => packet,
+            Err(rumqttc::Error::InsufficientBytes(_)) => match reader.read_buf(&mut buffer).await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => continue,
+            },
+            Err(_) => return,
+        };
+
+        let mut reply = BytesMut::new();
+        match packet {
+            Packet::Connect(_) => {
+                ConnAck::new(ConnectReturnCode::Success, false)
+                    .write(&mut reply)
+                    .unwrap();
+            }
+            Packet::Subscribe(subscribe) => {
+                let codes = subscribe
+                    .filters
+                    .iter()
+                    .map(|filter| SubscribeReasonCode::Success(filter.qos))
+                    .collect();
+                SubAck::new(subscribe.pkid, codes)
+                    .write(&mut reply)
+                    .unwrap();
+
+                let mut broker = broker.lock().unwrap();
+                for filter in subscribe.filters {
+                    for publish in broker.retained.values() {
+                        if matches(&publish.topic, &filter.path) {
+                            deliver(&outgoing, publish, true);
+                        }
+                    }
+                    broker.subscriptions.push((filter.path, outgoing.clone()));
+                }
+            }
+            Packet::Publish(publish) => {
+                if publish.qos != QoS::AtMostOnce {
+                    PubAck::new(publish.pkid).write(&mut reply).unwrap();
+                }
+
+                let mut broker = broker.lock().unwrap();
+                if publish.retain {
+                    if publish.payload.is_empty() {
+                        broker.retained.remove(&publish.topic);
+                    } else {
+                        broker
+                            .retained
+                            .insert(publish.topic.clone(), publish.clone());
+                    }
+                }
+                broker
+                    .subscriptions
+                    .retain(|(_, subscriber)| !subscriber.is_closed());
+                for (filter, subscriber) in &broker.subscriptions {
+                    if matches(&publish.topic, filter) {
+                        deliver(subscriber, &publish, false);
+                    }
+                }
+            }
+            Packet::PingReq => {
+                PingResp.write(&mut reply).unwrap();
+            }
+            Packet::Disconnect => return,
+            _ => (),
+        }
+        if !reply.is_empty() && outgoing.send(reply).is_err() {
+            return;
+        }
+    }
+}
+
+fn deliver(subscriber: &UnboundedSender<BytesMut>, publish: &Publish, retain: bool) {
+    let mut message = Publish::new(&publish.topic, QoS::AtMostOnce, publish.payload.to_vec());
+    message.retain = retain;
+    let mut packet = BytesMut::new();
+    message.write(&mut packet).unwrap();
+    let _ = subscriber.send(packet);
+}
+
+async fn new_house_mqtt() -> AppData {
+    let mut app_data = AppData::new(
+        HOUSE_NAME.to_string(),
+        HOUSE_ADDRESS.to_string(),
+        Box::new(SmartHouseStorageMemory::new()),
+    );
+    app_data.init(generate_mock_devices()).await.unwrap();
+
+    app_data
+}
+
+fn generate_mock_devices() -> HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>> {
+    HashMap::from([
+        (
+            KITCHEN,
+            HashMap::from([(
+                SOCKET_1,
+                SmartDeviceInfo::new(
+                    SOCKET_1.to_string(),
+                    DeviceStatus::On.to_string(),
+                    1500.0,
+                    0.0,
+                ),
+            )]),
+        ),
+        (
+            BEDROOM,
+            HashMap::from([(
+                THERMOMETER_1,
+                SmartDeviceInfo::new(
+                    THERMOMETER_1.to_string(),
+                    DeviceStatus::Unknown.to_string(),
+                    0.0,
+                    22.5,
+                ),
+            )]),
+        ),
+    ])
+}
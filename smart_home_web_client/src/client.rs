@@ -0,0 +1,554 @@
+use crate::events::{event_stream, EventStream};
+use reqwest::header::{ETAG, IF_MATCH};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use smart_home_web::prelude::{
+    ApiUser, ApiUserKey, AuditQuery, AuditRecord, AuthToken, DeviceCommand, DeviceCommandResult,
+    DeviceHistoryPoint, DeviceQuery, ErrorResponse, EventsQuery, ExportQuery, HealthStatus,
+    HistoryQuery, HouseSnapshot, ImportQuery, ImportReport, NewApiUser, NewSmartHouse, Page, Role,
+    RoomQuery, SmartDeviceInfo, SmartDeviceInfoUpdate, SmartDeviceMeta, SmartDeviceRecord,
+    SmartHouseError, SmartHouseRecord, SmartHouseReport, SnapshotFormat,
+};
+use std::io;
+use urlencoding::encode;
+
+const API_KEY_HEADER: &str = "X-API-Key";
+const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+
+#[derive(Clone)]
+enum Credentials {
+    ApiKey(String),
+    Token(String),
+}
+
+/// Асинхронный клиент REST API умного дома (`smart_home_web`).
+///
+/// Методы без префикса `house_` работают с основным домом сервера, устаревшие
+/// маршруты v1 заменены маршрутами `/rooms/{room_name}/devices/{device_name}`.
+/// Ошибки API возвращаются теми же вариантами `SmartHouseError`, что и на сервере,
+/// ошибки соединения - как `SmartHouseError::IoError`. Версии для `If-Match`
+/// передаются как `version`, `None` - изменение без проверки версии.
+#[derive(Clone)]
+pub struct SmartHomeClient {
+    http: Client,
+    base_url: String,
+    credentials: Option<Credentials>,
+}
+
+impl SmartHomeClient {
+    /// Клиент сервера по адресу вида `http://127.0.0.1:8000`
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            credentials: None,
+        }
+    }
+
+    /// Использовать свой `reqwest::Client`, например с тайм-аутами или прокси
+    pub fn with_http_client(mut self, http: Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Передавать ключ API в заголовке `X-API-Key`
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.credentials = Some(Credentials::ApiKey(api_key.to_string()));
+        self
+    }
+
+    /// Передавать JWT из `issue_token` в заголовке `Authorization: Bearer`
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.credentials = Some(Credentials::Token(token.to_string()));
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Комнаты основного дома и версия дома
+    pub async fn rooms(&self, query: &RoomQuery) -> Result<(Page<String>, u64), SmartHouseError> {
+        let request = self.request(Method::GET, &["rooms"]).query(query);
+
+        page(send(request).await?).await
+    }
+
+    pub async fn add_room(&self, room: &str) -> Result<(), SmartHouseError> {
+        send(self.request(Method::POST, &["rooms", room])).await?;
+
+        Ok(())
+    }
+
+    /// Удаляет комнату вместе с устройствами, `version` - версия комнаты из `devices`
+    pub async fn remove_room(
+        &self,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        let request = if_match(self.request(Method::DELETE, &["rooms", room]), version);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Реестр устройств в комнате и версия комнаты
+    pub async fn devices(
+        &self,
+        room: &str,
+        query: &DeviceQuery,
+    ) -> Result<(Page<SmartDeviceRecord>, u64), SmartHouseError> {
+        let request = self
+            .request(Method::GET, &["rooms", room, "devices"])
+            .query(query);
+
+        page(send(request).await?).await
+    }
+
+    pub async fn add_device(
+        &self,
+        room: &str,
+        device: &str,
+        meta: &SmartDeviceMeta,
+    ) -> Result<(), SmartHouseError> {
+        let request = self
+            .request(Method::POST, &["rooms", room, "devices", device])
+            .json(meta);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_device(
+        &self,
+        room: &str,
+        device: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        let request = self.request(Method::DELETE, &["rooms", room, "devices", device]);
+        send(if_match(request, version)).await?;
+
+        Ok(())
+    }
+
+    /// Параметры устройства из источника информации
+    pub async fn device_info(
+        &self,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceInfo, SmartHouseError> {
+        let request = self.request(Method::GET, &["rooms", room, "devices", device]);
+
+        json(send(request).await?).await
+    }
+
+    pub async fn device_record(
+        &self,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceRecord, SmartHouseError> {
+        let request = self.request(Method::GET, &["rooms", room, "devices", device, "record"]);
+
+        json(send(request).await?).await
+    }
+
+    pub async fn device_history(
+        &self,
+        room: &str,
+        device: &str,
+        query: &HistoryQuery,
+    ) -> Result<Vec<DeviceHistoryPoint>, SmartHouseError> {
+        let request = self
+            .request(Method::GET, &["rooms", room, "devices", device, "history"])
+            .query(query);
+
+        json(send(request).await?).await
+    }
+
+    /// Заменяет все параметры устройства, возвращает их и новую версию устройства
+    pub async fn replace_device_info(
+        &self,
+        room: &str,
+        device: &str,
+        update: &SmartDeviceInfoUpdate,
+        version: Option<u64>,
+    ) -> Result<(SmartDeviceInfo, u64), SmartHouseError> {
+        let request = self
+            .request(Method::PUT, &["rooms", room, "devices", device])
+            .json(update);
+
+        versioned(send(if_match(request, version)).await?).await
+    }
+
+    /// Изменяет заданные параметры устройства, возвращает их и новую версию устройства
+    pub async fn update_device_info(
+        &self,
+        room: &str,
+        device: &str,
+        update: &SmartDeviceInfoUpdate,
+        version: Option<u64>,
+    ) -> Result<(SmartDeviceInfo, u64), SmartHouseError> {
+        let request = self
+            .request(Method::PATCH, &["rooms", room, "devices", device])
+            .json(update);
+
+        versioned(send(if_match(request, version)).await?).await
+    }
+
+    pub async fn send_device_command(
+        &self,
+        room: &str,
+        device: &str,
+        command: &DeviceCommand,
+    ) -> Result<DeviceCommandResult, SmartHouseError> {
+        let request = self
+            .request(Method::POST, &["rooms", room, "devices", device, "command"])
+            .json(command);
+
+        json(send(request).await?).await
+    }
+
+    pub async fn device_on(
+        &self,
+        room: &str,
+        device: &str,
+    ) -> Result<DeviceCommandResult, SmartHouseError> {
+        let request = self.request(Method::POST, &["rooms", room, "devices", device, "on"]);
+
+        json(send(request).await?).await
+    }
+
+    pub async fn device_off(
+        &self,
+        room: &str,
+        device: &str,
+    ) -> Result<DeviceCommandResult, SmartHouseError> {
+        let request = self.request(Method::POST, &["rooms", room, "devices", device, "off"]);
+
+        json(send(request).await?).await
+    }
+
+    /// Отчёт о состоянии основного дома
+    pub async fn report(&self) -> Result<SmartHouseReport, SmartHouseError> {
+        json(send(self.request(Method::GET, &["house", "report"])).await?).await
+    }
+
+    /// Конфигурация дома, по умолчанию основного
+    pub async fn export_house(
+        &self,
+        house: Option<&str>,
+    ) -> Result<HouseSnapshot, SmartHouseError> {
+        let query = ExportQuery {
+            house: house.map(str::to_string),
+            format: Some(SnapshotFormat::Json),
+        };
+        let request = self
+            .request(Method::GET, &["house", "export"])
+            .query(&query);
+
+        json(send(request).await?).await
+    }
+
+    /// Импортирует конфигурацию в дом `snapshot.name`
+    pub async fn import_house(
+        &self,
+        snapshot: &HouseSnapshot,
+        query: &ImportQuery,
+    ) -> Result<ImportReport, SmartHouseError> {
+        let request = self
+            .request(Method::POST, &["house", "import"])
+            .query(query)
+            .json(snapshot);
+
+        json(send(request).await?).await
+    }
+
+    /// Метрики в текстовом формате Prometheus
+    pub async fn metrics(&self) -> Result<String, SmartHouseError> {
+        let response = send(self.request(Method::GET, &["metrics"])).await?;
+
+        response.text().await.map_err(transport_error)
+    }
+
+    /// Состояние сервера (liveness)
+    pub async fn health(&self) -> Result<HealthStatus, SmartHouseError> {
+        json(send(self.request(Method::GET, &["healthz"])).await?).await
+    }
+
+    /// Готовность сервера (readiness), недоступное хранилище - не ошибка, а статус
+    pub async fn readiness(&self) -> Result<HealthStatus, SmartHouseError> {
+        let response = self
+            .request(Method::GET, &["readyz"])
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        match response.status() {
+            StatusCode::SERVICE_UNAVAILABLE => json(response).await,
+            _ => json(check_status(response).await?).await,
+        }
+    }
+
+    pub async fn houses(&self) -> Result<Vec<SmartHouseRecord>, SmartHouseError> {
+        json(send(self.request(Method::GET, &["houses"])).await?).await
+    }
+
+    pub async fn house(&self, house: &str) -> Result<SmartHouseRecord, SmartHouseError> {
+        json(send(self.request(Method::GET, &["houses", house])).await?).await
+    }
+
+    pub async fn add_house(&self, house: &str, address: &str) -> Result<(), SmartHouseError> {
+        let request = self
+            .request(Method::POST, &["houses", house])
+            .json(&NewSmartHouse {
+                address: address.to_string(),
+            });
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Удаляет дом вместе с комнатами и устройствами
+    pub async fn remove_house(
+        &self,
+        house: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        let request = if_match(self.request(Method::DELETE, &["houses", house]), version);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Комнаты дома и версия дома
+    pub async fn house_rooms(
+        &self,
+        house: &str,
+        query: &RoomQuery,
+    ) -> Result<(Page<String>, u64), SmartHouseError> {
+        let request = self
+            .request(Method::GET, &["houses", house, "rooms"])
+            .query(query);
+
+        page(send(request).await?).await
+    }
+
+    pub async fn add_house_room(&self, house: &str, room: &str) -> Result<(), SmartHouseError> {
+        send(self.request(Method::POST, &["houses", house, "rooms", room])).await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_house_room(
+        &self,
+        house: &str,
+        room: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        let request = self.request(Method::DELETE, &["houses", house, "rooms", room]);
+        send(if_match(request, version)).await?;
+
+        Ok(())
+    }
+
+    /// Реестр устройств в комнате дома и версия комнаты
+    pub async fn house_devices(
+        &self,
+        house: &str,
+        room: &str,
+        query: &DeviceQuery,
+    ) -> Result<(Page<SmartDeviceRecord>, u64), SmartHouseError> {
+        let request = self
+            .request(Method::GET, &["houses", house, "rooms", room, "devices"])
+            .query(query);
+
+        page(send(request).await?).await
+    }
+
+    pub async fn add_house_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        meta: &SmartDeviceMeta,
+    ) -> Result<(), SmartHouseError> {
+        let request = self
+            .request(
+                Method::POST,
+                &["houses", house, "rooms", room, "devices", device],
+            )
+            .json(meta);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_house_device(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+        version: Option<u64>,
+    ) -> Result<(), SmartHouseError> {
+        let request = self.request(
+            Method::DELETE,
+            &["houses", house, "rooms", room, "devices", device],
+        );
+        send(if_match(request, version)).await?;
+
+        Ok(())
+    }
+
+    pub async fn house_device_info(
+        &self,
+        house: &str,
+        room: &str,
+        device: &str,
+    ) -> Result<SmartDeviceInfo, SmartHouseError> {
+        let request = self.request(
+            Method::GET,
+            &["houses", house, "rooms", room, "devices", device],
+        );
+
+        json(send(request).await?).await
+    }
+
+    pub async fn house_report(&self, house: &str) -> Result<SmartHouseReport, SmartHouseError> {
+        json(send(self.request(Method::GET, &["houses", house, "report"])).await?).await
+    }
+
+    /// Поток изменений, при заданном `house` только этого дома. Ошибки подключения
+    /// возвращаются сразу, поток заканчивается при закрытии соединения сервером.
+    pub async fn events(&self, house: Option<&str>) -> Result<EventStream, SmartHouseError> {
+        let query = EventsQuery {
+            house: house.map(str::to_string),
+        };
+        let response = send(self.request(Method::GET, &["events"]).query(&query)).await?;
+
+        Ok(event_stream(response))
+    }
+
+    /// Журнал аудита, от новых записей к старым
+    pub async fn audit_log(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>, SmartHouseError> {
+        json(send(self.request(Method::GET, &["audit"]).query(query)).await?).await
+    }
+
+    pub async fn api_users(&self) -> Result<Vec<ApiUser>, SmartHouseError> {
+        json(send(self.request(Method::GET, &["users"])).await?).await
+    }
+
+    /// Добавляет пользователя API, ключ возвращается только здесь
+    pub async fn add_api_user(
+        &self,
+        name: &str,
+        role: Role,
+    ) -> Result<ApiUserKey, SmartHouseError> {
+        let request = self
+            .request(Method::POST, &["users", name])
+            .json(&NewApiUser { role });
+
+        json(send(request).await?).await
+    }
+
+    pub async fn remove_api_user(&self, name: &str) -> Result<(), SmartHouseError> {
+        send(self.request(Method::DELETE, &["users", name])).await?;
+
+        Ok(())
+    }
+
+    /// Пользователь, от имени которого работает клиент
+    pub async fn current_user(&self) -> Result<ApiUser, SmartHouseError> {
+        json(send(self.request(Method::GET, &["auth", "me"])).await?).await
+    }
+
+    /// Обменивает ключ API клиента на JWT для `with_token`
+    pub async fn issue_token(&self) -> Result<AuthToken, SmartHouseError> {
+        json(send(self.request(Method::POST, &["auth", "token"])).await?).await
+    }
+
+    /// Запрос к маршруту из сегментов пути, каждый сегмент кодируется
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let path: Vec<_> = segments.iter().map(|segment| encode(segment)).collect();
+        let request = self
+            .http
+            .request(method, format!("{}/{}", self.base_url, path.join("/")));
+
+        match &self.credentials {
+            Some(Credentials::ApiKey(api_key)) => request.header(API_KEY_HEADER, api_key),
+            Some(Credentials::Token(token)) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+async fn send(request: RequestBuilder) -> Result<Response, SmartHouseError> {
+    let response = request.send().await.map_err(transport_error)?;
+
+    check_status(response).await
+}
+
+/// Ответ с ошибкой преобразуется в `SmartHouseError` из тела `ErrorResponse`
+async fn check_status(response: Response) -> Result<Response, SmartHouseError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.bytes().await.map_err(transport_error)?;
+    match serde_json::from_slice::<ErrorResponse>(&body) {
+        Ok(error) => Err(error.into()),
+        Err(_) => Err(SmartHouseError::OtherError(format!(
+            "HTTP {status}: {}",
+            String::from_utf8_lossy(&body)
+        ))),
+    }
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> Result<T, SmartHouseError> {
+    response.json().await.map_err(transport_error)
+}
+
+/// Тело ответа и версия из `ETag`
+async fn versioned<T: DeserializeOwned>(response: Response) -> Result<(T, u64), SmartHouseError> {
+    let version = etag_version(&response)?;
+
+    Ok((json(response).await?, version))
+}
+
+/// Страница списка с курсором из `X-Next-Cursor` и версия из `ETag`
+async fn page<T: DeserializeOwned>(response: Response) -> Result<(Page<T>, u64), SmartHouseError> {
+    let next_cursor = response
+        .headers()
+        .get(NEXT_CURSOR_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let (items, version) = versioned(response).await?;
+
+    Ok((Page { items, next_cursor }, version))
+}
+
+fn etag_version(response: &Response) -> Result<u64, SmartHouseError> {
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    etag.trim_matches('"').parse().map_err(|_| {
+        SmartHouseError::OtherError(format!("в ответе нет версии в ETag, получено '{etag}'"))
+    })
+}
+
+fn if_match(request: RequestBuilder, version: Option<u64>) -> RequestBuilder {
+    match version {
+        Some(version) => request.header(IF_MATCH, format!("\"{version}\"")),
+        None => request,
+    }
+}
+
+/// Ошибки соединения и разбора ответа
+pub(crate) fn transport_error(
+    err: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> SmartHouseError {
+    SmartHouseError::IoError(io::Error::other(err))
+}
@@ -0,0 +1,56 @@
+use crate::client::transport_error;
+use futures::stream::{self, BoxStream, StreamExt};
+use reqwest::Response;
+use smart_home_web::prelude::{SmartHouseError, SmartHouseEvent};
+
+/// Поток изменений умного дома из `SmartHomeClient::events`
+pub type EventStream = BoxStream<'static, Result<SmartHouseEvent, SmartHouseError>>;
+
+const MESSAGE_END: &[u8] = b"\n\n";
+const DATA_FIELD: &str = "data:";
+
+/// События из ответа в формате Server-Sent Events: сообщения разделены пустой
+/// строкой, событие в JSON - в полях `data`, комментарии пропускаются
+pub(crate) fn event_stream(response: Response) -> EventStream {
+    let chunks = response.bytes_stream().boxed();
+
+    stream::unfold(
+        (chunks, Vec::new()),
+        |(mut chunks, mut buffer)| async move {
+            loop {
+                if let Some(end) = buffer
+                    .windows(MESSAGE_END.len())
+                    .position(|window| window == MESSAGE_END)
+                {
+                    let message: Vec<u8> = buffer.drain(..end + MESSAGE_END.len()).collect();
+                    match parse_message(&message) {
+                        Some(event) => return Some((event, (chunks, buffer))),
+                        None => continue,
+                    }
+                }
+
+                match chunks.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                    Some(Err(err)) => return Some((Err(transport_error(err)), (chunks, buffer))),
+                    None => return None,
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+/// Событие из сообщения, `None` для сообщения без данных
+fn parse_message(message: &[u8]) -> Option<Result<SmartHouseEvent, SmartHouseError>> {
+    let message = String::from_utf8_lossy(message);
+    let data: Vec<&str> = message
+        .lines()
+        .filter_map(|line| line.strip_prefix(DATA_FIELD))
+        .map(str::trim_start)
+        .collect();
+    if data.is_empty() {
+        return None;
+    }
+
+    Some(serde_json::from_str(&data.join("\n")).map_err(transport_error))
+}
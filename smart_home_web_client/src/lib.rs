@@ -0,0 +1,7 @@
+mod client;
+mod events;
+
+pub mod prelude {
+    pub use crate::client::SmartHomeClient;
+    pub use crate::events::EventStream;
+}
@@ -0,0 +1,447 @@
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, HttpServer};
+use futures::StreamExt;
+use smart_home_web::http_handler::prelude::*;
+use smart_home_web::prelude::{
+    audit_middleware, auth_middleware, request_id_middleware, AppData, AuditQuery, Authenticator,
+    DeviceCommand, DeviceKind, DeviceStatus, ImportMode, ImportQuery, Role, SmartHouseError,
+    SmartHouseEvent, SmartHouseStorageMemory, SmartSocket,
+};
+use smart_home_web::smart_device::SmartDevice;
+use smart_home_web_client::prelude::SmartHomeClient;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const HOUSE_NAME: &str = "Мой умный дом (client)";
+const HOUSE_ADDRESS: &str = "ул. Умных домов, д.2, кв.5";
+const COUNTRY_HOUSE: &str = "Дача #1";
+const COUNTRY_ADDRESS: &str = "пос. Садовый, уч. 7";
+const KITCHEN: &str = "Кухня";
+const BEDROOM: &str = "Спальня";
+const HALLWAY: &str = "Прихожая, вход";
+const SOCKET_1: &str = "Розетка #1";
+const SOCKET_2: &str = "Розетка №2 (прихожая)";
+const THERMOMETER_1: &str = "Термометр-1";
+const JWT_SECRET: &str = "секрет";
+const ADMIN_KEY: &str = "admin-key";
+const SOCKET_ADDR: &str = "127.0.0.1:54325";
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[actix_web::test]
+async fn test_client_rooms_and_devices() {
+    let (client, _) = start_server(None).await;
+
+    let (rooms, house_version) = client.rooms(&RoomQuery::default()).await.unwrap();
+    assert_eq!(rooms.items, vec![KITCHEN, BEDROOM]);
+    assert_eq!(rooms.next_cursor, None);
+
+    client.add_room(HALLWAY).await.unwrap();
+    let err = client.add_room(HALLWAY).await.err().unwrap();
+    assert!(matches!(err, SmartHouseError::RoomAlreadyExistsError(room) if room == HALLWAY));
+
+    let query = RoomQuery {
+        limit: Some(2),
+        ..Default::default()
+    };
+    let (first, version) = client.rooms(&query).await.unwrap();
+    assert_eq!(first.items, vec![KITCHEN, HALLWAY]);
+    assert!(version > house_version);
+    let query = RoomQuery {
+        cursor: first.next_cursor,
+        ..query
+    };
+    let (second, _) = client.rooms(&query).await.unwrap();
+    assert_eq!(second.items, vec![BEDROOM]);
+    assert_eq!(second.next_cursor, None);
+
+    let meta = SmartDeviceMeta::new(DeviceKind::Socket);
+    client.add_device(HALLWAY, SOCKET_2, &meta).await.unwrap();
+    let err = client
+        .add_device(HALLWAY, SOCKET_2, &meta)
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err,
+        SmartHouseError::DeviceAlreadyExistsError(room, device) if room == HALLWAY && device == SOCKET_2
+    ));
+
+    let (devices, room_version) = client
+        .devices(HALLWAY, &DeviceQuery::default())
+        .await
+        .unwrap();
+    let names: Vec<_> = devices.items.iter().map(|record| &record.name).collect();
+    assert_eq!(names, vec![SOCKET_2]);
+    assert_eq!(devices.items[0].kind, DeviceKind::Socket);
+
+    let record = client.device_record(HALLWAY, SOCKET_2).await.unwrap();
+    assert_eq!(record.name, SOCKET_2);
+
+    let update = SmartDeviceInfoUpdate {
+        status: Some(DeviceStatus::On.to_string()),
+        power: Some(1000.0),
+        temp: Some(0.0),
+    };
+    let (info, device_version) = client
+        .replace_device_info(HALLWAY, SOCKET_2, &update, None)
+        .await
+        .unwrap();
+    assert_eq!(info.status(), DeviceStatus::On.to_string());
+    assert_eq!(info.power(), 1000.0);
+
+    let err = client
+        .update_device_info(HALLWAY, SOCKET_2, &update, Some(device_version + 1))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, SmartHouseError::PreconditionFailedError(_)));
+
+    let patch = SmartDeviceInfoUpdate {
+        power: Some(500.0),
+        ..Default::default()
+    };
+    let (info, version) = client
+        .update_device_info(HALLWAY, SOCKET_2, &patch, Some(device_version))
+        .await
+        .unwrap();
+    assert_eq!(info.power(), 500.0);
+    assert!(version > device_version);
+
+    let info = client.device_info(HALLWAY, SOCKET_2).await.unwrap();
+    assert_eq!(info.name(), SOCKET_2);
+    assert_eq!(info.power(), 500.0);
+
+    let history = client
+        .device_history(HALLWAY, SOCKET_2, &HistoryQuery::default())
+        .await
+        .unwrap();
+    assert!(!history.is_empty());
+
+    let err = client
+        .remove_device(HALLWAY, SOCKET_2, Some(0))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, SmartHouseError::PreconditionFailedError(_)));
+    client
+        .remove_device(HALLWAY, SOCKET_2, Some(version))
+        .await
+        .unwrap();
+    let err = client.device_record(HALLWAY, SOCKET_2).await.err().unwrap();
+    assert!(matches!(
+        err,
+        SmartHouseError::DeviceNotFoundError(room, device) if room == HALLWAY && device == SOCKET_2
+    ));
+
+    let (_, room_version_after) = client
+        .devices(HALLWAY, &DeviceQuery::default())
+        .await
+        .unwrap();
+    assert!(room_version_after > room_version);
+    let err = client
+        .remove_room(HALLWAY, Some(room_version))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, SmartHouseError::PreconditionFailedError(_)));
+    client
+        .remove_room(HALLWAY, Some(room_version_after))
+        .await
+        .unwrap();
+    let err = client.remove_room(HALLWAY, None).await.err().unwrap();
+    assert!(matches!(err, SmartHouseError::RoomNotFoundError(room) if room == HALLWAY));
+}
+
+#[actix_web::test]
+async fn test_client_commands_and_report() {
+    let (client, _) = start_server(None).await;
+
+    let socket = SmartSocket::new(
+        SOCKET_2.to_string(),
+        KITCHEN.to_string(),
+        DeviceStatus::Off,
+        0.0,
+    );
+    tokio::spawn(async move { socket.listen(SOCKET_ADDR).await });
+    tokio::time::sleep(Duration::from_secs_f32(0.5)).await;
+
+    let meta = SmartDeviceMeta::new(DeviceKind::Socket).with_address(SOCKET_ADDR);
+    client.add_device(KITCHEN, SOCKET_2, &meta).await.unwrap();
+    let result = client.device_on(KITCHEN, SOCKET_2).await.unwrap();
+    assert_eq!(result.status, Some(DeviceStatus::On.to_string()));
+    let result = client.device_off(KITCHEN, SOCKET_2).await.unwrap();
+    assert_eq!(result.status, Some(DeviceStatus::Off.to_string()));
+
+    let err = client.device_on(KITCHEN, SOCKET_1).await.err().unwrap();
+    assert!(matches!(err, SmartHouseError::ValidationError(_)));
+
+    let err = client
+        .send_device_command(BEDROOM, "Нет такого", &DeviceCommand::Off)
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, SmartHouseError::DeviceNotFoundError(_, _)));
+
+    let report = client.report().await.unwrap();
+    assert_eq!(report.name(), HOUSE_NAME);
+    assert_eq!(report.address(), HOUSE_ADDRESS);
+    assert_eq!(report.devices()[KITCHEN][0].name(), SOCKET_1);
+    assert_eq!(report.devices()[BEDROOM][0].temp(), 22.5);
+}
+
+#[actix_web::test]
+async fn test_client_houses() {
+    let (client, _) = start_server(None).await;
+
+    client
+        .add_house(COUNTRY_HOUSE, COUNTRY_ADDRESS)
+        .await
+        .unwrap();
+    let err = client
+        .add_house(COUNTRY_HOUSE, COUNTRY_ADDRESS)
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err, SmartHouseError::HouseAlreadyExistsError(house) if house == COUNTRY_HOUSE)
+    );
+
+    let houses = client.houses().await.unwrap();
+    let names: Vec<_> = houses.iter().map(|house| house.name.as_str()).collect();
+    assert!(names.contains(&HOUSE_NAME) && names.contains(&COUNTRY_HOUSE));
+    let house = client.house(COUNTRY_HOUSE).await.unwrap();
+    assert_eq!(house.address, COUNTRY_ADDRESS);
+
+    client.add_house_room(COUNTRY_HOUSE, KITCHEN).await.unwrap();
+    let (rooms, _) = client
+        .house_rooms(COUNTRY_HOUSE, &RoomQuery::default())
+        .await
+        .unwrap();
+    assert_eq!(rooms.items, vec![KITCHEN]);
+
+    let meta = SmartDeviceMeta::new(DeviceKind::Thermometer);
+    client
+        .add_house_device(COUNTRY_HOUSE, KITCHEN, THERMOMETER_1, &meta)
+        .await
+        .unwrap();
+    let (devices, room_version) = client
+        .house_devices(COUNTRY_HOUSE, KITCHEN, &DeviceQuery::default())
+        .await
+        .unwrap();
+    assert_eq!(devices.items[0].kind, DeviceKind::Thermometer);
+    let info = client
+        .house_device_info(COUNTRY_HOUSE, KITCHEN, THERMOMETER_1)
+        .await
+        .unwrap();
+    // без источника информации у нового устройства только название
+    assert!(info.name().starts_with(THERMOMETER_1));
+
+    let report = client.house_report(COUNTRY_HOUSE).await.unwrap();
+    assert_eq!(report.name(), COUNTRY_HOUSE);
+    assert_eq!(report.devices()[KITCHEN].len(), 1);
+
+    let snapshot = client.export_house(Some(COUNTRY_HOUSE)).await.unwrap();
+    assert_eq!(snapshot.name, COUNTRY_HOUSE);
+    assert_eq!(snapshot.rooms[0].devices[0].name, THERMOMETER_1);
+
+    client
+        .remove_house_device(COUNTRY_HOUSE, KITCHEN, THERMOMETER_1, None)
+        .await
+        .unwrap();
+    let (_, version) = client
+        .house_devices(COUNTRY_HOUSE, KITCHEN, &DeviceQuery::default())
+        .await
+        .unwrap();
+    assert!(version > room_version);
+
+    let query = ImportQuery {
+        mode: Some(ImportMode::Merge),
+        dry_run: Some(true),
+    };
+    let report = client.import_house(&snapshot, &query).await.unwrap();
+    assert!(report.dry_run);
+    assert_eq!(report.created.len(), 1);
+
+    client
+        .remove_house_room(COUNTRY_HOUSE, KITCHEN, Some(version))
+        .await
+        .unwrap();
+    let house = client.house(COUNTRY_HOUSE).await.unwrap();
+    let err = client
+        .remove_house(COUNTRY_HOUSE, Some(house.version + 1))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, SmartHouseError::PreconditionFailedError(_)));
+    client
+        .remove_house(COUNTRY_HOUSE, Some(house.version))
+        .await
+        .unwrap();
+    let err = client.house(COUNTRY_HOUSE).await.err().unwrap();
+    assert!(matches!(err, SmartHouseError::HouseNotFoundError(house) if house == COUNTRY_HOUSE));
+}
+
+#[actix_web::test]
+async fn test_client_auth() {
+    let (admin, app_data) = start_server(Some(
+        Authenticator::new().with_jwt_secret(JWT_SECRET, Duration::from_secs(60)),
+    ))
+    .await;
+
+    let anonymous = SmartHomeClient::new(admin.base_url());
+    let err = anonymous.rooms(&RoomQuery::default()).await.err().unwrap();
+    assert!(matches!(err, SmartHouseError::UnauthorizedError(_)));
+    assert_eq!(anonymous.health().await.unwrap().status, HealthState::Ok);
+
+    let admin = admin.with_api_key(ADMIN_KEY);
+    assert_eq!(admin.current_user().await.unwrap().role, Role::Admin);
+
+    let viewer_key = admin.add_api_user("viewer", Role::Viewer).await.unwrap();
+    assert_eq!(viewer_key.user.role, Role::Viewer);
+    let names: Vec<_> = admin
+        .api_users()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| user.name)
+        .collect();
+    assert_eq!(names, vec!["admin", "viewer"]);
+
+    let viewer = anonymous.clone().with_api_key(&viewer_key.api_key);
+    let token = viewer.issue_token().await.unwrap();
+    let viewer = anonymous.clone().with_token(&token.token);
+    assert_eq!(viewer.current_user().await.unwrap().name, "viewer");
+    assert_eq!(
+        viewer
+            .rooms(&RoomQuery::default())
+            .await
+            .unwrap()
+            .0
+            .items
+            .len(),
+        2
+    );
+    let err = viewer.add_room(HALLWAY).await.err().unwrap();
+    assert!(matches!(err, SmartHouseError::ForbiddenError(_)));
+    let err = viewer
+        .audit_log(&AuditQuery::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, SmartHouseError::ForbiddenError(_)));
+
+    admin.add_room(HALLWAY).await.unwrap();
+    let records = admin.audit_log(&AuditQuery::default()).await.unwrap();
+    assert_eq!(records[0].operation, "add_room");
+    assert_eq!(records[0].actor, "admin");
+
+    admin.remove_api_user("viewer").await.unwrap();
+    let err = viewer.current_user().await.err().unwrap();
+    assert!(matches!(err, SmartHouseError::UnauthorizedError(_)));
+    let err = admin.remove_api_user("viewer").await.err().unwrap();
+    assert!(matches!(err, SmartHouseError::UserNotFoundError(user) if user == "viewer"));
+
+    assert_eq!(app_data.api_users().await.unwrap().len(), 1);
+}
+
+#[actix_web::test]
+async fn test_client_events_and_health() {
+    let (client, _) = start_server(None).await;
+
+    let mut events = client.events(Some(HOUSE_NAME)).await.unwrap();
+    client.add_room(HALLWAY).await.unwrap();
+    let event = tokio::time::timeout(EVENT_TIMEOUT, events.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        event,
+        SmartHouseEvent::RoomAdded { house, room } if house == HOUSE_NAME && room == HALLWAY
+    ));
+
+    assert_eq!(client.health().await.unwrap().status, HealthState::Ok);
+    let readiness = client.readiness().await.unwrap();
+    assert_eq!(readiness.storage, HealthState::Ok);
+    let metrics = client.metrics().await.unwrap();
+    assert!(metrics.contains("device_power_watts"));
+
+    let err = SmartHomeClient::new("http://127.0.0.1:1")
+        .health()
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, SmartHouseError::IoError(_)));
+}
+
+/// Запускает REST API на свободном порту, с `authenticator` - с администратором
+/// `admin` и ключом `ADMIN_KEY`
+async fn start_server(
+    authenticator: Option<Authenticator>,
+) -> (SmartHomeClient, web::Data<AppData>) {
+    let mut app_data = AppData::new(
+        HOUSE_NAME.to_string(),
+        HOUSE_ADDRESS.to_string(),
+        Box::new(SmartHouseStorageMemory::new()),
+    );
+    app_data.init(generate_mock_devices()).await.unwrap();
+    if authenticator.is_some() {
+        app_data
+            .add_api_user_with_key("admin", Role::Admin, ADMIN_KEY)
+            .await
+            .unwrap();
+    }
+    let data = web::Data::new(app_data);
+    let authenticator = authenticator.map(web::Data::new);
+
+    let app_state = web::Data::clone(&data);
+    let server = HttpServer::new(move || {
+        let mut app = App::new();
+        if let Some(authenticator) = &authenticator {
+            app = app.app_data(web::Data::clone(authenticator));
+        }
+
+        app.wrap(from_fn(audit_middleware))
+            .wrap(from_fn(auth_middleware))
+            .wrap(from_fn(request_id_middleware))
+            .app_data(web::Data::clone(&app_state))
+            .configure(config)
+    })
+    .workers(1)
+    .disable_signals()
+    .bind("127.0.0.1:0")
+    .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+
+    (SmartHomeClient::new(&format!("http://{addr}/")), data)
+}
+
+fn generate_mock_devices() -> HashMap<&'static str, HashMap<&'static str, SmartDeviceInfo>> {
+    HashMap::from([
+        (
+            KITCHEN,
+            HashMap::from([(
+                SOCKET_1,
+                SmartDeviceInfo::new(
+                    SOCKET_1.to_string(),
+                    DeviceStatus::On.to_string(),
+                    1500.0,
+                    0.0,
+                ),
+            )]),
+        ),
+        (
+            BEDROOM,
+            HashMap::from([(
+                THERMOMETER_1,
+                SmartDeviceInfo::new(
+                    THERMOMETER_1.to_string(),
+                    DeviceStatus::Unknown.to_string(),
+                    0.0,
+                    22.5,
+                ),
+            )]),
+        ),
+    ])
+}